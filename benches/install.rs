@@ -0,0 +1,237 @@
+//! Benchmarks for the parts of an install that scale with the input: unpacking
+//! archives, hashing downloads and parsing the package index. Inputs are
+//! synthetic and generated once per run. Run with `cargo bench`.
+
+// leaf is a binary crate, so the modules under test are compiled in directly,
+// along with code (and test imports) that only the binary uses
+#![allow(dead_code, unused_imports)]
+
+#[path = "../src/checksum.rs"]
+mod checksum;
+#[path = "../src/cosign.rs"]
+mod cosign;
+#[path = "../src/extract.rs"]
+mod extract;
+#[path = "../src/package.rs"]
+mod package;
+#[path = "../src/platform.rs"]
+mod platform;
+#[path = "../src/version.rs"]
+mod version;
+
+use criterion::{BatchSize, Criterion, Throughput, criterion_group, criterion_main};
+use package::Package;
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Many small files plus a few large ones, like a typical release archive
+const SMALL_FILES: usize = 2000;
+const SMALL_FILE_SIZE: usize = 4 * 1024;
+const LARGE_FILES: usize = 4;
+const LARGE_FILE_SIZE: usize = 8 * 1024 * 1024;
+const HASHED_FILE_SIZE: usize = 64 * 1024 * 1024;
+const INDEX_PACKAGES: usize = 5000;
+
+fn work_dir() -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("leaf-bench-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Deterministic bytes that don't compress to nothing
+fn contents(seed: usize, size: usize) -> Vec<u8> {
+    let mut state = seed as u64 | 1;
+    (0..size)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            b"abcdefghijklmnopqrstuvwxyz0123456789\n"[(state % 37) as usize]
+        })
+        .collect()
+}
+
+fn members() -> Vec<(String, Vec<u8>)> {
+    let small = (0..SMALL_FILES).map(|i| {
+        (
+            format!("tool-1.0/share/{}/file-{}.txt", i % 50, i),
+            contents(i, SMALL_FILE_SIZE),
+        )
+    });
+    let large = (0..LARGE_FILES).map(|i| {
+        (
+            format!("tool-1.0/lib/large-{}.so", i),
+            contents(SMALL_FILES + i, LARGE_FILE_SIZE),
+        )
+    });
+    small.chain(large).collect()
+}
+
+fn write_tar<W: Write>(writer: W, members: &[(String, Vec<u8>)]) -> W {
+    let mut builder = tar::Builder::new(writer);
+    for (path, data) in members {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder
+            .append_data(&mut header, path, data.as_slice())
+            .unwrap();
+    }
+    builder.into_inner().unwrap()
+}
+
+/// The same members as .tar.gz, .tar.zst and .zip
+fn archives(dir: &Path) -> Vec<PathBuf> {
+    let members = members();
+
+    let tar_gz = dir.join("tool.tar.gz");
+    let encoder = flate2::write::GzEncoder::new(
+        std::fs::File::create(&tar_gz).unwrap(),
+        flate2::Compression::default(),
+    );
+    write_tar(encoder, &members).finish().unwrap();
+
+    let tar_zst = dir.join("tool.tar.zst");
+    let encoder = zstd::Encoder::new(std::fs::File::create(&tar_zst).unwrap(), 3).unwrap();
+    write_tar(encoder, &members).finish().unwrap();
+
+    let zip_path = dir.join("tool.zip");
+    let mut zip = zip::ZipWriter::new(std::fs::File::create(&zip_path).unwrap());
+    let options = zip::write::SimpleFileOptions::default().unix_permissions(0o644);
+    for (path, data) in &members {
+        zip.start_file(path.as_str(), options).unwrap();
+        zip.write_all(data).unwrap();
+    }
+    zip.finish().unwrap();
+
+    vec![tar_gz, tar_zst, zip_path]
+}
+
+fn bench_extraction(c: &mut Criterion) {
+    let dir = work_dir();
+    let archives = archives(&dir);
+    let mut group = c.benchmark_group("extract");
+    group.sample_size(10);
+    group.throughput(Throughput::Bytes(
+        (SMALL_FILES * SMALL_FILE_SIZE + LARGE_FILES * LARGE_FILE_SIZE) as u64,
+    ));
+    for archive in &archives {
+        let name = archive.file_name().unwrap().to_string_lossy().to_string();
+        let dest = dir.join("out");
+        group.bench_function(&name, |b| {
+            b.iter_batched(
+                || {
+                    std::fs::remove_dir_all(&dest).ok();
+                    std::fs::create_dir_all(&dest).unwrap();
+                },
+                |_| extract::extract_archive_sync(archive, &dest).unwrap(),
+                BatchSize::PerIteration,
+            )
+        });
+    }
+    // The zip crate's own single-threaded extraction, as a baseline for the
+    // parallel one above
+    let zip_path = dir.join("tool.zip");
+    let dest = dir.join("out");
+    group.bench_function("tool.zip (sequential)", |b| {
+        b.iter_batched(
+            || {
+                std::fs::remove_dir_all(&dest).ok();
+                std::fs::create_dir_all(&dest).unwrap();
+            },
+            |_| {
+                zip::ZipArchive::new(std::fs::File::open(&zip_path).unwrap())
+                    .unwrap()
+                    .extract(&dest)
+                    .unwrap()
+            },
+            BatchSize::PerIteration,
+        )
+    });
+    group.finish();
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+fn bench_checksum(c: &mut Criterion) {
+    let dir = work_dir();
+    let path = dir.join("download.bin");
+    std::fs::write(&path, contents(0, HASHED_FILE_SIZE)).unwrap();
+    let expected = checksum::sha256_file(&path).unwrap();
+
+    let mut group = c.benchmark_group("checksum");
+    group.sample_size(20);
+    group.throughput(Throughput::Bytes(HASHED_FILE_SIZE as u64));
+    group.bench_function("verify_sha256", |b| {
+        b.iter(|| checksum::verify_sha256(&path, &expected).unwrap())
+    });
+    group.finish();
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+/// An index shaped like packages.json: platforms, defaults, versions and variants
+fn index() -> String {
+    let mut index = serde_json::Map::new();
+    for i in 0..INDEX_PACKAGES {
+        let name = format!("package-{}", i);
+        let mut platforms = serde_json::Map::new();
+        for platform in [
+            "linux-x86_64",
+            "linux-aarch64",
+            "macos-x86_64",
+            "macos-aarch64",
+        ] {
+            platforms.insert(
+                platform.to_string(),
+                serde_json::json!({
+                    "url": format!("https://example.com/{0}/{0}-{1}.tar.gz", name, platform),
+                    "sha256": format!("{:064x}", i),
+                    "variants": [{
+                        "features": ["avx2"],
+                        "url": format!("https://example.com/{0}/{0}-{1}-avx2.tar.gz", name, platform)
+                    }]
+                }),
+            );
+        }
+        index.insert(
+            name.clone(),
+            serde_json::json!({
+                "description": format!("Synthetic package number {}", i),
+                "version": "2.0.0",
+                "defaults": {
+                    "type": "archive",
+                    "executables": [{"path": format!("{}-2.0.0/bin/{}", name, name), "name": name}]
+                },
+                "platforms": platforms,
+                "versions": {
+                    "1.0.0": {
+                        "linux-x86_64": {"url": format!("https://example.com/{}-1.0.0.tar.gz", name)}
+                    }
+                }
+            }),
+        );
+    }
+    serde_json::Value::Object(index).to_string()
+}
+
+fn bench_index(c: &mut Criterion) {
+    let content = index();
+    let mut group = c.benchmark_group("index");
+    group.sample_size(20);
+    group.throughput(Throughput::Bytes(content.len() as u64));
+    group.bench_function("parse", |b| {
+        b.iter(|| {
+            let mut packages: HashMap<String, Package> = serde_json::from_str(&content).unwrap();
+            for package in packages.values_mut() {
+                package.apply_defaults();
+                package.select_variants(|_| false);
+            }
+            packages
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_extraction, bench_checksum, bench_index);
+criterion_main!(benches);
@@ -1,5 +1,10 @@
+use std::process::Command;
+
 fn main() {
     println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+    println!("cargo:rerun-if-env-changed=LEAF_RELEASE_KEY");
 
     // Static linking for musl targets
     let target = std::env::var("TARGET").unwrap();
@@ -7,4 +12,25 @@ fn main() {
         println!("cargo:rustc-link-arg=-static");
         println!("cargo:rustc-env=RUSTFLAGS=-C target-feature=+crt-static");
     }
+
+    // Shown by `leaf --version --verbose`
+    println!("cargo:rustc-env=LEAF_BUILD_TARGET={}", target);
+    if let Some(commit) = output("git", &["rev-parse", "--short=12", "HEAD"]) {
+        println!("cargo:rustc-env=LEAF_BUILD_COMMIT={}", commit);
+    }
+    // Honour SOURCE_DATE_EPOCH so reproducible builds get a fixed date
+    let date = match std::env::var("SOURCE_DATE_EPOCH") {
+        Ok(epoch) => output("date", &["-u", "-d", &format!("@{}", epoch), "+%Y-%m-%d"]),
+        Err(_) => output("date", &["-u", "+%Y-%m-%d"]),
+    };
+    println!(
+        "cargo:rustc-env=LEAF_BUILD_DATE={}",
+        date.unwrap_or_else(|| "unknown".to_string())
+    );
+}
+
+fn output(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().ok()?;
+    let text = String::from_utf8(output.stdout).ok()?;
+    (output.status.success() && !text.trim().is_empty()).then(|| text.trim().to_string())
 }
@@ -0,0 +1,119 @@
+use crate::package::OsvPackage;
+use crate::version::compare_versions;
+use anyhow::{Result, anyhow};
+use reqwest::Client;
+use serde_json::{Value, json};
+use std::cmp::Ordering;
+
+const OSV_QUERY_URL: &str = "https://api.osv.dev/v1/query";
+
+/// A known vulnerability affecting an installed version
+pub struct Advisory {
+    pub id: String,
+    /// CVE and other identifiers for the same issue
+    pub aliases: Vec<String>,
+    pub summary: String,
+    /// Versions that fix it, from the advisory's affected ranges
+    pub fixed: Vec<String>,
+}
+
+/// Ask OSV which advisories affect `version` of a package
+pub async fn query(client: &Client, osv: &OsvPackage, version: &str) -> Result<Vec<Advisory>> {
+    let response = client
+        .post(OSV_QUERY_URL)
+        .json(&json!({
+            "package": {"ecosystem": osv.ecosystem, "name": osv.name},
+            "version": version,
+        }))
+        .send()
+        .await?;
+
+    if !response.status().is_success() {
+        return Err(anyhow!("OSV query failed: HTTP {}", response.status()));
+    }
+
+    let body: Value = response.json().await?;
+    Ok(body["vulns"]
+        .as_array()
+        .map(|vulns| vulns.iter().map(parse_advisory).collect())
+        .unwrap_or_default())
+}
+
+/// Whether upgrading from `installed` to `available` fixes every advisory: each
+/// must list a fixed version past `installed` that `available` has reached
+pub fn fixes_all(advisories: &[Advisory], installed: &str, available: &str) -> bool {
+    advisories.iter().all(|advisory| {
+        advisory.fixed.iter().any(|fixed| {
+            compare_versions(fixed, installed) == Ordering::Greater
+                && compare_versions(available, fixed) != Ordering::Less
+        })
+    })
+}
+
+fn parse_advisory(vuln: &Value) -> Advisory {
+    let strings = |value: &Value| -> Vec<String> {
+        value
+            .as_array()
+            .map(|items| {
+                items
+                    .iter()
+                    .filter_map(|item| item.as_str().map(String::from))
+                    .collect()
+            })
+            .unwrap_or_default()
+    };
+
+    let mut fixed: Vec<String> = vuln["affected"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .flat_map(|affected| affected["ranges"].as_array().into_iter().flatten())
+        .flat_map(|range| range["events"].as_array().into_iter().flatten())
+        .filter_map(|event| event["fixed"].as_str().map(String::from))
+        .collect();
+    fixed.sort();
+    fixed.dedup();
+
+    Advisory {
+        id: vuln["id"].as_str().unwrap_or("unknown").to_string(),
+        aliases: strings(&vuln["aliases"]),
+        summary: vuln["summary"]
+            .as_str()
+            .or_else(|| vuln["details"].as_str())
+            .unwrap_or("")
+            .lines()
+            .next()
+            .unwrap_or("")
+            .to_string(),
+        fixed,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_advisory() {
+        let vuln = json!({
+            "id": "GO-2024-2887",
+            "aliases": ["CVE-2024-24790"],
+            "summary": "Unexpected behavior in net/netip",
+            "affected": [{"ranges": [{"type": "SEMVER", "events": [
+                {"introduced": "0"}, {"fixed": "1.21.11"},
+                {"introduced": "1.22.0"}, {"fixed": "1.22.4"}
+            ]}]}]
+        });
+        let advisory = parse_advisory(&vuln);
+        assert_eq!(advisory.id, "GO-2024-2887");
+        assert_eq!(advisory.aliases, vec!["CVE-2024-24790"]);
+        assert_eq!(advisory.fixed, vec!["1.21.11", "1.22.4"]);
+
+        let advisories = [advisory];
+        assert!(fixes_all(&advisories, "1.22.1", "1.22.4"));
+        assert!(fixes_all(&advisories, "1.21.3", "1.22.5"));
+        assert!(!fixes_all(&advisories, "1.22.1", "1.22.3"));
+        let unfixed = parse_advisory(&json!({"id": "GO-2024-0001"}));
+        assert!(!fixes_all(&[unfixed], "1.22.1", "1.23.0"));
+    }
+}
@@ -0,0 +1,90 @@
+use crate::utils::{print_error, print_info, print_success};
+use anyhow::{Result, anyhow};
+use colored::Colorize;
+use std::collections::BTreeMap;
+
+/// Collects per-package outcomes of a multi-package operation so a single
+/// failure can be reported at the end instead of aborting the whole batch
+pub struct BatchReport {
+    action: &'static str,
+    total: usize,
+    succeeded: Vec<String>,
+    failed: Vec<(String, String)>,
+}
+
+impl BatchReport {
+    pub fn new(action: &'static str, total: usize) -> Self {
+        Self {
+            action,
+            total,
+            succeeded: Vec::new(),
+            failed: Vec::new(),
+        }
+    }
+
+    pub fn record(&mut self, name: &str, result: &Result<()>) {
+        match result {
+            Ok(()) => self.succeeded.push(name.to_string()),
+            Err(e) => self.failed.push((name.to_string(), e.to_string())),
+        }
+    }
+
+    pub fn has_failures(&self) -> bool {
+        !self.failed.is_empty()
+    }
+
+    pub fn print_summary(&self) {
+        let skipped = self.total - self.succeeded.len() - self.failed.len();
+        print_info(&tr!(
+            "batch.summary",
+            capitalize(self.action),
+            self.succeeded.len(),
+            self.failed.len(),
+            skipped
+        ));
+        for name in &self.succeeded {
+            println!("  {} {}", "✓".green(), name);
+        }
+
+        if self.failed.is_empty() {
+            return;
+        }
+
+        // Group packages by error so a shared cause (e.g. no network) is shown once
+        let mut grouped: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+        for (name, error) in &self.failed {
+            grouped.entry(error).or_default().push(name);
+        }
+
+        print_error(&tr!("batch.failed_header", self.action, self.failed.len()));
+        for (error, names) in grouped {
+            println!("  {} {}", "✗".red(), names.join(", "));
+            for line in error.lines() {
+                println!("      {}", line);
+            }
+        }
+    }
+
+    /// Print the summary and turn any recorded failures into an error
+    pub fn finish(self) -> Result<()> {
+        self.print_summary();
+        if self.has_failures() {
+            return Err(anyhow!(
+                "{} of {} package(s) failed to {}",
+                self.failed.len(),
+                self.total,
+                self.action
+            ));
+        }
+        print_success(&tr!("batch.done", capitalize(self.action)));
+        Ok(())
+    }
+}
+
+fn capitalize(word: &str) -> String {
+    let mut chars = word.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
@@ -0,0 +1,215 @@
+use crate::config::Config;
+use crate::trust;
+use anyhow::{Result, anyhow};
+use reqwest::Client;
+use serde::Deserialize;
+use serde_json::Value;
+use std::path::Path;
+use tokio::fs;
+
+/// Machine setup applied by `leaf bootstrap`
+#[derive(Debug, Default, Deserialize)]
+pub struct Manifest {
+    /// Packages to install, as given to `leaf install`
+    #[serde(default)]
+    pub packages: Vec<String>,
+    /// Extra package indexes to add to config.json
+    #[serde(default)]
+    pub repos: Vec<String>,
+    /// Settings merged into config.json, e.g. aliases or profiles
+    #[serde(default)]
+    pub config: Option<Value>,
+}
+
+/// Settings that describe this machine's layout and can't come from a manifest
+const PROTECTED_KEYS: [&str; 6] = [
+    "version",
+    "install_dir",
+    "bin_dir",
+    "packages_dir",
+    "cache_dir",
+    "default_versions",
+];
+
+/// Settings that only shape how leaf looks and behaves for this user. Anything
+/// else may change where packages come from, what may be installed or where
+/// leaf writes, so a manifest sets it only when applied with `--managed`.
+const UNMANAGED_KEYS: [&str; 11] = [
+    "aliases",
+    "profiles",
+    "color",
+    "progress_bar",
+    "locale",
+    "link_strategy",
+    "relative_links",
+    "strip_binaries",
+    "compress_binaries",
+    "build_timeout",
+    "log_shim_runs",
+];
+
+/// Fetch a manifest from a URL or local path and check that its detached GPG
+/// signature (`<location>.asc`) was made by the key with fingerprint `key`.
+/// Without a key, the manifest is only used when `allow_unsigned` is set.
+pub async fn fetch(
+    client: &Client,
+    location: &str,
+    work_dir: &Path,
+    key: Option<&str>,
+    allow_unsigned: bool,
+) -> Result<Manifest> {
+    let content = read(client, location)
+        .await?
+        .ok_or_else(|| anyhow!("Bootstrap manifest not found at {}", location))?;
+
+    match key {
+        Some(key) => {
+            let signature = read(client, &format!("{}.asc", location))
+                .await?
+                .ok_or_else(|| anyhow!("{} has no signature ({}.asc)", location, location))?;
+            fs::create_dir_all(work_dir).await?;
+            let manifest_path = work_dir.join("bootstrap.json");
+            let signature_path = work_dir.join("bootstrap.json.asc");
+            fs::write(&manifest_path, &content).await?;
+            fs::write(&signature_path, &signature).await?;
+            let status = trust::gpg_verify(&signature_path, &manifest_path).await;
+            fs::remove_file(&manifest_path).await.ok();
+            fs::remove_file(&signature_path).await.ok();
+            match status {
+                Ok(Some(status)) if trust::signed_by(&status, key) => {}
+                Ok(Some(_)) => return Err(anyhow!("{} is not signed by {}", location, key)),
+                Ok(None) => return Err(anyhow!("Signature verification of {} failed", location)),
+                Err(_) => return Err(anyhow!("gpg is needed to verify {}", location)),
+            }
+        }
+        None if allow_unsigned => {}
+        None => {
+            return Err(anyhow!(
+                "Pass --key with the fingerprint of the key that signs {}, or --allow-unsigned to use it unverified",
+                location
+            ));
+        }
+    }
+
+    Ok(serde_json::from_slice(&content)?)
+}
+
+/// Settings and repos in `manifest` that need `--managed`, by name
+pub fn managed_settings(manifest: &Manifest) -> Vec<&str> {
+    let mut keys: Vec<&str> = manifest
+        .config
+        .as_ref()
+        .and_then(Value::as_object)
+        .map(|config| {
+            config
+                .keys()
+                .map(String::as_str)
+                .filter(|key| !UNMANAGED_KEYS.contains(key) && !PROTECTED_KEYS.contains(key))
+                .collect()
+        })
+        .unwrap_or_default();
+    if !manifest.repos.is_empty() && !keys.contains(&"repos") {
+        keys.push("repos");
+    }
+    keys
+}
+
+/// Body of an http(s) URL or local file, or None if it doesn't exist
+async fn read(client: &Client, location: &str) -> Result<Option<Vec<u8>>> {
+    if location.starts_with("http://") || location.starts_with("https://") {
+        let response = client.get(location).send().await?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        return Ok(Some(response.error_for_status()?.bytes().await?.to_vec()));
+    }
+    let path = location.strip_prefix("file://").unwrap_or(location);
+    match fs::read(path).await {
+        Ok(content) => Ok(Some(content)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Merge manifest settings into the config
+pub fn apply_config(config: &mut Config, overrides: &Value) -> Result<()> {
+    let mut merged = serde_json::to_value(&*config)?;
+    merge(&mut merged, overrides)?;
+    let mut updated: Config = serde_json::from_value(merged)?;
+    updated.prefix = config.prefix.take();
+    updated.portable = config.portable;
+    *config = updated;
+    Ok(())
+}
+
+/// Overlay `overrides` on `base`. Maps such as aliases or profiles gain entries
+/// rather than being replaced; protected keys are rejected.
+fn merge(base: &mut Value, overrides: &Value) -> Result<()> {
+    let (Some(base), Some(overrides)) = (base.as_object_mut(), overrides.as_object()) else {
+        return Err(anyhow!("Bootstrap config must be a JSON object"));
+    };
+    for (key, value) in overrides {
+        if PROTECTED_KEYS.contains(&key.as_str()) {
+            return Err(anyhow!("Bootstrap config may not set '{}'", key));
+        }
+        match (base.get_mut(key), value) {
+            (Some(Value::Object(existing)), Value::Object(entries)) => {
+                existing.extend(entries.clone());
+            }
+            _ => {
+                base.insert(key.clone(), value.clone());
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_merge() {
+        let mut base = json!({
+            "bin_dir": "/home/me/.local/bin",
+            "aliases": {"py": "python"},
+            "strip_binaries": false,
+        });
+        merge(
+            &mut base,
+            &json!({"aliases": {"k": "kubectl"}, "strip_binaries": true}),
+        )
+        .unwrap();
+        assert_eq!(
+            base,
+            json!({
+                "bin_dir": "/home/me/.local/bin",
+                "aliases": {"py": "python", "k": "kubectl"},
+                "strip_binaries": true,
+            })
+        );
+        assert!(merge(&mut base, &json!({"bin_dir": "/usr/bin"})).is_err());
+    }
+
+    #[test]
+    fn test_managed_settings() {
+        let manifest: Manifest = serde_json::from_value(json!({
+            "packages": ["go"],
+            "config": {"aliases": {"k": "kubectl"}},
+        }))
+        .unwrap();
+        assert!(managed_settings(&manifest).is_empty());
+        let manifest: Manifest = serde_json::from_value(json!({
+            "repos": ["https://example.com/team.json"],
+            "config": {"policy": {"allowed": ["go"]}, "cache": {"remote_url": "/mnt"}},
+        }))
+        .unwrap();
+        assert_eq!(managed_settings(&manifest), ["cache", "policy", "repos"]);
+        let manifest: Manifest = serde_json::from_value(json!({
+            "config": {"shared_store": "/tmp/store", "color": "never"},
+        }))
+        .unwrap();
+        assert_eq!(managed_settings(&manifest), ["shared_store"]);
+    }
+}
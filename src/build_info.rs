@@ -0,0 +1,24 @@
+/// leaf's version, from Cargo.toml
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Sent with every HTTP request
+pub const USER_AGENT: &str = concat!("leaf-package-manager/", env!("CARGO_PKG_VERSION"));
+
+/// Target triple leaf was compiled for, e.g. x86_64-unknown-linux-musl
+const TARGET: &str = env!("LEAF_BUILD_TARGET");
+
+/// Git commit the build came from; absent when built from a source tarball
+const COMMIT: Option<&str> = option_env!("LEAF_BUILD_COMMIT");
+
+const DATE: &str = env!("LEAF_BUILD_DATE");
+
+/// `leaf --version --verbose`
+pub fn verbose() -> String {
+    format!(
+        "leaf {}\ntarget: {}\ncommit: {}\nbuild date: {}",
+        VERSION,
+        TARGET,
+        COMMIT.unwrap_or("unknown"),
+        DATE
+    )
+}
@@ -0,0 +1,232 @@
+use crate::utils::plain_file_name;
+use crate::version::split_spec;
+use anyhow::{Result, anyhow};
+use std::collections::HashMap;
+use std::env;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+
+/// Build logs kept per package; older ones go when a new build starts
+const KEPT_LOGS: usize = 5;
+
+/// Timestamp in log names; it sorts the same as the times it stands for
+const LOG_TIME_FORMAT: &str = "%Y%m%dT%H%M%S";
+
+/// Where `name` is built, under install_dir/builds. Versions of a package
+/// share it, so compiler caches kept there speed up rebuilding an upgrade.
+pub fn build_root(builds_dir: &Path, name: &str) -> PathBuf {
+    let (base, _) = split_spec(name);
+    builds_dir.join(base)
+}
+
+/// Where `version` of a package is unpacked and built, under its build root;
+/// each version gets its own tree so builds of two versions don't collide
+pub fn source_dir(build_root: &Path, version: &str) -> Result<PathBuf> {
+    if !plain_file_name(version) {
+        return Err(anyhow!("Cannot build version '{}' of a package", version));
+    }
+    Ok(build_root.join("src").join(version))
+}
+
+/// Lock held while `version` of a package is built, so two leaf processes
+/// don't build into the same tree
+pub fn lock_path(build_root: &Path, version: &str) -> PathBuf {
+    build_root.join(format!(".{}.lock", version))
+}
+
+/// Leaf packages a source build runs with, from its build_dependencies
+#[derive(Debug, Clone, Default)]
+pub struct BuildTools {
+    /// Directories of their executables, put ahead of PATH
+    pub path: Vec<PathBuf>,
+    /// Their own env variables
+    pub env: Vec<(String, String)>,
+    /// Package name -> installed directory, for {tool:<name>}
+    pub dirs: HashMap<String, PathBuf>,
+}
+
+impl BuildTools {
+    /// Replace {tool:<name>} with that tool's directory; unknown names are
+    /// left as they are
+    pub fn expand(&self, value: &str) -> String {
+        self.dirs
+            .iter()
+            .fold(value.to_string(), |value, (name, dir)| {
+                value.replace(&format!("{{tool:{}}}", name), &dir.display().to_string())
+            })
+    }
+
+    /// PATH for build commands: the tools first, then the caller's PATH
+    pub fn search_path(&self) -> Result<Option<std::ffi::OsString>> {
+        if self.path.is_empty() {
+            return Ok(None);
+        }
+        let inherited = env::var_os("PATH").unwrap_or_default();
+        let dirs = self
+            .path
+            .iter()
+            .cloned()
+            .chain(env::split_paths(&inherited));
+        Ok(Some(env::join_paths(dirs)?))
+    }
+}
+
+/// Environment that routes compilers through ccache or sccache, when either
+/// is installed, with their caches kept in `build_root`. Compilers the user
+/// already chose through CC, CXX or RUSTC_WRAPPER are left alone.
+pub fn compiler_cache_env(build_root: &Path) -> Vec<(String, String)> {
+    cache_env(
+        build_root,
+        |tool| find_in_path(tool).is_some(),
+        |name| env::var_os(name).is_some(),
+    )
+}
+
+fn cache_env(
+    build_root: &Path,
+    available: impl Fn(&str) -> bool,
+    is_set: impl Fn(&str) -> bool,
+) -> Vec<(String, String)> {
+    let mut vars = Vec::new();
+    let mut set = |name: &str, value: String| vars.push((name.to_string(), value));
+    let ccache = available("ccache");
+    let sccache = available("sccache");
+
+    if ccache {
+        set(
+            "CCACHE_DIR",
+            build_root.join("ccache").display().to_string(),
+        );
+    }
+    if sccache {
+        set(
+            "SCCACHE_DIR",
+            build_root.join("sccache").display().to_string(),
+        );
+        if !is_set("RUSTC_WRAPPER") {
+            set("RUSTC_WRAPPER", "sccache".to_string());
+        }
+    }
+    // ccache is the more widely tested of the two for C and C++
+    let wrapper = match (ccache, sccache) {
+        (true, _) => Some("ccache"),
+        (false, true) => Some("sccache"),
+        _ => None,
+    };
+    if let Some(wrapper) = wrapper {
+        for (var, compiler) in [("CC", "cc"), ("CXX", "c++")] {
+            if !is_set(var) {
+                set(var, format!("{} {}", wrapper, compiler));
+            }
+        }
+    }
+    vars
+}
+
+/// Start the log for a build of `name`, logs_dir/<name>-<timestamp>.log,
+/// pruning the oldest ones
+pub fn create_log(logs_dir: &Path, name: &str) -> Result<(PathBuf, File)> {
+    let (base, _) = split_spec(name);
+    let mut old = logs(logs_dir, name);
+    let excess = (old.len() + 1).saturating_sub(KEPT_LOGS);
+    for path in old.drain(..excess) {
+        fs::remove_file(path).ok();
+    }
+    fs::create_dir_all(logs_dir)?;
+    let path = logs_dir.join(format!(
+        "{}-{}.log",
+        base,
+        chrono::Local::now().format(LOG_TIME_FORMAT)
+    ));
+    let file = File::create(&path)?;
+    Ok((path, file))
+}
+
+/// Build logs of `name`, oldest first
+pub fn logs(logs_dir: &Path, name: &str) -> Vec<PathBuf> {
+    let (base, _) = split_spec(name);
+    let Ok(entries) = fs::read_dir(logs_dir) else {
+        return Vec::new();
+    };
+    let mut logs: Vec<PathBuf> = entries
+        .flatten()
+        .filter(|entry| is_log_of(&entry.file_name().to_string_lossy(), base))
+        .map(|entry| entry.path())
+        .collect();
+    logs.sort();
+    logs
+}
+
+/// Whether `file_name` is <base>-<timestamp>.log, so "go" doesn't pick up
+/// the logs of "go-task"
+fn is_log_of(file_name: &str, base: &str) -> bool {
+    file_name
+        .strip_prefix(base)
+        .and_then(|rest| rest.strip_prefix('-'))
+        .and_then(|rest| rest.strip_suffix(".log"))
+        .is_some_and(|time| chrono::NaiveDateTime::parse_from_str(time, LOG_TIME_FORMAT).is_ok())
+}
+
+/// Last `count` lines of `text`
+pub fn last_lines(text: &str, count: usize) -> String {
+    let lines: Vec<&str> = text.lines().collect();
+    lines[lines.len().saturating_sub(count)..].join("\n")
+}
+
+/// First executable called `tool` on PATH
+fn find_in_path(tool: &str) -> Option<PathBuf> {
+    env::split_paths(&env::var_os("PATH")?)
+        .map(|dir| dir.join(tool))
+        .find(|path| path.is_file())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cache_env() {
+        let root = Path::new("/leaf/builds/jq");
+        assert_eq!(build_root(Path::new("/leaf/builds"), "jq@1.6"), root);
+        assert!(cache_env(root, |_| false, |_| false).is_empty());
+
+        let vars = cache_env(root, |tool| tool == "ccache", |name| name == "CXX");
+        assert_eq!(
+            vars,
+            vec![
+                (
+                    "CCACHE_DIR".to_string(),
+                    "/leaf/builds/jq/ccache".to_string()
+                ),
+                ("CC".to_string(), "ccache cc".to_string()),
+            ]
+        );
+
+        let vars = cache_env(root, |tool| tool == "sccache", |_| false);
+        assert!(vars.contains(&("RUSTC_WRAPPER".to_string(), "sccache".to_string())));
+        assert!(vars.contains(&("CC".to_string(), "sccache cc".to_string())));
+    }
+
+    #[test]
+    fn test_build_tools_expand() {
+        let tools = BuildTools {
+            dirs: HashMap::from([("gcc".to_string(), PathBuf::from("/leaf/packages/gcc"))]),
+            ..Default::default()
+        };
+        assert_eq!(
+            tools.expand("{tool:gcc}/bin/gcc -B{tool:binutils}"),
+            "/leaf/packages/gcc/bin/gcc -B{tool:binutils}"
+        );
+        assert!(tools.search_path().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_is_log_of() {
+        assert!(is_log_of("go-20261016T185900.log", "go"));
+        assert!(!is_log_of("go-task-20261016T185900.log", "go"));
+        assert!(!is_log_of("go-20261016T185900.log.old", "go"));
+        assert!(!is_log_of("leaf.log", "leaf"));
+        assert_eq!(last_lines("a\nb\nc\n", 2), "b\nc");
+        assert_eq!(last_lines("a", 5), "a");
+    }
+}
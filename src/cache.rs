@@ -0,0 +1,337 @@
+use crate::checksum::sha256_file;
+use crate::interrupt;
+use crate::utils::plain_file_name;
+use anyhow::{Result, anyhow};
+use futures_util::StreamExt;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tokio::io::AsyncWriteExt;
+
+/// Maps download URLs to the files they produced, so a cached artifact can be
+/// found without asking the server for its name (e.g. when offline)
+const INDEX_FILE: &str = "cache-index.json";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CacheEntry {
+    /// File name inside the cache directory
+    pub file: String,
+    /// Where the URL redirected to when it was downloaded
+    pub resolved_url: String,
+    pub sha256: String,
+    /// Size in bytes, recorded alongside the hash so a truncated file is
+    /// caught without reading it; absent in indexes from older releases
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+}
+
+/// What `leaf cache verify` found
+#[derive(Debug, Default)]
+pub struct Verified {
+    /// Artifacts whose size and hash still match the index
+    pub intact: usize,
+    /// Artifacts that no longer match, now deleted along with their entries
+    pub corrupted: Vec<String>,
+    /// Index entries whose file had disappeared
+    pub missing: usize,
+    /// Leftovers of interrupted downloads, now deleted
+    pub partial: usize,
+}
+
+/// Entries whose file isn't a plain name in the cache directory are dropped, so
+/// an index (possibly from a shared remote) can't point outside it
+fn load(cache_dir: &Path) -> BTreeMap<String, CacheEntry> {
+    let mut index: BTreeMap<String, CacheEntry> = fs::read_to_string(cache_dir.join(INDEX_FILE))
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .unwrap_or_default();
+    index.retain(|_, entry| plain_file_name(&entry.file));
+    index
+}
+
+/// Every artifact in the index, by the URL it was downloaded from
+pub fn entries(cache_dir: &Path) -> BTreeMap<String, CacheEntry> {
+    load(cache_dir)
+}
+
+/// The cached file for `url`, provided it is still there and unchanged
+pub fn lookup(cache_dir: &Path, url: &str) -> Option<(PathBuf, CacheEntry)> {
+    let entry = load(cache_dir).remove(url)?;
+    let path = cache_dir.join(&entry.file);
+    intact(&path, &entry).then_some((path, entry))
+}
+
+/// Whether the file at `path` is still the one `entry` describes
+fn intact(path: &Path, entry: &CacheEntry) -> bool {
+    let Ok(metadata) = fs::metadata(path) else {
+        return false;
+    };
+    if entry.size.is_some_and(|size| size != metadata.len()) {
+        return false;
+    }
+    sha256_file(path).is_ok_and(|sha256| sha256 == entry.sha256)
+}
+
+/// Whether a file already in the cache under the name a download would get can
+/// stand in for it: its size must match the server's Content-Length (when
+/// sent) and, if an earlier download recorded it, its hash must still match
+pub fn reusable(cache_dir: &Path, path: &Path, content_length: Option<u64>) -> bool {
+    let Ok(metadata) = fs::metadata(path) else {
+        return false;
+    };
+    if content_length.is_some_and(|length| length != metadata.len()) {
+        return false;
+    }
+    let file = path.file_name().unwrap_or_default().to_string_lossy();
+    match load(cache_dir)
+        .into_values()
+        .find(|entry| entry.file == file)
+    {
+        Some(entry) => intact(path, &entry),
+        // Not indexed (e.g. copied in by hand); the size is all there is to go on
+        None => true,
+    }
+}
+
+/// Re-hash every indexed artifact, deleting the ones that no longer match
+/// (e.g. after disk errors or an interrupted copy) and dropping index entries
+/// for files that are gone, so they are downloaded again next time
+pub fn verify(cache_dir: &Path) -> Result<Verified> {
+    let mut verified = Verified::default();
+    let mut index = load(cache_dir);
+    // Mirrors of one artifact share a file; hash it once
+    let mut checked: BTreeMap<String, bool> = BTreeMap::new();
+    index.retain(|_, entry| {
+        let path = cache_dir.join(&entry.file);
+        if let Some(&ok) = checked.get(&entry.file) {
+            return ok;
+        }
+        let ok = intact(&path, entry);
+        if ok {
+            verified.intact += 1;
+        } else if path.exists() {
+            fs::remove_file(&path).ok();
+            verified.corrupted.push(entry.file.clone());
+        } else {
+            verified.missing += 1;
+        }
+        checked.insert(entry.file.clone(), ok);
+        ok
+    });
+    save(cache_dir, &index)?;
+
+    for dir_entry in fs::read_dir(cache_dir)? {
+        let path = dir_entry?.path();
+        if path
+            .extension()
+            .is_some_and(|extension| extension == "part")
+        {
+            fs::remove_file(&path)?;
+            verified.partial += 1;
+        }
+    }
+    Ok(verified)
+}
+
+/// Remember which file `url` was saved as
+pub fn record(cache_dir: &Path, url: &str, entry: CacheEntry) -> Result<()> {
+    let mut index = load(cache_dir);
+    index.insert(url.to_string(), entry);
+    save(cache_dir, &index)
+}
+
+fn save(cache_dir: &Path, index: &BTreeMap<String, CacheEntry>) -> Result<()> {
+    // Replace the index in one step; concurrent downloads may still drop each
+    // other's entries, which only costs a request next time
+    static WRITES: AtomicUsize = AtomicUsize::new(0);
+    let partial = cache_dir.join(format!(
+        "{}.{}-{}.part",
+        INDEX_FILE,
+        std::process::id(),
+        WRITES.fetch_add(1, Ordering::Relaxed)
+    ));
+    interrupt::started(&partial);
+    fs::write(&partial, serde_json::to_string_pretty(index)?)?;
+    fs::rename(&partial, cache_dir.join(INDEX_FILE))?;
+    interrupt::finished(&partial);
+    Ok(())
+}
+
+/// A cache shared between machines, laid out like the local cache: artifacts
+/// next to a cache-index.json. Either a (network) directory or an HTTP base URL,
+/// e.g. an S3 bucket's website endpoint.
+#[derive(Debug, Clone)]
+pub enum Remote {
+    Dir(PathBuf),
+    Http(String),
+}
+
+impl Remote {
+    pub fn parse(location: &str) -> Self {
+        if location.starts_with("http://") || location.starts_with("https://") {
+            return Remote::Http(location.trim_end_matches('/').to_string());
+        }
+        Remote::Dir(PathBuf::from(
+            location.strip_prefix("file://").unwrap_or(location),
+        ))
+    }
+
+    /// Copy the artifact for `url` into `cache_dir` if the remote has it
+    pub async fn fetch(
+        &self,
+        client: &Client,
+        url: &str,
+        cache_dir: &Path,
+    ) -> Result<Option<(PathBuf, CacheEntry)>> {
+        let entry = match self {
+            Remote::Dir(dir) => {
+                let (dir, url) = (dir.clone(), url.to_string());
+                let Some((path, entry)) =
+                    tokio::task::spawn_blocking(move || lookup(&dir, &url)).await?
+                else {
+                    return Ok(None);
+                };
+                tokio::fs::copy(&path, cache_dir.join(&entry.file)).await?;
+                entry
+            }
+            Remote::Http(base) => {
+                let response = client
+                    .get(format!("{}/{}", base, INDEX_FILE))
+                    .send()
+                    .await?;
+                if !response.status().is_success() {
+                    return Ok(None);
+                }
+                let mut index: BTreeMap<String, CacheEntry> = response.json().await?;
+                let Some(entry) = index.remove(url) else {
+                    return Ok(None);
+                };
+                if !plain_file_name(&entry.file) {
+                    return Err(anyhow!(
+                        "The remote cache index names an invalid file: {}",
+                        entry.file
+                    ));
+                }
+                let response = client
+                    .get(format!("{}/{}", base, entry.file))
+                    .send()
+                    .await?
+                    .error_for_status()?;
+                let partial = cache_dir.join(format!("{}.part", entry.file));
+                interrupt::started(&partial);
+                let mut file = tokio::fs::File::create(&partial).await?;
+                let mut stream = response.bytes_stream();
+                while let Some(chunk) = stream.next().await {
+                    file.write_all(&chunk?).await?;
+                }
+                file.sync_all().await?;
+                tokio::fs::rename(&partial, cache_dir.join(&entry.file)).await?;
+                interrupt::finished(&partial);
+                entry
+            }
+        };
+
+        // Only trust the copy if it is what the remote index says it is
+        let path = cache_dir.join(&entry.file);
+        let ok = tokio::task::spawn_blocking({
+            let (path, entry) = (path.clone(), entry.clone());
+            move || intact(&path, &entry)
+        })
+        .await?;
+        if !ok {
+            tokio::fs::remove_file(&path).await.ok();
+            return Err(anyhow!(
+                "{} does not match the remote cache index",
+                entry.file
+            ));
+        }
+        record(cache_dir, url, entry.clone())?;
+        Ok(Some((path, entry)))
+    }
+
+    /// Share a fresh download with other machines. HTTP remotes are read-only
+    /// and are expected to be filled by other means (e.g. syncing a cache dir).
+    pub async fn publish(&self, url: &str, path: &Path, entry: &CacheEntry) -> Result<()> {
+        let Remote::Dir(dir) = self else {
+            return Ok(());
+        };
+        let partial = dir.join(format!("{}.part", entry.file));
+        interrupt::started(&partial);
+        tokio::fs::copy(path, &partial).await?;
+        tokio::fs::rename(&partial, dir.join(&entry.file)).await?;
+        interrupt::finished(&partial);
+        let (dir, url, entry) = (dir.clone(), url.to_string(), entry.clone());
+        tokio::task::spawn_blocking(move || record(&dir, &url, entry)).await?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_remote_parse() {
+        assert!(matches!(
+            Remote::parse("https://cache.example.com/leaf/"),
+            Remote::Http(base) if base == "https://cache.example.com/leaf"
+        ));
+        assert!(matches!(
+            Remote::parse("file:///mnt/leaf-cache"),
+            Remote::Dir(dir) if dir == Path::new("/mnt/leaf-cache")
+        ));
+        assert!(matches!(
+            Remote::parse("/mnt/leaf-cache"),
+            Remote::Dir(dir) if dir == Path::new("/mnt/leaf-cache")
+        ));
+    }
+
+    #[test]
+    fn test_verify() {
+        let dir = std::env::temp_dir().join(format!("leaf-cache-{}", std::process::id()));
+        fs::remove_dir_all(&dir).ok();
+        fs::create_dir_all(&dir).unwrap();
+        for (url, file, content) in [
+            ("https://a", "a.tar.gz", "aaaa"),
+            ("https://b", "b.zip", "bbbb"),
+        ] {
+            let path = dir.join(file);
+            fs::write(&path, content).unwrap();
+            let entry = CacheEntry {
+                file: file.to_string(),
+                resolved_url: url.to_string(),
+                sha256: sha256_file(&path).unwrap(),
+                size: Some(4),
+            };
+            record(&dir, url, entry).unwrap();
+        }
+        // A truncated write and an interrupted download
+        fs::write(dir.join("b.zip"), "bb").unwrap();
+        fs::write(dir.join("c.tar.gz.part"), "c").unwrap();
+
+        let verified = verify(&dir).unwrap();
+        assert_eq!(verified.intact, 1);
+        assert_eq!(verified.corrupted, vec!["b.zip"]);
+        assert_eq!(verified.partial, 1);
+        assert!(lookup(&dir, "https://a").is_some());
+        assert!(!dir.join("b.zip").exists());
+        assert_eq!(load(&dir).len(), 1);
+
+        assert!(reusable(&dir, &dir.join("a.tar.gz"), Some(4)));
+        assert!(!reusable(&dir, &dir.join("a.tar.gz"), Some(5)));
+        fs::write(dir.join("a.tar.gz"), "axxa").unwrap();
+        assert!(!reusable(&dir, &dir.join("a.tar.gz"), None));
+
+        let escaping = CacheEntry {
+            file: "../a.tar.gz".to_string(),
+            resolved_url: "https://d".to_string(),
+            sha256: String::new(),
+            size: None,
+        };
+        record(&dir, "https://d", escaping).unwrap();
+        assert!(lookup(&dir, "https://d").is_none());
+        fs::remove_dir_all(&dir).ok();
+    }
+}
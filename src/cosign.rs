@@ -0,0 +1,124 @@
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use std::ffi::OsString;
+use std::path::Path;
+
+/// Keyless cosign signature of a platform's artifact, checked with `cosign
+/// verify-blob` against the identity that is allowed to sign it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Cosign {
+    /// Sigstore bundle holding both the signature and the signing certificate
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bundle_url: Option<String>,
+    /// Detached signature, for releases without a bundle
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature_url: Option<String>,
+    /// Signing certificate to go with `signature_url`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub certificate_url: Option<String>,
+    /// Identity the certificate must name, e.g. the release workflow
+    /// https://github.com/org/tool/.github/workflows/release.yml@refs/tags/v1.0;
+    /// a regular expression when it starts with ^
+    pub identity: String,
+    /// OIDC issuer that vouched for the identity, e.g. https://token.actions.githubusercontent.com
+    pub issuer: String,
+}
+
+/// Where the signature material of a download ended up
+pub enum Material<'a> {
+    Bundle(&'a Path),
+    Detached {
+        signature: &'a Path,
+        certificate: &'a Path,
+    },
+}
+
+impl Cosign {
+    /// URLs to fetch before verifying: the bundle, or the signature and certificate
+    pub fn material_urls(&self) -> Result<Vec<&str>> {
+        match (&self.bundle_url, &self.signature_url, &self.certificate_url) {
+            (Some(bundle), _, _) => Ok(vec![bundle]),
+            (None, Some(signature), Some(certificate)) => Ok(vec![signature, certificate]),
+            _ => Err(anyhow!(
+                "cosign needs a bundle_url, or a signature_url with a certificate_url"
+            )),
+        }
+    }
+
+    /// Arguments for `cosign` that verify `artifact` with `material`
+    pub fn verify_args(&self, artifact: &Path, material: &Material) -> Vec<OsString> {
+        let mut args: Vec<OsString> = vec!["verify-blob".into()];
+        match material {
+            Material::Bundle(bundle) => {
+                args.push("--bundle".into());
+                args.push(bundle.into());
+            }
+            Material::Detached {
+                signature,
+                certificate,
+            } => {
+                args.push("--signature".into());
+                args.push(signature.into());
+                args.push("--certificate".into());
+                args.push(certificate.into());
+            }
+        }
+        let identity_flag = if self.identity.starts_with('^') {
+            "--certificate-identity-regexp"
+        } else {
+            "--certificate-identity"
+        };
+        args.push(identity_flag.into());
+        args.push(self.identity.as_str().into());
+        args.push("--certificate-oidc-issuer".into());
+        args.push(self.issuer.as_str().into());
+        args.push(artifact.into());
+        args
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verify_args() {
+        let cosign: Cosign = serde_json::from_str(
+            r#"{
+                "signature_url": "https://example.com/tool.tar.gz.sig",
+                "certificate_url": "https://example.com/tool.tar.gz.pem",
+                "identity": "^https://github.com/org/tool/",
+                "issuer": "https://token.actions.githubusercontent.com"
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(cosign.material_urls().unwrap().len(), 2);
+        let material = Material::Detached {
+            signature: Path::new("tool.tar.gz.sig"),
+            certificate: Path::new("tool.tar.gz.pem"),
+        };
+        let args = cosign.verify_args(Path::new("tool.tar.gz"), &material);
+        let args: Vec<&str> = args.iter().map(|arg| arg.to_str().unwrap()).collect();
+        assert_eq!(
+            args,
+            [
+                "verify-blob",
+                "--signature",
+                "tool.tar.gz.sig",
+                "--certificate",
+                "tool.tar.gz.pem",
+                "--certificate-identity-regexp",
+                "^https://github.com/org/tool/",
+                "--certificate-oidc-issuer",
+                "https://token.actions.githubusercontent.com",
+                "tool.tar.gz"
+            ]
+        );
+
+        let incomplete = Cosign {
+            certificate_url: None,
+            ..cosign
+        };
+        assert!(incomplete.material_urls().is_err());
+    }
+}
@@ -0,0 +1,150 @@
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// One difference between two JSON documents, at a dotted path such as
+/// `platform.executables[1]`
+#[derive(Debug, PartialEq)]
+pub enum Change {
+    Added(String, Value),
+    Removed(String, Value),
+    Changed(String, Value, Value),
+}
+
+/// Differences that turn `old` into `new`, descending into objects and arrays
+/// so that only the fields that changed are reported
+pub fn diff(old: &Value, new: &Value) -> Vec<Change> {
+    let mut changes = Vec::new();
+    walk("", old, new, &mut changes);
+    changes
+}
+
+fn walk(path: &str, old: &Value, new: &Value, changes: &mut Vec<Change>) {
+    match (old, new) {
+        (Value::Object(old), Value::Object(new)) => {
+            let mut keys: Vec<&String> = old.keys().chain(new.keys()).collect();
+            keys.sort();
+            keys.dedup();
+            for key in keys {
+                let path = if path.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{}.{}", path, key)
+                };
+                compare(path, old.get(key), new.get(key), changes);
+            }
+        }
+        (Value::Array(old), Value::Array(new)) => {
+            for i in 0..old.len().max(new.len()) {
+                compare(format!("{}[{}]", path, i), old.get(i), new.get(i), changes);
+            }
+        }
+        _ if old != new => {
+            changes.push(Change::Changed(path.to_string(), old.clone(), new.clone()))
+        }
+        _ => {}
+    }
+}
+
+/// Missing and null fields count as the same, since definitions leave
+/// optional fields out while installed metadata may spell them as null
+fn compare(path: String, old: Option<&Value>, new: Option<&Value>, changes: &mut Vec<Change>) {
+    match (old.filter(|v| !v.is_null()), new.filter(|v| !v.is_null())) {
+        (Some(old), Some(new)) => walk(&path, old, new, changes),
+        (Some(old), None) => changes.push(Change::Removed(path, old.clone())),
+        (None, Some(new)) => changes.push(Change::Added(path, new.clone())),
+        (None, None) => {}
+    }
+}
+
+/// How a package index changed between two syncs
+#[derive(Debug, Default, PartialEq)]
+pub struct IndexChanges {
+    /// Name and version of packages that are new
+    pub added: Vec<(String, String)>,
+    /// Name, old version and new version of packages with a new version
+    pub updated: Vec<(String, String, String)>,
+    pub removed: Vec<String>,
+}
+
+impl IndexChanges {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.updated.is_empty() && self.removed.is_empty()
+    }
+}
+
+/// Compare two indexes given as package name -> version, in name order
+pub fn index_changes(old: &HashMap<String, String>, new: &HashMap<String, String>) -> IndexChanges {
+    let mut changes = IndexChanges::default();
+    for (name, version) in new {
+        match old.get(name) {
+            None => changes.added.push((name.clone(), version.clone())),
+            Some(old_version) if old_version != version => {
+                changes
+                    .updated
+                    .push((name.clone(), old_version.clone(), version.clone()))
+            }
+            Some(_) => {}
+        }
+    }
+    changes.removed = old
+        .keys()
+        .filter(|name| !new.contains_key(*name))
+        .cloned()
+        .collect();
+    changes.added.sort();
+    changes.updated.sort();
+    changes.removed.sort();
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_diff() {
+        let old = json!({
+            "version": "1.6",
+            "caveats": null,
+            "platform": {"url": "https://a/1.6.tar.gz", "executables": ["jq"]}
+        });
+        let new = json!({
+            "version": "1.7",
+            "platform": {
+                "url": "https://a/1.6.tar.gz",
+                "executables": ["jq", "jq-debug"],
+                "env": {"JQ_COLORS": "1"}
+            }
+        });
+        assert_eq!(
+            diff(&old, &new),
+            vec![
+                Change::Added("platform.env".to_string(), json!({"JQ_COLORS": "1"})),
+                Change::Added("platform.executables[1]".to_string(), json!("jq-debug")),
+                Change::Changed("version".to_string(), json!("1.6"), json!("1.7")),
+            ]
+        );
+        assert!(diff(&old, &old).is_empty());
+    }
+
+    #[test]
+    fn test_index_changes() {
+        let versions = |entries: &[(&str, &str)]| -> HashMap<String, String> {
+            entries
+                .iter()
+                .map(|(name, version)| (name.to_string(), version.to_string()))
+                .collect()
+        };
+        let old = versions(&[("jq", "1.6"), ("rg", "14.0"), ("exa", "0.10")]);
+        let new = versions(&[("jq", "1.7"), ("rg", "14.0"), ("eza", "0.18")]);
+        let changes = index_changes(&old, &new);
+        assert_eq!(changes.added, vec![("eza".to_string(), "0.18".to_string())]);
+        assert_eq!(
+            changes.updated,
+            vec![("jq".to_string(), "1.6".to_string(), "1.7".to_string())]
+        );
+        assert_eq!(changes.removed, vec!["exa".to_string()]);
+        assert!(index_changes(&new, &new).is_empty());
+    }
+}
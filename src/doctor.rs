@@ -0,0 +1,165 @@
+use crate::links;
+use crate::relocate::{LINK_DIRS, links_in};
+use crate::shadow;
+use crate::utils::link_target;
+use anyhow::Result;
+use std::ffi::OsStr;
+use std::fs;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+/// Something `leaf doctor` found wrong with a path
+#[derive(Debug, Clone)]
+pub struct Finding {
+    pub path: PathBuf,
+    pub problem: Problem,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Problem {
+    /// Owned by another user (usually root after a `sudo leaf ...`)
+    NotOwned { uid: u32 },
+    /// Ours, but missing the owner write bit
+    ReadOnly,
+    /// Link into leaf's directory whose target is gone, e.g. after a move
+    Dangling,
+    /// Link still leading to where leaf lived before it was moved
+    Stale,
+    /// Executable elsewhere on PATH that one of leaf's with the same name comes before
+    Shadowed,
+    /// Executable elsewhere on PATH that comes before leaf's with the same name
+    Shadows,
+}
+
+impl std::fmt::Display for Problem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Problem::NotOwned { uid: 0 } => write!(f, "owned by root"),
+            Problem::NotOwned { uid } => write!(f, "owned by uid {}", uid),
+            Problem::ReadOnly => write!(f, "not writable"),
+            Problem::Dangling => write!(f, "dangling link"),
+            Problem::Stale => write!(f, "old location"),
+            Problem::Shadowed => write!(f, "shadowed by leaf"),
+            Problem::Shadows => write!(f, "runs before leaf's"),
+        }
+    }
+}
+
+/// Find paths under `roots` that `uid` can't modify. Symlinks are checked but
+/// not followed, so shared-store links don't pull in files we don't manage.
+pub fn check_ownership(roots: &[&Path], uid: u32) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    for root in roots {
+        walk(root, uid, &mut findings);
+    }
+    findings
+}
+
+fn walk(path: &Path, uid: u32, findings: &mut Vec<Finding>) {
+    let Ok(metadata) = fs::symlink_metadata(path) else {
+        return;
+    };
+    let problem = if metadata.uid() != uid {
+        Some(Problem::NotOwned {
+            uid: metadata.uid(),
+        })
+    } else if !metadata.is_symlink() && metadata.mode() & 0o200 == 0 {
+        Some(Problem::ReadOnly)
+    } else {
+        None
+    };
+    if let Some(problem) = problem {
+        findings.push(Finding {
+            path: path.to_path_buf(),
+            problem,
+        });
+    }
+
+    if metadata.is_dir()
+        && let Ok(entries) = fs::read_dir(path)
+    {
+        for entry in entries.flatten() {
+            walk(&entry.path(), uid, findings);
+        }
+    }
+}
+
+/// Links under `root` that are broken or still lead into `previous`, leaf's
+/// directory before a move. Broken links elsewhere aren't leaf's business.
+pub fn check_links(root: &Path, install_dir: &Path, previous: Option<&Path>) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    for (dir, depth) in LINK_DIRS {
+        for link in links_in(&root.join(dir), depth) {
+            let Some(target) = link_target(&link) else {
+                continue;
+            };
+            let problem = if previous.is_some_and(|previous| target.starts_with(previous)) {
+                Problem::Stale
+            } else if target.starts_with(install_dir) && !link.exists() {
+                Problem::Dangling
+            } else {
+                continue;
+            };
+            findings.push(Finding {
+                path: link,
+                problem,
+            });
+        }
+    }
+    // Wrapper scripts in bin are files, not links, but break the same way
+    for entry in fs::read_dir(root.join("bin"))
+        .into_iter()
+        .flatten()
+        .flatten()
+    {
+        let path = entry.path();
+        if path.is_symlink() {
+            continue;
+        }
+        if let Some(target) = links::target(&path)
+            && target.starts_with(install_dir)
+            && !target.exists()
+        {
+            findings.push(Finding {
+                path,
+                problem: Problem::Dangling,
+            });
+        }
+    }
+    findings
+}
+
+/// Recorded name clashes between bin_dir and the rest of `path` (a PATH value)
+/// that still hold; the findings are the other executables
+pub fn check_shadowing(bin_dir: &Path, install_dir: &Path, path: &OsStr) -> Vec<Finding> {
+    shadow::recorded(install_dir)
+        .into_keys()
+        .filter(|name| fs::symlink_metadata(bin_dir.join(name)).is_ok())
+        .filter_map(|name| shadow::find(&name, bin_dir, path))
+        .map(|found| Finding {
+            path: found.other,
+            problem: if found.leaf_first {
+                Problem::Shadowed
+            } else {
+                Problem::Shadows
+            },
+        })
+        .collect()
+}
+
+/// Whether `doctor --fix` may make `path` writable: leaf's own directories and
+/// files, and package directories themselves, but not what is inside a
+/// package, whose permissions came with it
+pub fn may_restore_write(path: &Path, packages_dir: &Path) -> bool {
+    path.strip_prefix(packages_dir)
+        .map_or(true, |rest| rest.components().count() <= 1)
+}
+
+/// Give a read-only path its owner write bit back. Paths owned by someone
+/// else need `sudo chown`, which leaf leaves to the user.
+pub fn restore_write(path: &Path) -> Result<()> {
+    let mut permissions = fs::metadata(path)?.permissions();
+    permissions.set_mode(permissions.mode() | 0o200);
+    fs::set_permissions(path, permissions)?;
+    Ok(())
+}
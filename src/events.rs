@@ -0,0 +1,42 @@
+use serde_json::{Map, Value, json};
+use std::fs::File;
+use std::io::Write;
+use std::os::fd::{AsFd, OwnedFd};
+use std::sync::{Mutex, OnceLock};
+
+/// Original stdout while `--json-lines` is active; fd 1 is pointed at stderr
+/// so that every human-readable line, not just print_*, stays off the stream
+static EVENTS: OnceLock<Mutex<File>> = OnceLock::new();
+
+/// Switch to machine-readable output: events on stdout, everything else on stderr
+pub fn enable() -> std::io::Result<()> {
+    let stdout: OwnedFd = std::io::stdout().as_fd().try_clone_to_owned()?;
+    // SAFETY: dup2 on the process's own standard descriptors
+    if unsafe { libc::dup2(libc::STDERR_FILENO, libc::STDOUT_FILENO) } < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    EVENTS.set(Mutex::new(File::from(stdout))).ok();
+    Ok(())
+}
+
+pub fn enabled() -> bool {
+    EVENTS.get().is_some()
+}
+
+/// Write `{"event": <name>, ...fields}` as one line
+pub fn emit(event: &str, fields: Value) {
+    let Some(events) = EVENTS.get() else {
+        return;
+    };
+
+    let mut object = Map::new();
+    object.insert("event".to_string(), json!(event));
+    if let Value::Object(fields) = fields {
+        object.extend(fields);
+    }
+
+    if let Ok(mut file) = events.lock() {
+        writeln!(file, "{}", Value::Object(object)).ok();
+        file.flush().ok();
+    }
+}
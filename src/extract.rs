@@ -0,0 +1,516 @@
+use anyhow::{Result, anyhow};
+use flate2::read::GzDecoder;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::os::unix::fs::{PermissionsExt, symlink};
+use std::path::{Component, Path, PathBuf};
+use std::sync::mpsc;
+use std::thread;
+use tar::{Archive, EntryType};
+use xz2::read::XzDecoder;
+use zip::ZipArchive;
+
+/// Tar members up to this size are read into memory and written by a worker;
+/// bigger ones are written straight from the decompressor
+const BUFFERED_ENTRY_LIMIT: u64 = 16 * 1024 * 1024;
+
+fn worker_count() -> usize {
+    thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+/// Resolve an archive member path under `dest`, refusing absolute paths, `..`
+/// and paths through a symlink already under `dest`, which could point anywhere
+fn member_path(dest: &Path, name: &Path) -> Result<PathBuf> {
+    let mut path = dest.to_path_buf();
+    for component in name.components() {
+        match component {
+            Component::Normal(part) => {
+                path.push(part);
+                if fs::symlink_metadata(&path).is_ok_and(|metadata| metadata.is_symlink()) {
+                    return Err(anyhow!(
+                        "Archive entry goes through a symlink: {}",
+                        name.display()
+                    ));
+                }
+            }
+            Component::CurDir => {}
+            _ => {
+                return Err(anyhow!(
+                    "Archive entry escapes the target: {}",
+                    name.display()
+                ));
+            }
+        }
+    }
+    Ok(path)
+}
+
+/// Refuse a symlink at `path` whose target leads outside `dest`. `..` may only
+/// lead the target: after a name it would climb out of wherever that name
+/// points, which may itself be a symlink.
+fn check_link_target(dest: &Path, path: &Path, target: &Path) -> Result<()> {
+    let mut depth = path
+        .parent()
+        .and_then(|parent| parent.strip_prefix(dest).ok())
+        .map_or(0, |parent| parent.components().count());
+    let mut descended = false;
+    for component in target.components() {
+        let inside = match component {
+            Component::Normal(_) => {
+                depth += 1;
+                descended = true;
+                true
+            }
+            Component::CurDir => true,
+            Component::ParentDir if !descended && depth > 0 => {
+                depth -= 1;
+                true
+            }
+            _ => false,
+        };
+        if !inside {
+            return Err(anyhow!(
+                "Archive symlink {} points outside the target: {}",
+                path.display(),
+                target.display()
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Create the symlinks an archive held, once everything else is written, so
+/// that no member can be written through one of them
+fn create_symlinks(dest: &Path, links: Vec<(PathBuf, PathBuf)>) -> Result<()> {
+    for (name, target) in links {
+        let path = member_path(dest, &name)?;
+        check_link_target(dest, &path, &target)?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        symlink(target, &path)?;
+    }
+    Ok(())
+}
+
+fn write_file(path: &Path, contents: &mut impl Read, mode: Option<u32>) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut file = File::create(path)?;
+    io::copy(contents, &mut file)?;
+    if let Some(mode) = mode {
+        fs::set_permissions(path, fs::Permissions::from_mode(mode & 0o7777))?;
+    }
+    Ok(())
+}
+
+/// Unpack `archive_path` into `extract_to`, going by its file extension
+pub fn extract_archive_sync(archive_path: &Path, extract_to: &Path) -> Result<()> {
+    let file = File::open(archive_path)?;
+    let filename = archive_path.file_name().unwrap().to_string_lossy();
+
+    if filename.ends_with(".tar.gz") || filename.ends_with(".tgz") {
+        let decoder = GzDecoder::new(file);
+        let mut archive = Archive::new(decoder);
+        archive.unpack(extract_to)?;
+    } else if filename.ends_with(".tar.xz") {
+        let decoder = XzDecoder::new(file);
+        let mut archive = Archive::new(decoder);
+        archive.unpack(extract_to)?;
+    } else if filename.ends_with(".tar.zst") || filename.ends_with(".tzst") {
+        untar_zstd(archive_path, extract_to)?;
+    } else if filename.ends_with(".zip") {
+        unzip(archive_path, extract_to)?;
+    } else {
+        return Err(anyhow!("Unsupported archive format: {}", filename));
+    }
+
+    Ok(())
+}
+
+/// Unpack `archive_path` into `dest` the way an install does, dropping the
+/// first `strip` components of every member path like tar's
+/// --strip-components. Members with no more than `strip` components are left
+/// out.
+pub fn extract_stripped(archive_path: &Path, dest: &Path, strip: usize) -> Result<()> {
+    fs::create_dir_all(dest)?;
+    if strip == 0 {
+        return extract_archive_sync(archive_path, dest);
+    }
+    // Next to `dest` rather than inside it, so the archive can't collide with it
+    // and a crash leaves nothing among the extracted files; on the same
+    // filesystem, so moving out of it is a rename
+    let staging = dest.with_file_name(format!(
+        ".{}.leaf-extract-{}",
+        dest.file_name().unwrap_or_default().to_string_lossy(),
+        std::process::id()
+    ));
+    let result = extract_archive_sync(archive_path, &staging)
+        .and_then(|()| move_stripped(&staging, dest, strip));
+    fs::remove_dir_all(&staging).ok();
+    result
+}
+
+fn move_stripped(dir: &Path, dest: &Path, strip: usize) -> Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if strip == 0 {
+            move_into(&entry.path(), &dest.join(entry.file_name()))?;
+        } else if entry.file_type()?.is_dir() {
+            move_stripped(&entry.path(), dest, strip - 1)?;
+        }
+    }
+    Ok(())
+}
+
+/// Move `source` to `target`, merging directories and replacing files already there
+fn move_into(source: &Path, target: &Path) -> Result<()> {
+    match fs::symlink_metadata(target) {
+        Err(_) => fs::rename(source, target)?,
+        Ok(existing) if existing.is_dir() => {
+            if !fs::symlink_metadata(source)?.is_dir() {
+                return Err(anyhow!(
+                    "Cannot replace directory {} with a file",
+                    target.display()
+                ));
+            }
+            for entry in fs::read_dir(source)? {
+                let entry = entry?;
+                move_into(&entry.path(), &target.join(entry.file_name()))?;
+            }
+        }
+        Ok(_) => {
+            fs::remove_file(target)?;
+            fs::rename(source, target)?;
+        }
+    }
+    Ok(())
+}
+
+/// Extract a zip archive, with entries spread over one worker per CPU. Each
+/// worker opens its own handle since zip members can be read independently.
+pub fn unzip(archive_path: &Path, dest: &Path) -> Result<()> {
+    let entries = ZipArchive::new(File::open(archive_path)?)?.len();
+    let workers = worker_count().min(entries.max(1));
+
+    let links = thread::scope(|scope| {
+        let handles: Vec<_> = (0..workers)
+            .map(|worker| {
+                scope.spawn(move || -> Result<Vec<(PathBuf, PathBuf)>> {
+                    let mut archive = ZipArchive::new(File::open(archive_path)?)?;
+                    let mut links = Vec::new();
+                    for index in (worker..entries).step_by(workers) {
+                        let mut entry = archive.by_index(index)?;
+                        let Some(name) = entry.enclosed_name() else {
+                            return Err(anyhow!(
+                                "Archive entry escapes the target: {}",
+                                entry.name()
+                            ));
+                        };
+                        if entry.is_symlink() {
+                            let mut target = String::new();
+                            entry.read_to_string(&mut target)?;
+                            links.push((name, PathBuf::from(target)));
+                            continue;
+                        }
+                        let path = member_path(dest, &name)?;
+                        if entry.is_dir() {
+                            fs::create_dir_all(&path)?;
+                        } else {
+                            let mode = entry.unix_mode();
+                            write_file(&path, &mut entry, mode)?;
+                        }
+                    }
+                    Ok(links)
+                })
+            })
+            .collect();
+
+        let mut links = Vec::new();
+        for handle in handles {
+            links.extend(
+                handle
+                    .join()
+                    .map_err(|_| anyhow!("Extraction worker panicked"))??,
+            );
+        }
+        Ok::<_, anyhow::Error>(links)
+    })?;
+    create_symlinks(dest, links)
+}
+
+/// Extract a zstd-compressed tarball. Decompression is inherently sequential,
+/// so one thread reads the stream while workers write the files out.
+pub fn untar_zstd(archive_path: &Path, dest: &Path) -> Result<()> {
+    let decoder = zstd::stream::read::Decoder::new(File::open(archive_path)?)?;
+    let mut archive = Archive::new(decoder);
+    let workers = worker_count();
+    let (sender, receiver) = mpsc::sync_channel::<(PathBuf, Vec<u8>, u32)>(workers * 4);
+    let receiver = std::sync::Mutex::new(receiver);
+
+    thread::scope(|scope| {
+        let handles: Vec<_> = (0..workers)
+            .map(|_| {
+                let receiver = &receiver;
+                scope.spawn(move || -> Result<()> {
+                    loop {
+                        let next = receiver.lock().unwrap().recv();
+                        let Ok((path, data, mode)) = next else {
+                            return Ok(());
+                        };
+                        write_file(&path, &mut data.as_slice(), Some(mode))?;
+                    }
+                })
+            })
+            .collect();
+
+        // Hard links may point at files a worker hasn't written yet, and
+        // symlinks wait until nothing more is written
+        let mut hard_links = Vec::new();
+        let mut links = Vec::new();
+        let read = (|| -> Result<()> {
+            for entry in archive.entries()? {
+                let mut entry = entry?;
+                let name = entry.path()?.into_owned();
+                if entry.header().entry_type() == EntryType::Symlink {
+                    let target = entry
+                        .link_name()?
+                        .ok_or_else(|| anyhow!("Symlink without a target"))?;
+                    links.push((name, target.into_owned()));
+                    continue;
+                }
+                let path = member_path(dest, &name)?;
+                let header = entry.header();
+                match header.entry_type() {
+                    EntryType::Directory => fs::create_dir_all(&path)?,
+                    EntryType::Regular | EntryType::Continuous
+                        if header.size()? <= BUFFERED_ENTRY_LIMIT =>
+                    {
+                        let mode = header.mode()?;
+                        let mut data = Vec::with_capacity(header.size()? as usize);
+                        entry.read_to_end(&mut data)?;
+                        // A closed channel means a worker failed; its error is reported below
+                        if sender.send((path, data, mode)).is_err() {
+                            break;
+                        }
+                    }
+                    EntryType::Link => {
+                        let target = entry
+                            .link_name()?
+                            .ok_or_else(|| anyhow!("Hard link without a target"))?;
+                        hard_links.push((path, member_path(dest, &target)?));
+                    }
+                    _ => {
+                        entry.unpack_in(dest)?;
+                    }
+                }
+            }
+            Ok(())
+        })();
+        drop(sender);
+
+        for handle in handles {
+            handle
+                .join()
+                .map_err(|_| anyhow!("Extraction worker panicked"))??;
+        }
+        read?;
+
+        for (path, target) in hard_links {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::hard_link(target, path)?;
+        }
+        create_symlinks(dest, links)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use zip::write::SimpleFileOptions;
+
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("leaf-extract-{}-{}", name, std::process::id()));
+        fs::remove_dir_all(&dir).ok();
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn file_contents(index: usize, size: usize) -> Vec<u8> {
+        format!("file {} ", index)
+            .bytes()
+            .cycle()
+            .take(size)
+            .collect()
+    }
+
+    fn write_zip(path: &Path, files: usize, size: usize) {
+        let mut zip = zip::ZipWriter::new(File::create(path).unwrap());
+        zip.add_directory("pkg/bin/", SimpleFileOptions::default())
+            .unwrap();
+        zip.start_file(
+            "pkg/bin/tool",
+            SimpleFileOptions::default().unix_permissions(0o755),
+        )
+        .unwrap();
+        zip.write_all(b"#!/bin/sh\n").unwrap();
+        zip.add_symlink("pkg/tool", "bin/tool", SimpleFileOptions::default())
+            .unwrap();
+        for index in 0..files {
+            zip.start_file(
+                format!("pkg/data/{}.txt", index),
+                SimpleFileOptions::default(),
+            )
+            .unwrap();
+            zip.write_all(&file_contents(index, size)).unwrap();
+        }
+        zip.finish().unwrap();
+    }
+
+    #[test]
+    fn test_unzip() {
+        let dir = scratch_dir("zip");
+        let archive = dir.join("pkg.zip");
+        write_zip(&archive, 20, 1000);
+        unzip(&archive, &dir.join("out")).unwrap();
+
+        let out = dir.join("out/pkg");
+        assert_eq!(
+            fs::read(out.join("data/7.txt")).unwrap(),
+            file_contents(7, 1000)
+        );
+        let mode = fs::metadata(out.join("bin/tool"))
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o755);
+        assert_eq!(
+            fs::read_link(out.join("tool")).unwrap(),
+            Path::new("bin/tool")
+        );
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_untar_zstd() {
+        let dir = scratch_dir("zstd");
+        let archive = dir.join("pkg.tar.zst");
+        let encoder = zstd::stream::write::Encoder::new(File::create(&archive).unwrap(), 3)
+            .unwrap()
+            .auto_finish();
+        let mut tar = tar::Builder::new(encoder);
+        for index in 0..20 {
+            let data = file_contents(index, 1000);
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o640);
+            tar.append_data(
+                &mut header,
+                format!("pkg/data/{}.txt", index),
+                data.as_slice(),
+            )
+            .unwrap();
+        }
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(EntryType::Link);
+        header.set_size(0);
+        tar.append_link(&mut header, "pkg/link.txt", "pkg/data/3.txt")
+            .unwrap();
+        tar.into_inner().unwrap();
+
+        untar_zstd(&archive, &dir.join("out")).unwrap();
+        let out = dir.join("out/pkg");
+        assert_eq!(
+            fs::read(out.join("data/19.txt")).unwrap(),
+            file_contents(19, 1000)
+        );
+        assert_eq!(
+            fs::read(out.join("link.txt")).unwrap(),
+            file_contents(3, 1000)
+        );
+        let mode = fs::metadata(out.join("data/0.txt"))
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o640);
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_extract_stripped() {
+        let dir = scratch_dir("strip");
+        let archive = dir.join("pkg.zip");
+        write_zip(&archive, 2, 10);
+        let out = dir.join("out");
+        fs::create_dir_all(out.join("data")).unwrap();
+        fs::write(out.join("data/0.txt"), "old").unwrap();
+        fs::write(out.join("kept.txt"), "").unwrap();
+
+        extract_stripped(&archive, &out, 1).unwrap();
+        assert_eq!(
+            fs::read(out.join("data/0.txt")).unwrap(),
+            file_contents(0, 10)
+        );
+        assert!(out.join("data/1.txt").exists());
+        assert!(out.join("bin/tool").exists());
+        assert!(out.join("kept.txt").exists());
+        assert!(!out.join("pkg").exists());
+        let entries = fs::read_dir(&out).unwrap().count();
+        assert_eq!(entries, 4, "staging directory left behind");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+
+        extract_stripped(&archive, &dir.join("deep"), 2).unwrap();
+        assert!(dir.join("deep/tool").exists());
+        assert!(!dir.join("deep/bin").exists());
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_member_path_rejects_escapes() {
+        let dest = Path::new("/tmp/pkg");
+        assert_eq!(
+            member_path(dest, Path::new("./bin/go")).unwrap(),
+            Path::new("/tmp/pkg/bin/go")
+        );
+        assert!(member_path(dest, Path::new("../etc/passwd")).is_err());
+        assert!(member_path(dest, Path::new("/etc/passwd")).is_err());
+    }
+
+    #[test]
+    fn test_symlinks_stay_inside() {
+        let dest = Path::new("/tmp/pkg");
+        let link = Path::new("/tmp/pkg/lib/libfoo.so");
+        for target in ["libfoo.so.1", "../share/foo", "./x"] {
+            assert!(check_link_target(dest, link, Path::new(target)).is_ok());
+        }
+        for target in ["/etc/passwd", "../../etc", "a/../../.."] {
+            assert!(check_link_target(dest, link, Path::new(target)).is_err());
+        }
+
+        // A member written through a symlink another member created
+        let dir = scratch_dir("symlink");
+        let archive = dir.join("evil.zip");
+        let mut zip = zip::ZipWriter::new(File::create(&archive).unwrap());
+        zip.add_symlink("pkg/escape", "../..", SimpleFileOptions::default())
+            .unwrap();
+        zip.start_file("pkg/escape/owned", SimpleFileOptions::default())
+            .unwrap();
+        zip.finish().unwrap();
+        assert!(unzip(&archive, &dir.join("out")).is_err());
+        assert!(!dir.join("owned").exists());
+
+        // A target already under dest that is a symlink out of it
+        let out = dir.join("existing");
+        fs::create_dir_all(&out).unwrap();
+        symlink(&dir, out.join("pkg")).unwrap();
+        write_zip(&archive, 1, 10);
+        assert!(unzip(&archive, &out).is_err());
+        assert!(!dir.join("data").exists());
+        fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,40 @@
+use anyhow::{Result, anyhow};
+use std::sync::OnceLock;
+
+/// Environment variable naming a fault point, for child processes and tests
+/// that can't pass `--inject-fault`
+pub const ENV_VAR: &str = "LEAF_INJECT_FAULT";
+
+/// Where an install can be made to fail on purpose
+pub const POINTS: &[&str] = &[
+    "mid-download",
+    "mid-extract",
+    "before-symlink",
+    "after-symlink",
+];
+
+static FAULT: OnceLock<Option<String>> = OnceLock::new();
+
+/// Fail at `point` from now on; without a call the environment variable decides
+pub fn set(point: &str) {
+    FAULT.set(Some(point.to_string())).ok();
+}
+
+fn armed() -> Option<&'static str> {
+    FAULT
+        .get_or_init(|| {
+            std::env::var(ENV_VAR)
+                .ok()
+                .filter(|point| !point.is_empty())
+        })
+        .as_deref()
+}
+
+/// Error out when the fault injected is the one at `point`
+pub fn check(point: &str) -> Result<()> {
+    debug_assert!(POINTS.contains(&point), "unknown fault point {}", point);
+    if armed() == Some(point) {
+        return Err(anyhow!("Injected fault: {}", point));
+    }
+    Ok(())
+}
@@ -0,0 +1,82 @@
+use crate::config::Config;
+use crate::package::FileCategory;
+use anyhow::Result;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+const FONT_EXTENSIONS: [&str; 6] = ["ttf", "otf", "ttc", "otc", "woff", "woff2"];
+
+pub fn is_font(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| FONT_EXTENSIONS.contains(&ext.to_string_lossy().to_lowercase().as_str()))
+}
+
+/// Per-user font directory: ~/Library/Fonts on macOS, otherwise
+/// ~/.local/share/fonts (or share/fonts under a prefix)
+pub fn fonts_dir(config: &Config) -> PathBuf {
+    match dirs::home_dir() {
+        Some(home) if cfg!(target_os = "macos") && config.prefix.is_none() => {
+            home.join("Library").join("Fonts")
+        }
+        _ => config.extra_files_dir(FileCategory::Share).join("fonts"),
+    }
+}
+
+/// Expose every font file in `package_dir` under `dest`, returning how many
+/// there were. macOS doesn't load symlinked fonts, so they are copied there.
+pub fn install(package_dir: &Path, dest: &Path) -> Result<usize> {
+    let mut fonts = Vec::new();
+    collect_fonts(package_dir, &mut fonts)?;
+    fs::create_dir_all(dest)?;
+    for font in &fonts {
+        let target = dest.join(font.file_name().unwrap_or_default());
+        if fs::symlink_metadata(&target).is_ok() {
+            fs::remove_file(&target)?;
+        }
+        if cfg!(target_os = "macos") {
+            fs::copy(font, &target)?;
+        } else {
+            std::os::unix::fs::symlink(font, &target)?;
+        }
+    }
+    Ok(fonts.len())
+}
+
+/// Rebuild the fontconfig cache so new fonts show up without logging out.
+/// Systems without fontconfig (e.g. macOS) pick fonts up on their own.
+pub fn refresh_cache(fonts_dir: &Path) {
+    Command::new("fc-cache")
+        .arg("-f")
+        .arg(fonts_dir)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .ok();
+}
+
+fn collect_fonts(dir: &Path, fonts: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            collect_fonts(&entry.path(), fonts)?;
+        } else if file_type.is_file() && is_font(&entry.path()) {
+            fonts.push(entry.path());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_font() {
+        assert!(is_font(Path::new("FiraCode/FiraCodeNerdFont-Regular.ttf")));
+        assert!(is_font(Path::new("Hack.OTF")));
+        assert!(!is_font(Path::new("LICENSE.txt")));
+        assert!(!is_font(Path::new("fonts.tar.xz")));
+    }
+}
@@ -0,0 +1,162 @@
+use crate::config::Config;
+use crate::links;
+use crate::status::dir_size;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Something `leaf gc` can delete
+#[derive(Debug, Clone, PartialEq)]
+pub struct Garbage {
+    pub path: PathBuf,
+    pub kind: Kind,
+    /// Bytes freed by deleting it
+    pub size: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    /// Link or wrapper in bin into leaf's directory whose target is gone
+    BrokenLink,
+    /// Package directory without leaf-package.json, so no package owns it
+    Orphan,
+    /// Staging directory of an install, or the old version kept by an
+    /// upgrade, that never finished
+    Staging,
+    /// Source tree of a build that never finished; compiler caches are kept
+    Build,
+    /// Download that never finished
+    PartialDownload,
+}
+
+impl std::fmt::Display for Kind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Kind::BrokenLink => write!(f, "broken link"),
+            Kind::Orphan => write!(f, "no metadata"),
+            Kind::Staging => write!(f, "unfinished install"),
+            Kind::Build => write!(f, "abandoned build"),
+            Kind::PartialDownload => write!(f, "partial download"),
+        }
+    }
+}
+
+fn entries(dir: &Path) -> impl Iterator<Item = PathBuf> {
+    fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| entry.path())
+}
+
+/// Everything leaf left behind that nothing refers to. Links in bin are only
+/// considered when they lead into leaf's directory, since other programs may
+/// keep their own links there.
+pub fn find(config: &Config) -> Vec<Garbage> {
+    let mut garbage = Vec::new();
+    let mut add = |path: PathBuf, kind: Kind| {
+        let size = dir_size(&path);
+        garbage.push(Garbage { path, kind, size });
+    };
+
+    for path in entries(&config.bin_dir) {
+        if links::target(&path)
+            .is_some_and(|target| target.starts_with(&config.install_dir) && !target.exists())
+        {
+            add(path, Kind::BrokenLink);
+        }
+    }
+
+    for path in entries(&config.packages_dir) {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        if name.starts_with('.') && (name.ends_with(".partial") || name.ends_with(".previous")) {
+            add(path, Kind::Staging);
+        } else if !name.starts_with('.') && !path.join("leaf-package.json").exists() {
+            add(path, Kind::Orphan);
+        }
+    }
+
+    for root in entries(&config.builds_dir()) {
+        let source = root.join("src");
+        if fs::symlink_metadata(&source).is_ok() {
+            add(source, Kind::Build);
+        }
+    }
+
+    for path in entries(&config.cache_dir) {
+        if path
+            .extension()
+            .is_some_and(|extension| extension == "part")
+        {
+            add(path, Kind::PartialDownload);
+        }
+    }
+    garbage
+}
+
+/// Delete `item`, whatever kind of file it is
+pub fn remove(item: &Garbage) -> std::io::Result<()> {
+    let metadata = fs::symlink_metadata(&item.path)?;
+    if metadata.is_dir() {
+        fs::remove_dir_all(&item.path)
+    } else {
+        fs::remove_file(&item.path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+
+    #[tokio::test]
+    async fn test_find() {
+        let root = std::env::temp_dir().join(format!("leaf-gc-test-{}", std::process::id()));
+        fs::remove_dir_all(&root).ok();
+        let config = Config::load_or_create(Some(&root), None).await.unwrap();
+        let packages = &config.packages_dir;
+        fs::create_dir_all(packages.join("jq")).unwrap();
+        fs::write(packages.join("jq").join("leaf-package.json"), "{}").unwrap();
+        fs::write(packages.join("jq").join("jq"), "").unwrap();
+        fs::create_dir_all(packages.join("rg")).unwrap();
+        fs::write(packages.join("rg").join("rg"), "12345").unwrap();
+        fs::create_dir_all(packages.join(".fd.partial")).unwrap();
+        fs::create_dir_all(&config.bin_dir).unwrap();
+        symlink(packages.join("jq").join("jq"), config.bin_dir.join("jq")).unwrap();
+        symlink(
+            packages.join("gone").join("gone"),
+            config.bin_dir.join("gone"),
+        )
+        .unwrap();
+        symlink("/nonexistent/elsewhere", config.bin_dir.join("other")).unwrap();
+        fs::create_dir_all(config.builds_dir().join("git").join("src")).unwrap();
+        fs::create_dir_all(config.builds_dir().join("git").join("ccache")).unwrap();
+        fs::create_dir_all(&config.cache_dir).unwrap();
+        fs::write(config.cache_dir.join("fd.tar.gz.part"), "123").unwrap();
+        fs::write(config.cache_dir.join("jq.tar.gz"), "123").unwrap();
+
+        let link_size = packages.join("gone").join("gone").as_os_str().len() as u64;
+        let mut found: Vec<(String, Kind, u64)> = find(&config)
+            .into_iter()
+            .map(|item| {
+                let path = item.path.strip_prefix(&root).unwrap();
+                (path.display().to_string(), item.kind, item.size)
+            })
+            .collect();
+        found.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(
+            found,
+            vec![
+                ("bin/gone".to_string(), Kind::BrokenLink, link_size),
+                ("leaf/builds/git/src".to_string(), Kind::Build, 0),
+                (
+                    "leaf/cache/fd.tar.gz.part".to_string(),
+                    Kind::PartialDownload,
+                    3
+                ),
+                ("leaf/packages/.fd.partial".to_string(), Kind::Staging, 0),
+                ("leaf/packages/rg".to_string(), Kind::Orphan, 5),
+            ]
+        );
+        fs::remove_dir_all(&root).ok();
+    }
+}
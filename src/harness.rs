@@ -0,0 +1,257 @@
+use crate::package::Package;
+use crate::package_manager::PackageManager;
+use crate::platform;
+use anyhow::{Result, anyhow};
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// A throwaway leaf root with its own index, for running install, upgrade and
+/// remove flows without touching the user's packages. Deleted when dropped.
+pub struct Sandbox {
+    root: PathBuf,
+    pub pm: PackageManager,
+}
+
+impl Sandbox {
+    /// Sandbox whose packages.json is `index`
+    pub async fn new(index: &str) -> Result<Self> {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let root = std::env::temp_dir().join(format!(
+            "leaf-sandbox-{}-{}",
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::remove_dir_all(&root).ok();
+        std::fs::create_dir_all(root.join("leaf"))?;
+        std::fs::write(root.join("leaf").join("packages.json"), index)?;
+        let pm = PackageManager::new(Some(&root), None, true).await?;
+        Ok(Self { root, pm })
+    }
+
+    /// Replace the index, as `leaf update` would, and reload
+    #[cfg(test)]
+    pub async fn set_index(&mut self, index: &str) -> Result<()> {
+        std::fs::write(self.root.join("leaf").join("packages.json"), index)?;
+        self.pm = PackageManager::new(Some(&self.root), None, true).await?;
+        Ok(())
+    }
+
+    pub fn bin_dir(&self) -> PathBuf {
+        self.root.join("bin")
+    }
+
+    pub fn package_dir(&self, name: &str) -> PathBuf {
+        self.root.join("leaf").join("packages").join(name)
+    }
+
+    /// Installed metadata of `name`, as leaf-package.json records it
+    pub fn installed(&self, name: &str) -> Result<Package> {
+        let metadata = std::fs::read_to_string(self.package_dir(name).join("leaf-package.json"))?;
+        Ok(serde_json::from_str(&metadata)?)
+    }
+
+    /// Problems with how `name` is laid out after an install: executables that
+    /// are missing or not executable, and commands missing from bin
+    pub fn check_installed(&self, name: &str) -> Result<Vec<String>> {
+        let package = self.installed(name)?;
+        let details = package
+            .platform(&platform::current())
+            .ok_or_else(|| anyhow!("'{}' has no build for {}", name, platform::current()))?;
+        let mut problems = Vec::new();
+        for executable in details.get_executables() {
+            let path = self.package_dir(name).join(&executable.path);
+            match std::fs::metadata(&path) {
+                Ok(metadata) if metadata.permissions().mode() & 0o111 == 0 => {
+                    problems.push(format!("{} is not executable", executable.path))
+                }
+                Ok(_) => {}
+                Err(_) => problems.push(format!("{} is not in the package", executable.path)),
+            }
+            let command = self.bin_dir().join(executable.exposed_name());
+            if !command.exists() {
+                problems.push(format!(
+                    "{} was not linked into bin",
+                    executable.exposed_name()
+                ));
+            }
+        }
+        Ok(problems)
+    }
+
+    /// Whatever is left in bin and the package directory after a removal
+    pub fn leftovers(&self, name: &str) -> Vec<String> {
+        let mut leftovers: Vec<String> = std::fs::read_dir(self.bin_dir())
+            .into_iter()
+            .flatten()
+            .flatten()
+            .map(|entry| format!("bin/{}", entry.file_name().to_string_lossy()))
+            .collect();
+        if std::fs::symlink_metadata(self.package_dir(name)).is_ok() {
+            leftovers.push(format!("leaf/packages/{}", name));
+        }
+        leftovers
+    }
+}
+
+impl Drop for Sandbox {
+    fn drop(&mut self) {
+        std::fs::remove_dir_all(&self.root).ok();
+    }
+}
+
+/// Install `name` from `index` into a sandbox, check its files and links, then
+/// remove it and check that nothing is left behind
+pub async fn test_definition(index: &str, name: &str) -> Result<()> {
+    let mut sandbox = Sandbox::new(index).await?;
+    sandbox.pm.install_package(name).await?;
+    let (_, _, key) = sandbox.pm.resolve_spec(name);
+    let problems = sandbox.check_installed(&key)?;
+    if !problems.is_empty() {
+        return Err(anyhow!(problems.join("; ")));
+    }
+    sandbox.pm.remove_package(name).await?;
+    let leftovers = sandbox.leftovers(&key);
+    if !leftovers.is_empty() {
+        return Err(anyhow!(
+            "left behind after removal: {}",
+            leftovers.join(", ")
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+#[path = "../tests/support/serve.rs"]
+mod serve;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prompt::{self, Mode};
+    use std::collections::HashMap;
+
+    fn serve(files: HashMap<String, Vec<u8>>) -> String {
+        serve::serve(move |path| files.get(path).cloned())
+    }
+
+    /// tar.gz holding one shell script at `tool-<version>/bin/tool`
+    fn tool_archive(version: &str) -> Vec<u8> {
+        let mut builder = tar::Builder::new(flate2::write::GzEncoder::new(
+            Vec::new(),
+            flate2::Compression::default(),
+        ));
+        let script = format!("#!/bin/sh\necho {}\n", version);
+        let mut header = tar::Header::new_gnu();
+        header.set_size(script.len() as u64);
+        header.set_mode(0o755);
+        header.set_cksum();
+        builder
+            .append_data(
+                &mut header,
+                format!("tool-{}/bin/tool", version),
+                script.as_bytes(),
+            )
+            .unwrap();
+        builder.into_inner().unwrap().finish().unwrap()
+    }
+
+    fn index(base_url: &str, version: &str) -> String {
+        let mut platforms = serde_json::Map::new();
+        platforms.insert(
+            platform::current(),
+            serde_json::json!({"url": format!("{}/tool-{}.tar.gz", base_url, version)}),
+        );
+        serde_json::json!({
+            "tool": {
+                "description": "test tool",
+                "version": version,
+                "defaults": {
+                    "type": "archive",
+                    "executables": [{"path": format!("tool-{}/bin/tool", version), "name": "tool"}]
+                },
+                "platforms": platforms
+            }
+        })
+        .to_string()
+    }
+
+    #[tokio::test]
+    async fn test_install_upgrade_remove() {
+        let base_url = serve(HashMap::from([
+            ("/tool-1.0.tar.gz".to_string(), tool_archive("1.0")),
+            ("/tool-2.0.tar.gz".to_string(), tool_archive("2.0")),
+        ]));
+        let mut sandbox = Sandbox::new(&index(&base_url, "1.0")).await.unwrap();
+
+        sandbox.pm.install_package("tool").await.unwrap();
+        assert!(sandbox.check_installed("tool").unwrap().is_empty());
+        let target = std::fs::read_link(sandbox.bin_dir().join("tool")).unwrap();
+        assert!(target.ends_with("tool-1.0/bin/tool"));
+        let provenance = sandbox.installed("tool").unwrap().provenance.unwrap();
+        assert_eq!(provenance.url, format!("{}/tool-1.0.tar.gz", base_url));
+
+        sandbox.set_index(&index(&base_url, "2.0")).await.unwrap();
+        let mode = prompt::TEST_MODE_LOCK.lock().await;
+        prompt::set_mode(Mode::AssumeYes);
+        let upgraded = sandbox
+            .pm
+            .upgrade_packages(&["tool".to_string()], false, false)
+            .await;
+        prompt::set_mode(Mode::Ask);
+        drop(mode);
+        upgraded.unwrap();
+        assert_eq!(sandbox.installed("tool").unwrap().version, "2.0");
+        assert!(sandbox.check_installed("tool").unwrap().is_empty());
+
+        sandbox.pm.remove_package("tool").await.unwrap();
+        assert!(sandbox.leftovers("tool").is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_nuke_clears_copies_from_bin() {
+        let base_url = serve(HashMap::from([(
+            "/tool-1.0.tar.gz".to_string(),
+            tool_archive("1.0"),
+        )]));
+        let index = index(&base_url, "1.0");
+        let mut sandbox = Sandbox::new(&index).await.unwrap();
+        let config_file = sandbox.root.join("leaf").join("config.json");
+        let mut config: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&config_file).unwrap()).unwrap();
+        config["link_strategy"] = "copy".into();
+        std::fs::write(&config_file, config.to_string()).unwrap();
+        sandbox.set_index(&index).await.unwrap();
+
+        sandbox.pm.install_package("tool").await.unwrap();
+        assert!(!sandbox.bin_dir().join("tool").is_symlink());
+        std::fs::write(sandbox.bin_dir().join("other"), "#!/bin/sh\n").unwrap();
+        sandbox.pm.nuke_everything(true).await.unwrap();
+        assert_eq!(sandbox.leftovers("tool"), ["bin/other"]);
+    }
+
+    #[tokio::test]
+    async fn test_invalid_override_with_odd_name_is_skipped() {
+        let index = index("http://127.0.0.1:1", "1.0");
+        let mut sandbox = Sandbox::new(&index).await.unwrap();
+        let overlay_dir = sandbox.root.join("leaf").join("overlay");
+        std::fs::create_dir_all(&overlay_dir).unwrap();
+        std::fs::write(overlay_dir.join("a..b.json"), r#"{"description": "odd"}"#).unwrap();
+        sandbox.set_index(&index).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_definition_reports_missing_executable() {
+        let base_url = serve(HashMap::from([(
+            "/tool-1.0.tar.gz".to_string(),
+            tool_archive("1.0"),
+        )]));
+        let broken = index(&base_url, "1.0").replace("bin/tool\"", "bin/tool-missing\"");
+        assert!(
+            test_definition(&index(&base_url, "1.0"), "tool")
+                .await
+                .is_ok()
+        );
+        assert!(test_definition(&broken, "tool").await.is_err());
+    }
+}
@@ -0,0 +1,111 @@
+use crate::build_info::USER_AGENT;
+use anyhow::{Result, anyhow};
+use chrono::{DateTime, Local};
+use reqwest::{Client, RequestBuilder, Response, StatusCode};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+/// Token sent to api.github.com, from GITHUB_TOKEN or config.json
+static GITHUB_TOKEN: OnceLock<String> = OnceLock::new();
+
+/// The HTTP client behind every request leaf makes. Sharing one keeps
+/// connections, and HTTP/2 sessions negotiated over TLS, open to the same hosts
+/// across a batch of installs instead of reconnecting per package.
+pub fn client() -> Client {
+    static CLIENT: OnceLock<Client> = OnceLock::new();
+    CLIENT
+        .get_or_init(|| {
+            Client::builder()
+                .user_agent(USER_AGENT)
+                .gzip(true)
+                .brotli(true)
+                .pool_idle_timeout(Duration::from_secs(90))
+                .http2_adaptive_window(true)
+                // Lets repos be pinned to their certificate
+                .tls_info(true)
+                .build()
+                .expect("failed to initialize the HTTP client")
+        })
+        .clone()
+}
+
+/// Authenticate GitHub API requests with GITHUB_TOKEN (or GH_TOKEN, as the gh
+/// CLI uses), falling back to `configured` from config.json
+pub fn set_github_token(configured: Option<&str>) {
+    let token = ["GITHUB_TOKEN", "GH_TOKEN"]
+        .iter()
+        .find_map(|name| std::env::var(name).ok().filter(|token| !token.is_empty()))
+        .or_else(|| configured.map(String::from));
+    if let Some(token) = token {
+        GITHUB_TOKEN.set(token).ok();
+    }
+}
+
+/// GET `url`, with the GitHub token attached when it is an api.github.com URL.
+/// reqwest drops the header if a redirect leaves that host.
+pub fn get(client: &Client, url: &str) -> RequestBuilder {
+    let request = client.get(url);
+    match GITHUB_TOKEN.get() {
+        Some(token) if is_github_api(url) => request.bearer_auth(token),
+        _ => request,
+    }
+}
+
+fn is_github_api(url: &str) -> bool {
+    reqwest::Url::parse(url).is_ok_and(|url| url.host_str() == Some("api.github.com"))
+}
+
+/// Turn a GitHub rate-limit rejection into an error saying when the limit
+/// resets; any other response is passed through
+pub fn check_rate_limit(response: Response) -> Result<Response> {
+    let status = response.status();
+    if status != StatusCode::FORBIDDEN && status != StatusCode::TOO_MANY_REQUESTS {
+        return Ok(response);
+    }
+    let header = |name: &str| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string)
+    };
+    if header("x-ratelimit-remaining").as_deref() != Some("0") && header("retry-after").is_none() {
+        return Ok(response);
+    }
+    let reset = header("x-ratelimit-reset")
+        .and_then(|reset| reset.parse().ok())
+        .and_then(|reset| DateTime::from_timestamp(reset, 0))
+        .map(|reset| reset.with_timezone(&Local).format("%H:%M").to_string());
+    let host = response.url().host_str().unwrap_or_default().to_string();
+    let hint = if is_github_api(response.url().as_str()) && GITHUB_TOKEN.get().is_none() {
+        "; set GITHUB_TOKEN (or github_token in config.json) for a higher limit"
+    } else {
+        ""
+    };
+    Err(match (reset, header("retry-after")) {
+        (Some(reset), _) => anyhow!("{} rate limit reached until {}{}", host, reset, hint),
+        (None, Some(seconds)) => anyhow!(
+            "{} rate limit reached; retry in {} seconds{}",
+            host,
+            seconds,
+            hint
+        ),
+        (None, None) => anyhow!("{} rate limit reached{}", host, hint),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_github_api() {
+        assert!(is_github_api(
+            "https://api.github.com/repos/ktauchathuranga/leaf/releases"
+        ));
+        assert!(!is_github_api(
+            "https://github.com/owner/tool/releases/download/v1/tool.tar.gz"
+        ));
+        assert!(!is_github_api("https://api.github.com.example.com/"));
+    }
+}
@@ -0,0 +1,128 @@
+use serde_json::Value;
+use std::collections::HashMap;
+use std::env;
+use std::sync::{OnceLock, RwLock};
+
+/// Message catalogs compiled into the binary. To add a translation, copy
+/// `locales/en.json` to `locales/<lang>.json`, translate the values and add
+/// an entry here; keys missing from a translation fall back to English.
+const CATALOGS: &[(&str, &str)] = &[("en", include_str!("../locales/en.json"))];
+
+const FALLBACK: &str = "en";
+
+static PARSED: OnceLock<HashMap<&'static str, HashMap<String, String>>> = OnceLock::new();
+static LANGUAGE: RwLock<Option<String>> = RwLock::new(None);
+
+/// Format a catalog message, e.g. `tr!("install.done", name)`
+macro_rules! tr {
+    ($key:expr) => {
+        $crate::i18n::message($key, &[])
+    };
+    ($key:expr, $($arg:expr),+ $(,)?) => {
+        $crate::i18n::message($key, &[$($arg.to_string()),+])
+    };
+}
+
+fn catalogs() -> &'static HashMap<&'static str, HashMap<String, String>> {
+    PARSED.get_or_init(|| {
+        CATALOGS
+            .iter()
+            .filter_map(|(lang, source)| {
+                let messages = serde_json::from_str::<HashMap<String, Value>>(source).ok()?;
+                let messages = messages
+                    .into_iter()
+                    .filter_map(|(key, value)| Some((key, value.as_str()?.to_string())))
+                    .collect();
+                Some((*lang, messages))
+            })
+            .collect()
+    })
+}
+
+/// Pick the message language from the config setting, then LC_ALL,
+/// LC_MESSAGES and LANG. Languages without a catalog fall back to English.
+pub fn set_language(configured: Option<&str>) {
+    let language = configured
+        .and_then(language_from_locale)
+        .or_else(|| {
+            ["LC_ALL", "LC_MESSAGES", "LANG"]
+                .iter()
+                .filter_map(|var| env::var(var).ok())
+                .find_map(|value| language_from_locale(&value))
+        })
+        .filter(|lang| catalogs().contains_key(lang.as_str()));
+
+    if let Ok(mut current) = LANGUAGE.write() {
+        *current = language;
+    }
+}
+
+/// `de_DE.UTF-8` -> `de`; `C` and `POSIX` mean the default language
+fn language_from_locale(locale: &str) -> Option<String> {
+    let language = locale
+        .split(['_', '.', '@'])
+        .next()
+        .unwrap_or("")
+        .trim()
+        .to_lowercase();
+    match language.as_str() {
+        "" => None,
+        "c" | "posix" => Some(FALLBACK.to_string()),
+        _ => Some(language),
+    }
+}
+
+pub fn message(key: &str, args: &[String]) -> String {
+    let catalogs = catalogs();
+    let language = LANGUAGE
+        .read()
+        .ok()
+        .and_then(|current| current.clone())
+        .unwrap_or_else(|| FALLBACK.to_string());
+
+    let template = catalogs
+        .get(language.as_str())
+        .and_then(|messages| messages.get(key))
+        .or_else(|| catalogs.get(FALLBACK)?.get(key));
+
+    match template {
+        Some(template) => format_message(template, args),
+        None => key.to_string(),
+    }
+}
+
+/// Substitute `{0}`, `{1}`, ... with the positional arguments
+fn format_message(template: &str, args: &[String]) -> String {
+    let mut result = template.to_string();
+    for (i, arg) in args.iter().enumerate() {
+        result = result.replace(&format!("{{{}}}", i), arg);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_formats_positional_arguments() {
+        let args = ["node".to_string(), "20".to_string()];
+        assert_eq!(format_message("{0} now at {1}", &args), "node now at 20");
+        assert_eq!(format_message("{1} then {0}", &args), "20 then node");
+    }
+
+    #[test]
+    fn test_parses_locale_names() {
+        assert_eq!(language_from_locale("de_DE.UTF-8").as_deref(), Some("de"));
+        assert_eq!(language_from_locale("fr").as_deref(), Some("fr"));
+        assert_eq!(language_from_locale("C").as_deref(), Some("en"));
+        assert_eq!(language_from_locale(""), None);
+    }
+
+    #[test]
+    fn test_english_catalog_is_valid() {
+        assert!(catalogs().contains_key(FALLBACK));
+        assert_eq!(message("label.info", &[]), "[INFO]");
+        assert_eq!(message("no.such.key", &[]), "no.such.key");
+    }
+}
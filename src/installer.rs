@@ -1,56 +1,62 @@
-use crate::config::Config;
-use crate::package::{Package, PlatformDetails};
-use crate::utils::{print_info, print_step, print_success};
-use anyhow::{Result, anyhow};
-use flate2::read::GzDecoder;
+use crate::build_info;
+use crate::builds::{self, BuildTools};
+use crate::cache::{self, CacheEntry, Remote};
+use crate::checksum::{find_in_sums, sha256_file};
+use crate::config::{BarStyle, Config, Emulation, ModePolicy};
+use crate::cosign::Material;
+use crate::events;
+use crate::extract::extract_archive_sync;
+use crate::fault;
+use crate::fonts;
+use crate::http;
+use crate::interrupt;
+use crate::lock;
+use crate::manifest;
+use crate::modes;
+use crate::optimize::{self, Optimized};
+#[cfg(feature = "p2p")]
+use crate::p2p;
+use crate::package::{Package, PlatformDetails, Provenance};
+use crate::platform;
+use crate::progress::{self, Transfer};
+use crate::utils::{
+    plain_file_name, print_info, print_step, print_success, print_warning, shell_quote,
+};
+use anyhow::{Context, Result, anyhow};
+use chrono::Utc;
 use futures_util::StreamExt;
 use indicatif::{ProgressBar, ProgressStyle};
 use reqwest::Client;
+use serde_json::json;
+use std::io::Write;
 use std::os::unix::fs::PermissionsExt;
 use std::path::{Path, PathBuf};
-use std::process::Command;
-use tar::Archive;
+use std::process::Stdio;
+use std::time::Duration;
 use tokio::fs::{self, File};
 use tokio::io::AsyncWriteExt;
-use xz2::read::XzDecoder;
-
-fn extract_archive_sync(archive_path: &Path, extract_to: &Path) -> Result<()> {
-    let file = std::fs::File::open(archive_path)?;
-    let filename = archive_path.file_name().unwrap().to_string_lossy();
-
-    if filename.ends_with(".tar.gz") || filename.ends_with(".tgz") {
-        let decoder = GzDecoder::new(file);
-        let mut archive = Archive::new(decoder);
-        archive.unpack(extract_to)?;
-    } else if filename.ends_with(".tar.xz") {
-        let decoder = XzDecoder::new(file);
-        let mut archive = Archive::new(decoder);
-        archive.unpack(extract_to)?;
-    } else {
-        return Err(anyhow!("Unsupported archive format: {}", filename));
-    }
-
-    Ok(())
-}
+use tokio::process::Command;
 
-/// Parse filename from Content-Disposition header, handling both regular and RFC 5987 encoded formats
+/// Parse filename from Content-Disposition header, handling both regular and RFC 5987 encoded formats.
+/// The server picks the name, so only its last path component is kept, and a
+/// name that still isn't a plain file name (e.g. `..`) is ignored.
 fn parse_content_disposition_filename(content_disposition: &str) -> Option<String> {
     // Handle RFC 5987 encoded filenames: filename*=UTF-8''example.zip
-    if let Some(encoded_part) = content_disposition.split("filename*=").nth(1) {
-        if let Some(filename_part) = encoded_part.split("''").nth(1) {
-            // Simple URL decoding for basic cases (just remove %XX sequences)
-            let decoded = filename_part.replace("%20", " ");
-            return Some(decoded);
-        }
-    }
-
-    // Handle regular filenames: filename="example.zip" or filename=example.zip
-    if let Some(regular_part) = content_disposition.split("filename=").nth(1) {
+    let filename = if let Some(encoded_part) = content_disposition.split("filename*=").nth(1)
+        && let Some(filename_part) = encoded_part.split("''").nth(1)
+    {
+        // Simple URL decoding for basic cases (just remove %XX sequences)
+        filename_part.replace("%20", " ")
+    } else if let Some(regular_part) = content_disposition.split("filename=").nth(1) {
+        // Handle regular filenames: filename="example.zip" or filename=example.zip
         let filename = regular_part.split(';').next().unwrap_or(regular_part);
-        return Some(filename.trim_matches('"').to_string());
-    }
+        filename.trim_matches('"').to_string()
+    } else {
+        return None;
+    };
 
-    None
+    let last = filename.rsplit(['/', '\\']).next()?.to_string();
+    plain_file_name(&last).then_some(last)
 }
 
 /// Sanitize filename for the current platform
@@ -69,40 +75,378 @@ fn sanitize_filename(filename: &str) -> String {
             .collect();
     }
 
-    // Ensure we have a reasonable fallback filename
-    if sanitized.is_empty() {
+    // Ensure we have a reasonable fallback filename that stays in the cache
+    if !plain_file_name(&sanitized) {
         sanitized = "download".to_string();
     }
 
     sanitized
 }
 
+/// Bytes between download-progress events in --json-lines mode
+const PROGRESS_EVENT_BYTES: u64 = 256 * 1024;
+
+/// Progress bar for a download; servers that omit Content-Length get a
+/// spinner instead of a bar stuck at 0 bytes. During a batch the bar joins the
+/// batch's others, labeled so they can be told apart.
+fn download_progress(total_size: Option<u64>, style: BarStyle, label: &str) -> Result<Transfer> {
+    let prefix = if progress::active() {
+        "  {prefix:20!} "
+    } else {
+        "  "
+    };
+    let pb = match total_size {
+        Some(total) => {
+            let pb = ProgressBar::new(total);
+            pb.set_style(
+                ProgressStyle::default_bar()
+                    .template(&format!(
+                        "{}[{{bar:30}}] {{percent:>3}}% ({{bytes}}/{{total_bytes}}, {{binary_bytes_per_sec}}, ETA {{eta}})",
+                        prefix
+                    ))?
+                    .progress_chars(progress::bar_chars(style)),
+            );
+            pb
+        }
+        None => {
+            let ticks = match style {
+                BarStyle::Unicode => "⠋⠙⠹⠸⠼⠴⠦⠧⠇⠏✓",
+                BarStyle::Ascii => "|/-\\*",
+            };
+            let pb = ProgressBar::new_spinner();
+            pb.set_style(
+                ProgressStyle::default_spinner()
+                    .template(&format!(
+                        "{}{{spinner}} {{bytes}} ({{binary_bytes_per_sec}})",
+                        prefix
+                    ))?
+                    .tick_chars(ticks),
+            );
+            pb.enable_steady_tick(Duration::from_millis(100));
+            pb
+        }
+    };
+    pb.set_prefix(label.to_string());
+    Ok(Transfer::new(progress::attach(pb), label, total_size))
+}
+
+/// Hidden sibling of `package_dir` that an install is unpacked into. It sits on
+/// the same filesystem, so moving it into place is a single atomic rename and a
+/// crash never leaves a half-extracted package behind.
+fn staging_path(package_dir: &Path) -> PathBuf {
+    let name = package_dir
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy();
+    package_dir.with_file_name(format!(".{}.partial", name))
+}
+
+/// The package directory a staging directory will be moved to
+fn final_path(staging_dir: &Path) -> PathBuf {
+    let name = staging_dir
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy();
+    let name = name.strip_prefix('.').unwrap_or(&name);
+    staging_dir.with_file_name(name.strip_suffix(".partial").unwrap_or(name))
+}
+
+/// Clear out whatever an interrupted earlier attempt left in the staging directory
+async fn prepare_staging(package_dir: &Path) -> PathBuf {
+    let staging_dir = staging_path(package_dir);
+    fs::remove_dir_all(&staging_dir).await.ok();
+    interrupt::started(&staging_dir);
+    staging_dir
+}
+
+async fn commit_staging(staging_dir: &Path, package_dir: &Path) -> Result<()> {
+    // Only reached for packages that aren't installed, so anything here is debris
+    // from a leaf that extracted in place
+    if fs::symlink_metadata(package_dir).await.is_ok() {
+        fs::remove_dir_all(package_dir).await?;
+    }
+    fs::rename(staging_dir, package_dir).await?;
+    interrupt::finished(staging_dir);
+    Ok(())
+}
+
+/// Values for the `{placeholders}` in build commands
+struct BuildVars<'a> {
+    name: &'a str,
+    version: &'a str,
+    /// Directory being filled, moved into place once the build succeeds
+    package_dir: &'a Path,
+    /// Where the package ends up, for paths compiled into the program
+    prefix: &'a Path,
+}
+
+impl BuildVars<'_> {
+    /// Fill in {prefix}, {package_dir}, {version}, {name} and {jobs}; other
+    /// braces, such as shell ${VARIABLES}, are left alone
+    fn expand(&self, value: &str) -> String {
+        self.fill(value, str::to_string)
+    }
+
+    /// Like `expand`, for a command run with `sh -c`: values with characters
+    /// the shell would interpret (spaces, quotes, `$`...) are quoted
+    fn expand_command(&self, command: &str) -> String {
+        self.fill(command, shell_word)
+    }
+
+    fn fill(&self, template: &str, quote: fn(&str) -> String) -> String {
+        let jobs = std::thread::available_parallelism().map_or(1, |jobs| jobs.get());
+        [
+            ("{prefix}", self.prefix.display().to_string()),
+            ("{package_dir}", self.package_dir.display().to_string()),
+            ("{version}", self.version.to_string()),
+            ("{name}", self.name.to_string()),
+            ("{jobs}", jobs.to_string()),
+        ]
+        .iter()
+        .fold(template.to_string(), |template, (placeholder, value)| {
+            template.replace(placeholder, &quote(value))
+        })
+    }
+}
+
+/// `value` as one shell word: unchanged when it is made of characters the
+/// shell takes literally, so it also reads right inside quotes, else quoted
+fn shell_word(value: &str) -> String {
+    if !value.is_empty()
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "/._-+:,=@%".contains(c))
+    {
+        value.to_string()
+    } else {
+        shell_quote(value)
+    }
+}
+
+/// Process group of a running build command, killed when dropped before the
+/// command finishes: on timeout, or when Ctrl-C cancels the install
+struct BuildGroup(Option<u32>);
+
+/// Lines of build output quoted in the error when a build command fails
+const BUILD_ERROR_LINES: usize = 20;
+
+impl Drop for BuildGroup {
+    fn drop(&mut self) {
+        if let Some(pid) = self.0 {
+            // SAFETY: signals only the group created for this command
+            unsafe {
+                libc::kill(-(pid as i32), libc::SIGKILL);
+            }
+        }
+    }
+}
+
+/// A file fetched into the cache
+pub struct Download {
+    pub path: PathBuf,
+    /// Final URL after redirects
+    pub resolved_url: String,
+    pub sha256: String,
+}
+
+#[derive(Clone)]
 pub struct Installer {
     client: Client,
+    bar_style: BarStyle,
+    remote_cache: Option<Remote>,
+    #[cfg(feature = "p2p")]
+    ipfs_gateways: Vec<String>,
+    /// Downloads run alongside other output, so don't draw a progress bar
+    background: bool,
+    /// Name shown on download bars in place of the file name
+    label: Option<String>,
+    /// Mach-O executables must contain one of these
+    architectures: Vec<String>,
+    /// Platform being installed for when it isn't this machine's (--platform)
+    target: Option<String>,
+    /// Limit for each build command of a source package
+    build_timeout: Option<Duration>,
+    /// Source packages are built under here rather than in their package directory
+    builds_dir: PathBuf,
+    /// Where the output of each build is saved
+    logs_dir: PathBuf,
+    /// Leaf packages that source builds run with
+    build_tools: BuildTools,
+    mode_policy: ModePolicy,
+    /// Missing cosign is an error rather than a warning
+    require_signature_checks: bool,
 }
 
 impl Installer {
-    pub fn new() -> Self {
+    pub fn new(config: &Config) -> Self {
         Self {
-            client: Client::new(),
+            client: http::client(),
+            bar_style: config.progress_bar,
+            remote_cache: config.cache.remote_url.as_deref().map(Remote::parse),
+            #[cfg(feature = "p2p")]
+            ipfs_gateways: if config.ipfs_gateways.is_empty() {
+                p2p::DEFAULT_IPFS_GATEWAYS.map(String::from).to_vec()
+            } else {
+                config.ipfs_gateways.clone()
+            },
+            background: false,
+            label: None,
+            architectures: platform::runnable_architectures(config.emulation != Emulation::Never)
+                .into_iter()
+                .map(String::from)
+                .collect(),
+            target: None,
+            build_timeout: config.build_timeout(),
+            builds_dir: config.builds_dir(),
+            logs_dir: config.install_dir.join("logs"),
+            build_tools: BuildTools::default(),
+            mode_policy: config.mode_policy,
+            require_signature_checks: config.policy.require_signature_checks,
         }
     }
 
+    /// Same installer producing packages for another platform, e.g. to fill a
+    /// prefix for a machine or container of a different architecture. Steps
+    /// that run the package's code on this machine are skipped.
+    pub fn for_platform(&self, platform: &str) -> Self {
+        let arch = platform.split_once('-').map_or(platform, |(_, arch)| arch);
+        Self {
+            architectures: vec![arch.to_string()],
+            target: Some(platform.to_string()),
+            ..self.clone()
+        }
+    }
+
+    /// Same installer building with the given leaf packages' tools and env
+    pub fn with_build_tools(&self, build_tools: BuildTools) -> Self {
+        Self {
+            build_tools,
+            ..self.clone()
+        }
+    }
+
+    /// Same installer with its download bars labeled `label`, e.g. the package
+    pub fn labeled(&self, label: &str) -> Self {
+        Self {
+            label: Some(label.to_string()),
+            ..self.clone()
+        }
+    }
+
+    /// Same installer without progress bars, for downloads that run while
+    /// another package is being installed
+    pub fn background(&self) -> Self {
+        Self {
+            background: true,
+            ..self.clone()
+        }
+    }
+
+    /// Install a package into packages_dir, stripping binaries if configured and
+    /// recording the file manifest before the directory is moved into place
     pub async fn install_package(
         &self,
         name: &str,
-        _package: &Package,
+        package: &Package,
         platform_details: &PlatformDetails,
         config: &Config,
-    ) -> Result<()> {
+    ) -> Result<(Provenance, Optimized)> {
         let package_dir = config.packages_dir.join(name);
-        let cache_dir = &config.cache_dir;
+        let staging_dir = prepare_staging(&package_dir).await;
+        let result = async {
+            let provenance = self
+                .unpack(
+                    name,
+                    &package.version,
+                    platform_details,
+                    &staging_dir,
+                    &config.cache_dir,
+                )
+                .await?;
+            // strip and upx may not understand foreign binaries
+            let native = self.target.is_none();
+            let (strip, compress) = (
+                native && config.strip_binaries,
+                native && config.compress_binaries,
+            );
+            let optimized = tokio::task::spawn_blocking({
+                let staging_dir = staging_dir.clone();
+                move || {
+                    let optimized = if strip || compress {
+                        optimize::optimize(&staging_dir, strip, compress)?
+                    } else {
+                        Optimized::default()
+                    };
+                    manifest::record(&staging_dir, &optimized.originals)?;
+                    anyhow::Ok(optimized)
+                }
+            })
+            .await??;
+            commit_staging(&staging_dir, &package_dir).await?;
+            Ok((provenance, optimized))
+        }
+        .await;
+        if result.is_err() {
+            fs::remove_dir_all(&staging_dir).await.ok();
+        }
+        result
+    }
+
+    /// Download and unpack a package into an arbitrary directory without touching
+    /// bin_dir or installed metadata
+    pub async fn install_into(
+        &self,
+        name: &str,
+        version: &str,
+        platform_details: &PlatformDetails,
+        package_dir: &Path,
+        cache_dir: &Path,
+    ) -> Result<Provenance> {
+        let staging_dir = prepare_staging(package_dir).await;
+        let result = async {
+            let provenance = self
+                .unpack(name, version, platform_details, &staging_dir, cache_dir)
+                .await?;
+            commit_staging(&staging_dir, package_dir).await?;
+            Ok(provenance)
+        }
+        .await;
+        if result.is_err() {
+            fs::remove_dir_all(&staging_dir).await.ok();
+        }
+        result
+    }
+
+    async fn unpack(
+        &self,
+        name: &str,
+        version: &str,
+        platform_details: &PlatformDetails,
+        package_dir: &Path,
+        cache_dir: &Path,
+    ) -> Result<Provenance> {
+        let package_dir = package_dir.to_path_buf();
 
         fs::create_dir_all(&package_dir).await?;
         fs::create_dir_all(cache_dir).await?;
 
         // Download the file
-        let cache_file_path = self.download_file(&platform_details.url, cache_dir).await?;
+        let download = self
+            .download_any(&platform_details.sources(), cache_dir)
+            .await?;
+        self.verify_published_checksum(platform_details, &download)
+            .await?;
+        self.verify_cosign(platform_details, &download, cache_dir)
+            .await?;
+        let cache_file_path = download.path;
+        let provenance = Provenance {
+            url: platform_details.url.clone(),
+            resolved_url: download.resolved_url,
+            sha256: download.sha256,
+            downloaded_at: Some(Utc::now()),
+            leaf_version: build_info::VERSION.to_string(),
+            platform: self.target.clone(),
+        };
 
         let package_type = platform_details
             .package_type
@@ -111,17 +455,23 @@ impl Installer {
 
         match package_type {
             "archive" => {
-                print_step("Extracting archive...");
+                print_step(&tr!("installer.extracting"));
+                events::emit(
+                    "extract",
+                    json!({"package": name, "archive": cache_file_path, "into": package_dir}),
+                );
                 let extract_path = package_dir.clone();
                 tokio::task::spawn_blocking(move || {
                     extract_archive_sync(&cache_file_path, &extract_path)
                 })
                 .await??;
+                // Files are in the staging directory, which isn't committed yet
+                fault::check("mid-extract")?;
             }
             "binary" => {
-                print_step("Installing binary...");
+                print_step(&tr!("installer.binary"));
                 let executables = platform_details.get_executables();
-                let executable = executables.get(0).ok_or_else(|| {
+                let executable = executables.first().ok_or_else(|| {
                     anyhow!("Binary package '{}' has no executables listed", name)
                 })?;
 
@@ -137,9 +487,37 @@ impl Installer {
                 perms.set_mode(0o755);
                 fs::set_permissions(&dest_path, perms).await?;
             }
+            // A font archive, or a single font file
+            "font" => {
+                print_step(&tr!("installer.extracting"));
+                let extract_path = package_dir.clone();
+                tokio::task::spawn_blocking(move || {
+                    if fonts::is_font(&cache_file_path) {
+                        let file_name = cache_file_path.file_name().unwrap_or_default();
+                        std::fs::copy(&cache_file_path, extract_path.join(file_name))?;
+                        Ok(())
+                    } else {
+                        extract_archive_sync(&cache_file_path, &extract_path)
+                    }
+                })
+                .await??;
+            }
+            "build" if self.target.is_some() => {
+                return Err(anyhow!(
+                    "{} is built from source, which can't be done for {} on this machine",
+                    name,
+                    self.target.as_deref().unwrap_or_default()
+                ));
+            }
             "build" => {
-                print_step("Building from source...");
-                self.build_from_source(name, platform_details, &cache_file_path, &package_dir)
+                print_step(&tr!("installer.building"));
+                let vars = BuildVars {
+                    name,
+                    version,
+                    package_dir: &package_dir,
+                    prefix: &final_path(&package_dir),
+                };
+                self.build_from_source(&vars, platform_details, &cache_file_path)
                     .await?;
             }
             _ => {
@@ -147,23 +525,74 @@ impl Installer {
             }
         }
 
-        print_success(&format!("Installation complete for '{}'", name));
-        Ok(())
+        // A universal binary (or a mislabelled one) without our slice would only
+        // fail once run
+        for executable_info in platform_details.get_executables() {
+            let path = package_dir.join(&executable_info.path);
+            if let Some(architectures) = platform::macho_architectures(&path)
+                && !architectures
+                    .iter()
+                    .any(|arch| self.architectures.iter().any(|ours| ours == arch))
+            {
+                return Err(anyhow!(
+                    "{} only contains {} code, not {}",
+                    executable_info.path,
+                    architectures.join(" and "),
+                    std::env::consts::ARCH
+                ));
+            }
+        }
+
+        tokio::task::spawn_blocking({
+            let (policy, package_dir) = (self.mode_policy, package_dir.clone());
+            move || modes::apply(policy, &package_dir)
+        })
+        .await??;
+
+        print_success(&tr!("installer.done", name));
+        Ok(provenance)
     }
 
     async fn build_from_source(
         &self,
-        name: &str,
+        vars: &BuildVars<'_>,
         platform_details: &PlatformDetails,
         cache_file_path: &Path,
-        package_dir: &Path,
     ) -> Result<()> {
-        // Create a temporary build directory
-        let build_dir = package_dir.join("build_temp");
+        let (name, package_dir) = (vars.name, vars.package_dir);
+        let build_root = builds::build_root(&self.builds_dir, name);
+        let _lock = lock::acquire_async(builds::lock_path(&build_root, vars.version)).await?;
+
+        // Anything at the final location is debris; `make install` into {prefix}
+        // must start from an empty directory
+        if fs::symlink_metadata(vars.prefix).await.is_ok() {
+            fs::remove_dir_all(vars.prefix).await?;
+        }
+
+        // Build out of tree, next to the compiler caches kept from earlier builds;
+        // only the source tree starts afresh
+        let build_dir = builds::source_dir(&build_root, vars.version)?;
+        if fs::symlink_metadata(&build_dir).await.is_ok() {
+            fs::remove_dir_all(&build_dir).await?;
+        }
         fs::create_dir_all(&build_dir).await?;
+        // Build tools come after the compiler caches, and the definition's own
+        // build_env after both, so each can override the one before
+        let mut build_env = builds::compiler_cache_env(&build_root);
+        build_env.extend(self.build_tools.env.iter().cloned());
+        if let Some(path) = self.build_tools.search_path()? {
+            build_env.push(("PATH".to_string(), path.to_string_lossy().to_string()));
+        }
+        for (key, value) in platform_details.build_env.iter().flatten() {
+            build_env.push((key.clone(), self.build_tools.expand(&vars.expand(value))));
+        }
 
         // Extract source code to build directory
-        print_step("Extracting source code...");
+        print_step(&tr!("installer.extracting_source"));
+        events::emit(
+            "extract",
+            json!({"package": name, "archive": cache_file_path, "into": build_dir}),
+        );
         tokio::task::spawn_blocking({
             let cache_file_path = cache_file_path.to_path_buf();
             let build_dir = build_dir.clone();
@@ -183,35 +612,82 @@ impl Installer {
         // Find the actual source directory (often extracted archives create a subdirectory)
         let source_dir = self.find_source_directory(&build_dir).await?;
 
-        // Execute build commands
-        print_step("Running build commands...");
+        // Execute build commands, their output going to the build log
+        print_step(&tr!("installer.running_build"));
+        let (log_path, mut log) = builds::create_log(&self.logs_dir, name)?;
+        print_info(&tr!("installer.build_log", log_path.display()));
         for (i, command) in build_commands.iter().enumerate() {
-            print_info(&format!("Step {}/{}: {}", i + 1, build_commands.len(), command));
+            let command = &vars.expand_command(command);
+            print_info(&tr!(
+                "installer.build_step",
+                i + 1,
+                build_commands.len(),
+                command
+            ));
+            writeln!(log, "$ {}", command)?;
 
-            let output = Command::new("sh")
+            // In its own process group, so a stalled step can be stopped along
+            // with everything it started
+            let mut child = Command::new("sh")
                 .arg("-c")
                 .arg(command)
                 .current_dir(&source_dir)
-                .output()?;
+                .envs(build_env.iter().map(|(name, value)| (name, value)))
+                .stdin(Stdio::null())
+                .stdout(log.try_clone()?)
+                .stderr(log.try_clone()?)
+                .process_group(0)
+                .spawn()?;
+            let mut group = BuildGroup(child.id());
+            let status = match self.build_timeout {
+                Some(limit) => tokio::time::timeout(limit, child.wait())
+                    .await
+                    .map_err(|_| {
+                        anyhow!(
+                            "Build step {}/{} was stopped after {}s without finishing: {}\nRaise \"build_timeout\" in config.json (seconds, 0 for no limit) if it needs longer; output so far is in {}",
+                            i + 1,
+                            build_commands.len(),
+                            limit.as_secs(),
+                            command,
+                            log_path.display()
+                        )
+                    })??,
+                None => child.wait().await?,
+            };
+            group.0 = None;
 
-            if !output.status.success() {
-                let stderr = String::from_utf8_lossy(&output.stderr);
-                let stdout = String::from_utf8_lossy(&output.stdout);
+            if !status.success() {
+                let output = std::fs::read_to_string(&log_path).unwrap_or_default();
                 return Err(anyhow!(
-                    "Build command failed: {}\nStdout: {}\nStderr: {}",
+                    "Build command failed: {}\n{}\nFull log: {} (leaf logs {})",
                     command,
-                    stdout,
-                    stderr
+                    builds::last_lines(&output, BUILD_ERROR_LINES),
+                    log_path.display(),
+                    name
                 ));
             }
         }
 
+        // Files installed into {prefix} join the staged package, which is then
+        // moved back there
+        if fs::symlink_metadata(vars.prefix).await.is_ok() {
+            let mut entries = fs::read_dir(vars.prefix).await?;
+            while let Some(entry) = entries.next_entry().await? {
+                fs::rename(entry.path(), package_dir.join(entry.file_name())).await?;
+            }
+            fs::remove_dir_all(vars.prefix).await?;
+        }
+
         // Copy built executables to package directory
-        print_step("Installing built executables...");
+        print_step(&tr!("installer.installing_built"));
         for executable_info in platform_details.get_executables() {
             let source_exe = source_dir.join(&executable_info.path);
             let dest_exe = package_dir.join(&executable_info.path);
 
+            // Already there when the build installed it itself
+            if dest_exe.exists() {
+                continue;
+            }
             if !source_exe.exists() {
                 return Err(anyhow!(
                     "Built executable not found: {}",
@@ -231,7 +707,7 @@ impl Installer {
             fs::set_permissions(&dest_exe, perms).await?;
         }
 
-        // Clean up build directory
+        // Keep the caches, not the sources
         fs::remove_dir_all(&build_dir).await?;
 
         Ok(())
@@ -256,56 +732,494 @@ impl Installer {
         }
     }
 
+    /// Ask the server for the artifact size without downloading it
+    pub async fn fetch_content_length(&self, url: &str) -> Option<u64> {
+        let response = self.client.head(url).send().await.ok()?;
+        if !response.status().is_success() {
+            return None;
+        }
+        // HEAD responses have no body, so read the header rather than the body size hint
+        response
+            .headers()
+            .get(reqwest::header::CONTENT_LENGTH)?
+            .to_str()
+            .ok()?
+            .parse()
+            .ok()
+            .filter(|&len: &u64| len > 0)
+    }
+
+    /// Check a download against the package's `checksum_url`, which lists it by
+    /// its file name. A download that doesn't match is dropped from the cache so
+    /// the next attempt fetches it again.
+    pub async fn verify_published_checksum(
+        &self,
+        platform_details: &PlatformDetails,
+        download: &Download,
+    ) -> Result<()> {
+        let Some(checksum_url) = &platform_details.checksum_url else {
+            return Ok(());
+        };
+        let response = http::check_rate_limit(http::get(&self.client, checksum_url).send().await?)?
+            .error_for_status()
+            .map_err(|e| anyhow!("Cannot fetch checksums from {}: {}", checksum_url, e))?;
+        let sums = response.text().await?;
+
+        // The cached file is named as the server named it; the index may differ
+        let names = [
+            download
+                .path
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string()),
+            platform_details
+                .url
+                .split(['?', '#'])
+                .next()
+                .and_then(|url| url.rsplit('/').next())
+                .map(str::to_string),
+        ];
+        let (name, expected) = names
+            .iter()
+            .flatten()
+            .find_map(|name| find_in_sums(&sums, name).map(|expected| (name, expected)))
+            .ok_or_else(|| {
+                anyhow!(
+                    "{} does not list {}",
+                    checksum_url,
+                    names
+                        .iter()
+                        .flatten()
+                        .next()
+                        .map_or("the download", |name| name)
+                )
+            })?;
+        if !expected.eq_ignore_ascii_case(&download.sha256) {
+            fs::remove_file(&download.path).await.ok();
+            return Err(anyhow!(
+                "Checksum mismatch for {}: {} lists {}, got {}",
+                name,
+                checksum_url,
+                expected,
+                download.sha256
+            ));
+        }
+        if !self.background {
+            print_info(&tr!("installer.checksum_ok", name));
+        }
+        Ok(())
+    }
+
+    /// Check a download's keyless cosign signature against the identity the
+    /// package declares. A download that fails is dropped from the cache like
+    /// one with the wrong checksum.
+    pub async fn verify_cosign(
+        &self,
+        platform_details: &PlatformDetails,
+        download: &Download,
+        cache_dir: &Path,
+    ) -> Result<()> {
+        let Some(cosign) = &platform_details.cosign else {
+            return Ok(());
+        };
+        let mut paths = Vec::new();
+        for url in cosign.material_urls()? {
+            paths.push(self.download_file(url, cache_dir).await?);
+        }
+        let material = match paths.as_slice() {
+            [bundle] => Material::Bundle(bundle),
+            [signature, certificate] => Material::Detached {
+                signature,
+                certificate,
+            },
+            _ => unreachable!(),
+        };
+        let output = Command::new("cosign")
+            .args(cosign.verify_args(&download.path, &material))
+            .stdin(Stdio::null())
+            .output()
+            .await;
+        let name = download
+            .path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy();
+        match output {
+            Ok(output) if output.status.success() => {
+                if !self.background {
+                    print_info(&tr!("installer.signature_ok", name, cosign.identity));
+                }
+                Ok(())
+            }
+            Ok(output) => {
+                fs::remove_file(&download.path).await.ok();
+                Err(anyhow!(
+                    "Signature verification of {} failed: {}",
+                    name,
+                    String::from_utf8_lossy(&output.stderr).trim()
+                ))
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                if self.require_signature_checks {
+                    return Err(anyhow!(
+                        "{} is signed, but cosign is not installed to verify it",
+                        name
+                    ));
+                }
+                print_warning(&tr!("installer.no_cosign", name));
+                Ok(())
+            }
+            Err(e) => Err(anyhow!("Cannot run cosign: {}", e)),
+        }
+    }
+
     pub async fn download_file(&self, url: &str, cache_dir: &Path) -> Result<PathBuf> {
-        let response = self.client.get(url).send().await?;
-        let total_size = response.content_length().unwrap_or(0);
+        Ok(self.download(url, cache_dir).await?.path)
+    }
+
+    /// Download from the first of `sources` that works, warning about the ones
+    /// that didn't
+    pub async fn download_any(&self, sources: &[&str], cache_dir: &Path) -> Result<Download> {
+        let mut errors = Vec::new();
+        for (i, source) in sources.iter().enumerate() {
+            let result = if source.starts_with("ipfs://") || source.starts_with("magnet:") {
+                self.download_p2p(source, cache_dir).await
+            } else {
+                self.download(source, cache_dir).await
+            };
+            match result {
+                Ok(download) => return Ok(download),
+                Err(e) if i + 1 < sources.len() => {
+                    print_warning(&tr!("download.source_failed", source, e));
+                    errors.push(e);
+                }
+                Err(e) => errors.push(e),
+            }
+        }
+        match errors.len() {
+            0 => Err(anyhow!("No download sources")),
+            1 => Err(errors.remove(0)),
+            count => Err(anyhow!(
+                "All {} download sources failed; last error: {}",
+                count,
+                errors[count - 1]
+            )),
+        }
+    }
+
+    #[cfg(feature = "p2p")]
+    async fn download_p2p(&self, source: &str, cache_dir: &Path) -> Result<Download> {
+        if let Some(ipfs_path) = source.strip_prefix("ipfs://") {
+            let mut last_error = anyhow!("No IPFS gateways configured");
+            for gateway in &self.ipfs_gateways {
+                match self
+                    .download(&p2p::gateway_url(gateway, ipfs_path), cache_dir)
+                    .await
+                {
+                    Ok(download) => return Ok(download),
+                    Err(e) => last_error = e,
+                }
+            }
+            return Err(last_error);
+        }
+
+        let cached = tokio::task::spawn_blocking({
+            let (cache_dir, source) = (cache_dir.to_path_buf(), source.to_string());
+            move || cache::lookup(&cache_dir, &source)
+        })
+        .await?;
+        if let Some((path, entry)) = cached {
+            return Ok(Download {
+                path,
+                resolved_url: entry.resolved_url,
+                sha256: entry.sha256,
+            });
+        }
+        if !self.background {
+            print_info(&tr!("download.magnet"));
+        }
+        let path = tokio::task::spawn_blocking({
+            let (cache_dir, source) = (cache_dir.to_path_buf(), source.to_string());
+            move || p2p::fetch_magnet(&source, &cache_dir)
+        })
+        .await??;
+        self.cached_download(source, cache_dir, path, source.to_string())
+            .await
+    }
+
+    #[cfg(not(feature = "p2p"))]
+    async fn download_p2p(&self, source: &str, _cache_dir: &Path) -> Result<Download> {
+        let scheme = source.split(':').next().unwrap_or(source);
+        Err(anyhow!(
+            "{} sources need leaf built with the p2p feature",
+            scheme
+        ))
+    }
+
+    /// Download `url` into `cache_dir`, naming the file after the response's
+    /// Content-Disposition or, failing that, the URL after redirects
+    pub async fn download(&self, url: &str, cache_dir: &Path) -> Result<Download> {
+        // Known URLs are served from the cache without touching the network
+        let cached = tokio::task::spawn_blocking({
+            let (cache_dir, url) = (cache_dir.to_path_buf(), url.to_string());
+            move || cache::lookup(&cache_dir, &url)
+        })
+        .await?;
+        if let Some((path, entry)) = cached {
+            if !self.background {
+                print_info(&tr!("download.cache_hit", entry.file));
+            }
+            return Ok(Download {
+                path,
+                resolved_url: entry.resolved_url,
+                sha256: entry.sha256,
+            });
+        }
+
+        if let Some(remote) = &self.remote_cache {
+            match remote.fetch(&self.client, url, cache_dir).await {
+                Ok(Some((path, entry))) => {
+                    if !self.background {
+                        print_info(&tr!("download.remote_hit", entry.file));
+                    }
+                    return Ok(Download {
+                        path,
+                        resolved_url: entry.resolved_url,
+                        sha256: entry.sha256,
+                    });
+                }
+                Ok(None) => {}
+                Err(e) => print_warning(&tr!("download.remote_failed", e)),
+            }
+        }
+
+        // Ask for the file as-is so its hash is that of the published artifact
+        let response = http::get(&self.client, url)
+            .header(reqwest::header::ACCEPT_ENCODING, "identity")
+            .send()
+            .await
+            .with_context(|| format!("Failed to download {}", url))?;
+        let response = http::check_rate_limit(response)?;
+
+        // Don't cache and "extract" an error page
+        let status = response.status();
+        if !status.is_success() {
+            return Err(anyhow!("HTTP {} downloading {}", status, url));
+        }
+        let content_type = response
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or("");
+        if content_type.starts_with("text/html") {
+            return Err(anyhow!(
+                "{} returned an HTML page instead of a file; the download link may be broken",
+                url
+            ));
+        }
+
+        let total_size = response.content_length().filter(|&len| len > 0);
+        // Redirect targets (e.g. GitHub release assets) carry the real file name
+        let resolved_url = response.url().to_string();
+        let url_filename = response
+            .url()
+            .path_segments()
+            .and_then(|mut segments| segments.next_back())
+            .filter(|segment| !segment.is_empty())
+            .unwrap_or("download")
+            .to_string();
 
         let content_disposition = response
             .headers()
             .get(reqwest::header::CONTENT_DISPOSITION)
             .and_then(|value| value.to_str().ok());
 
-        let filename = if let Some(cd) = content_disposition {
-            parse_content_disposition_filename(cd).unwrap_or_else(|| {
-                // Fallback to URL-based filename
-                url.split('/').last().unwrap_or("download").to_string()
-            })
-        } else {
-            url.split('/').last().unwrap_or("download").to_string()
-        };
+        let filename = content_disposition
+            .and_then(parse_content_disposition_filename)
+            .unwrap_or(url_filename);
 
         // Sanitize the filename for the current platform
         let safe_filename = sanitize_filename(&filename);
         let filepath = cache_dir.join(&safe_filename);
 
-        // If file already exists in cache, skip download
+        // A file of the same name may be from another URL or a broken earlier
+        // download, so only reuse it when it checks out
         if filepath.exists() {
-            print_info(&format!("Found {} in cache", safe_filename));
-            return Ok(filepath);
+            let reusable = tokio::task::spawn_blocking({
+                let (cache_dir, filepath) = (cache_dir.to_path_buf(), filepath.clone());
+                move || cache::reusable(&cache_dir, &filepath, total_size)
+            })
+            .await?;
+            if reusable {
+                if !self.background {
+                    print_info(&tr!("download.cache_hit", safe_filename));
+                }
+                return self
+                    .cached_download(url, cache_dir, filepath, resolved_url)
+                    .await;
+            }
+            if !self.background {
+                print_warning(&tr!("download.cache_stale", safe_filename));
+            }
         }
 
-        print_info(&format!("Downloading {}", safe_filename));
-        let pb = ProgressBar::new(total_size);
-        pb.set_style(
-            ProgressStyle::default_bar()
-                .template("  [{bar:30}] {percent}% ({bytes}/{total_bytes})")?
-                .progress_chars("█▉▊"),
-        );
+        if !self.background {
+            print_info(&tr!("download.start", safe_filename));
+        }
+        // Tools reading --json-lines get progress events instead of a bar, and
+        // background downloads only get one among a batch's bars
+        let mut pb = if events::enabled() || (self.background && !progress::active()) {
+            Transfer::hidden()
+        } else {
+            let label = self.label.as_deref().unwrap_or(&safe_filename);
+            download_progress(total_size, self.bar_style, label)?
+        };
+        let mut reported = 0u64;
 
-        let mut file = File::create(&filepath).await?;
+        // Written under a temporary name so an interrupted download is never
+        // mistaken for a cached file
+        let partial_path = cache_dir.join(format!("{}.part", safe_filename));
+        interrupt::started(&partial_path);
+        let mut file = File::create(&partial_path).await?;
         let mut stream = response.bytes_stream();
         let mut downloaded = 0u64;
 
-        while let Some(chunk_result) = stream.next().await {
-            let chunk = chunk_result?;
-            file.write_all(&chunk).await?;
-            downloaded += chunk.len() as u64;
-            pb.set_position(downloaded);
+        let streamed = async {
+            while let Some(chunk_result) = stream.next().await {
+                let chunk = chunk_result?;
+                file.write_all(&chunk).await?;
+                fault::check("mid-download")?;
+                downloaded += chunk.len() as u64;
+                pb.set_position(downloaded);
+                if downloaded - reported >= PROGRESS_EVENT_BYTES {
+                    reported = downloaded;
+                    events::emit(
+                        "download-progress",
+                        json!({"url": url, "downloaded": downloaded, "total": total_size}),
+                    );
+                }
+            }
+            anyhow::Ok(())
+        }
+        .await;
+        // A broken connection leaves nothing worth resuming from
+        if let Err(e) = streamed {
+            pb.finish();
+            fs::remove_file(&partial_path).await.ok();
+            return Err(e);
         }
+        events::emit(
+            "download-progress",
+            json!({"url": url, "downloaded": downloaded, "total": total_size, "complete": true}),
+        );
 
-        pb.finish_and_clear();
+        pb.finish();
         file.sync_all().await?;
+        fs::rename(&partial_path, &filepath).await?;
+        interrupt::finished(&partial_path);
+
+        self.cached_download(url, cache_dir, filepath, resolved_url)
+            .await
+    }
+
+    /// Hash a file now in the cache and index it under `url`
+    async fn cached_download(
+        &self,
+        url: &str,
+        cache_dir: &Path,
+        path: PathBuf,
+        resolved_url: String,
+    ) -> Result<Download> {
+        let sha256 = tokio::task::spawn_blocking({
+            let path = path.clone();
+            move || sha256_file(&path)
+        })
+        .await??;
+        let entry = CacheEntry {
+            file: path
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_string(),
+            resolved_url: resolved_url.clone(),
+            sha256: sha256.clone(),
+            size: fs::metadata(&path)
+                .await
+                .ok()
+                .map(|metadata| metadata.len()),
+        };
+        cache::record(cache_dir, url, entry.clone())?;
+        if let Some(remote) = &self.remote_cache
+            && let Err(e) = remote.publish(url, &path, &entry).await
+        {
+            print_warning(&tr!("download.publish_failed", e));
+        }
+        Ok(Download {
+            path,
+            resolved_url,
+            sha256,
+        })
+    }
+}
 
-        Ok(filepath)
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_content_disposition_filename() {
+        assert_eq!(
+            parse_content_disposition_filename("attachment; filename=\"rg.tar.gz\"").as_deref(),
+            Some("rg.tar.gz")
+        );
+        assert_eq!(
+            parse_content_disposition_filename("attachment; filename=\"../../.bashrc\"").as_deref(),
+            Some(".bashrc")
+        );
+        assert_eq!(
+            parse_content_disposition_filename("attachment; filename*=UTF-8''a%20b.zip").as_deref(),
+            Some("a b.zip")
+        );
+        assert_eq!(
+            parse_content_disposition_filename("attachment; filename=\"..\""),
+            None
+        );
+        assert_eq!(
+            parse_content_disposition_filename("attachment; filename=\"a/\""),
+            None
+        );
+        assert_eq!(parse_content_disposition_filename("inline"), None);
+        assert_eq!(sanitize_filename(".."), "download");
+    }
+
+    #[test]
+    fn test_build_vars() {
+        let staging = Path::new("/home/me/.local/leaf/packages/.jq.partial");
+        let prefix = final_path(staging);
+        assert_eq!(prefix, Path::new("/home/me/.local/leaf/packages/jq"));
+        let vars = BuildVars {
+            name: "jq",
+            version: "1.7.1",
+            package_dir: staging,
+            prefix: &prefix,
+        };
+        assert_eq!(
+            vars.expand("./configure --prefix={prefix} CFLAGS=${CFLAGS} && echo {version}"),
+            "./configure --prefix=/home/me/.local/leaf/packages/jq CFLAGS=${CFLAGS} && echo 1.7.1"
+        );
+        assert!(!vars.expand("make -j{jobs}").contains('{'));
+
+        let odd = Path::new("/home/me/my tools/$(x)/jq");
+        let vars = BuildVars {
+            prefix: odd,
+            ..vars
+        };
+        assert_eq!(
+            vars.expand_command("./configure --prefix={prefix}"),
+            "./configure --prefix='/home/me/my tools/$(x)/jq'"
+        );
+        assert_eq!(
+            vars.expand("{prefix}/include"),
+            "/home/me/my tools/$(x)/jq/include"
+        );
     }
 }
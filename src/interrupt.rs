@@ -0,0 +1,72 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Exit status after Ctrl-C, following the shell convention of 128 + SIGINT
+pub const EXIT_CODE: i32 = 130;
+
+/// Temporary files and directories this process is writing, so an interrupt
+/// removes its own leftovers and not those of another leaf running alongside
+static IN_PROGRESS: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+/// Note that this process is writing `path`, a half-downloaded file, staging
+/// directory or metadata file that only exists until it is moved into place
+pub fn started(path: &Path) {
+    if let Ok(mut paths) = IN_PROGRESS.lock() {
+        paths.push(path.to_path_buf());
+    }
+}
+
+/// Note that `path` was moved into place (or removed) and is no longer debris
+pub fn finished(path: &Path) {
+    if let Ok(mut paths) = IN_PROGRESS.lock() {
+        paths.retain(|other| other != path);
+    }
+}
+
+/// Remove what an interrupted command leaves behind: half-downloaded files in
+/// the cache, packages still being unpacked or built in their staging
+/// directories, and metadata that was being written. Only this process's
+/// leftovers are touched. Returns how many were removed; anything missed is
+/// cleared the next time the same package is installed, or by `leaf gc`.
+pub fn cleanup() -> usize {
+    let paths = IN_PROGRESS
+        .lock()
+        .map(|mut paths| std::mem::take(&mut *paths))
+        .unwrap_or_default();
+    paths
+        .iter()
+        .filter(|path| match fs::symlink_metadata(path) {
+            Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(path).is_ok(),
+            Ok(_) => fs::remove_file(path).is_ok(),
+            Err(_) => false,
+        })
+        .count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cleanup() {
+        let dir = std::env::temp_dir().join(format!("leaf-interrupt-{}", std::process::id()));
+        fs::remove_dir_all(&dir).ok();
+        fs::create_dir_all(dir.join(".go.partial/build_temp")).unwrap();
+        fs::write(dir.join("rg.tar.gz.part"), "").unwrap();
+        fs::write(dir.join("fd.tar.gz.part"), "").unwrap();
+        fs::write(dir.join("jq.tar.gz.part"), "").unwrap();
+
+        started(&dir.join(".go.partial"));
+        started(&dir.join("rg.tar.gz.part"));
+        started(&dir.join("jq.tar.gz.part"));
+        finished(&dir.join("jq.tar.gz.part"));
+        assert_eq!(cleanup(), 2);
+        assert!(!dir.join(".go.partial").exists());
+        assert!(!dir.join("rg.tar.gz.part").exists());
+        // Another process's download, and one this process finished
+        assert!(dir.join("fd.tar.gz.part").exists());
+        assert!(dir.join("jq.tar.gz.part").exists());
+        fs::remove_dir_all(&dir).ok();
+    }
+}
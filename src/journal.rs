@@ -0,0 +1,52 @@
+use crate::report::redact_command;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// One line of the operation journal: which command ran and how it ended
+#[derive(Debug, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub time: DateTime<Utc>,
+    pub command: String,
+    pub success: bool,
+    pub error: Option<String>,
+}
+
+pub fn journal_path(install_dir: &Path) -> PathBuf {
+    install_dir.join("journal.jsonl")
+}
+
+/// Append an entry, with secrets and personal paths redacted from the command;
+/// journaling is best-effort and never fails the command itself
+pub fn record(install_dir: &Path, command: &str, result: &Result<()>) {
+    let entry = JournalEntry {
+        time: Utc::now(),
+        command: redact_command(command),
+        success: result.is_ok(),
+        error: result.as_ref().err().map(|e| e.to_string()),
+    };
+
+    let Ok(line) = serde_json::to_string(&entry) else {
+        return;
+    };
+    if let Ok(mut file) = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(journal_path(install_dir))
+    {
+        writeln!(file, "{}", line).ok();
+    }
+}
+
+/// The most recent `count` entries, oldest first
+pub fn tail(install_dir: &Path, count: usize) -> Vec<JournalEntry> {
+    let content = std::fs::read_to_string(journal_path(install_dir)).unwrap_or_default();
+    let entries: Vec<JournalEntry> = content
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect();
+    let skip = entries.len().saturating_sub(count);
+    entries.into_iter().skip(skip).collect()
+}
@@ -0,0 +1,126 @@
+use std::io::IsTerminal;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Set by --wide: print full lines even on a narrow terminal
+static WIDE: AtomicBool = AtomicBool::new(false);
+
+pub fn set_wide() {
+    WIDE.store(true, Ordering::Relaxed);
+}
+
+/// Columns available for output, or None when lines shouldn't be shortened
+/// (--wide, or stdout isn't a terminal and is likely being parsed)
+pub fn width() -> Option<usize> {
+    if WIDE.load(Ordering::Relaxed) || !std::io::stdout().is_terminal() {
+        return None;
+    }
+    if let Some(columns) = std::env::var("COLUMNS")
+        .ok()
+        .and_then(|columns| columns.parse().ok())
+    {
+        return Some(columns);
+    }
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    let ok = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) } == 0;
+    (ok && size.ws_col > 0).then_some(size.ws_col as usize)
+}
+
+/// `prefix`, `text` and `suffix` on one line, with `text` cut short (ending in
+/// "…") so the line fits the terminal
+pub fn row(prefix: &str, text: &str, suffix: &str) -> String {
+    match width() {
+        Some(width) => fit_row(prefix, text, suffix, width),
+        None => format!("{}{}{}", prefix, text, suffix),
+    }
+}
+
+/// `text`, printed from column `start`, wrapped at word boundaries to the
+/// terminal, with continuation lines indented by `indent` spaces
+pub fn wrapped(text: &str, start: usize, indent: usize) -> String {
+    match width() {
+        Some(width) => wrap(text, width, start, indent).join("\n"),
+        None => text.to_string(),
+    }
+}
+
+fn fit_row(prefix: &str, text: &str, suffix: &str, width: usize) -> String {
+    let fixed = prefix.chars().count() + suffix.chars().count();
+    let room = width.saturating_sub(fixed);
+    format!("{}{}{}", prefix, truncate(text, room), suffix)
+}
+
+fn truncate(text: &str, max: usize) -> String {
+    if text.chars().count() <= max {
+        return text.to_string();
+    }
+    if max == 0 {
+        return String::new();
+    }
+    let kept: String = text.chars().take(max - 1).collect();
+    format!("{}…", kept.trim_end())
+}
+
+fn wrap(text: &str, width: usize, start: usize, indent: usize) -> Vec<String> {
+    // Keep the text's own indentation, e.g. "  Tags: ..."
+    let body = text.trim_start();
+    let mut lines = Vec::new();
+    let mut line = text[..text.len() - body.len()].to_string();
+    let mut line_width = line.chars().count();
+    let mut words_on_line = 0;
+    for word in body.split_whitespace() {
+        let word_width = word.chars().count();
+        let limit = width.saturating_sub(if lines.is_empty() { start } else { indent });
+        if words_on_line > 0 && line_width + 1 + word_width > limit {
+            lines.push(std::mem::take(&mut line));
+            line_width = 0;
+            words_on_line = 0;
+        }
+        if words_on_line > 0 {
+            line.push(' ');
+            line_width += 1;
+        }
+        // A single word longer than the line (e.g. a URL) is left whole
+        line.push_str(word);
+        line_width += word_width;
+        words_on_line += 1;
+    }
+    lines.push(line);
+    let padding = " ".repeat(indent);
+    lines
+        .into_iter()
+        .enumerate()
+        .map(|(i, line)| {
+            if i == 0 {
+                line
+            } else {
+                format!("{}{}", padding, line)
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fit_row() {
+        assert_eq!(
+            fit_row("  go - ", "The Go programming language", " (1.22)", 30),
+            "  go - The Go programm… (1.22)"
+        );
+        assert_eq!(fit_row("  go - ", "Go", " (1.22)", 30), "  go - Go (1.22)");
+    }
+
+    #[test]
+    fn test_wrap() {
+        assert_eq!(
+            wrap("Tags: cli, search, grep, rust", 16, 0, 6),
+            vec!["Tags: cli,", "      search,", "      grep, rust"]
+        );
+        assert_eq!(
+            wrap("  https://example.com/a/very/long/url", 10, 0, 2),
+            vec!["  https://example.com/a/very/long/url"]
+        );
+    }
+}
@@ -0,0 +1,157 @@
+use crate::config::LinkStrategy;
+use crate::utils::{link_target, resolve_from, shell_quote};
+use anyhow::{Result, anyhow};
+use std::fs;
+use std::io::Read;
+use std::os::unix::fs::{MetadataExt, PermissionsExt, symlink};
+use std::path::{Path, PathBuf};
+
+const WRAPPER_MARKER: &str = "# leaf wrapper:";
+
+/// Put executable `target` at `path` the way `strategy` says. `shown` is how
+/// symlinks and wrappers refer to it: `target` itself, or a path relative to
+/// `path`'s directory for roots that may move.
+pub fn expose(strategy: LinkStrategy, target: &Path, shown: &Path, path: &Path) -> Result<()> {
+    if fs::symlink_metadata(path).is_ok() {
+        fs::remove_file(path)?;
+    }
+    match strategy {
+        // Versioned names are plain links under shims too
+        LinkStrategy::Symlink | LinkStrategy::Shim => symlink(shown, path)?,
+        LinkStrategy::Hardlink => fs::hard_link(target, path).map_err(|e| {
+            anyhow!(
+                "Cannot hard link {} to {}: {}; hard links can't cross filesystems, so set \"link_strategy\" to \"copy\" instead",
+                path.display(),
+                target.display(),
+                e
+            )
+        })?,
+        LinkStrategy::Copy => {
+            fs::copy(target, path)?;
+            fs::set_permissions(path, fs::Permissions::from_mode(0o755))?;
+        }
+        LinkStrategy::Wrapper => {
+            let quoted = shell_quote(&shown.display().to_string());
+            let run = if shown.is_absolute() {
+                quoted
+            } else {
+                format!("\"$(dirname \"$0\")\"/{}", quoted)
+            };
+            let script = format!(
+                "#!/bin/sh\n{} {}\nexec {} \"$@\"\n",
+                WRAPPER_MARKER,
+                shown.display(),
+                run
+            );
+            fs::write(path, script)?;
+            fs::set_permissions(path, fs::Permissions::from_mode(0o755))?;
+        }
+    }
+    Ok(())
+}
+
+/// Where a symlink, or a wrapper script written by `expose`, at `path` leads
+pub fn target(path: &Path) -> Option<PathBuf> {
+    if let Some(target) = link_target(path) {
+        return Some(target);
+    }
+    Some(resolve_from(path, &wrapper_target(path)?))
+}
+
+fn wrapper_target(path: &Path) -> Option<PathBuf> {
+    let metadata = fs::symlink_metadata(path).ok()?;
+    if !metadata.is_file() {
+        return None;
+    }
+    // Wrappers are tiny; don't read whole binaries to find out
+    let mut head = String::new();
+    fs::File::open(path)
+        .ok()?
+        .take(4096)
+        .read_to_string(&mut head)
+        .ok()?;
+    let shown = head.lines().nth(1)?.strip_prefix(WRAPPER_MARKER)?.trim();
+    Some(PathBuf::from(shown))
+}
+
+/// Whether `path` is a hard link to `executable` or a copy of it
+pub fn is_copy_of(path: &Path, executable: &Path) -> bool {
+    let (Ok(entry), Ok(original)) = (fs::symlink_metadata(path), fs::metadata(executable)) else {
+        return false;
+    };
+    if !entry.is_file() || entry.len() != original.len() {
+        return false;
+    }
+    if entry.dev() == original.dev() && entry.ino() == original.ino() {
+        return true;
+    }
+    match (fs::read(path), fs::read(executable)) {
+        (Ok(entry), Ok(original)) => entry == original,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expose() {
+        let dir = std::env::temp_dir().join(format!("leaf-links-test-{}", std::process::id()));
+        fs::remove_dir_all(&dir).ok();
+        fs::create_dir_all(dir.join("pkg")).unwrap();
+        let executable = dir.join("pkg").join("tool");
+        fs::write(&executable, "#!/bin/sh\necho tool\n").unwrap();
+        fs::set_permissions(&executable, fs::Permissions::from_mode(0o755)).unwrap();
+        let other = dir.join("other");
+        fs::write(&other, "#!/bin/sh\necho else\n").unwrap();
+
+        for strategy in [
+            LinkStrategy::Symlink,
+            LinkStrategy::Hardlink,
+            LinkStrategy::Copy,
+            LinkStrategy::Wrapper,
+        ] {
+            let path = dir.join("tool");
+            expose(strategy, &executable, &executable, &path).unwrap();
+            let output = std::process::Command::new(&path).output().unwrap();
+            assert_eq!(output.stdout, b"tool\n", "{:?}", strategy);
+            let leads_there = target(&path).as_deref() == Some(executable.as_path());
+            match strategy {
+                LinkStrategy::Symlink | LinkStrategy::Wrapper => assert!(leads_there),
+                _ => {
+                    assert!(!leads_there);
+                    assert!(is_copy_of(&path, &executable));
+                    assert!(!is_copy_of(&path, &other));
+                }
+            }
+        }
+
+        let path = dir.join("relative");
+        expose(
+            LinkStrategy::Wrapper,
+            &executable,
+            Path::new("pkg/tool"),
+            &path,
+        )
+        .unwrap();
+        assert_eq!(target(&path), Some(dir.join("pkg/tool")));
+        let output = std::process::Command::new(&path).output().unwrap();
+        assert_eq!(output.stdout, b"tool\n");
+
+        // The shell must take the path as it is
+        let odd = dir.join("it's $HOME `x`");
+        fs::create_dir_all(&odd).unwrap();
+        fs::copy(&executable, odd.join("tool")).unwrap();
+        expose(
+            LinkStrategy::Wrapper,
+            &odd.join("tool"),
+            &odd.join("tool"),
+            &path,
+        )
+        .unwrap();
+        let output = std::process::Command::new(&path).output().unwrap();
+        assert_eq!(output.stdout, b"tool\n");
+        fs::remove_dir_all(&dir).ok();
+    }
+}
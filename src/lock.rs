@@ -0,0 +1,63 @@
+use std::fs::{self, File};
+use std::io;
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+
+/// Exclusive advisory lock on a file, shared by every leaf process working on
+/// the same thing. It is released when dropped, or when the process dies.
+pub struct FileLock {
+    _file: File,
+}
+
+/// Wait until this process holds the lock at `path`, creating the file (and
+/// its directory) if needed
+pub fn acquire(path: &Path) -> io::Result<FileLock> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let file = File::options()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path)?;
+    // SAFETY: flock only reads the descriptor, which `file` keeps open
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(FileLock { _file: file })
+}
+
+/// `acquire` for async code, waiting on a blocking thread
+pub async fn acquire_async(path: PathBuf) -> io::Result<FileLock> {
+    tokio::task::spawn_blocking(move || acquire(&path))
+        .await
+        .map_err(io::Error::other)?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    #[test]
+    fn test_lock_waits() {
+        let path = std::env::temp_dir()
+            .join(format!("leaf-lock-test-{}", std::process::id()))
+            .join("test.lock");
+        let held = acquire(&path).unwrap();
+        let (sender, receiver) = mpsc::channel();
+        let waiter = std::thread::spawn({
+            let path = path.clone();
+            move || {
+                let _lock = acquire(&path).unwrap();
+                sender.send(()).unwrap();
+            }
+        });
+        assert!(receiver.recv_timeout(Duration::from_millis(100)).is_err());
+        drop(held);
+        assert!(receiver.recv_timeout(Duration::from_secs(5)).is_ok());
+        waiter.join().unwrap();
+        fs::remove_dir_all(path.parent().unwrap()).ok();
+    }
+}
@@ -1,33 +1,280 @@
+#[macro_use]
+mod i18n;
+
+mod audit;
+mod batch;
+mod bootstrap;
+mod build_info;
+mod builds;
+mod cache;
+mod checksum;
+mod completions;
 mod config;
+mod container;
+mod cosign;
+mod diff;
+mod doctor;
+mod events;
+mod extract;
+mod fault;
+mod fonts;
+mod gc;
+mod harness;
+mod http;
 mod installer;
+mod interrupt;
+mod journal;
+mod layout;
+mod links;
+mod lock;
+mod manifest;
+mod migrate;
+mod modes;
+mod modulefile;
+mod onboarding;
+mod optimize;
+mod overlay;
+#[cfg(feature = "p2p")]
+mod p2p;
 mod package;
 mod package_manager;
+mod platform;
+mod policy;
+mod progress;
+mod prompt;
+mod relocate;
+mod report;
+mod sbom;
+mod self_update;
+mod service;
+mod shadow;
+mod shim;
+mod status;
+mod toolchain;
+mod trail;
+mod trust;
+mod usage;
 mod utils;
+mod version;
+mod wsl;
 
-use crate::package_manager::PackageManager;
-use crate::utils::{print_error, print_info};
+use crate::config::Config;
+use crate::package::BinFilter;
+use crate::package_manager::{InstallStateFilter, PackageManager};
+use crate::utils::{edit_distance, print_error, print_info, print_warning};
 use clap::{Arg, Command};
+use std::path::{Path, PathBuf};
 use std::process;
 
-#[tokio::main]
-async fn main() {
-    let matches = Command::new("leaf")
-        .version(env!("CARGO_PKG_VERSION")) // Use version from Cargo.toml
+fn fail_fast_arg() -> Arg {
+    Arg::new("fail-fast")
+        .long("fail-fast")
+        .help("Stop at the first package that fails instead of continuing")
+        .action(clap::ArgAction::SetTrue)
+}
+
+fn platform_arg() -> Arg {
+    Arg::new("platform")
+        .long("platform")
+        .value_name("PLATFORM")
+        .help("Install for another platform, e.g. linux-aarch64, to provision a prefix or container for that machine")
+}
+
+fn report_arg() -> Arg {
+    Arg::new("report-to")
+        .long("report")
+        .value_name("PATH")
+        .help("Write a JSON record of what was downloaded, installed and linked to PATH, for audit trails")
+}
+
+fn cli() -> Command {
+    Command::new("leaf")
+        .version(build_info::VERSION) // Use version from Cargo.toml
         .author("ktauchathuranga")
         .about("🍃 A simple, sudo-free package manager for Linux")
-        .subcommand_required(true)
         .arg_required_else_help(true)
+        // Handled in main so --verbose can extend it
+        .disable_version_flag(true)
+        .arg(
+            Arg::new("version")
+                .long("version")
+                .short('V')
+                .help("Print version")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("verbose")
+                .long("verbose")
+                .help("With --version, also print the build target, commit and date")
+                .requires("version")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("prefix")
+                .long("prefix")
+                .help("Use <dir>/leaf and <dir>/bin instead of ~/.local (for chroots, containers or portable installs); defaults to $LEAF_PREFIX")
+                .value_name("dir")
+                .global(true)
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("json-lines")
+                .long("json-lines")
+                .help("Emit one JSON event per line on stdout and print messages to stderr")
+                .global(true)
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("allow-root")
+                .long("allow-root")
+                .help("Allow commands that change packages to run as root")
+                .global(true)
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("inject-fault")
+                .long("inject-fault")
+                .help("Fail on purpose at the given point of an install, for testing recovery")
+                .value_name("point")
+                .value_parser(fault::POINTS.to_vec())
+                .global(true)
+                .hide(true),
+        )
+        .arg(
+            Arg::new("wide")
+                .long("wide")
+                .help("Don't shorten or wrap lines to the terminal width")
+                .global(true)
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("yes")
+                .long("yes")
+                .short('y')
+                .help("Answer yes to every confirmation prompt")
+                .global(true)
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("non-interactive")
+                .long("non-interactive")
+                .visible_alias("no-input")
+                .help("Never prompt; questions without a default fail instead")
+                .global(true)
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("system")
+                .long("system")
+                .help("Manage the shared system-wide store in /opt/leaf that every user links from")
+                .global(true)
+                .conflicts_with("prefix")
+                .action(clap::ArgAction::SetTrue),
+        )
         .subcommand(
-            Command::new("install").about("Install a package").arg(
-                Arg::new("package")
-                    .help("Package name to install")
-                    .required(true)
-                    .index(1),
-            ),
+            Command::new("install")
+                .visible_aliases(["add", "in"])
+                .about("Install one or more packages")
+                .arg(report_arg())
+                .arg(
+                    Arg::new("package")
+                        .help("Package name(s) to install")
+                        .required(true)
+                        .num_args(1..)
+                        .index(1),
+                )
+                .arg(fail_fast_arg())
+                .arg(platform_arg())
+                .arg(
+                    Arg::new("only")
+                        .long("only")
+                        .value_name("EXECUTABLES")
+                        .value_delimiter(',')
+                        .help("Link only these executables into bin, e.g. --only rg,rgx; leaf expose adds others later"),
+                )
+                .arg(
+                    Arg::new("skip-bins")
+                        .long("skip-bins")
+                        .help("Install without linking any executables into bin")
+                        .action(clap::ArgAction::SetTrue)
+                        .conflicts_with("only"),
+                )
+                .arg(
+                    Arg::new("link")
+                        .long("link")
+                        .value_name("STRATEGY")
+                        .value_parser(["symlink", "hardlink", "copy", "wrapper", "shim"])
+                        .help("How to put executables in bin for this install, overriding \"link_strategy\"; use copy or wrapper where symlinks aren't supported"),
+                ),
+        )
+        .subcommand(
+            Command::new("prefetch")
+                .about("Download packages into the cache without installing them, for offline installs later")
+                .arg(report_arg())
+                .arg(
+                    Arg::new("packages")
+                        .help("Packages to download")
+                        .required(true)
+                        .num_args(1..),
+                )
+                .arg(platform_arg()),
+        )
+        .subcommand(
+            Command::new("download")
+                .about("Download and verify a package's artifact without installing it, printing its URL, file name and hash")
+                .arg(
+                    Arg::new("package")
+                        .help("Package to download")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::new("out")
+                        .long("out")
+                        .short('o')
+                        .value_name("PATH")
+                        .help("Also copy the artifact to this file or directory"),
+                )
+                .arg(platform_arg()),
+        )
+        .subcommand(
+            Command::new("extract")
+                .about("Unpack a .tar.gz, .tar.xz, .tar.zst or .zip archive the way installs do, e.g. to repair a package by hand")
+                .arg(
+                    Arg::new("archive")
+                        .help("Archive to unpack")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::new("to")
+                        .long("to")
+                        .value_name("DIR")
+                        .default_value(".")
+                        .help("Directory to unpack into"),
+                )
+                .arg(
+                    Arg::new("strip-components")
+                        .long("strip-components")
+                        .value_name("N")
+                        .value_parser(clap::value_parser!(usize))
+                        .default_value("0")
+                        .help("Drop the first N directories of every path in the archive"),
+                ),
+        )
+        .subcommand(
+            Command::new("cache")
+                .about("Inspect the download cache")
+                .subcommand_required(true)
+                .subcommand(Command::new("verify").about(
+                    "Re-check cached downloads against their recorded hashes, deleting corrupted ones",
+                )),
         )
         .subcommand(
             Command::new("remove")
+                .visible_aliases(["rm", "uninstall"])
                 .about("Remove an installed package")
+                .arg(report_arg())
                 .arg(
                     Arg::new("package")
                         .help("Package name to remove")
@@ -35,13 +282,226 @@ async fn main() {
                         .index(1),
                 ),
         )
-        .subcommand(Command::new("list").about("List installed packages"))
+        .subcommand(
+            Command::new("list")
+                .visible_alias("ls")
+                .about("List installed packages")
+                .arg(
+                    Arg::new("tree")
+                        .long("tree")
+                        .help("Group installed packages by tag")
+                        .action(clap::ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(Command::new("categories").about("List the tags in the index with package counts"))
         .subcommand(
             Command::new("search")
                 .about("Search available packages")
-                .arg(Arg::new("term").help("Search term").required(true).index(1)),
+                .arg(Arg::new("term").help("Search term").required(true).index(1))
+                .arg(
+                    Arg::new("installed-only")
+                        .long("installed-only")
+                        .help("Only show installed packages")
+                        .action(clap::ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("not-installed")
+                        .long("not-installed")
+                        .help("Only show packages that are not installed")
+                        .action(clap::ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("outdated")
+                        .long("outdated")
+                        .help("Only show installed packages with updates available")
+                        .action(clap::ArgAction::SetTrue),
+                )
+                .group(
+                    clap::ArgGroup::new("install-state")
+                        .args(["installed-only", "not-installed", "outdated"])
+                        .multiple(false),
+                ),
+        )
+        .subcommand(
+            Command::new("info")
+                .about("Show details about a package")
+                .arg(
+                    Arg::new("package")
+                        .help("Package name or alias")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::new("caveats")
+                        .long("caveats")
+                        .help("Only print the package's post-install notes")
+                        .action(clap::ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("diff")
+                .about("Show how a package's definition changed since it was installed")
+                .arg(
+                    Arg::new("package")
+                        .help("Installed package name")
+                        .required(true)
+                        .index(1),
+                ),
+        )
+        .subcommand(
+            Command::new("edit")
+                .about("Edit a package's definition in $EDITOR, keeping it as a local override of the index")
+                .arg(
+                    Arg::new("package")
+                        .help("Package name or alias")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::new("reset")
+                        .long("reset")
+                        .help("Remove the local override and use the index's definition again")
+                        .action(clap::ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("resolve")
+                .about("Show the platform entry, URL, type and executables an install would use on this machine")
+                .arg(
+                    Arg::new("package")
+                        .help("Package name, optionally name@version")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(platform_arg()),
+        )
+        .subcommand(
+            Command::new("exec")
+                .about("Run a command with a package's executables on PATH")
+                .arg(
+                    Arg::new("package")
+                        .help("Package name or alias")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("command")
+                        .help("Command to run, after --")
+                        .required(true)
+                        .num_args(1..)
+                        .last(true),
+                ),
+        )
+        .subcommand(
+            Command::new("switch")
+                .about("Choose which installed version provides the unsuffixed executables")
+                .arg(report_arg())
+                .arg(
+                    Arg::new("package")
+                        .help("Package name")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::new("version")
+                        .help("Installed version, as given to install (e.g. 20 for node@20)")
+                        .required(true)
+                        .index(2),
+                ),
+        )
+        .subcommand(
+            Command::new("expose")
+                .about("Link an executable of an installed package into bin")
+                .arg(report_arg())
+                .arg(
+                    Arg::new("package")
+                        .help("Package name")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::new("executable")
+                        .help("Executable, as named in bin")
+                        .required(true)
+                        .index(2),
+                ),
+        )
+        .subcommand(
+            Command::new("unexpose")
+                .about("Remove an installed package's executable from bin, keeping the package")
+                .arg(report_arg())
+                .arg(
+                    Arg::new("package")
+                        .help("Package name")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::new("executable")
+                        .help("Executable, as named in bin")
+                        .required(true)
+                        .index(2),
+                ),
+        )
+        .subcommand(
+            Command::new("link")
+                .about("Expose an executable of an installed package under another name, e.g. bat as cat")
+                .arg(report_arg())
+                .arg(
+                    Arg::new("package")
+                        .help("Package name")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::new("executable")
+                        .help("Executable, by its usual name or its name in bin")
+                        .required(true)
+                        .index(2),
+                )
+                .arg(
+                    Arg::new("as")
+                        .long("as")
+                        .value_name("NAME")
+                        .help("Name to give it in bin; its usual name undoes the rename")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            Command::new("update")
+                .about("Update package definitions")
+                .arg(
+                    Arg::new("changes")
+                        .long("changes")
+                        .help("List the packages that were added, updated or removed")
+                        .action(clap::ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("trust-changed")
+                        .long("trust-changed")
+                        .value_name("REPO")
+                        .help("Accept the new fingerprint of a repo whose certificate or signing key changed")
+                        .action(clap::ArgAction::Append),
+                ),
+        )
+        .subcommand(
+            Command::new("upgrade")
+                .visible_alias("up")
+                .about("Upgrade installed packages to the versions in the index")
+                .arg(report_arg())
+                .arg(
+                    Arg::new("packages")
+                        .help("Packages to upgrade")
+                        .num_args(0..)
+                        .index(1),
+                )
+                .arg(
+                    Arg::new("all")
+                        .long("all")
+                        .help("Upgrade every outdated package")
+                        .action(clap::ArgAction::SetTrue),
+                )
+                .arg(fail_fast_arg()),
         )
-        .subcommand(Command::new("update").about("Update package definitions"))
         .subcommand(
             Command::new("nuke")
                 .about("Remove all packages and Leaf itself (DESTRUCTIVE)")
@@ -57,8 +517,9 @@ async fn main() {
                 .about("Update the leaf package manager itself")
                 .arg(
                     Arg::new("version")
-                        .long("version")
-                        .help("Update to specific version (e.g., v1.2.3 or v1.2.3-beta)")
+                        .long("to")
+                        .alias("version")
+                        .help("Update (or downgrade) to a specific version (e.g., v1.2.3 or v1.2.3-beta)")
                         .num_args(1),
                 )
                 .arg(
@@ -66,55 +527,977 @@ async fn main() {
                         .long("prerelease")
                         .help("Update to the latest prerelease version")
                         .action(clap::ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("rollback")
+                        .long("rollback")
+                        .help("Restore the version that was running before the last update")
+                        .conflicts_with_all(["version", "prerelease"])
+                        .action(clap::ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("check")
+                        .long("check")
+                        .help("Report the newest version on your channel without installing it")
+                        .conflicts_with_all(["version", "rollback"])
+                        .action(clap::ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("direnv")
+                .about("Load packages per directory with direnv")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("export")
+                        .about("Print an .envrc snippet that follows the project's .leaf-versions")
+                        .arg(
+                            Arg::new("packages")
+                                .help("Extra packages to load, e.g. go or nodejs@20")
+                                .num_args(0..),
+                        ),
+                )
+                .subcommand(
+                    Command::new("env")
+                        .about("Print the direnv commands for the current directory (used by the snippet)")
+                        .arg(Arg::new("packages").num_args(0..)),
+                ),
+        )
+        .subcommand(
+            Command::new("bootstrap")
+                .about("Set up this machine from a signed manifest of packages, repos and settings")
+                .arg(report_arg())
+                .arg(
+                    Arg::new("manifest")
+                        .help("URL or path of the manifest; its GPG signature is read from <manifest>.asc")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::new("key")
+                        .long("key")
+                        .value_name("fingerprint")
+                        .help("Fingerprint of the GPG key the manifest must be signed with")
+                        .conflicts_with("allow-unsigned"),
+                )
+                .arg(
+                    Arg::new("allow-unsigned")
+                        .long("allow-unsigned")
+                        .help("Apply the manifest without verifying its signature")
+                        .action(clap::ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("managed")
+                        .long("managed")
+                        .help("Let the manifest add package repos and set policy and the cache remote")
+                        .action(clap::ArgAction::SetTrue),
+                )
+                .arg(fail_fast_arg()),
+        )
+        .subcommand(
+            Command::new("note")
+                .about("Keep your own notes on installed packages, shown by list and info")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("add")
+                        .about("Attach a note to an installed package")
+                        .arg(
+                            Arg::new("package")
+                                .help("Installed package name")
+                                .required(true)
+                                .index(1),
+                        )
+                        .arg(
+                            Arg::new("text")
+                                .help("The note, e.g. \"pinned for scripts\"")
+                                .required(true)
+                                .index(2),
+                        ),
+                )
+                .subcommand(
+                    Command::new("remove")
+                        .about("Remove one of a package's notes, or all of them")
+                        .arg(
+                            Arg::new("package")
+                                .help("Installed package name")
+                                .required(true)
+                                .index(1),
+                        )
+                        .arg(
+                            Arg::new("number")
+                                .help("Which note, as numbered by `leaf note list`; all when left out")
+                                .value_parser(clap::value_parser!(usize))
+                                .index(2),
+                        ),
+                )
+                .subcommand(Command::new("list").about("List the notes on installed packages")),
+        )
+        .subcommand(
+            Command::new("profile")
+                .about("Install or compare named package sets from config.json")
+                .subcommand_required(true)
+                .subcommand(Command::new("list").about("List the profiles and their packages"))
+                .subcommand(
+                    Command::new("apply")
+                        .about("Install the profile's packages that are missing")
+                        .arg(
+                            Arg::new("name")
+                                .help("Profile name")
+                                .required(true)
+                                .index(1),
+                        )
+                        .arg(fail_fast_arg()),
+                )
+                .subcommand(
+                    Command::new("diff")
+                        .about("Show missing (+), outdated (~) and unlisted (-) packages")
+                        .arg(
+                            Arg::new("name")
+                                .help("Profile name")
+                                .required(true)
+                                .index(1),
+                        ),
+                ),
+        )
+        .subcommand(
+            Command::new("service")
+                .about("Manage the user services of installed packages")
+                .subcommand_required(true)
+                .subcommands(
+                    [
+                        ("enable", "Start a package's services now and at login"),
+                        ("disable", "Stop a package's services and keep them from starting"),
+                        ("status", "Show whether a package's services are running"),
+                    ]
+                    .map(|(action, about)| {
+                        Command::new(action).about(about).arg(
+                            Arg::new("package")
+                                .help("Installed package name")
+                                .required(true)
+                                .index(1),
+                        )
+                    }),
+                ),
+        )
+        .subcommand(
+            Command::new("modules")
+                .about("Regenerate Environment Modules/Lmod files for installed packages"),
+        )
+        .subcommand(
+            Command::new("containerize")
+                .about("Export installed packages as a container image build context")
+                .arg(
+                    Arg::new("packages")
+                        .help("Installed packages to include")
+                        .required(true)
+                        .num_args(1..),
+                )
+                .arg(
+                    Arg::new("tag")
+                        .long("tag")
+                        .short('t')
+                        .help("Image name and tag, e.g. mytools:latest")
+                        .required(true)
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("output")
+                        .long("output")
+                        .short('o')
+                        .help("Directory for the Dockerfile and context (default: <tag>-image)")
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("base")
+                        .long("base")
+                        .help("Base image")
+                        .default_value(container::DEFAULT_BASE),
+                )
+                .arg(
+                    Arg::new("build")
+                        .long("build")
+                        .help("Build the image with docker (or podman) after writing the context")
+                        .action(clap::ArgAction::SetTrue),
                 ),
         )
-        .get_matches();
+        .subcommand(
+            Command::new("audit")
+                .about("Check installed packages against the OSV vulnerability database"),
+        )
+        .subcommand(
+            Command::new("verify")
+                .about("Check installed files and links against their install manifests (offline)")
+                .arg(
+                    Arg::new("packages")
+                        .help("Packages to verify")
+                        .num_args(1..)
+                        .required_unless_present("all"),
+                )
+                .arg(
+                    Arg::new("all")
+                        .long("all")
+                        .help("Verify every installed package")
+                        .conflicts_with("packages")
+                        .action(clap::ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("report")
+                        .long("report")
+                        .help("Report format; json is meant for compliance tooling")
+                        .value_parser(["text", "json"])
+                        .default_value("text"),
+                )
+                .arg(
+                    Arg::new("output")
+                        .long("output")
+                        .short('o')
+                        .help("Write a JSON report to a file (implies --report json)")
+                        .num_args(1),
+                ),
+        )
+        .subcommand(
+            Command::new("sbom")
+                .about("Export a software bill of materials for installed packages")
+                .arg(
+                    Arg::new("format")
+                        .long("format")
+                        .help("SBOM format")
+                        .value_parser(["spdx", "cyclonedx"])
+                        .default_value("spdx"),
+                )
+                .arg(
+                    Arg::new("output")
+                        .long("output")
+                        .short('o')
+                        .help("Write the SBOM to a file instead of printing it")
+                        .num_args(1),
+                ),
+        )
+        .subcommand(
+            Command::new("status").about(
+                "Summarize leaf's state: version, index age, installed and outdated packages, cache size and broken links",
+            ),
+        )
+        .subcommand(
+            Command::new("stats")
+                .about("Show how often installed packages were installed and run, and which haven't been run in months")
+                .arg(
+                    Arg::new("unused-days")
+                        .long("unused-days")
+                        .help("Days without a run after which a package is suggested for removal")
+                        .value_parser(clap::value_parser!(i64).range(1..))
+                        .default_value("90"),
+                ),
+        )
+        .subcommand(
+            Command::new("gc")
+                .about("Delete broken links, package directories without metadata, and unfinished installs, builds and downloads")
+                .arg(report_arg())
+                .arg(
+                    Arg::new("dry-run")
+                        .long("dry-run")
+                        .help("List what would be deleted without deleting it")
+                        .action(clap::ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("doctor")
+                .about("Check leaf's directories for problems, such as files left owned by root")
+                .arg(
+                    Arg::new("fix")
+                        .long("fix")
+                        .help("Repair what can be repaired without sudo")
+                        .action(clap::ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("relocate")
+                .about("Move leaf to another root (it gets leaf/ and bin/, like ~/.local), or repair links after moving it yourself")
+                .arg(
+                    Arg::new("root")
+                        .help("New root directory")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            Command::new("migrate")
+                .about("Upgrade config.json and package directories written by an older leaf, after backing them up")
+                .arg(
+                    Arg::new("dry-run")
+                        .long("dry-run")
+                        .help("Show the migrations and their changes without applying them")
+                        .action(clap::ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("logs")
+                .about("Show the output of a package's latest source build")
+                .arg(
+                    Arg::new("package")
+                        .help("Package name")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::new("follow")
+                        .long("follow")
+                        .short('f')
+                        .help("Keep printing output as a running build writes it")
+                        .action(clap::ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("list")
+                        .long("list")
+                        .help("List the saved build logs instead")
+                        .action(clap::ArgAction::SetTrue)
+                        .conflicts_with("follow"),
+                ),
+        )
+        .subcommand(
+            Command::new("test-package")
+                .about("Check package definitions by installing and removing them in a throwaway root")
+                .arg(
+                    Arg::new("packages")
+                        .help("Package names")
+                        .required(true)
+                        .num_args(1..)
+                        .index(1),
+                )
+                .arg(
+                    Arg::new("index")
+                        .long("index")
+                        .help("Index to take the definitions from, e.g. ./packages.json in a checkout")
+                        .num_args(1),
+                ),
+        )
+        .subcommand(
+            Command::new("report")
+                .about("Create a redacted diagnostic report to attach to bug reports")
+                .arg(
+                    Arg::new("output")
+                        .long("output")
+                        .short('o')
+                        .help("Write the report to a file instead of printing it")
+                        .num_args(1),
+                ),
+        )
+        .subcommand(
+            Command::new("completions")
+                .about("Print a shell completion script")
+                .arg(
+                    Arg::new("shell")
+                        .help("Shell to generate completions for")
+                        .required(true)
+                        .value_parser(clap::value_parser!(clap_complete::Shell)),
+                )
+                .arg(
+                    Arg::new("dynamic")
+                        .long("dynamic")
+                        .help("Complete package names from the live index (bash, zsh and fish)")
+                        .action(clap::ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("__complete")
+                .hide(true)
+                .arg(
+                    Arg::new("kind")
+                        .required(true)
+                        .value_parser(["available", "installed"]),
+                )
+                .arg(Arg::new("word").default_value("")),
+        )
+        .subcommand(
+            Command::new("__shim")
+                .hide(true)
+                .arg(Arg::new("package").required(true))
+                .arg(Arg::new("executable").required(true))
+                .arg(
+                    Arg::new("args")
+                        .num_args(0..)
+                        .last(true)
+                        .allow_hyphen_values(true),
+                ),
+        )
+}
+
+/// The visible subcommand closest to a mistyped one, matching aliases too
+fn suggest_subcommand(cli: &Command, typed: &str) -> Option<String> {
+    let typed = typed.to_lowercase();
+    let max_distance = (typed.chars().count() / 3).max(1);
+    cli.get_subcommands()
+        .filter(|sub| !sub.is_hide_set())
+        .flat_map(|sub| {
+            std::iter::once(sub.get_name())
+                .chain(sub.get_all_aliases())
+                .map(|candidate| (edit_distance(&typed, candidate), sub.get_name()))
+        })
+        .filter(|(distance, _)| *distance <= max_distance)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, name)| name.to_string())
+}
+
+/// Whether the command changes installed packages, the index or config.json
+fn is_mutating(matches: &clap::ArgMatches) -> bool {
+    match matches.subcommand() {
+        Some(("profile", sub_matches)) => sub_matches.subcommand_name() == Some("apply"),
+        Some(("note", sub_matches)) => sub_matches.subcommand_name() != Some("list"),
+        Some(("service", sub_matches)) => sub_matches.subcommand_name() != Some("status"),
+        Some(("doctor", sub_matches)) => sub_matches.get_flag("fix"),
+        Some((name, _)) => matches!(
+            name,
+            "install"
+                | "prefetch"
+                | "download"
+                | "edit"
+                | "cache"
+                | "remove"
+                | "exec"
+                | "switch"
+                | "expose"
+                | "unexpose"
+                | "link"
+                | "update"
+                | "upgrade"
+                | "nuke"
+                | "self-update"
+                | "modules"
+                | "bootstrap"
+                | "relocate"
+                | "migrate"
+                | "gc"
+                | "test-package"
+                | "containerize"
+        ),
+        None => false,
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let matches = cli().try_get_matches().unwrap_or_else(|e| {
+        if e.kind() == clap::error::ErrorKind::InvalidSubcommand
+            && let Some(clap::error::ContextValue::String(typed)) =
+                e.get(clap::error::ContextKind::InvalidSubcommand)
+            && let Some(suggestion) = suggest_subcommand(&cli(), typed)
+        {
+            print_error(&tr!("command.did_you_mean", typed, suggestion));
+            process::exit(2);
+        }
+        e.exit()
+    });
+
+    if matches.get_flag("version") {
+        if matches.get_flag("verbose") {
+            println!("{}", build_info::verbose());
+        } else {
+            println!("leaf {}", build_info::VERSION);
+        }
+        return;
+    }
+    if matches.subcommand().is_none() {
+        cli()
+            .error(
+                clap::error::ErrorKind::MissingSubcommand,
+                "a subcommand is required but one was not provided",
+            )
+            .exit();
+    }
+
+    // Only --system and --prefix pick an alternate root; LEAF_PREFIX and the
+    // root chosen during setup just move the user's own one
+    let prefix = if matches.get_flag("system") {
+        Some(PathBuf::from(config::SYSTEM_ROOT))
+    } else {
+        matches.get_one::<String>("prefix").map(PathBuf::from)
+    };
+    let mut default_root = onboarding::default_root();
+
+    // Shims run on every tool invocation, so skip the index and all output
+    if let Some(("__shim", sub_matches)) = matches.subcommand() {
+        let package = sub_matches.get_one::<String>("package").unwrap();
+        let executable = sub_matches.get_one::<String>("executable").unwrap();
+        let args: Vec<String> = sub_matches
+            .get_many::<String>("args")
+            .map(|values| values.cloned().collect())
+            .unwrap_or_default();
+        let config = Config::load_or_create(prefix.as_deref(), default_root.as_deref()).await;
+        let result = match config {
+            Ok(config) => shim::run(&config, package, executable, &args).await,
+            Err(e) => Err(e),
+        };
+        let Err(e) = result;
+        eprintln!("leaf: {}", e);
+        process::exit(127);
+    }
+
+    // Follow LANG until the config has been read and can override it
+    i18n::set_language(None);
+    if let Some(("completions", sub_matches)) = matches.subcommand() {
+        let shell = *sub_matches
+            .get_one::<clap_complete::Shell>("shell")
+            .unwrap();
+        if let Err(e) = completions::print(&mut cli(), shell, sub_matches.get_flag("dynamic")) {
+            print_error(&tr!("command.failed", e));
+            process::exit(1);
+        }
+        return;
+    }
+
+    if let Some(point) = matches.get_one::<String>("inject-fault") {
+        fault::set(point);
+    }
+
+    if matches.get_flag("json-lines")
+        && let Err(e) = events::enable()
+    {
+        print_error(&tr!("command.failed", e));
+        process::exit(1);
+    }
 
-    print_info("Initializing Leaf package manager...");
+    // direnv, shell completion, SBOM and JSON report consumers read our stdout,
+    // so keep it free of status messages
+    let quiet = matches!(
+        matches.subcommand_name(),
+        Some("direnv" | "__complete" | "sbom")
+    ) || matches.subcommand_matches("verify").is_some_and(|m| {
+        m.get_one::<String>("report").map(String::as_str) == Some("json")
+            && !m.contains_id("output")
+    });
 
-    let mut pm = match PackageManager::new().await {
+    if matches.get_flag("yes") {
+        prompt::set_mode(prompt::Mode::AssumeYes);
+    } else if matches.get_flag("non-interactive") {
+        prompt::set_mode(prompt::Mode::NoInput);
+    }
+
+    // Another machine's builds don't belong in the root this machine runs from
+    if let Some(("install", sub_matches)) = matches.subcommand()
+        && sub_matches.get_one::<String>("platform").is_some()
+        && prefix.is_none()
+    {
+        print_error(&tr!("install.platform_needs_prefix"));
+        process::exit(1);
+    }
+
+    // Under sudo, files in the user's ~/.local end up owned by root and later
+    // runs without sudo fail. --system and --prefix target other roots on purpose.
+    if unsafe { libc::geteuid() } == 0 && prefix.is_none() {
+        if is_mutating(&matches) && !matches.get_flag("allow-root") {
+            print_error(&tr!("root.refused"));
+            process::exit(1);
+        }
+        if !quiet {
+            print_warning(&tr!("root.warning"));
+        }
+    }
+
+    // Someone running leaf for the first time gets a short setup rather than
+    // directories appearing under ~/.local unannounced
+    let mut fetch_index = true;
+    if !quiet
+        && prefix.is_none()
+        && default_root.is_none()
+        && config::portable_root().is_none()
+        && prompt::mode() == prompt::Mode::Ask
+        && onboarding::first_run()
+    {
+        match onboarding::run().await {
+            Ok(setup) => {
+                default_root = setup.prefix;
+                fetch_index = setup.sync_index;
+                if !fetch_index {
+                    print_info(&tr!("onboarding.sync_later"));
+                }
+            }
+            Err(e) => {
+                print_error(&tr!("command.failed", e));
+                process::exit(1);
+            }
+        }
+    }
+
+    if !quiet {
+        print_info(&tr!("init.start"));
+    }
+
+    let init = PackageManager::new(prefix.as_deref(), default_root.as_deref(), fetch_index).await;
+    let mut pm = match init {
         Ok(pm) => {
-            print_info("Package manager initialized successfully");
+            if !quiet {
+                print_info(&tr!("init.done"));
+            }
             pm
         }
         Err(e) => {
-            print_error(&format!("Failed to initialize package manager: {}", e));
+            print_error(&tr!("init.failed", e));
+            events::emit("error", serde_json::json!({"message": e.to_string()}));
             process::exit(1);
         }
     };
 
-    let result = match matches.subcommand() {
-        Some(("install", sub_matches)) => {
-            let package = sub_matches.get_one::<String>("package").unwrap();
-            pm.install_package(package).await
-        }
-        Some(("remove", sub_matches)) => {
-            let package = sub_matches.get_one::<String>("package").unwrap();
-            pm.remove_package(package).await
+    pm.set_own_root(prefix.is_none());
+    if matches.get_flag("wide") {
+        layout::set_wide();
+    }
+    if !quiet {
+        pm.check_wsl_paths();
+    }
+
+    if let Some((_, sub_matches)) = matches.subcommand()
+        && let Ok(Some(platform)) = sub_matches.try_get_one::<String>("platform")
+        && let Err(e) = pm.set_platform(platform).await
+    {
+        print_error(&tr!("command.failed", e));
+        process::exit(1);
+    }
+
+    // Changing state laid out by another release could corrupt it, so only
+    // migrate and self-update may write until the layout matches
+    if let Err(e) = pm.check_layout() {
+        let exempt = matches!(matches.subcommand_name(), Some("migrate" | "self-update"));
+        if is_mutating(&matches) && !exempt {
+            print_error(&tr!("command.failed", e));
+            process::exit(1);
         }
-        Some(("list", _)) => pm.list_packages().await,
-        Some(("search", sub_matches)) => {
-            let term = sub_matches.get_one::<String>("term").unwrap();
-            pm.search_packages(term).await
+        if !quiet && !exempt {
+            print_warning(&e.to_string());
         }
-        Some(("update", _)) => pm.update_packages().await,
-        Some(("nuke", sub_matches)) => {
-            let confirmed = sub_matches.get_flag("confirmed");
-            pm.nuke_everything(confirmed).await
+    }
+
+    let report = matches
+        .subcommand()
+        .and_then(|(_, sub_matches)| sub_matches.try_get_one::<String>("report-to").ok()?)
+        .map(|path| (path, pm.snapshot()));
+
+    let command = async {
+        match matches.subcommand() {
+            Some(("install", sub_matches)) => {
+                let packages: Vec<String> = sub_matches
+                    .get_many::<String>("package")
+                    .unwrap()
+                    .cloned()
+                    .collect();
+                let fail_fast = sub_matches.get_flag("fail-fast");
+                if let Some(only) = sub_matches.get_many::<String>("only") {
+                    if packages.len() > 1 {
+                        return Err(anyhow::anyhow!("--only applies to a single package"));
+                    }
+                    pm.set_bin_filter(BinFilter::Only(only.cloned().collect()));
+                } else if sub_matches.get_flag("skip-bins") {
+                    pm.set_bin_filter(BinFilter::Only(Vec::new()));
+                }
+                if let Some(strategy) = sub_matches.get_one::<String>("link") {
+                    pm.set_link_strategy(strategy.parse()?);
+                }
+                pm.install_packages(&packages, fail_fast).await
+            }
+            Some(("prefetch", sub_matches)) => {
+                let packages: Vec<String> = sub_matches
+                    .get_many::<String>("packages")
+                    .unwrap()
+                    .cloned()
+                    .collect();
+                pm.prefetch_packages(&packages).await
+            }
+            Some(("download", sub_matches)) => {
+                let package = sub_matches.get_one::<String>("package").unwrap();
+                let out = sub_matches.get_one::<String>("out").map(Path::new);
+                pm.download_package(package, out).await
+            }
+            Some(("resolve", sub_matches)) => {
+                let package = sub_matches.get_one::<String>("package").unwrap();
+                pm.resolve_package(package)
+            }
+            Some(("extract", sub_matches)) => {
+                let archive = sub_matches.get_one::<String>("archive").unwrap();
+                let to = sub_matches.get_one::<String>("to").unwrap();
+                let strip = *sub_matches.get_one::<usize>("strip-components").unwrap();
+                pm.extract_archive(Path::new(archive), Path::new(to), strip)
+                    .await
+            }
+            Some(("cache", _)) => pm.verify_cache().await,
+            Some(("remove", sub_matches)) => {
+                let package = sub_matches.get_one::<String>("package").unwrap();
+                pm.remove_package(package).await
+            }
+            Some(("list", sub_matches)) => pm.list_packages(sub_matches.get_flag("tree")).await,
+            Some(("categories", _)) => pm.list_categories().await,
+            Some(("search", sub_matches)) => {
+                let term = sub_matches.get_one::<String>("term").unwrap();
+                let state = if sub_matches.get_flag("installed-only") {
+                    InstallStateFilter::Installed
+                } else if sub_matches.get_flag("not-installed") {
+                    InstallStateFilter::NotInstalled
+                } else if sub_matches.get_flag("outdated") {
+                    InstallStateFilter::Outdated
+                } else {
+                    InstallStateFilter::Any
+                };
+                pm.search_packages(term, state).await
+            }
+            Some(("info", sub_matches)) => {
+                let package = sub_matches.get_one::<String>("package").unwrap();
+                pm.show_package_info(package, sub_matches.get_flag("caveats"))
+                    .await
+            }
+            Some(("edit", sub_matches)) => {
+                let package = sub_matches.get_one::<String>("package").unwrap();
+                pm.edit_package(package, sub_matches.get_flag("reset"))
+                    .await
+            }
+            Some(("diff", sub_matches)) => {
+                let package = sub_matches.get_one::<String>("package").unwrap();
+                pm.diff_package(package)
+            }
+            Some(("exec", sub_matches)) => {
+                let package = sub_matches.get_one::<String>("package").unwrap();
+                let command: Vec<String> = sub_matches
+                    .get_many::<String>("command")
+                    .unwrap()
+                    .cloned()
+                    .collect();
+                match pm.exec_package(package, &command).await {
+                    Ok(code) => process::exit(code),
+                    Err(e) => Err(e),
+                }
+            }
+            Some(("expose", sub_matches)) => {
+                let package = sub_matches.get_one::<String>("package").unwrap();
+                let executable = sub_matches.get_one::<String>("executable").unwrap();
+                pm.expose_executable(package, executable).await
+            }
+            Some(("unexpose", sub_matches)) => {
+                let package = sub_matches.get_one::<String>("package").unwrap();
+                let executable = sub_matches.get_one::<String>("executable").unwrap();
+                pm.unexpose_executable(package, executable).await
+            }
+            Some(("link", sub_matches)) => {
+                let package = sub_matches.get_one::<String>("package").unwrap();
+                let executable = sub_matches.get_one::<String>("executable").unwrap();
+                let name = sub_matches.get_one::<String>("as").unwrap();
+                pm.rename_executable(package, executable, name).await
+            }
+            Some(("switch", sub_matches)) => {
+                let package = sub_matches.get_one::<String>("package").unwrap();
+                let version = sub_matches.get_one::<String>("version").unwrap();
+                pm.switch_version(package, version).await
+            }
+            Some(("update", sub_matches)) => {
+                let repin: Vec<String> = sub_matches
+                    .get_many::<String>("trust-changed")
+                    .unwrap_or_default()
+                    .cloned()
+                    .collect();
+                pm.update_packages(sub_matches.get_flag("changes"), &repin)
+                    .await
+            }
+            Some(("upgrade", sub_matches)) => {
+                let packages: Vec<String> = sub_matches
+                    .get_many::<String>("packages")
+                    .map(|values| values.cloned().collect())
+                    .unwrap_or_default();
+                let all = sub_matches.get_flag("all");
+                let fail_fast = sub_matches.get_flag("fail-fast");
+                pm.upgrade_packages(&packages, all, fail_fast).await
+            }
+            Some(("nuke", sub_matches)) => {
+                let confirmed = sub_matches.get_flag("confirmed");
+                pm.nuke_everything(confirmed).await
+            }
+            Some(("self-update", sub_matches)) => {
+                let version = sub_matches.get_one::<String>("version").map(|s| s.as_str());
+                let prerelease = sub_matches.get_flag("prerelease");
+                let rollback = sub_matches.get_flag("rollback");
+                let check = sub_matches.get_flag("check");
+                pm.self_update(version, prerelease, rollback, check).await
+            }
+            Some(("direnv", sub_matches)) => {
+                let (action, action_matches) = sub_matches.subcommand().unwrap();
+                let packages: Vec<String> = action_matches
+                    .get_many::<String>("packages")
+                    .map(|values| values.cloned().collect())
+                    .unwrap_or_default();
+                if action == "export" {
+                    pm.direnv_snippet(&packages);
+                    Ok(())
+                } else {
+                    pm.direnv_env(&packages)
+                }
+            }
+            Some(("__complete", sub_matches)) => {
+                let kind = sub_matches.get_one::<String>("kind").unwrap();
+                let word = sub_matches.get_one::<String>("word").unwrap();
+                pm.complete(kind, word);
+                Ok(())
+            }
+            Some(("bootstrap", sub_matches)) => {
+                let manifest = sub_matches.get_one::<String>("manifest").unwrap();
+                pm.bootstrap(
+                    manifest,
+                    sub_matches.get_one::<String>("key").map(|s| s.as_str()),
+                    sub_matches.get_flag("allow-unsigned"),
+                    sub_matches.get_flag("managed"),
+                    sub_matches.get_flag("fail-fast"),
+                )
+                .await
+            }
+            Some(("note", sub_matches)) => match sub_matches.subcommand() {
+                Some(("add", action_matches)) => {
+                    let package = action_matches.get_one::<String>("package").unwrap();
+                    let text = action_matches.get_one::<String>("text").unwrap();
+                    pm.add_note(package, text)
+                }
+                Some(("remove", action_matches)) => {
+                    let package = action_matches.get_one::<String>("package").unwrap();
+                    let number = action_matches.get_one::<usize>("number").copied();
+                    pm.remove_note(package, number)
+                }
+                _ => pm.list_notes(),
+            },
+            Some(("profile", sub_matches)) => match sub_matches.subcommand() {
+                Some(("apply", action_matches)) => {
+                    let name = action_matches.get_one::<String>("name").unwrap();
+                    pm.apply_profile(name, action_matches.get_flag("fail-fast"))
+                        .await
+                }
+                Some(("diff", action_matches)) => {
+                    pm.diff_profile(action_matches.get_one::<String>("name").unwrap())
+                }
+                _ => {
+                    pm.list_profiles();
+                    Ok(())
+                }
+            },
+            Some(("service", sub_matches)) => {
+                let (action, action_matches) = sub_matches.subcommand().unwrap();
+                let package = action_matches.get_one::<String>("package").unwrap();
+                pm.service(action, package).await
+            }
+            Some(("modules", _)) => pm.generate_modules().await,
+            Some(("containerize", sub_matches)) => {
+                let packages: Vec<String> = sub_matches
+                    .get_many::<String>("packages")
+                    .unwrap()
+                    .cloned()
+                    .collect();
+                let tag = sub_matches.get_one::<String>("tag").unwrap();
+                let output = sub_matches.get_one::<String>("output").map(Path::new);
+                let base = sub_matches.get_one::<String>("base").unwrap();
+                let build = sub_matches.get_flag("build");
+                pm.containerize(&packages, tag, output, base, build).await
+            }
+            Some(("audit", _)) => pm.audit().await,
+            Some(("verify", sub_matches)) => {
+                let packages: Vec<String> = sub_matches
+                    .get_many::<String>("packages")
+                    .unwrap_or_default()
+                    .cloned()
+                    .collect();
+                let output = sub_matches.get_one::<String>("output").map(Path::new);
+                let json =
+                    output.is_some() || sub_matches.get_one::<String>("report").unwrap() == "json";
+                pm.verify(&packages, json, output).await
+            }
+            Some(("sbom", sub_matches)) => {
+                let format = sub_matches.get_one::<String>("format").unwrap();
+                let output = sub_matches.get_one::<String>("output").map(Path::new);
+                match format.parse() {
+                    Ok(format) => pm.export_sbom(format, output).await,
+                    Err(e) => Err(e),
+                }
+            }
+            Some(("status", _)) => pm.show_status().await,
+            Some(("stats", sub_matches)) => {
+                pm.show_stats(*sub_matches.get_one::<i64>("unused-days").unwrap())
+            }
+            Some(("gc", sub_matches)) => pm.collect_garbage(sub_matches.get_flag("dry-run")).await,
+            Some(("doctor", sub_matches)) => pm.doctor(sub_matches.get_flag("fix")).await,
+            Some(("relocate", sub_matches)) => {
+                let root = sub_matches.get_one::<String>("root").unwrap();
+                pm.relocate(Path::new(root)).await
+            }
+            Some(("migrate", sub_matches)) => pm.migrate(sub_matches.get_flag("dry-run")).await,
+            Some(("logs", sub_matches)) => {
+                let package = sub_matches.get_one::<String>("package").unwrap();
+                pm.show_logs(
+                    package,
+                    sub_matches.get_flag("follow"),
+                    sub_matches.get_flag("list"),
+                )
+                .await
+            }
+            Some(("test-package", sub_matches)) => {
+                let packages: Vec<String> = sub_matches
+                    .get_many::<String>("packages")
+                    .unwrap()
+                    .cloned()
+                    .collect();
+                let index = sub_matches.get_one::<String>("index").map(Path::new);
+                pm.test_packages(&packages, index).await
+            }
+            Some(("report", sub_matches)) => {
+                let output = sub_matches.get_one::<String>("output").map(Path::new);
+                pm.create_report(output).await
+            }
+            _ => {
+                print_error(&tr!("command.unknown"));
+                Ok(())
+            }
         }
-        Some(("self-update", sub_matches)) => {
-            let version = sub_matches.get_one::<String>("version").map(|s| s.as_str());
-            let prerelease = sub_matches.get_flag("prerelease");
-            pm.self_update(version, prerelease).await
+    };
+    // Ctrl-C drops the command, cancelling its downloads and builds; exec
+    // leaves it to the program it runs
+    let result = if matches.subcommand_name() == Some("exec") {
+        Some(command.await)
+    } else {
+        tokio::select! {
+            result = command => Some(result),
+            _ = tokio::signal::ctrl_c() => None,
         }
-        _ => {
-            print_error("Unknown command received");
-            Ok(())
+    };
+    let Some(result) = result else {
+        eprintln!();
+        // Read-only commands (like `logs --follow`) leave nothing behind, and
+        // cleaning up could remove another leaf's downloads in progress
+        if is_mutating(&matches) {
+            print_warning(&tr!("interrupt.cleaning"));
+            let removed = pm.clean_up_interrupted();
+            if removed > 0 {
+                print_info(&tr!("interrupt.removed", removed));
+            }
         }
+        let command_line = std::env::args().skip(1).collect::<Vec<_>>().join(" ");
+        pm.record_operation(&command_line, &Err(anyhow::anyhow!("interrupted")));
+        events::emit(
+            "error",
+            serde_json::json!({"command": matches.subcommand_name(), "message": "interrupted"}),
+        );
+        process::exit(interrupt::EXIT_CODE);
     };
 
+    if !matches!(
+        matches.subcommand_name(),
+        Some("report" | "direnv" | "__complete")
+    ) {
+        let command_line = std::env::args().skip(1).collect::<Vec<_>>().join(" ");
+        pm.record_operation(&command_line, &result);
+    }
+
+    if let Some((path, before)) = report {
+        let command_line = std::env::args().skip(1).collect::<Vec<_>>().join(" ");
+        if let Err(e) = pm.write_report(Path::new(path), &before, &command_line, &result) {
+            print_warning(&tr!("trail.failed", e));
+        }
+    }
+
+    let command = matches.subcommand_name().unwrap_or_default();
     if let Err(e) = result {
-        print_error(&format!("Command execution failed: {}", e));
+        print_error(&tr!("command.failed", e));
+        events::emit(
+            "error",
+            serde_json::json!({"command": command, "message": e.to_string()}),
+        );
         process::exit(1);
     }
+    events::emit("done", serde_json::json!({"command": command}));
 }
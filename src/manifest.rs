@@ -0,0 +1,284 @@
+use crate::checksum::sha256_file;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+/// Written next to leaf-package.json after an install so `leaf verify` can
+/// detect later changes without network access
+pub const MANIFEST_FILE: &str = "leaf-manifest.json";
+
+/// Files written by leaf itself, which are never part of the manifest
+const LEAF_FILES: [&str; 2] = ["leaf-package.json", MANIFEST_FILE];
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Manifest {
+    /// Regular files by path relative to the package directory
+    pub files: BTreeMap<String, FileEntry>,
+    /// Symlinks inside the package and their targets
+    #[serde(default)]
+    pub symlinks: BTreeMap<String, String>,
+    /// Extra files placed outside the package directory, removed with it
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exposed: Vec<ExposedFile>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExposedFile {
+    /// Absolute path of the link or copy
+    pub path: String,
+    /// Hash of a copied file, so removal can leave it alone once edited; links have none
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FileEntry {
+    pub sha256: String,
+    /// Hash as extracted, for files leaf changed on purpose (e.g. stripped binaries)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub original_sha256: Option<String>,
+}
+
+/// A difference between a package directory and its manifest
+#[derive(Debug, Clone, Serialize)]
+pub struct Issue {
+    pub path: String,
+    pub problem: Problem,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Problem {
+    /// No manifest, e.g. installed by an older leaf
+    NoManifest,
+    Modified,
+    Missing,
+    /// Present on disk but not in the manifest
+    Unexpected,
+    /// Symlink inside the package now points somewhere else
+    Retargeted,
+    /// Link in bin_dir whose target no longer exists
+    Dangling,
+    /// Executable that should be linked into bin_dir but isn't
+    Unlinked,
+}
+
+impl std::fmt::Display for Problem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let text = match self {
+            Problem::NoManifest => "no manifest",
+            Problem::Modified => "modified",
+            Problem::Missing => "missing",
+            Problem::Unexpected => "unexpected",
+            Problem::Retargeted => "retargeted",
+            Problem::Dangling => "dangling",
+            Problem::Unlinked => "unlinked",
+        };
+        f.write_str(text)
+    }
+}
+
+/// Hash everything in `package_dir` and write the manifest. `originals` holds the
+/// pre-optimization hashes of files changed after extraction.
+pub fn record(package_dir: &Path, originals: &BTreeMap<String, String>) -> Result<()> {
+    let mut manifest = scan(package_dir)?;
+    for (path, original) in originals {
+        if let Some(entry) = manifest.files.get_mut(path) {
+            entry.original_sha256 = Some(original.clone());
+        }
+    }
+    fs::write(
+        package_dir.join(MANIFEST_FILE),
+        serde_json::to_string_pretty(&manifest)?,
+    )?;
+    Ok(())
+}
+
+pub fn load(package_dir: &Path) -> Option<Manifest> {
+    let content = fs::read_to_string(package_dir.join(MANIFEST_FILE)).ok()?;
+    serde_json::from_str(&content).ok()
+}
+
+/// Record the extra files an install exposed outside `package_dir`
+pub fn set_exposed(package_dir: &Path, exposed: Vec<ExposedFile>) -> Result<()> {
+    let mut manifest = load(package_dir).unwrap_or_default();
+    manifest.exposed = exposed;
+    fs::write(
+        package_dir.join(MANIFEST_FILE),
+        serde_json::to_string_pretty(&manifest)?,
+    )?;
+    Ok(())
+}
+
+/// Extra files recorded for `package_dir`, if it has a manifest
+pub fn exposed(package_dir: &Path) -> Option<Vec<ExposedFile>> {
+    load(package_dir).map(|manifest| manifest.exposed)
+}
+
+/// Compare `package_dir` against its manifest
+pub fn check(package_dir: &Path) -> Result<Vec<Issue>> {
+    let Ok(content) = fs::read_to_string(package_dir.join(MANIFEST_FILE)) else {
+        return Ok(vec![Issue {
+            path: MANIFEST_FILE.to_string(),
+            problem: Problem::NoManifest,
+        }]);
+    };
+    let expected: Manifest = serde_json::from_str(&content)?;
+    let actual = scan(package_dir)?;
+    let mut issues = compare(&expected, &actual);
+    // Copies are the user's to edit, but links should still resolve
+    for file in expected.exposed.iter().filter(|file| file.sha256.is_none()) {
+        let path = Path::new(&file.path);
+        let problem = match fs::symlink_metadata(path) {
+            Err(_) => Problem::Missing,
+            Ok(_) if !path.exists() => Problem::Dangling,
+            Ok(_) => continue,
+        };
+        issues.push(Issue {
+            path: file.path.clone(),
+            problem,
+        });
+    }
+    Ok(issues)
+}
+
+fn compare(expected: &Manifest, actual: &Manifest) -> Vec<Issue> {
+    let mut issues = Vec::new();
+    let mut issue = |path: &String, problem| {
+        issues.push(Issue {
+            path: path.clone(),
+            problem,
+        })
+    };
+
+    for (path, entry) in &expected.files {
+        match actual.files.get(path) {
+            None => issue(path, Problem::Missing),
+            // A file back in its original form still came from the package
+            Some(found)
+                if found.sha256 != entry.sha256
+                    && Some(&found.sha256) != entry.original_sha256.as_ref() =>
+            {
+                issue(path, Problem::Modified)
+            }
+            Some(_) => {}
+        }
+    }
+    for (path, target) in &expected.symlinks {
+        match actual.symlinks.get(path) {
+            None => issue(path, Problem::Missing),
+            Some(found) if found != target => issue(path, Problem::Retargeted),
+            Some(_) => {}
+        }
+    }
+    for path in actual.files.keys().chain(actual.symlinks.keys()) {
+        if !expected.files.contains_key(path) && !expected.symlinks.contains_key(path) {
+            issue(path, Problem::Unexpected);
+        }
+    }
+    issues.sort_by(|a, b| a.path.cmp(&b.path));
+    issues
+}
+
+fn scan(package_dir: &Path) -> Result<Manifest> {
+    let mut manifest = Manifest::default();
+    scan_dir(package_dir, package_dir, &mut manifest)?;
+    for name in LEAF_FILES {
+        manifest.files.remove(name);
+    }
+    Ok(manifest)
+}
+
+fn scan_dir(root: &Path, dir: &Path, manifest: &mut Manifest) -> Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        let relative = path
+            .strip_prefix(root)?
+            .to_string_lossy()
+            .replace('\\', "/");
+        let file_type = entry.file_type()?;
+        if file_type.is_symlink() {
+            let target = fs::read_link(&path)?;
+            manifest
+                .symlinks
+                .insert(relative, target.to_string_lossy().to_string());
+        } else if file_type.is_dir() {
+            scan_dir(root, &path, manifest)?;
+        } else {
+            let sha256 = sha256_file(&path)?;
+            manifest.files.insert(
+                relative,
+                FileEntry {
+                    sha256,
+                    original_sha256: None,
+                },
+            );
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn manifest(files: &[(&str, &str)], symlinks: &[(&str, &str)]) -> Manifest {
+        Manifest {
+            files: files
+                .iter()
+                .map(|(path, sha256)| {
+                    (
+                        path.to_string(),
+                        FileEntry {
+                            sha256: sha256.to_string(),
+                            original_sha256: None,
+                        },
+                    )
+                })
+                .collect(),
+            symlinks: symlinks
+                .iter()
+                .map(|(path, target)| (path.to_string(), target.to_string()))
+                .collect(),
+            exposed: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_compare() {
+        let expected = manifest(
+            &[("bin/go", "aa"), ("lib/a", "bb"), ("lib/b", "cc")],
+            &[("lib/c", "a")],
+        );
+        let actual = manifest(
+            &[("bin/go", "aa"), ("lib/a", "ff"), ("lib/d", "dd")],
+            &[("lib/c", "b")],
+        );
+        let issues: Vec<_> = compare(&expected, &actual)
+            .into_iter()
+            .map(|issue| (issue.path, issue.problem))
+            .collect();
+        assert_eq!(
+            issues,
+            vec![
+                ("lib/a".to_string(), Problem::Modified),
+                ("lib/b".to_string(), Problem::Missing),
+                ("lib/c".to_string(), Problem::Retargeted),
+                ("lib/d".to_string(), Problem::Unexpected),
+            ]
+        );
+        assert!(compare(&expected, &expected).is_empty());
+
+        // Stripped binaries verify in either form
+        let mut stripped = manifest(&[("bin/go", "aa")], &[]);
+        stripped.files.get_mut("bin/go").unwrap().original_sha256 = Some("ee".to_string());
+        assert!(compare(&stripped, &manifest(&[("bin/go", "ee")], &[])).is_empty());
+        assert_eq!(
+            compare(&stripped, &manifest(&[("bin/go", "ff")], &[]))[0].problem,
+            Problem::Modified
+        );
+    }
+}
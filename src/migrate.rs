@@ -0,0 +1,152 @@
+use crate::config::Config;
+use crate::manifest::{self, MANIFEST_FILE};
+use anyhow::{Result, anyhow};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Version of the on-disk layout (config.json, package directories and the
+/// files leaf keeps in them) this release reads and writes. Bump it together
+/// with a new entry in MIGRATIONS whenever that layout changes.
+pub const LAYOUT_VERSION: u32 = 1;
+
+/// Files that migrations may rewrite, relative to install_dir and to each
+/// package directory; `leaf migrate` backs these up first
+const ROOT_FILES: [&str; 2] = ["config.json", "packages.json"];
+const PACKAGE_FILES: [&str; 2] = ["leaf-package.json", MANIFEST_FILE];
+
+/// The step from one layout version to the next
+pub struct Migration {
+    pub to: u32,
+    pub description: &'static str,
+    plan: fn(&Config) -> Result<Vec<Action>>,
+}
+
+/// One change a migration makes, listed by `leaf migrate --dry-run`
+#[derive(Debug)]
+pub enum Action {
+    /// Hash a package installed before leaf kept manifests, so `leaf verify`
+    /// has something to compare against
+    RecordManifest(PathBuf),
+}
+
+impl std::fmt::Display for Action {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Action::RecordManifest(dir) => write!(f, "record a manifest for {}", dir.display()),
+        }
+    }
+}
+
+const MIGRATIONS: [Migration; 1] = [Migration {
+    to: 1,
+    description: "Record manifests for packages installed by older releases",
+    plan: plan_manifests,
+}];
+
+fn plan_manifests(config: &Config) -> Result<Vec<Action>> {
+    Ok(package_dirs(&config.packages_dir)?
+        .into_iter()
+        .filter(|dir| !dir.join(MANIFEST_FILE).exists())
+        .map(Action::RecordManifest)
+        .collect())
+}
+
+/// Package directories, skipping links into the shared store, which belongs
+/// to whoever runs `leaf --system`
+fn package_dirs(packages_dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut dirs = Vec::new();
+    for entry in fs::read_dir(packages_dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() && entry.path().join("leaf-package.json").exists() {
+            dirs.push(entry.path());
+        }
+    }
+    dirs.sort();
+    Ok(dirs)
+}
+
+/// Migrations needed to bring a `from` layout up to this release's
+pub fn pending(from: u32) -> Vec<&'static Migration> {
+    MIGRATIONS
+        .iter()
+        .filter(|migration| migration.to > from)
+        .collect()
+}
+
+/// Fail unless `version` is the layout this release uses
+pub fn check(version: u32) -> Result<()> {
+    if version > LAYOUT_VERSION {
+        return Err(anyhow!(
+            "This leaf directory uses layout version {}, written by a newer leaf (this one knows up to {}); run leaf self-update",
+            version,
+            LAYOUT_VERSION
+        ));
+    }
+    if version < LAYOUT_VERSION {
+        return Err(anyhow!(
+            "This leaf directory uses layout version {} and needs migrating to {}; run leaf migrate (or leaf migrate --dry-run to see what changes)",
+            version,
+            LAYOUT_VERSION
+        ));
+    }
+    Ok(())
+}
+
+impl Migration {
+    pub fn plan(&self, config: &Config) -> Result<Vec<Action>> {
+        (self.plan)(config)
+    }
+}
+
+pub fn apply(action: &Action) -> Result<()> {
+    match action {
+        Action::RecordManifest(dir) => manifest::record(dir, &BTreeMap::new()),
+    }
+}
+
+/// Copy the files migrations can change into `dest`, keeping their layout
+/// relative to install_dir
+pub fn backup(install_dir: &Path, packages_dir: &Path, dest: &Path) -> Result<()> {
+    fs::create_dir_all(dest)?;
+    for name in ROOT_FILES {
+        let path = install_dir.join(name);
+        if path.exists() {
+            fs::copy(&path, dest.join(name))?;
+        }
+    }
+    for dir in package_dirs(packages_dir)? {
+        let Some(package) = dir.file_name() else {
+            continue;
+        };
+        let package_dest = dest.join("packages").join(package);
+        fs::create_dir_all(&package_dest)?;
+        for name in PACKAGE_FILES {
+            let path = dir.join(name);
+            if path.exists() {
+                fs::copy(&path, package_dest.join(name))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pending_and_check() {
+        assert_eq!(pending(0).len(), MIGRATIONS.len());
+        assert!(pending(LAYOUT_VERSION).is_empty());
+        assert!(
+            MIGRATIONS.windows(2).all(|pair| pair[0].to < pair[1].to),
+            "migrations must be in order"
+        );
+        assert_eq!(MIGRATIONS.last().map(|m| m.to), Some(LAYOUT_VERSION));
+
+        assert!(check(LAYOUT_VERSION).is_ok());
+        assert!(check(0).is_err());
+        assert!(check(LAYOUT_VERSION + 1).is_err());
+    }
+}
@@ -0,0 +1,100 @@
+use crate::config::ModePolicy;
+use anyhow::Result;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::sync::OnceLock;
+
+/// The process umask. Linux reports it in /proc; elsewhere it can only be read
+/// by setting it, so it is read once and put straight back.
+pub fn umask() -> u32 {
+    static UMASK: OnceLock<u32> = OnceLock::new();
+    *UMASK.get_or_init(|| {
+        fs::read_to_string("/proc/self/status")
+            .ok()
+            .and_then(|status| {
+                let line = status.lines().find(|line| line.starts_with("Umask:"))?;
+                u32::from_str_radix(line["Umask:".len()..].trim(), 8).ok()
+            })
+            .unwrap_or_else(|| {
+                // SAFETY: umask only swaps the process's file creation mask
+                let mask = unsafe { libc::umask(0o022) };
+                unsafe { libc::umask(mask) };
+                mask as u32
+            })
+    })
+}
+
+/// Permission bits installed files may keep under `policy`
+pub fn allowed(policy: ModePolicy) -> u32 {
+    match policy {
+        ModePolicy::Umask => 0o777 & !umask(),
+        ModePolicy::Strict => 0o700 & !umask(),
+    }
+}
+
+/// Bring everything under `root` in line with `policy`. Archives set modes
+/// explicitly, which bypasses the umask, and may carry set-id bits; both are
+/// taken away here.
+pub fn apply(policy: ModePolicy, root: &Path) -> Result<()> {
+    restrict(root, allowed(policy))
+}
+
+/// Clear every bit outside `keep`, symlinks aside. Directories stay usable by
+/// their owner so the package can still be removed.
+fn restrict(path: &Path, keep: u32) -> Result<()> {
+    let metadata = fs::symlink_metadata(path)?;
+    if metadata.is_symlink() {
+        return Ok(());
+    }
+    let mode = metadata.permissions().mode() & 0o7777;
+    let wanted = if metadata.is_dir() {
+        (mode & keep) | 0o700
+    } else {
+        mode & keep
+    };
+    if wanted != mode {
+        fs::set_permissions(path, fs::Permissions::from_mode(wanted))?;
+    }
+    if metadata.is_dir() {
+        for entry in fs::read_dir(path)? {
+            restrict(&entry?.path(), keep)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mode(path: &Path) -> u32 {
+        fs::metadata(path).unwrap().permissions().mode() & 0o7777
+    }
+
+    #[test]
+    fn test_restrict() {
+        let root = std::env::temp_dir().join(format!("leaf-modes-test-{}", std::process::id()));
+        fs::remove_dir_all(&root).ok();
+        fs::create_dir_all(root.join("bin")).unwrap();
+        let tool = root.join("bin").join("tool");
+        let readme = root.join("README");
+        fs::write(&tool, "").unwrap();
+        fs::write(&readme, "").unwrap();
+        fs::set_permissions(&tool, fs::Permissions::from_mode(0o4777)).unwrap();
+        fs::set_permissions(&readme, fs::Permissions::from_mode(0o666)).unwrap();
+        fs::set_permissions(root.join("bin"), fs::Permissions::from_mode(0o555)).unwrap();
+        std::os::unix::fs::symlink("README", root.join("link")).unwrap();
+
+        restrict(&root, 0o755).unwrap();
+        assert_eq!(mode(&tool), 0o755);
+        assert_eq!(mode(&readme), 0o644);
+        assert_eq!(mode(&root.join("bin")), 0o755);
+
+        restrict(&root, 0o700).unwrap();
+        assert_eq!(mode(&tool), 0o700);
+        assert_eq!(mode(&readme), 0o600);
+        assert_eq!(mode(&root), 0o700);
+        fs::remove_dir_all(&root).ok();
+    }
+}
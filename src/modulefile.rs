@@ -0,0 +1,80 @@
+use crate::package::{Package, PlatformDetails};
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+use tokio::fs;
+
+/// Environment Modules / Lmod tree; `module use <install_dir>/modules` makes
+/// packages loadable as `module load leaf/<name>`
+pub fn modules_dir(install_dir: &Path) -> PathBuf {
+    install_dir.join("modules")
+}
+
+fn modulefile_path(modules_dir: &Path, name: &str, version: &str) -> PathBuf {
+    modules_dir.join("leaf").join(name).join(version)
+}
+
+/// Write the modulefile for one installed package version
+pub async fn write(
+    modules_dir: &Path,
+    name: &str,
+    package: &Package,
+    package_dir: &Path,
+    platform_details: &PlatformDetails,
+) -> Result<()> {
+    let mut content = format!(
+        "#%Module1.0\n## Generated by leaf\nmodule-whatis \"{}\"\nconflict leaf/{}\n",
+        tcl_escape(&package.description),
+        name
+    );
+
+    for dir in platform_details.bin_dirs(package_dir) {
+        content.push_str(&format!(
+            "prepend-path PATH \"{}\"\n",
+            tcl_escape(&dir.to_string_lossy())
+        ));
+    }
+
+    for man_dir in [
+        package_dir.join("share").join("man"),
+        package_dir.join("man"),
+    ] {
+        if man_dir.is_dir() {
+            content.push_str(&format!(
+                "prepend-path MANPATH \"{}\"\n",
+                tcl_escape(&man_dir.to_string_lossy())
+            ));
+        }
+    }
+
+    for (key, value) in platform_details.get_env(package_dir) {
+        content.push_str(&format!("setenv {} \"{}\"\n", key, tcl_escape(&value)));
+    }
+
+    let path = modulefile_path(modules_dir, name, &package.version);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).await?;
+    }
+    fs::write(path, content).await?;
+    Ok(())
+}
+
+/// Remove a package version's modulefile and its directory once empty
+pub async fn remove(modules_dir: &Path, name: &str, version: &str) -> Result<()> {
+    let path = modulefile_path(modules_dir, name, version);
+    if path.exists() {
+        fs::remove_file(&path).await?;
+    }
+    if let Some(parent) = path.parent() {
+        // Fails while other versions remain, which is what we want
+        fs::remove_dir(parent).await.ok();
+    }
+    Ok(())
+}
+
+fn tcl_escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('$', "\\$")
+        .replace('[', "\\[")
+}
@@ -0,0 +1,186 @@
+use crate::config::{BarStyle, ColorMode, Config};
+use crate::prompt;
+use crate::utils::{print_info, print_success, print_warning};
+use anyhow::{Result, anyhow};
+use dirs::home_dir;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Environment variable naming the root to use when --prefix isn't given, for
+/// users who keep leaf somewhere other than ~/.local
+pub const PREFIX_VAR: &str = "LEAF_PREFIX";
+
+/// What the first-run setup settled on
+pub struct Setup {
+    /// Root chosen instead of ~/.local
+    pub prefix: Option<PathBuf>,
+    /// Whether to download the index now rather than on a later run
+    pub sync_index: bool,
+}
+
+/// Whether leaf has never been set up for this user: the default root has no
+/// config yet (install.sh writes one), and no other root was chosen
+pub fn first_run() -> bool {
+    saved_prefix().is_none()
+        && home_dir().is_some_and(|home| {
+            !home
+                .join(".local")
+                .join("leaf")
+                .join("config.json")
+                .exists()
+        })
+}
+
+/// Where setup remembers a root other than ~/.local, for shells started before
+/// it exported LEAF_PREFIX or users who didn't let it touch their startup file
+fn saved_prefix_path() -> Option<PathBuf> {
+    Some(dirs::config_dir()?.join("leaf").join("prefix"))
+}
+
+/// Root chosen during setup, if it wasn't ~/.local
+pub fn saved_prefix() -> Option<PathBuf> {
+    let content = std::fs::read_to_string(saved_prefix_path()?).ok()?;
+    let prefix = content.trim_end_matches('\n');
+    (!prefix.is_empty()).then(|| PathBuf::from(prefix))
+}
+
+/// The user's own root when it isn't ~/.local: LEAF_PREFIX, or the one chosen
+/// during setup. Unlike --prefix, it is used as if it were ~/.local.
+pub fn default_root() -> Option<PathBuf> {
+    std::env::var_os(PREFIX_VAR)
+        .map(PathBuf::from)
+        .or_else(saved_prefix)
+}
+
+/// Walk a new user through choosing a root, putting its bin directory on PATH
+/// and picking how output looks, then save the config
+pub async fn run() -> Result<Setup> {
+    let home = home_dir().ok_or_else(|| anyhow!("Cannot find home directory"))?;
+    let default_root = home.join(".local");
+    print_info(&tr!("onboarding.welcome"));
+
+    let answer = prompt::text(
+        &tr!("onboarding.prefix"),
+        Some(&default_root.display().to_string()),
+    )?;
+    let root = match answer.strip_prefix("~/") {
+        Some(rest) => home.join(rest),
+        None => std::path::absolute(&answer)?,
+    };
+    let prefix = (root != default_root).then_some(root);
+
+    if let (Some(prefix), Some(path)) = (&prefix, saved_prefix_path()) {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(&path, format!("{}\n", prefix.display()))?;
+    }
+    let mut config = Config::load_or_create(None, prefix.as_deref()).await?;
+    if !prompt::yes_no_default(&tr!("onboarding.color"), true) {
+        config.color = ColorMode::Never;
+        config.color.apply();
+    }
+    if !prompt::yes_no_default(&tr!("onboarding.progress"), true) {
+        config.progress_bar = BarStyle::Ascii;
+    }
+    config.save().await?;
+
+    let shell = std::env::var("SHELL").unwrap_or_default();
+    let on_path = std::env::var_os("PATH")
+        .is_some_and(|path| std::env::split_paths(&path).any(|dir| dir == config.bin_dir));
+    let lines = shell_lines(&shell, &config.bin_dir, prefix.as_deref(), on_path);
+    if !lines.is_empty() {
+        let rc_file = rc_file(&shell, &home);
+        if prompt::yes_no_default(&tr!("onboarding.path", rc_file.display()), true) {
+            append_lines(&rc_file, &lines)?;
+            print_success(&tr!("onboarding.path_done", rc_file.display()));
+        } else {
+            print_warning(&tr!("onboarding.path_skipped"));
+            for line in &lines {
+                println!("  {}", line);
+            }
+        }
+    }
+
+    let sync_index = prompt::yes_no_default(&tr!("onboarding.sync"), true);
+    print_success(&tr!("onboarding.done", config.install_dir.display()));
+    Ok(Setup { prefix, sync_index })
+}
+
+/// Startup file of the user's shell, where PATH changes go
+fn rc_file(shell: &str, home: &Path) -> PathBuf {
+    match Path::new(shell).file_name().and_then(|name| name.to_str()) {
+        Some("bash") => home.join(".bashrc"),
+        Some("zsh") => home.join(".zshrc"),
+        Some("fish") => home.join(".config").join("fish").join("config.fish"),
+        _ => home.join(".profile"),
+    }
+}
+
+/// Lines for the shell's startup file that put `bin_dir` on PATH (unless it
+/// already is) and point leaf at `prefix`
+fn shell_lines(shell: &str, bin_dir: &Path, prefix: Option<&Path>, on_path: bool) -> Vec<String> {
+    let fish = shell.ends_with("fish");
+    let mut lines = Vec::new();
+    if let Some(prefix) = prefix {
+        lines.push(if fish {
+            format!("set -gx {} \"{}\"", PREFIX_VAR, prefix.display())
+        } else {
+            format!("export {}=\"{}\"", PREFIX_VAR, prefix.display())
+        });
+    }
+    if !on_path {
+        lines.push(if fish {
+            format!("fish_add_path \"{}\"", bin_dir.display())
+        } else {
+            format!("export PATH=\"{}:$PATH\"", bin_dir.display())
+        });
+    }
+    lines
+}
+
+fn append_lines(rc_file: &Path, lines: &[String]) -> Result<()> {
+    if let Some(parent) = rc_file.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(rc_file)?;
+    writeln!(file, "\n# Added by leaf")?;
+    for line in lines {
+        writeln!(file, "{}", line)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shell_setup() {
+        let home = Path::new("/home/me");
+        assert_eq!(rc_file("/bin/zsh", home), home.join(".zshrc"));
+        assert_eq!(rc_file("", home), home.join(".profile"));
+
+        let bin_dir = Path::new("/home/me/.local/bin");
+        assert_eq!(
+            shell_lines("/bin/bash", bin_dir, None, false),
+            ["export PATH=\"/home/me/.local/bin:$PATH\""]
+        );
+        assert!(shell_lines("/bin/bash", bin_dir, None, true).is_empty());
+        assert_eq!(
+            shell_lines(
+                "/usr/bin/fish",
+                Path::new("/opt/tools/bin"),
+                Some(Path::new("/opt/tools")),
+                false
+            ),
+            [
+                "set -gx LEAF_PREFIX \"/opt/tools\"",
+                "fish_add_path \"/opt/tools/bin\""
+            ]
+        );
+    }
+}
@@ -0,0 +1,107 @@
+use crate::checksum::sha256_file;
+use crate::utils::print_warning;
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Read;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// What a post-install optimization pass changed
+#[derive(Debug, Default)]
+pub struct Optimized {
+    /// SHA-256 before the change, by path relative to the package directory
+    pub originals: BTreeMap<String, String>,
+    pub bytes_saved: u64,
+}
+
+/// Strip debug symbols from (and optionally upx-compress) the ELF binaries in
+/// `package_dir`. Tools that are not installed are skipped with a warning.
+pub fn optimize(package_dir: &Path, strip: bool, compress: bool) -> Result<Optimized> {
+    let strip = strip && tool_available("strip");
+    let compress = compress && tool_available("upx");
+
+    let mut files = Vec::new();
+    collect_files(package_dir, &mut files)?;
+
+    let mut optimized = Optimized::default();
+    for path in files {
+        if !is_elf(&path) {
+            continue;
+        }
+        let original = sha256_file(&path)?;
+        let size_before = fs::metadata(&path)?.len();
+
+        // Failures leave the file as it was, e.g. upx refusing an already packed binary
+        if strip {
+            run_quietly(Command::new("strip").arg("--strip-debug").arg(&path));
+        }
+        if compress && is_executable(&path) && !is_shared_library(&path) {
+            run_quietly(Command::new("upx").arg("-q").arg(&path));
+        }
+
+        if sha256_file(&path)? != original {
+            let relative = path
+                .strip_prefix(package_dir)?
+                .to_string_lossy()
+                .to_string();
+            optimized.originals.insert(relative, original);
+            let size_after = fs::metadata(&path)?.len();
+            optimized.bytes_saved += size_before.saturating_sub(size_after);
+        }
+    }
+    Ok(optimized)
+}
+
+fn tool_available(tool: &str) -> bool {
+    let found = Command::new(tool)
+        .arg("--version")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok();
+    if !found {
+        print_warning(&tr!("optimize.tool_missing", tool));
+    }
+    found
+}
+
+fn run_quietly(command: &mut Command) {
+    command
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .ok();
+}
+
+fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            collect_files(&entry.path(), files)?;
+        } else if file_type.is_file() {
+            files.push(entry.path());
+        }
+    }
+    Ok(())
+}
+
+fn is_elf(path: &Path) -> bool {
+    let mut magic = [0u8; 4];
+    fs::File::open(path)
+        .and_then(|mut file| file.read_exact(&mut magic))
+        .is_ok()
+        && magic == *b"\x7fELF"
+}
+
+fn is_executable(path: &Path) -> bool {
+    fs::metadata(path).is_ok_and(|metadata| metadata.permissions().mode() & 0o111 != 0)
+}
+
+// upx-packed shared libraries fail to load, so only executables are compressed
+fn is_shared_library(path: &Path) -> bool {
+    path.file_name()
+        .is_some_and(|name| name.to_string_lossy().contains(".so"))
+}
@@ -0,0 +1,155 @@
+use crate::package::{Package, needs_newer_leaf};
+use crate::utils::plain_file_name;
+use anyhow::{Context, Result, anyhow};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// File holding the local override of `name`'s definition
+pub fn path(dir: &Path, name: &str) -> Result<PathBuf> {
+    check_name(name)?;
+    Ok(dir.join(format!("{}.json", name)))
+}
+
+/// Where `leaf edit` keeps a definition while it is being edited
+pub fn draft_path(dir: &Path, name: &str) -> Result<PathBuf> {
+    check_name(name)?;
+    Ok(dir.join(format!(".{}.json.edit", name)))
+}
+
+/// Package names come from the index, so one could lead out of `dir`
+fn check_name(name: &str) -> Result<()> {
+    if !plain_file_name(name) || name.contains("..") {
+        return Err(anyhow!("'{}' can't be the name of a local override", name));
+    }
+    Ok(())
+}
+
+/// Local overrides read from the overlay directory
+pub struct Overrides {
+    /// Definitions keyed by package name
+    pub definitions: serde_json::Map<String, serde_json::Value>,
+    /// File each definition was read from, since the name alone may not lead
+    /// back to it (see `path`)
+    pub paths: HashMap<String, PathBuf>,
+    /// Files that aren't JSON, with why
+    pub broken: Vec<(String, String)>,
+}
+
+/// Local overrides in `dir`
+pub fn load(dir: &Path) -> Overrides {
+    let mut definitions = serde_json::Map::new();
+    let mut paths = HashMap::new();
+    let mut broken = Vec::new();
+    for entry in fs::read_dir(dir).into_iter().flatten().flatten() {
+        let path = entry.path();
+        let Some(name) = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_suffix(".json"))
+            .filter(|name| !name.starts_with('.'))
+        else {
+            continue;
+        };
+        let parsed = fs::read_to_string(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|content| serde_json::from_str(&content).map_err(|e| e.to_string()));
+        match parsed {
+            Ok(definition) => {
+                definitions.insert(name.to_string(), definition);
+                paths.insert(name.to_string(), path.clone());
+            }
+            Err(e) => broken.push((name.to_string(), format!("{}: {}", path.display(), e))),
+        }
+    }
+    broken.sort();
+    Overrides {
+        definitions,
+        paths,
+        broken,
+    }
+}
+
+/// Check an edited definition before it is saved: it must be JSON that this
+/// leaf reads as a package
+pub fn validate(content: &str, leaf_version: &str) -> Result<serde_json::Value> {
+    let definition: serde_json::Value = serde_json::from_str(content).context("Not valid JSON")?;
+    if let Some(required) = needs_newer_leaf(&definition, leaf_version) {
+        return Err(anyhow!(
+            "min_leaf_version asks for leaf {}, but this is {}",
+            required,
+            leaf_version
+        ));
+    }
+    let package: Package =
+        serde_json::from_value(definition.clone()).context("Not a valid package definition")?;
+    if package.platforms.is_empty() {
+        return Err(anyhow!("The definition has no platforms"));
+    }
+    Ok(definition)
+}
+
+/// Open `file` in $VISUAL or $EDITOR, falling back to vi. The variable may
+/// carry arguments (e.g. "code --wait"), so it goes through the shell.
+pub fn edit(file: &Path) -> Result<()> {
+    let editor = std::env::var("VISUAL")
+        .ok()
+        .or_else(|| std::env::var("EDITOR").ok())
+        .filter(|editor| !editor.trim().is_empty())
+        .unwrap_or_else(|| "vi".to_string());
+    let status = std::process::Command::new("sh")
+        .arg("-c")
+        .arg(format!("{} \"$@\"", editor))
+        .arg(&editor)
+        .arg(file)
+        .status()
+        .with_context(|| format!("Failed to run editor '{}'", editor))?;
+    if !status.success() {
+        return Err(anyhow!("Editor '{}' exited with {}", editor, status));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_and_validate() {
+        let dir = std::env::temp_dir().join(format!("leaf-overlay-test-{}", std::process::id()));
+        fs::remove_dir_all(&dir).ok();
+        fs::create_dir_all(&dir).unwrap();
+        let definition = r#"{"description": "tool", "version": "1.0", "platforms": {"any": {"url": "https://example.com/tool"}}}"#;
+        fs::write(path(&dir, "tool").unwrap(), definition).unwrap();
+        fs::write(path(&dir, "broken").unwrap(), "{").unwrap();
+        fs::write(draft_path(&dir, "tool").unwrap(), "{").unwrap();
+        assert!(path(&dir, "../tool").is_err());
+        assert!(path(&dir, "..").is_err());
+        assert!(draft_path(&dir, "a/b").is_err());
+
+        // Names `path` refuses can still be found in the directory
+        fs::write(dir.join("a..b.json"), r#"{"description": "odd"}"#).unwrap();
+
+        let overrides = load(&dir);
+        let mut names: Vec<_> = overrides.definitions.keys().collect();
+        names.sort();
+        assert_eq!(names, ["a..b", "tool"]);
+        assert_eq!(overrides.paths["a..b"], dir.join("a..b.json"));
+        assert_eq!(overrides.broken.len(), 1);
+        assert_eq!(overrides.broken[0].0, "broken");
+
+        assert!(validate(definition, "1.0.0").is_ok());
+        assert!(validate("{", "1.0.0").is_err());
+        assert!(validate(r#"{"description": "tool"}"#, "1.0.0").is_err());
+        assert!(
+            validate(
+                r#"{"description": "tool", "version": "1.0", "platforms": {}}"#,
+                "1.0.0"
+            )
+            .is_err()
+        );
+        let future = definition.replacen('{', r#"{"min_leaf_version": "9.0", "#, 1);
+        assert!(validate(&future, "1.0.0").is_err());
+        fs::remove_dir_all(&dir).ok();
+    }
+}
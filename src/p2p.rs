@@ -0,0 +1,64 @@
+use anyhow::{Result, anyhow};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Gateways tried for `ipfs://` sources when config.json doesn't list any:
+/// a local IPFS daemon first, then the public gateway
+pub const DEFAULT_IPFS_GATEWAYS: [&str; 2] = ["http://127.0.0.1:8080", "https://ipfs.io"];
+
+/// HTTP URL of an `ipfs://<cid>/<path>` source on `gateway`
+pub fn gateway_url(gateway: &str, ipfs_path: &str) -> String {
+    format!("{}/ipfs/{}", gateway.trim_end_matches('/'), ipfs_path)
+}
+
+/// Fetch a magnet link's single file into `cache_dir` with aria2c
+pub fn fetch_magnet(magnet: &str, cache_dir: &Path) -> Result<PathBuf> {
+    let work_dir = cache_dir.join(".magnet");
+    fs::remove_dir_all(&work_dir).ok();
+    fs::create_dir_all(&work_dir)?;
+
+    let status = Command::new("aria2c")
+        .arg("--quiet")
+        .arg("--seed-time=0")
+        .arg("--follow-torrent=mem")
+        .arg("--dir")
+        .arg(&work_dir)
+        .arg(magnet)
+        .status()
+        .map_err(|_| anyhow!("aria2c is needed to download magnet links"))?;
+    if !status.success() {
+        return Err(anyhow!("aria2c failed with {}", status));
+    }
+
+    let mut files = Vec::new();
+    for entry in fs::read_dir(&work_dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_file() {
+            files.push(entry.path());
+        }
+    }
+    let [file] = files.as_slice() else {
+        return Err(anyhow!(
+            "Magnet link must contain exactly one file, found {}",
+            files.len()
+        ));
+    };
+    let dest = cache_dir.join(file.file_name().unwrap_or_default());
+    fs::rename(file, &dest)?;
+    fs::remove_dir_all(&work_dir).ok();
+    Ok(dest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gateway_url() {
+        assert_eq!(
+            gateway_url("https://ipfs.io/", "bafy123/tool.tar.gz"),
+            "https://ipfs.io/ipfs/bafy123/tool.tar.gz"
+        );
+    }
+}
@@ -1,25 +1,436 @@
+use crate::cosign::Cosign;
+use crate::version::{compare_versions, matches_spec};
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct Package {
     pub description: String,
     pub version: String,
     pub tags: Option<Vec<String>>,
+    // Alternative names the package can be referred to by, e.g. "rg" for ripgrep
+    pub aliases: Option<Vec<String>>,
     pub platforms: HashMap<String, PlatformDetails>,
+    // Settings shared by every platform entry, which can override them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub defaults: Option<PlatformDefaults>,
+    // How the package is known to the OSV vulnerability database, for `leaf audit`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub osv: Option<OsvPackage>,
+    // Shown after installing and by `leaf info --caveats`, e.g. setup the user must do
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub caveats: Option<String>,
+    // License terms that must be accepted before installing, e.g. a vendor EULA; text or a URL
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub license_terms: Option<String>,
+    // Package to move to when this one is retired or superseded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub replaced_by: Option<String>,
+    // Oldest leaf release that understands this definition; older ones refuse it
+    // with a hint to self-update rather than failing to parse it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_leaf_version: Option<String>,
+    // Older or alternative releases that can be installed side by side as `name@version`
+    pub versions: Option<HashMap<String, HashMap<String, PlatformDetails>>>,
+    // Only present in installed metadata: where the files actually came from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provenance: Option<Provenance>,
+    // Only present in installed metadata: executables kept out of bin_dir
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub hidden_executables: Vec<String>,
+    // Only present in installed metadata: bin_dir names chosen with `leaf link --as`,
+    // keyed by the executable's usual name
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub exposed_as: HashMap<String, String>,
+    // Only present in installed metadata: the user's own notes, e.g. why it is installed
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub notes: Vec<String>,
+}
+
+/// Which of a package's executables an install puts in bin_dir
+#[derive(Debug, Clone, PartialEq)]
+pub enum BinFilter {
+    /// Just these (`--only`); empty for none at all (`--skip-bins`)
+    Only(Vec<String>),
+    /// All but these
+    Except(Vec<String>),
+}
+
+impl Default for BinFilter {
+    fn default() -> Self {
+        BinFilter::Except(Vec::new())
+    }
+}
+
+impl BinFilter {
+    /// The executables among `names` to keep out of bin_dir, or an error naming
+    /// the ones `--only` asked for that the package doesn't have
+    pub fn hidden(&self, names: &[String]) -> Result<Vec<String>, String> {
+        match self {
+            BinFilter::Only(wanted) => {
+                let unknown: Vec<&str> = wanted
+                    .iter()
+                    .filter(|name| !names.contains(name))
+                    .map(String::as_str)
+                    .collect();
+                if !unknown.is_empty() {
+                    return Err(unknown.join(", "));
+                }
+                Ok(names
+                    .iter()
+                    .filter(|name| !wanted.contains(name))
+                    .cloned()
+                    .collect())
+            }
+            BinFilter::Except(hidden) => Ok(names
+                .iter()
+                .filter(|name| hidden.contains(name))
+                .cloned()
+                .collect()),
+        }
+    }
+}
+
+/// Ecosystem and name identifying a package in https://osv.dev
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct OsvPackage {
+    pub ecosystem: String,
+    pub name: String,
+}
+
+/// Download record kept with an installed package
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Provenance {
+    /// URL from the package definition
+    pub url: String,
+    /// Where that URL redirected to, e.g. objects.githubusercontent.com for GitHub releases
+    pub resolved_url: String,
+    /// Hash of the downloaded artifact
+    #[serde(default)]
+    pub sha256: String,
+    #[serde(default)]
+    pub downloaded_at: Option<DateTime<Utc>>,
+    /// Version of leaf that performed the install
+    #[serde(default)]
+    pub leaf_version: String,
+    /// Platform key the build is for, when installed with --platform for another machine
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub platform: Option<String>,
+}
+
+/// What became of an installed package that the index stopped offering
+#[derive(Debug, PartialEq)]
+pub enum Retirement {
+    /// Gone without a successor
+    Removed,
+    /// Now known under another name, which lists the old one as an alias
+    Renamed(String),
+    /// Still listed, but its definition names a successor
+    Replaced(String),
+}
+
+/// Whether the index has retired package `name`, and in favour of what
+pub fn retirement(name: &str, index: &HashMap<String, Package>) -> Option<Retirement> {
+    if let Some(package) = index.get(name) {
+        return package.replaced_by.clone().map(Retirement::Replaced);
+    }
+    let renamed = index
+        .iter()
+        .find(|(_, package)| package.aliases.iter().flatten().any(|alias| alias == name));
+    Some(match renamed {
+        Some((new_name, _)) => Retirement::Renamed(new_name.clone()),
+        None => Retirement::Removed,
+    })
+}
+
+/// Top-level key of packages.json giving the version of its format; an index
+/// without one is format 1
+pub const SCHEMA_KEY: &str = "schema_version";
+/// Newest packages.json format this leaf reads
+pub const SCHEMA_VERSION: u64 = 1;
+
+/// packages.json, read as far as this leaf understands it
+#[derive(Debug, Default)]
+pub struct Index {
+    pub packages: HashMap<String, Package>,
+    /// Packages whose definition needs a newer leaf, with the version it needs
+    pub too_new: HashMap<String, String>,
+    /// Packages whose definition couldn't be read, with why
+    pub invalid: Vec<(String, String)>,
+}
+
+#[derive(Debug)]
+pub enum IndexError {
+    /// Not JSON, or not an object of packages
+    Invalid(serde_json::Error),
+    /// Written in a format newer than this leaf knows, so nothing in it can be
+    /// trusted to mean what this leaf would take it to mean
+    NewerFormat(u64),
+}
+
+impl std::fmt::Display for IndexError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IndexError::Invalid(e) => write!(f, "{}", e),
+            IndexError::NewerFormat(version) => write!(
+                f,
+                "the index uses format {}, but this leaf only reads up to format {}; run `leaf self-update`",
+                version, SCHEMA_VERSION
+            ),
+        }
+    }
+}
+
+/// Read an index written for `leaf_version` or any older leaf. Unknown fields
+/// are ignored, and a definition that still doesn't parse only costs that
+/// package rather than the whole index.
+pub fn parse_index(content: &str, leaf_version: &str) -> Result<Index, IndexError> {
+    let mut definitions: serde_json::Map<String, serde_json::Value> =
+        serde_json::from_str(content).map_err(IndexError::Invalid)?;
+    if let Some(schema) = definitions.remove(SCHEMA_KEY) {
+        let version: u64 = serde_json::from_value(schema).map_err(IndexError::Invalid)?;
+        if version > SCHEMA_VERSION {
+            return Err(IndexError::NewerFormat(version));
+        }
+    }
+    Ok(parse_definitions(definitions, leaf_version))
+}
+
+/// Sort definitions keyed by package name into packages, ones that need a
+/// newer leaf, and ones that don't parse
+pub fn parse_definitions(
+    definitions: serde_json::Map<String, serde_json::Value>,
+    leaf_version: &str,
+) -> Index {
+    let mut index = Index::default();
+    for (name, definition) in definitions {
+        if let Some(required) = needs_newer_leaf(&definition, leaf_version) {
+            index.too_new.insert(name, required);
+            continue;
+        }
+        match serde_json::from_value(definition) {
+            Ok(package) => {
+                index.packages.insert(name, package);
+            }
+            Err(e) => index.invalid.push((name, e.to_string())),
+        }
+    }
+    index.invalid.sort();
+    index
+}
+
+/// The `min_leaf_version` of a raw definition, when it is newer than `current`
+pub fn needs_newer_leaf(definition: &serde_json::Value, current: &str) -> Option<String> {
+    let required = definition.get("min_leaf_version")?.as_str()?;
+    (compare_versions(required, current) == std::cmp::Ordering::Greater)
+        .then(|| required.to_string())
+}
+
+/// Platform settings that usually don't differ between platforms, given once
+/// in a definition's "defaults"
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct PlatformDefaults {
+    #[serde(rename = "type")]
+    pub package_type: Option<String>,
+    pub executables: Option<serde_json::Value>,
+    pub services: Option<Vec<Service>>,
+    pub extra_files: Option<Vec<ExtraFile>>,
+    pub build_commands: Option<Vec<String>>,
+    pub build_dependencies: Option<Vec<String>>,
+    pub build_env: Option<HashMap<String, String>>,
+    pub env: Option<HashMap<String, String>>,
+}
+
+impl Package {
+    /// Name `executable` has in bin_dir: its usual one unless `leaf link --as` renamed it
+    pub fn bin_name(&self, executable: &ExecutableInfo) -> String {
+        let name = executable.exposed_name();
+        self.exposed_as.get(&name).cloned().unwrap_or(name)
+    }
+
+    /// The definition as the index has it, without what an install records
+    /// (the "Only present in installed metadata" fields above)
+    pub fn without_install_state(&self) -> Package {
+        Package {
+            provenance: None,
+            hidden_executables: Vec::new(),
+            exposed_as: HashMap::new(),
+            notes: Vec::new(),
+            ..self.clone()
+        }
+    }
+
+    /// Fill what platform entries (including those of other versions) leave
+    /// out from the definition's defaults
+    pub fn apply_defaults(&mut self) {
+        let Some(defaults) = self.defaults.take() else {
+            return;
+        };
+        let versions = self
+            .versions
+            .iter_mut()
+            .flat_map(|versions| versions.values_mut());
+        for platforms in std::iter::once(&mut self.platforms).chain(versions) {
+            for details in platforms.values_mut() {
+                details.inherit(&defaults);
+            }
+        }
+    }
+
+    /// Pick the build variant for this CPU in every platform entry
+    pub fn select_variants(&mut self, has_feature: impl Fn(&str) -> bool + Copy) {
+        let versions = self
+            .versions
+            .iter_mut()
+            .flat_map(|versions| versions.values_mut());
+        for platforms in std::iter::once(&mut self.platforms).chain(versions) {
+            for details in platforms.values_mut() {
+                details.select_variant(has_feature);
+            }
+        }
+    }
+
+    /// Build for `platform` (e.g. "macos-aarch64"), falling back to one shared
+    /// with other platforms such as a macOS universal binary
+    pub fn platform(&self, platform: &str) -> Option<&PlatformDetails> {
+        self.platform_keyed(platform).map(|(_, details)| details)
+    }
+
+    fn platform_keyed(&self, platform: &str) -> Option<(&str, &PlatformDetails)> {
+        crate::platform::candidates(platform)
+            .into_iter()
+            .find_map(|key| self.platforms.get_key_value(key))
+            .map(|(key, details)| (key.as_str(), details))
+    }
+
+    /// Like `platform`, but without a native build falls back to one that
+    /// runs under emulation (e.g. Rosetta 2), flagged by the `true`
+    pub fn platform_or_emulated(&self, platform: &str) -> Option<(&PlatformDetails, bool)> {
+        self.resolve_platform(platform)
+            .map(|(_, details, emulated)| (details, emulated))
+    }
+
+    /// Like `platform_or_emulated`, also naming the key the build is listed under
+    pub fn resolve_platform(&self, platform: &str) -> Option<(&str, &PlatformDetails, bool)> {
+        if let Some((key, details)) = self.platform_keyed(platform) {
+            return Some((key, details, false));
+        }
+        let emulated = crate::platform::emulated_by(platform)?;
+        self.platform_keyed(emulated)
+            .map(|(key, details)| (key, details, true))
+    }
+
+    /// Pick the newest release matching `spec` (e.g. "18" for "18.20.4"), returning a
+    /// copy of the package describing just that release
+    pub fn with_version(&self, spec: &str) -> Option<Package> {
+        let current = std::iter::once((&self.version, &self.platforms));
+        let (version, platforms) = current
+            .chain(self.versions.iter().flatten())
+            .filter(|(version, _)| matches_spec(version, spec))
+            .max_by(|(a, _), (b, _)| compare_versions(a, b))?;
+
+        Some(Package {
+            version: version.clone(),
+            platforms: platforms.clone(),
+            versions: None,
+            ..self.clone()
+        })
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct PlatformDetails {
     pub url: String,
+    // Tried in order when `url` fails, e.g. an HTTPS mirror for an ipfs:// or magnet: url
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mirrors: Option<Vec<String>>,
+    // Faster builds for CPUs with extra features; `url` is the baseline build
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub variants: Option<Vec<Variant>>,
+    // Features of the variant picked for this CPU; empty for the baseline build
+    #[serde(skip)]
+    pub variant_features: Vec<String>,
+    // SHA256SUMS-style file published next to the artifact, which the download must match
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub checksum_url: Option<String>,
+    // Keyless cosign signature of the artifact and the identity expected to have signed it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cosign: Option<Cosign>,
     #[serde(rename = "type")]
     pub package_type: Option<String>,
     pub executables: Option<serde_json::Value>,
+    // Daemons that can run as user services via `leaf service enable`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub services: Option<Vec<Service>>,
+    // Non-executable files to expose next to bin_dir, e.g. libraries or headers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extra_files: Option<Vec<ExtraFile>>,
     // Add build commands for "build" type packages
     pub build_commands: Option<Vec<String>>,
+    // Leaf packages a build needs, e.g. ["cmake", "ninja"], with their executables put
+    // on PATH and their env vars set; ones that aren't installed are fetched into the
+    // cache for the build, as `leaf exec` does
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub build_dependencies: Option<Vec<String>>,
+    // Environment for build commands, e.g. {"CC": "{tool:gcc}/bin/gcc", "CFLAGS": "-O2"};
+    // values take the build command placeholders and {tool:<name>} for a build dependency's directory
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub build_env: Option<HashMap<String, String>>,
+    // Environment variables set when running the package's tools via `leaf exec`.
+    // "{package_dir}" in a value is replaced with the package's install directory.
+    pub env: Option<HashMap<String, String>>,
+}
+
+/// A build of the same release for CPUs with the given features
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Variant {
+    /// e.g. ["avx2", "bmi2"] or ["x86-64-v3"]
+    pub features: Vec<String>,
+    pub url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mirrors: Option<Vec<String>>,
 }
 
 impl PlatformDetails {
+    fn inherit(&mut self, defaults: &PlatformDefaults) {
+        fn fill<T: Clone>(field: &mut Option<T>, default: &Option<T>) {
+            if field.is_none() {
+                field.clone_from(default);
+            }
+        }
+        fill(&mut self.package_type, &defaults.package_type);
+        fill(&mut self.executables, &defaults.executables);
+        fill(&mut self.services, &defaults.services);
+        fill(&mut self.extra_files, &defaults.extra_files);
+        fill(&mut self.build_commands, &defaults.build_commands);
+        fill(&mut self.build_dependencies, &defaults.build_dependencies);
+        fill(&mut self.build_env, &defaults.build_env);
+        fill(&mut self.env, &defaults.env);
+    }
+
+    /// Switch to the variant needing the most features that `has_feature`
+    /// confirms, keeping the baseline build when none qualifies. Installed
+    /// metadata then records the build that was actually used.
+    pub fn select_variant(&mut self, has_feature: impl Fn(&str) -> bool) {
+        let Some(variants) = self.variants.take() else {
+            return;
+        };
+        let best = variants
+            .into_iter()
+            .filter(|variant| variant.features.iter().all(|feature| has_feature(feature)))
+            .fold(None::<Variant>, |best, variant| match best {
+                Some(best) if best.features.len() >= variant.features.len() => Some(best),
+                _ => Some(variant),
+            });
+        if let Some(variant) = best {
+            self.url = variant.url;
+            self.mirrors = variant.mirrors;
+            self.variant_features = variant.features;
+        }
+    }
+
     pub fn get_executables(&self) -> Vec<ExecutableInfo> {
         match &self.executables {
             Some(serde_json::Value::String(path)) => {
@@ -47,6 +458,36 @@ impl PlatformDetails {
         }
     }
 
+    pub fn get_env(&self, package_dir: &Path) -> Vec<(String, String)> {
+        let package_dir = package_dir.to_string_lossy();
+        self.env
+            .iter()
+            .flatten()
+            .map(|(key, value)| (key.clone(), value.replace("{package_dir}", &package_dir)))
+            .collect()
+    }
+
+    /// Everywhere the artifact can be downloaded from, in order of preference
+    pub fn sources(&self) -> Vec<&str> {
+        std::iter::once(&self.url)
+            .chain(self.mirrors.iter().flatten())
+            .map(String::as_str)
+            .collect()
+    }
+
+    /// Directories holding the executables, in declaration order, for PATH
+    pub fn bin_dirs(&self, package_dir: &Path) -> Vec<PathBuf> {
+        let mut dirs: Vec<PathBuf> = Vec::new();
+        for executable_info in self.get_executables() {
+            if let Some(parent) = package_dir.join(&executable_info.path).parent()
+                && !dirs.iter().any(|dir| dir == parent)
+            {
+                dirs.push(parent.to_path_buf());
+            }
+        }
+        dirs
+    }
+
     // Add method to get build commands
     pub fn get_build_commands(&self) -> Vec<String> {
         self.build_commands.clone().unwrap_or_default()
@@ -58,3 +499,237 @@ pub struct ExecutableInfo {
     pub path: String,
     pub name: Option<String>,
 }
+
+impl ExecutableInfo {
+    /// Name the executable is exposed as in bin_dir
+    pub fn exposed_name(&self) -> String {
+        self.name.clone().unwrap_or_else(|| {
+            Path::new(&self.path)
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_string()
+        })
+    }
+}
+
+/// A long-running program installed as a systemd user unit (launchd agent on macOS)
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Service {
+    /// Unit name, prefixed with "leaf-" (or "dev.leaf." for launchd)
+    pub name: String,
+    /// Program relative to the package directory, then its arguments;
+    /// "{package_dir}" is replaced as in `env`
+    pub command: Vec<String>,
+    pub description: Option<String>,
+}
+
+/// A file (or directory) from the package exposed outside bin_dir
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ExtraFile {
+    /// Path inside the package
+    pub path: String,
+    pub category: FileCategory,
+    /// Name at the destination; defaults to the last component of `path`
+    pub name: Option<String>,
+}
+
+impl ExtraFile {
+    pub fn exposed_name(&self) -> String {
+        self.name.clone().unwrap_or_else(|| {
+            Path::new(&self.path)
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_string()
+        })
+    }
+}
+
+/// Where an extra file goes, relative to the root holding bin_dir (~/.local)
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum FileCategory {
+    /// Shared libraries, linked into lib/
+    Lib,
+    /// Headers, linked into include/
+    Include,
+    /// Data such as man pages or completions, linked into share/
+    Share,
+    /// Configuration templates, copied into ~/.config once and left for the user to edit
+    Config,
+}
+
+impl FileCategory {
+    pub fn subdir(self) -> &'static str {
+        match self {
+            FileCategory::Lib => "lib",
+            FileCategory::Include => "include",
+            FileCategory::Share => "share",
+            FileCategory::Config => "etc",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bin_filter() {
+        let names: Vec<String> = ["rg", "rgx", "cc"].map(String::from).to_vec();
+        let only = BinFilter::Only(vec!["rg".to_string(), "rgx".to_string()]);
+        assert_eq!(only.hidden(&names), Ok(vec!["cc".to_string()]));
+        assert_eq!(
+            BinFilter::Only(Vec::new()).hidden(&names),
+            Ok(names.clone())
+        );
+        assert_eq!(
+            BinFilter::Only(vec!["rg".to_string(), "gcc".to_string()]).hidden(&names),
+            Err("gcc".to_string())
+        );
+        assert_eq!(BinFilter::default().hidden(&names), Ok(Vec::new()));
+        assert_eq!(
+            BinFilter::Except(vec!["cc".to_string(), "gone".to_string()]).hidden(&names),
+            Ok(vec!["cc".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_select_variant() {
+        let mut details: PlatformDetails = serde_json::from_str(
+            r#"{
+                "url": "https://example.com/tool-baseline.tar.gz",
+                "variants": [
+                    {"features": ["avx2"], "url": "https://example.com/tool-avx2.tar.gz"},
+                    {"features": ["avx2", "avx512f"], "url": "https://example.com/tool-avx512.tar.gz"}
+                ]
+            }"#,
+        )
+        .unwrap();
+        let mut baseline = details.clone();
+
+        details.select_variant(|feature| feature == "avx2");
+        assert_eq!(details.url, "https://example.com/tool-avx2.tar.gz");
+        assert!(details.variants.is_none());
+        assert_eq!(details.variant_features, ["avx2"]);
+
+        baseline.select_variant(|_| false);
+        assert_eq!(baseline.url, "https://example.com/tool-baseline.tar.gz");
+        assert!(baseline.variant_features.is_empty());
+    }
+
+    #[test]
+    fn test_needs_newer_leaf() {
+        let definition = serde_json::json!({"version": "1.0", "min_leaf_version": "1.2.0"});
+        assert_eq!(
+            needs_newer_leaf(&definition, "1.1.1").as_deref(),
+            Some("1.2.0")
+        );
+        assert_eq!(needs_newer_leaf(&definition, "1.2.0"), None);
+        assert_eq!(needs_newer_leaf(&serde_json::json!({}), "1.1.1"), None);
+    }
+
+    #[test]
+    fn test_parse_index() {
+        let index = parse_index(
+            r#"{
+                "schema_version": 1,
+                "rg": {"description": "grep", "version": "14", "platforms": {}, "sandbox": {"net": false}},
+                "broken": {"description": "bad", "version": "1", "platforms": []},
+                "future": {"min_leaf_version": "99.0", "platforms": "anything"}
+            }"#,
+            "1.1.1",
+        )
+        .unwrap();
+        assert_eq!(index.packages.keys().collect::<Vec<_>>(), ["rg"]);
+        assert_eq!(index.invalid.len(), 1);
+        assert_eq!(index.invalid[0].0, "broken");
+        assert_eq!(
+            index.too_new.get("future").map(String::as_str),
+            Some("99.0")
+        );
+
+        assert!(matches!(
+            parse_index(r#"{"schema_version": 2}"#, "1.1.1"),
+            Err(IndexError::NewerFormat(2))
+        ));
+        assert!(matches!(
+            parse_index("[]", "1.1.1"),
+            Err(IndexError::Invalid(_))
+        ));
+    }
+
+    #[test]
+    fn test_retirement() {
+        let index: HashMap<String, Package> = serde_json::from_str(
+            r#"{
+                "exa": {"description": "ls", "version": "0.10", "platforms": {}, "replaced_by": "eza"},
+                "eza": {"description": "ls", "version": "0.18", "platforms": {}},
+                "fd": {"description": "find", "version": "9", "aliases": ["fd-find"], "platforms": {}}
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(retirement("eza", &index), None);
+        assert_eq!(
+            retirement("exa", &index),
+            Some(Retirement::Replaced("eza".to_string()))
+        );
+        assert_eq!(
+            retirement("fd-find", &index),
+            Some(Retirement::Renamed("fd".to_string()))
+        );
+        assert_eq!(retirement("gone", &index), Some(Retirement::Removed));
+    }
+
+    #[test]
+    fn test_apply_defaults() {
+        let mut package: Package = serde_json::from_str(
+            r#"{
+                "description": "tool",
+                "version": "2.0",
+                "defaults": {"type": "archive", "executables": ["tool"]},
+                "platforms": {
+                    "linux-x86_64": {"url": "https://example.com/tool-linux.tar.gz"},
+                    "windows-x86_64": {
+                        "url": "https://example.com/tool.zip",
+                        "executables": ["tool.exe"]
+                    }
+                },
+                "versions": {
+                    "1.0": {"linux-x86_64": {"url": "https://example.com/tool-1.tar.gz"}}
+                }
+            }"#,
+        )
+        .unwrap();
+        package.apply_defaults();
+        assert!(package.defaults.is_none());
+
+        let linux = &package.platforms["linux-x86_64"];
+        assert_eq!(linux.package_type.as_deref(), Some("archive"));
+        assert_eq!(linux.get_executables()[0].path, "tool");
+        assert_eq!(
+            package.platforms["windows-x86_64"].get_executables()[0].path,
+            "tool.exe"
+        );
+        let old = &package.versions.as_ref().unwrap()["1.0"]["linux-x86_64"];
+        assert_eq!(old.get_executables()[0].path, "tool");
+    }
+
+    #[test]
+    fn test_without_install_state() {
+        let indexed: Package =
+            serde_json::from_str(r#"{"description": "tool", "version": "1.0", "platforms": {}}"#)
+                .unwrap();
+        let mut installed = indexed.clone();
+        installed.hidden_executables = vec!["helper".to_string()];
+        installed
+            .exposed_as
+            .insert("tool".to_string(), "t".to_string());
+        installed.notes.push("for work".to_string());
+        assert_eq!(
+            serde_json::to_value(installed.without_install_state()).unwrap(),
+            serde_json::to_value(&indexed).unwrap()
+        );
+    }
+}
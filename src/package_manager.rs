@@ -1,39 +1,164 @@
-use crate::config::Config;
-use crate::installer::Installer;
-use crate::package::{Package, PlatformDetails};
-use crate::utils::{print_error, print_info, print_success, print_warning};
+use crate::audit;
+use crate::batch::BatchReport;
+use crate::bootstrap;
+use crate::build_info;
+use crate::builds::{self, BuildTools};
+use crate::cache;
+use crate::checksum::sha256_file;
+use crate::config::{Config, Emulation, LinkStrategy};
+use crate::container::{self, ImagePackage};
+use crate::diff::{self, Change, IndexChanges};
+use crate::doctor;
+use crate::events;
+use crate::extract;
+use crate::fault;
+use crate::fonts;
+use crate::gc;
+use crate::harness;
+use crate::http;
+use crate::installer::{Download, Installer};
+use crate::interrupt;
+use crate::journal;
+use crate::layout;
+use crate::links;
+use crate::lock::{self, FileLock};
+use crate::manifest::{self, ExposedFile, Issue, Problem};
+use crate::migrate;
+use crate::modulefile;
+use crate::overlay;
+use crate::package::{
+    self, BinFilter, FileCategory, IndexError, Package, PlatformDetails, Retirement,
+};
+use crate::platform;
+use crate::progress;
+use crate::prompt::{self, Mode};
+use crate::relocate;
+use crate::report;
+use crate::sbom::{self, SbomFormat};
+use crate::self_update;
+use crate::service;
+use crate::shadow;
+use crate::shim::{shim_package, shim_target, write_shim};
+use crate::status;
+use crate::toolchain::{Toolchain, VERSIONS_FILE};
+use crate::trail::{self, Snapshot};
+use crate::trust::{self, Trust};
+use crate::usage;
+use crate::utils::{
+    init_log, is_env_name, log_path, plain_file_name, print_error, print_info, print_step,
+    print_success, print_warning, relative_path, shell_quote, write_atomic,
+};
+use crate::version::{compare_versions, matches_spec, split_spec};
+use crate::wsl;
 use anyhow::{Context, Result, anyhow};
+use colored::Colorize;
+use indicatif::HumanBytes;
 use serde_json::Value;
-use std::collections::HashMap;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::env;
+use std::path::{Component, Path, PathBuf};
+use std::time::Duration;
 use tokio::fs;
+use tokio::io::AsyncWriteExt;
+use tokio::task::JoinHandle;
+
+/// How long `leaf status` waits to hear about new leaf releases
+const STATUS_CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+struct UpgradePlanEntry {
+    name: String,
+    current_version: String,
+    new_version: String,
+    download_size: Option<u64>,
+}
+
+/// An executable of an installed package, for `leaf expose`, `unexpose` and `link`
+struct InstalledExecutable {
+    key: String,
+    path: PathBuf,
+    /// Usual name, from the package definition
+    name: String,
+    /// Name in bin_dir, which `leaf link --as` may have changed
+    bin_name: String,
+}
+
+/// Restricts search results by whether a package is installed locally
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InstallStateFilter {
+    Any,
+    Installed,
+    NotInstalled,
+    Outdated,
+}
 
 pub struct PackageManager {
     config: Config,
     packages: HashMap<String, Package>,
     installed: HashMap<String, Package>,
     platform: String,
+    // Installing for another machine's platform (--platform), not this one's
+    cross: bool,
+    /// Working on the root of the running leaf rather than one picked with
+    /// --prefix or --system, so upgrading "everything" includes leaf itself
+    own_root: bool,
+    /// How executables go into bin_dir: the config's choice unless `install --link` overrides it
+    link_strategy: LinkStrategy,
+    /// Which executables installs put in bin_dir (`install --only/--skip-bins`)
+    bin_filter: BinFilter,
+    /// Index packages left out because their definition needs a newer leaf,
+    /// with the version it needs
+    too_new: HashMap<String, String>,
+    /// Index entries that couldn't be read, with why; they are left out so the
+    /// rest of the index stays usable
+    unreadable: Vec<(String, String)>,
+    /// Packages whose definition comes from a local override (`leaf edit`)
+    /// rather than the index
+    overridden: HashSet<String>,
 }
 
 impl PackageManager {
-    pub async fn new() -> Result<Self> {
-        let config = Config::load_or_create().await?;
+    /// Set up the root at `prefix` (the user's own, at `default_root` or
+    /// ~/.local, without one), downloading the index if there is none yet and
+    /// `fetch_index` allows it
+    pub async fn new(
+        prefix: Option<&Path>,
+        default_root: Option<&Path>,
+        fetch_index: bool,
+    ) -> Result<Self> {
+        let config = Config::load_or_create(prefix, default_root).await?;
 
         // Ensure directories exist
         fs::create_dir_all(&config.packages_dir).await?;
         fs::create_dir_all(&config.cache_dir).await?;
         fs::create_dir_all(&config.bin_dir).await?;
 
-        let platform = format!("{}-{}", env::consts::OS, env::consts::ARCH);
+        fs::create_dir_all(config.install_dir.join("logs")).await?;
+        init_log(&log_path(&config.install_dir));
+        crate::i18n::set_language(config.locale.as_deref());
+        config.color.apply();
+        http::set_github_token(config.github_token.as_deref());
+
+        let platform = platform::current();
+        let link_strategy = config.link_strategy;
 
         let mut pm = PackageManager {
             config,
             packages: HashMap::new(),
             installed: HashMap::new(),
             platform,
+            cross: false,
+            own_root: true,
+            link_strategy,
+            bin_filter: BinFilter::default(),
+            too_new: HashMap::new(),
+            unreadable: Vec::new(),
+            overridden: HashSet::new(),
         };
 
-        pm.load_packages().await?;
+        if fetch_index || pm.config.install_dir.join("packages.json").exists() {
+            pm.load_packages().await?;
+        }
         pm.load_installed().await?;
 
         Ok(pm)
@@ -43,33 +168,62 @@ impl PackageManager {
         let packages_file = self.config.install_dir.join("packages.json");
 
         if !packages_file.exists() {
-            Box::pin(self.update_packages()).await?;
+            Box::pin(self.update_packages(false, &[])).await?;
         }
 
         if packages_file.exists() {
             let content = fs::read_to_string(&packages_file).await?;
 
             if content.trim().is_empty() {
-                print_error("Downloaded packages.json is empty");
+                print_error(&tr!("index.empty"));
                 return Ok(());
             }
 
             if content.trim_start().starts_with("<!DOCTYPE html>")
                 || content.trim_start().starts_with("<html")
             {
-                print_error("Downloaded packages.json appears to be HTML instead of JSON");
+                print_error(&tr!("index.html"));
                 fs::remove_file(&packages_file).await.ok();
                 return Ok(());
             }
 
-            match serde_json::from_str::<HashMap<String, Package>>(&content) {
-                Ok(packages) => {
+            match package::parse_index(&content, build_info::VERSION) {
+                Ok(index) => {
+                    let mut packages = index.packages;
+                    let overrides = overlay::load(&self.config.overlay_dir());
+                    let local =
+                        package::parse_definitions(overrides.definitions, build_info::VERSION);
+                    self.overridden = local.packages.keys().cloned().collect();
+                    packages.extend(local.packages);
+                    for package in packages.values_mut() {
+                        package.apply_defaults();
+                        // This CPU's features say nothing about another machine's
+                        if !self.cross {
+                            package.select_variants(platform::has_cpu_feature);
+                        }
+                    }
                     self.packages = packages;
+                    self.too_new = index.too_new;
+                    self.unreadable = index.invalid;
+                    // A broken override leaves the index's definition in place
+                    self.unreadable.extend(overrides.broken);
+                    let needs_newer = local.too_new.into_iter().map(|(name, required)| {
+                        (name, format!("needs leaf {} or newer", required))
+                    });
+                    for (name, e) in local.invalid.into_iter().chain(needs_newer) {
+                        let reason = match overrides.paths.get(&name) {
+                            Some(path) => format!("{}: {}", path.display(), e),
+                            None => e,
+                        };
+                        self.unreadable.push((name, reason));
+                    }
                 }
+                // Leave the index empty, so that self-update still works
+                Err(e @ IndexError::NewerFormat(_)) => print_error(&tr!("index.newer_format", e)),
                 Err(e) => {
-                    print_error(&format!("Failed to parse packages.json: {}", e));
+                    print_error(&tr!("index.parse_failed", e));
                     let preview = content.chars().take(200).collect::<String>();
-                    print_error(&format!("Content preview: '{}'", preview));
+                    print_error(&tr!("index.preview", preview));
                     return Err(anyhow::anyhow!("Invalid packages.json format"));
                 }
             }
@@ -85,7 +239,9 @@ impl PackageManager {
 
         let mut entries = fs::read_dir(&self.config.packages_dir).await?;
         while let Some(entry) = entries.next_entry().await? {
-            if entry.file_type().await?.is_dir() {
+            // Follows symlinks, which point packages at the shared store. Dot
+            // names are installs and upgrades in progress.
+            if entry.path().is_dir() && !entry.file_name().to_string_lossy().starts_with('.') {
                 let package_name = entry.file_name().to_string_lossy().to_string();
                 let metadata_file = entry.path().join("leaf-package.json");
 
@@ -102,400 +258,3923 @@ impl PackageManager {
     }
 
     fn get_platform_details<'a>(&self, package: &'a Package) -> Result<&'a PlatformDetails> {
-        let platform_key = match self.platform.as_str() {
-            "linux-x86_64" => "linux-x86_64",
-            "linux-aarch64" => "linux-aarch64",
-            _ => {
-                return Err(anyhow!(
-                    "Unsupported platform: {}. Leaf only supports Linux.",
-                    self.platform
-                ));
-            }
-        };
-
-        package
-            .platforms
-            .get(platform_key)
-            .ok_or_else(|| anyhow!("Package not available for platform {}", self.platform))
+        self.platform_entry(package).map(|(_, details, _)| details)
     }
 
-    pub async fn install_package(&mut self, name: &str) -> Result<()> {
-        if self.installed.contains_key(name) {
-            print_warning(&format!("Package '{}' is already installed", name));
-            return Ok(());
+    /// The platform key a package's build is taken from, that build, and
+    /// whether it runs under emulation
+    fn platform_entry<'a>(
+        &self,
+        package: &'a Package,
+    ) -> Result<(&'a str, &'a PlatformDetails, bool)> {
+        if !platform::SUPPORTED.contains(&self.platform.as_str()) {
+            return Err(anyhow!(
+                "Unsupported platform: {}. Leaf supports {}.",
+                self.platform,
+                platform::SUPPORTED.join(", ")
+            ));
         }
 
-        let package = self
-            .packages
-            .get(name)
-            .ok_or_else(|| anyhow::anyhow!("Package '{}' not found", name))?
-            .clone();
-
-        let platform_details = self.get_platform_details(&package)?;
-
-        print_info(&format!("Installing {} for {}...", name, self.platform));
-
-        let installer = Installer::new();
-        installer
-            .install_package(name, &package, platform_details, &self.config)
-            .await?;
-
-        // Create symlinks for executables
-        let package_dir = self.config.packages_dir.join(name);
-        for executable_info in platform_details.get_executables() {
-            let exe_path = package_dir.join(&executable_info.path);
-
-            let default_name = exe_path
-                .file_name()
-                .unwrap_or_default()
-                .to_string_lossy()
-                .to_string();
+        // Packages installed with --platform keep using that platform's entry
+        let platform = package
+            .provenance
+            .as_ref()
+            .and_then(|provenance| provenance.platform.as_deref())
+            .unwrap_or(&self.platform);
+        match package.resolve_platform(platform) {
+            Some((key, details, false)) => Ok((key, details, false)),
+            Some((key, details, true)) if self.config.emulation != Emulation::Never => {
+                Ok((key, details, true))
+            }
+            _ => Err(anyhow!(
+                "{}",
+                platform::Unsupported::new(platform, package.platforms.keys())
+            )),
+        }
+    }
 
-            let symlink_name = executable_info.name.as_ref().unwrap_or(&default_name);
-            let symlink_path = self.config.bin_dir.join(symlink_name);
+    pub async fn install_packages(&mut self, names: &[String], fail_fast: bool) -> Result<()> {
+        let names = &self.disambiguate(names)?;
+        if let [name] = names.as_slice() {
+            return self.install_package(name).await;
+        }
 
-            if exe_path.exists() {
-                if symlink_path.exists() {
-                    fs::remove_file(&symlink_path).await?;
-                }
+        let mut report = BatchReport::new("install", names.len());
+        let installer = Installer::new(&self.config).background();
+        let mut prefetch: Option<JoinHandle<()>> = None;
+        let batch = progress::Batch::new(names.len(), self.config.progress_bar)?;
+        for (i, name) in names.iter().enumerate() {
+            print_step(&tr!("install.batch_step", i + 1, names.len(), name));
+            // Let this package's download finish so the install finds it in the cache,
+            // then start on the next one while this one extracts
+            if let Some(handle) = prefetch.take() {
+                handle.await.ok();
+            }
+            prefetch = names
+                .get(i + 1)
+                .and_then(|next| self.start_prefetch(&installer, next));
 
-                use tokio::fs::symlink;
-                symlink(&exe_path, &symlink_path).await?;
+            let result = self.install_package(name).await;
+            if let Err(e) = &result {
+                print_error(&tr!("install.failed", name, e));
             }
+            report.record(name, &result);
+            batch.inc();
+            if fail_fast && report.has_failures() {
+                break;
+            }
+        }
+        if let Some(handle) = prefetch {
+            handle.abort();
         }
+        drop(batch);
 
-        // Save package metadata
-        let metadata_file = package_dir.join("leaf-package.json");
-        let metadata = serde_json::to_string_pretty(&package)?;
-        fs::write(&metadata_file, metadata).await?;
+        report.finish()
+    }
 
-        self.installed.insert(name.to_string(), package);
+    /// Download artifacts into the cache without installing them, so they can be
+    /// installed later without network access
+    pub async fn prefetch_packages(&self, names: &[String]) -> Result<()> {
+        fs::create_dir_all(&self.config.cache_dir).await?;
+        let installer = Installer::new(&self.config);
+        let mut report = BatchReport::new("prefetch", names.len());
+        for name in names {
+            let result = self.prefetch_package(&installer, name).await;
+            if let Err(e) = &result {
+                print_error(&tr!("prefetch.failed", name, e));
+            }
+            report.record(name, &result);
+        }
+        report.finish()
+    }
 
-        print_success(&format!("Successfully installed {}", name));
+    async fn prefetch_package(&self, installer: &Installer, name: &str) -> Result<()> {
+        let (key, package, download) = self.fetch_artifact(installer, name).await?;
+        print_info(&tr!("prefetch.done", key, package.version, download.sha256));
         Ok(())
     }
 
-    pub async fn remove_package(&mut self, name: &str) -> Result<()> {
-        if !self.installed.contains_key(name) {
-            print_warning(&format!("Package '{}' is not installed", name));
-            return Ok(());
-        }
+    /// Download the artifact an install of `name` would use into the cache and
+    /// verify it against whatever checksums and signatures the package declares
+    async fn fetch_artifact(
+        &self,
+        installer: &Installer,
+        name: &str,
+    ) -> Result<(String, Package, Download)> {
+        let (resolved, spec, key) = self.resolve_spec(name);
+        let package = self.index_package(&resolved, spec)?;
+        let platform_details = self.get_platform_details(&package)?;
+        let sources = platform_details.sources();
+        self.config.policy.check(&resolved, &sources)?;
 
-        print_info(&format!("Removing {}...", name));
+        let download = installer
+            .download_any(&sources, &self.config.cache_dir)
+            .await?;
+        installer
+            .verify_published_checksum(platform_details, &download)
+            .await?;
+        installer
+            .verify_cosign(platform_details, &download, &self.config.cache_dir)
+            .await?;
+        Ok((key, package, download))
+    }
 
-        let package_dir = self.config.packages_dir.join(name);
+    /// Just the download step of an install, for mirroring artifacts and
+    /// debugging package definitions: fetch and verify `name`'s artifact, copy
+    /// it to `out` if given, and say where it came from
+    pub async fn download_package(&self, name: &str, out: Option<&Path>) -> Result<()> {
+        fs::create_dir_all(&self.config.cache_dir).await?;
+        let installer = Installer::new(&self.config);
+        let (key, package, download) = self.fetch_artifact(&installer, name).await?;
+        let file_name = download.path.file_name().unwrap_or_default();
 
-        // Remove symlinks
-        if let Some(package) = self.installed.get(name) {
-            if let Ok(platform_details) = self.get_platform_details(package) {
-                for executable_info in platform_details.get_executables() {
-                    let exe_path = package_dir.join(&executable_info.path);
-                    let default_name = exe_path
-                        .file_name()
-                        .unwrap_or_default()
-                        .to_string_lossy()
-                        .to_string();
+        let path = match out {
+            Some(out) => {
+                let out = if out.is_dir() {
+                    out.join(file_name)
+                } else {
+                    out.to_path_buf()
+                };
+                fs::copy(&download.path, &out).await?;
+                out
+            }
+            None => download.path.clone(),
+        };
 
-                    let symlink_name = executable_info.name.as_ref().unwrap_or(&default_name);
-                    let symlink_path = self.config.bin_dir.join(symlink_name);
+        let platform_details = self.get_platform_details(&package)?;
+        println!("  Package: {} {}", key, package.version);
+        println!("  URL: {}", platform_details.url);
+        println!("  Resolved URL: {}", download.resolved_url);
+        println!("  File: {}", file_name.to_string_lossy());
+        println!("  Path: {}", path.display());
+        println!("  SHA-256: {}", download.sha256);
+        Ok(())
+    }
 
-                    if symlink_path.exists() {
-                        fs::remove_file(&symlink_path).await?;
-                    }
-                }
-            }
-        }
+    /// Show which platform entry, URL, type and executables an install of
+    /// `name` would use here, after fallbacks, emulation and variant selection
+    pub fn resolve_package(&self, name: &str) -> Result<()> {
+        let (resolved, spec, key) = self.resolve_spec(name);
+        let package = self.index_package(&resolved, spec)?;
+        let (platform_key, details, emulated) = self.platform_entry(&package)?;
 
-        // Remove package directory
-        if package_dir.exists() {
-            fs::remove_dir_all(&package_dir).await?;
+        println!("  Package: {} {}", key, package.version);
+        match (platform_key == self.platform, emulated) {
+            (true, _) => println!("  Platform: {}", platform_key),
+            (false, false) => println!("  Platform: {} (for {})", platform_key, self.platform),
+            (false, true) => println!(
+                "  Platform: {} (for {}, under emulation)",
+                platform_key, self.platform
+            ),
         }
+        if !details.variant_features.is_empty() {
+            println!("  Variant: {}", details.variant_features.join(", "));
+        } else if details.variants.is_some() {
+            println!("  Variant: baseline (variants are picked on the machine that installs)");
+        }
+        println!("  URL: {}", details.url);
+        for mirror in details.mirrors.iter().flatten() {
+            println!("  Mirror: {}", mirror);
+        }
+        println!(
+            "  Type: {}",
+            details.package_type.as_deref().unwrap_or("archive")
+        );
+        if let Some(checksum_url) = &details.checksum_url {
+            println!("  Checksums: {}", checksum_url);
+        }
+        if let Some(cosign) = &details.cosign {
+            println!("  Signed by: {} ({})", cosign.identity, cosign.issuer);
+        }
+        println!("  Executables:");
+        for executable in details.get_executables() {
+            println!("    {} -> {}", executable.exposed_name(), executable.path);
+        }
+        Ok(())
+    }
 
-        self.installed.remove(name);
-
-        print_success(&format!("Successfully removed {}", name));
+    /// Unpack an archive outside of any install, with the same formats and
+    /// protection against members escaping `dest`
+    pub async fn extract_archive(&self, archive: &Path, dest: &Path, strip: usize) -> Result<()> {
+        if !archive.is_file() {
+            return Err(anyhow!("Archive {} not found", archive.display()));
+        }
+        tokio::task::spawn_blocking({
+            let (archive, dest) = (archive.to_path_buf(), dest.to_path_buf());
+            move || extract::extract_stripped(&archive, &dest, strip)
+        })
+        .await??;
+        print_success(&tr!("extract.done", archive.display(), dest.display()));
         Ok(())
     }
 
-    pub async fn list_packages(&self) -> Result<()> {
-        if self.installed.is_empty() {
-            print_info("No packages installed");
+    /// Re-hash the download cache, deleting artifacts that no longer match what
+    /// was downloaded so they aren't installed from a damaged copy
+    pub async fn verify_cache(&self) -> Result<()> {
+        if !self.config.cache_dir.exists() {
+            print_info(&tr!("cache.empty"));
             return Ok(());
         }
-
-        print_info("Installed packages:");
-        for (name, package) in &self.installed {
-            println!("  {} - {} ({})", name, package.description, package.version);
+        let verified = tokio::task::spawn_blocking({
+            let cache_dir = self.config.cache_dir.clone();
+            move || cache::verify(&cache_dir)
+        })
+        .await??;
+        for file in &verified.corrupted {
+            print_warning(&tr!("cache.corrupted", file));
         }
-
+        if verified.missing > 0 {
+            print_info(&tr!("cache.missing", verified.missing));
+        }
+        if verified.partial > 0 {
+            print_info(&tr!("cache.partial", verified.partial));
+        }
+        print_success(&tr!(
+            "cache.verified",
+            verified.intact,
+            verified.corrupted.len()
+        ));
         Ok(())
     }
 
-    pub async fn search_packages(&self, term: &str) -> Result<()> {
-        let mut found = Vec::new();
-        let term_lower = term.to_lowercase();
+    /// Try definitions from `index` (by default the installed packages.json) by
+    /// installing and removing each package in a throwaway root
+    pub async fn test_packages(&self, names: &[String], index: Option<&Path>) -> Result<()> {
+        let index = index.map_or_else(
+            || self.config.install_dir.join("packages.json"),
+            Path::to_path_buf,
+        );
+        let content = fs::read_to_string(&index)
+            .await
+            .with_context(|| format!("Failed to read {}", index.display()))?;
+        let mut report = BatchReport::new("test", names.len());
+        for name in names {
+            print_step(&tr!("test.package", name));
+            let result = harness::test_definition(&content, name).await;
+            if let Err(e) = &result {
+                print_error(&tr!("test.failed", name, e));
+            }
+            report.record(name, &result);
+        }
+        report.finish()
+    }
 
-        for (name, package) in &self.packages {
-            if !package.platforms.contains_key(&self.platform) {
-                continue;
+    /// Print the latest build log of `name`, or list them all; with `follow`,
+    /// keep printing what a running build adds until Ctrl-C
+    pub async fn show_logs(&self, name: &str, follow: bool, list: bool) -> Result<()> {
+        let logs_dir = self.config.install_dir.join("logs");
+        let logs = builds::logs(&logs_dir, name);
+        let Some(latest) = logs.last() else {
+            print_info(&tr!("logs.none", name));
+            return Ok(());
+        };
+        if list {
+            for path in &logs {
+                println!("{}", path.display());
             }
+            return Ok(());
+        }
+        let mut file = fs::File::open(latest).await?;
+        let mut stdout = tokio::io::stdout();
+        loop {
+            tokio::io::copy(&mut file, &mut stdout).await?;
+            stdout.flush().await?;
+            if !follow {
+                return Ok(());
+            }
+            tokio::time::sleep(Duration::from_millis(500)).await;
+        }
+    }
 
-            let matches_name = name.to_lowercase().contains(&term_lower);
-            let matches_desc = package.description.to_lowercase().contains(&term_lower);
-            let matches_tags = package.tags.as_ref().map_or(false, |tags| {
-                tags.iter()
-                    .any(|tag| tag.to_lowercase().contains(&term_lower))
-            });
+    /// Download `name` into the cache in the background. Skipped for anything
+    /// install_package wouldn't download; errors are left for it to report.
+    fn start_prefetch(&self, installer: &Installer, name: &str) -> Option<JoinHandle<()>> {
+        let (resolved, spec, key) = self.resolve_spec(name);
+        if self.installed.contains_key(&key) {
+            return None;
+        }
+        let package = self.index_package(&resolved, spec).ok()?;
+        if self.shared_copy(&key, &package).is_some() {
+            return None;
+        }
+        let platform_details = self.get_platform_details(&package).ok()?;
+        let sources = platform_details.sources();
+        self.config.policy.check(&resolved, &sources).ok()?;
+        let sources: Vec<String> = sources.into_iter().map(String::from).collect();
 
-            if matches_name || matches_desc || matches_tags {
-                found.push((name, package));
+        let installer = installer.labeled(&key);
+        let cache_dir = self.config.cache_dir.clone();
+        Some(tokio::spawn(async move {
+            if fs::create_dir_all(&cache_dir).await.is_ok() {
+                let sources: Vec<&str> = sources.iter().map(String::as_str).collect();
+                installer.download_any(&sources, &cache_dir).await.ok();
             }
-        }
+        }))
+    }
 
-        if found.is_empty() {
-            print_info(&format!(
-                "No packages found matching '{}' for your platform",
-                term
+    /// Expose executables with `strategy` instead of the configured one, for this run only
+    pub fn set_link_strategy(&mut self, strategy: LinkStrategy) {
+        self.link_strategy = strategy;
+    }
+
+    /// Whether this run works on the running leaf's own root; --prefix and
+    /// --system pick another
+    pub fn set_own_root(&mut self, own_root: bool) {
+        self.own_root = own_root;
+    }
+
+    /// Link only some executables of the packages installed in this run
+    pub fn set_bin_filter(&mut self, filter: BinFilter) {
+        self.bin_filter = filter;
+    }
+
+    /// Install and download for `platform` instead of this machine's, e.g. to
+    /// provision a prefix that will be copied elsewhere
+    pub async fn set_platform(&mut self, platform: &str) -> Result<()> {
+        if !platform::SUPPORTED.contains(&platform) {
+            return Err(anyhow!(
+                "Unknown platform {}; expected one of {}",
+                platform,
+                platform::SUPPORTED.join(", ")
             ));
+        }
+        if platform == self.platform {
             return Ok(());
         }
+        self.platform = platform.to_string();
+        self.cross = true;
+        // Reload so variants aren't picked by this CPU's features
+        self.load_packages().await
+    }
 
-        print_info(&format!("Found {} package(s):", found.len()));
-        for (name, package) in found {
-            let installed = if self.installed.contains_key(name) {
-                " [INSTALLED]"
-            } else {
-                ""
-            };
-            println!(
-                "  {}{} - {} ({})",
-                name, installed, package.description, package.version
-            );
-            if let Some(tags) = &package.tags {
-                if !tags.is_empty() {
-                    println!("    Tags: {}", tags.join(", "));
-                }
+    /// Replace names that match no package with the packages they are part of,
+    /// letting the user pick when a terminal is attached (e.g. "python" for
+    /// python3.11, python3.12 and pypy's "python" alias)
+    fn disambiguate(&self, names: &[String]) -> Result<Vec<String>> {
+        let mut chosen = Vec::new();
+        for name in names {
+            let (base, spec) = split_spec(name);
+            let resolved = self.resolve_name(base);
+            let candidates = self.candidates(&resolved);
+            if self.packages.contains_key(&resolved)
+                || self.installed.contains_key(&resolved)
+                || self.too_new.contains_key(&resolved)
+                || self.unreadable.iter().any(|(name, _)| *name == resolved)
+                || candidates.is_empty()
+            {
+                chosen.push(name.clone());
+                continue;
+            }
+            if prompt::mode() != Mode::Ask {
+                return Err(anyhow!(
+                    "'{}' matches no package exactly; candidates: {}",
+                    base,
+                    candidates.join(", ")
+                ));
+            }
+
+            print_info(&tr!("install.ambiguous", base));
+            let selected = prompt::select(&tr!("install.choose"), &candidates)?;
+            if selected.is_empty() {
+                return Err(anyhow!("No package selected for '{}'", base));
+            }
+            for index in selected {
+                chosen.push(match spec {
+                    Some(spec) => format!("{}@{}", candidates[index], spec),
+                    None => candidates[index].clone(),
+                });
             }
         }
+        Ok(chosen)
+    }
 
-        Ok(())
+    /// Packages for this platform whose name or an alias contains `term`
+    fn candidates(&self, term: &str) -> Vec<String> {
+        let term = term.to_lowercase();
+        let mut candidates: Vec<String> = self
+            .packages
+            .iter()
+            .filter(|(_, package)| self.get_platform_details(package).is_ok())
+            .filter(|(name, package)| {
+                name.to_lowercase().contains(&term)
+                    || package
+                        .aliases
+                        .iter()
+                        .flatten()
+                        .any(|alias| alias.to_lowercase().contains(&term))
+            })
+            .map(|(name, _)| name.clone())
+            .collect();
+        candidates.sort();
+        candidates
     }
 
-    pub async fn update_packages(&mut self) -> Result<()> {
-        print_info("Updating package definitions...");
+    /// The index entry for a package, narrowed to the release matching `spec`
+    fn index_package(&self, resolved: &str, spec: Option<&str>) -> Result<Package> {
+        let available = self
+            .packages
+            .get(resolved)
+            .ok_or_else(|| self.not_in_index(resolved))?;
+        match spec {
+            Some(spec) => available
+                .with_version(spec)
+                .ok_or_else(|| anyhow!("Version '{}' of package '{}' not found", spec, resolved)),
+            None => Ok(available.clone()),
+        }
+    }
 
-        let packages_url =
-            "https://raw.githubusercontent.com/ktauchathuranga/leaf/main/packages.json";
-        let packages_file = self.config.install_dir.join("packages.json");
+    /// Error for a package the index doesn't offer, pointing at self-update
+    /// when its definition needs a newer leaf
+    fn not_in_index(&self, name: &str) -> anyhow::Error {
+        match self.too_new.get(name) {
+            Some(required) => anyhow!(
+                "Package '{}' needs leaf {} or newer (this is {}); run `leaf self-update`",
+                name,
+                required,
+                build_info::VERSION
+            ),
+            None => match self
+                .unreadable
+                .iter()
+                .find(|(unreadable, _)| unreadable == name)
+            {
+                Some((_, e)) => anyhow!(
+                    "The index's definition of '{}' cannot be read by this leaf: {}",
+                    name,
+                    e
+                ),
+                None => anyhow!("Package '{}' not found", name),
+            },
+        }
+    }
 
-        let client = reqwest::Client::builder()
-            .user_agent("leaf-package-manager/1.0.0")
-            .build()?;
+    /// Map an alias to its package name; user aliases from config take precedence
+    /// over aliases declared by package definitions. When several definitions
+    /// claim the same alias, the first package by name gets it.
+    fn resolve_name(&self, name: &str) -> String {
+        if self.packages.contains_key(name) || self.installed.contains_key(name) {
+            return name.to_string();
+        }
 
-        match client.get(packages_url).send().await {
-            Ok(response) => {
-                let status = response.status();
-                if status.is_success() {
-                    let content = response.text().await?;
+        if let Some(target) = self.config.aliases.get(name) {
+            return target.clone();
+        }
 
-                    match serde_json::from_str::<Value>(&content) {
-                        Ok(_) => {
-                            fs::write(&packages_file, &content).await?;
-                            self.packages.clear();
-                            self.load_packages().await?;
-                            print_success("Package definitions updated successfully");
-                            Ok(())
-                        }
-                        Err(e) => Err(anyhow::anyhow!(
-                            "Downloaded content is not valid JSON: {}",
-                            e
-                        )),
-                    }
-                } else {
-                    let error_body = response
-                        .text()
-                        .await
-                        .unwrap_or_else(|_| "Could not read error body".to_string());
-                    Err(anyhow::anyhow!(
-                        "Failed to download packages.json: HTTP {} - {}",
-                        status,
-                        error_body
-                    ))
+        self.packages
+            .iter()
+            .chain(self.installed.iter())
+            .filter(|(_, package)| {
+                package
+                    .aliases
+                    .as_ref()
+                    .is_some_and(|aliases| aliases.iter().any(|a| a == name))
+            })
+            .map(|(package_name, _)| package_name)
+            .min()
+            .map_or_else(|| name.to_string(), |package_name| package_name.clone())
+    }
+
+    /// Agree to installing a build that only runs under emulation, as config.json's
+    /// `emulation` setting says
+    fn confirm_emulation(&self, name: &str) -> Result<()> {
+        let emulated = platform::emulated_by(&self.platform).unwrap_or_default();
+        match self.config.emulation {
+            Emulation::Ask => {
+                print_warning(&tr!(
+                    "emulation.warning",
+                    name,
+                    emulated,
+                    platform::emulator_name(&self.platform)
+                ));
+                let agreed = prompt::yes_no(&tr!("emulation.confirm")).map_err(|_| {
+                    anyhow!(
+                        "{} has no {} build, only {}; pass --yes or set \"emulation\": \"always\" in config.json to install it anyway",
+                        name,
+                        self.platform,
+                        emulated
+                    )
+                })?;
+                if !agreed {
+                    return Err(anyhow!("Installation of {} cancelled", name));
                 }
             }
-            Err(e) => Err(anyhow::anyhow!(
-                "Network error downloading packages.json: {}",
-                e
+            _ => print_warning(&tr!(
+                "emulation.warning",
+                name,
+                emulated,
+                platform::emulator_name(&self.platform)
             )),
         }
+        Ok(())
     }
 
-    pub async fn nuke_everything(&self, confirmed: bool) -> Result<()> {
-        if !confirmed {
-            print_error("This will completely remove all packages and Leaf itself!");
-            print_error("This action cannot be undone.");
-            print_error("");
-            print_error("If you're sure, run: leaf nuke --confirmed");
-            return Ok(());
-        }
-
-        print_warning("NUCLEAR OPTION ACTIVATED!");
-        print_warning("Removing all packages and Leaf itself...");
-
-        // Remove all symlinks in bin directory
-        if self.config.bin_dir.exists() {
-            let mut entries = fs::read_dir(&self.config.bin_dir).await?;
-            while let Some(entry) = entries.next_entry().await? {
-                let path = entry.path();
+    /// Resolve a `name[@version]` argument into the package name, the requested version
+    /// and the key it is installed under. Versioned installs live in their own
+    /// `name@version` directory so several releases of one package can coexist.
+    pub fn resolve_spec<'a>(&self, name: &'a str) -> (String, Option<&'a str>, String) {
+        let (base, spec) = split_spec(name);
+        let resolved = self.resolve_name(base);
+        let key = match spec {
+            Some(spec) => format!("{}@{}", resolved, spec),
+            None => resolved.clone(),
+        };
+        (resolved, spec, key)
+    }
 
-                if path.is_symlink() {
-                    if let Ok(target) = fs::read_link(&path).await {
-                        if target.to_string_lossy().contains("leaf/packages") {
-                            fs::remove_file(&path).await?;
-                            print_info(&format!("Removed symlink: {}", path.display()));
-                        }
-                    }
-                }
+    /// Collect the PATH entries and env vars a source build gets from its
+    /// build_dependencies. `chain` holds the packages waiting on this build, so
+    /// dependencies that lead back to one of them are caught.
+    async fn prepare_build_dependencies(
+        &self,
+        name: &str,
+        platform_details: &PlatformDetails,
+        chain: &[String],
+    ) -> Result<BuildTools> {
+        let mut tools = BuildTools::default();
+        let Some(specs) = &platform_details.build_dependencies else {
+            return Ok(tools);
+        };
+        if self.cross {
+            return Err(anyhow!(
+                "'{}' is built with other leaf packages, which can't be fetched for {} to run here",
+                name,
+                self.platform
+            ));
+        }
+        let chain = [chain, &[name.to_string()]].concat();
+        for spec in specs {
+            let (resolved, spec, key) = self.resolve_spec(spec);
+            if chain.contains(&key) {
+                return Err(anyhow!(
+                    "Build dependencies form a loop: {} -> {}",
+                    chain.join(" -> "),
+                    key
+                ));
             }
+            let (dir, details) = self.package_for_run(&resolved, spec, &key, &chain).await?;
+            tools.path.extend(details.bin_dirs(&dir));
+            tools.env.extend(details.get_env(&dir));
+            tools.dirs.insert(resolved, dir);
         }
+        Ok(tools)
+    }
 
-        // Remove the entire leaf directory
-        if self.config.install_dir.exists() {
-            fs::remove_dir_all(&self.config.install_dir).await?;
-            print_info(&format!(
-                "Removed leaf directory: {}",
-                self.config.install_dir.display()
+    /// Where `key` can be run from: its package directory when installed,
+    /// otherwise a copy unpacked into the cache (once) without installing it
+    async fn package_for_run(
+        &self,
+        resolved: &str,
+        spec: Option<&str>,
+        key: &str,
+        chain: &[String],
+    ) -> Result<(PathBuf, PlatformDetails)> {
+        if let Some(package) = self.installed.get(key) {
+            return Ok((
+                self.config.packages_dir.join(key),
+                self.get_platform_details(package)?.clone(),
             ));
         }
+        let package = self.index_package(resolved, spec)?;
+        let platform_details = self.get_platform_details(&package)?.clone();
+        self.config
+            .policy
+            .check(resolved, &platform_details.sources())?;
+        let exec_dir = self
+            .config
+            .cache_dir
+            .join("exec")
+            .join(resolved)
+            .join(&package.version);
 
-        print_success("Leaf and all packages have been nuked!");
-        print_info("To complete the uninstallation, please remove the executable:");
-        print_info(&format!(
-            "  rm {}",
-            self.config.bin_dir.join("leaf").display()
-        ));
+        if !exec_dir.exists() {
+            print_info(&tr!("exec.fetching", key));
+            let build_tools =
+                Box::pin(self.prepare_build_dependencies(key, &platform_details, chain)).await?;
+            Installer::new(&self.config)
+                .with_build_tools(build_tools)
+                .install_into(
+                    key,
+                    &package.version,
+                    &platform_details,
+                    &exec_dir,
+                    &self.config.cache_dir,
+                )
+                .await?;
+        }
+        Ok((exec_dir, platform_details))
+    }
 
-        Ok(())
+    pub async fn install_package(&mut self, name: &str) -> Result<()> {
+        let _lock = self.lock_installs().await?;
+        self.install_locked(name, None).await
     }
-    pub async fn self_update(&self, version: Option<&str>, prerelease: bool) -> Result<()> {
-        if version.is_some() && prerelease {
-            return Err(anyhow::anyhow!(
-                "Cannot specify both --version and --prerelease"
-            ));
-        }
 
-        print_info("Checking for new version of Leaf...");
+    /// Keep other leaf processes from installing into this root, or collecting
+    /// its garbage, until the lock is dropped
+    async fn lock_installs(&self) -> Result<FileLock> {
+        Ok(lock::acquire_async(self.config.install_dir.join("leaf.lock")).await?)
+    }
 
-        let client = reqwest::Client::builder()
-            .user_agent("leaf-package-manager/1.0.0")
-            .build()?;
-
-        let releases_url = "https://api.github.com/repos/ktauchathuranga/leaf/releases";
-        let response = client
-            .get(releases_url)
-            .header("Accept", "application/vnd.github+json")
-            .send()
-            .await?;
+    /// `install_package` for callers that already hold the install lock. An
+    /// upgrade passes the version it replaces as `previous`: executables it kept
+    /// out of bin_dir stay out, renamed ones keep their names, and the user's
+    /// notes stay with the package.
+    async fn install_locked(&mut self, name: &str, previous: Option<&Package>) -> Result<()> {
+        let (resolved, spec, key) = self.resolve_spec(name);
+        let (requested, _) = split_spec(name);
+        if resolved != requested {
+            print_info(&tr!("install.alias", requested, resolved));
+        }
+        let name = key.as_str();
 
-        if !response.status().is_success() {
-            return Err(anyhow::anyhow!(
-                "Failed to fetch releases: HTTP {}",
-                response.status()
-            ));
+        if self.installed.contains_key(name) {
+            print_warning(&tr!("install.already_installed", name));
+            return Ok(());
+        }
+
+        let mut package = self.index_package(&resolved, spec)?;
+        let filter = match previous {
+            Some(previous) => BinFilter::Except(previous.hidden_executables.clone()),
+            None => self.bin_filter.clone(),
+        };
+        package.hidden_executables = self.hidden_executables(name, &package, &filter)?;
+        if let Some(previous) = previous {
+            package.exposed_as = previous.exposed_as.clone();
+            package.notes = previous.notes.clone();
+        }
+        let platform_details = self.get_platform_details(&package)?;
+        // A blocked package is refused before the user is asked anything about it
+        self.config
+            .policy
+            .check(&resolved, &platform_details.sources())?;
+        if !self.cross && package.platform(&self.platform).is_none() {
+            self.confirm_emulation(&resolved)?;
+        }
+        if let Some(terms) = &package.license_terms {
+            accept_license(&resolved, terms)?;
         }
 
-        let releases: Vec<serde_json::Value> = response.json().await?;
+        print_info(&tr!("install.start", name, self.platform));
 
-        let current_version = env!("CARGO_PKG_VERSION");
-        let target_release = if let Some(v) = version {
-            // Handle specific version
-            let requested_version = v.trim_start_matches('v');
-            releases
-                .into_iter()
-                .find(|r| {
-                    r["tag_name"]
-                        .as_str()
-                        .map_or(false, |t| t == v || t == requested_version)
-                })
-                .ok_or_else(|| anyhow::anyhow!("Version {} not found", v))?
+        let package_dir = self.config.packages_dir.join(name);
+        // The shared store holds builds for this machine
+        let shared_dir = if self.cross {
+            None
         } else {
-            // Find latest stable or prerelease
-            releases
-                .into_iter()
-                .filter(|r| {
-                    if prerelease {
-                        r["prerelease"].as_bool().unwrap_or(false) // Only prerelease versions
-                    } else {
-                        !r["prerelease"].as_bool().unwrap_or(false) // Only stable releases
-                    }
-                })
-                .max_by(|a, b| {
-                    a["published_at"]
-                        .as_str()
-                        .unwrap_or("")
-                        .cmp(b["published_at"].as_str().unwrap_or(""))
-                })
-                .ok_or_else(|| {
-                    if prerelease {
-                        anyhow::anyhow!("No prerelease versions found")
-                    } else {
-                        anyhow::anyhow!("No stable releases found")
-                    }
-                })?
+            self.shared_copy(name, &package)
         };
+        let mut provenance = None;
+        match &shared_dir {
+            Some(shared_dir) => {
+                print_info(&tr!("install.shared", shared_dir.display()));
+                fs::symlink(shared_dir, &package_dir).await?;
+            }
+            None => {
+                let build_tools = self
+                    .prepare_build_dependencies(name, platform_details, &[])
+                    .await?;
+                let mut installer = Installer::new(&self.config)
+                    .with_build_tools(build_tools)
+                    .labeled(name);
+                if self.cross {
+                    installer = installer.for_platform(&self.platform);
+                }
+                let (downloaded, optimized) = installer
+                    .install_package(name, &package, platform_details, &self.config)
+                    .await?;
+                provenance = Some(downloaded);
+                if !optimized.originals.is_empty() {
+                    print_info(&tr!(
+                        "install.optimized",
+                        optimized.originals.len(),
+                        HumanBytes(optimized.bytes_saved)
+                    ));
+                }
+            }
+        }
 
-        let target_version = target_release["tag_name"]
-            .as_str()
-            .ok_or_else(|| anyhow::anyhow!("Invalid release data: missing tag_name"))?;
-
-        if target_version.trim_start_matches('v') == current_version && version.is_none() {
-            print_info("You are already running the latest version");
-            return Ok(());
+        // Save package metadata first so `leaf gc` doesn't take the directory for
+        // an orphan; the shared store already has it and is read-only
+        if shared_dir.is_none() {
+            let metadata_file = package_dir.join("leaf-package.json");
+            let metadata = serde_json::to_string_pretty(&Package {
+                provenance: provenance.clone(),
+                ..package.clone()
+            })?;
+            if let Err(e) = write_atomic(&metadata_file, &metadata) {
+                self.roll_back_install(name, &package, &package_dir, platform_details)
+                    .await;
+                return Err(e.into());
+            }
         }
 
-        if target_release["prerelease"].as_bool().unwrap_or(false) {
-            print_warning("Installing a prerelease version of Leaf");
+        // A failure while exposing the package takes back everything it got
+        if let Err(e) = self
+            .expose_package(
+                name,
+                &resolved,
+                spec,
+                &package,
+                platform_details,
+                shared_dir.is_some(),
+            )
+            .await
+        {
+            self.roll_back_install(name, &package, &package_dir, platform_details)
+                .await;
+            return Err(e);
         }
+        package.provenance = provenance;
 
+        usage::record_install(&self.config.install_dir, &resolved);
+
+        let caveats = package.caveats.clone();
+        self.installed.insert(name.to_string(), package);
+
+        print_success(&tr!("install.done", name));
+        if let Some(caveats) = caveats {
+            print_caveats(name, &caveats);
+        }
+        Ok(())
+    }
+
+    /// Executables of `package` that `filter` keeps out of bin_dir
+    fn hidden_executables(
+        &self,
+        name: &str,
+        package: &Package,
+        filter: &BinFilter,
+    ) -> Result<Vec<String>> {
+        let names: Vec<String> = self
+            .get_platform_details(package)?
+            .get_executables()
+            .iter()
+            .map(|executable| executable.exposed_name())
+            .collect();
+        filter.hidden(&names).map_err(|unknown| {
+            anyhow!(
+                "{} has no executable named {} (it has {})",
+                name,
+                unknown,
+                names.join(", ")
+            )
+        })
+    }
+
+    /// Link a freshly unpacked package into bin and wherever else it exposes files
+    async fn expose_package(
+        &mut self,
+        name: &str,
+        resolved: &str,
+        spec: Option<&str>,
+        package: &Package,
+        platform_details: &PlatformDetails,
+        shared: bool,
+    ) -> Result<()> {
+        let package_dir = self.config.packages_dir.join(name);
+        fault::check("before-symlink")?;
+
+        // Create symlinks for executables
+        let claim_default = spec.is_some() && !self.config.default_versions.contains_key(resolved);
+        let mut exposed_names = Vec::new();
+        for executable_info in platform_details.get_executables() {
+            let exe_path = package_dir.join(&executable_info.path);
+            let exe_name = executable_info.exposed_name();
+            if !exe_path.exists() || package.hidden_executables.contains(&exe_name) {
+                continue;
+            }
+            let bin_name = package.bin_name(&executable_info);
+            exposed_names.extend(
+                self.link_into_bin(
+                    resolved,
+                    spec,
+                    claim_default,
+                    &exe_path,
+                    &exe_name,
+                    &bin_name,
+                )
+                .await?,
+            );
+        }
+        exposed_names.dedup();
+        self.expose_to_windows(&exposed_names).await?;
+
+        if let (true, Some(spec)) = (claim_default, spec) {
+            self.config
+                .default_versions
+                .insert(resolved.to_string(), spec.to_string());
+            self.config.save().await?;
+        }
+
+        if platform_details.package_type.as_deref() == Some("font") {
+            let fonts_dir = fonts::fonts_dir(&self.config);
+            let count = tokio::task::spawn_blocking({
+                let (package_dir, dest) = (package_dir.clone(), fonts_dir.join(name));
+                move || fonts::install(&package_dir, &dest)
+            })
+            .await??;
+            if !self.cross {
+                tokio::task::spawn_blocking(move || fonts::refresh_cache(&fonts_dir)).await?;
+            }
+            print_info(&tr!("install.fonts", count));
+        }
+
+        let exposed = self
+            .expose_extra_files(&package_dir, platform_details)
+            .await?;
+        if !shared && !exposed.is_empty() {
+            let package_dir = package_dir.clone();
+            tokio::task::spawn_blocking(move || manifest::set_exposed(&package_dir, exposed))
+                .await??;
+        }
+
+        modulefile::write(
+            &modulefile::modules_dir(&self.config.install_dir),
+            resolved,
+            package,
+            &package_dir,
+            platform_details,
+        )
+        .await?;
+
+        // Units would start the foreign build on this machine
+        if platform_details.services.is_some() && !self.cross {
+            service::write(&package_dir, &package.version, platform_details).await?;
+            print_info(&tr!("install.services", resolved));
+        }
+        fault::check("after-symlink")?;
+
+        Ok(())
+    }
+
+    /// Put one executable into bin_dir as `bin_name` with the version suffix and,
+    /// with shims or when `claim_default`, without. Shims run it by `exe_name`,
+    /// its usual name. Returns the names it got there.
+    async fn link_into_bin(
+        &self,
+        resolved: &str,
+        spec: Option<&str>,
+        claim_default: bool,
+        exe_path: &Path,
+        exe_name: &str,
+        bin_name: &str,
+    ) -> Result<Vec<String>> {
+        let suffix = spec.unwrap_or("");
+        if spec.is_some() || self.link_strategy != LinkStrategy::Shim {
+            let symlink_path = self.config.bin_dir.join(format!("{}{}", bin_name, suffix));
+            if !self.may_replace(&symlink_path, exe_path)? {
+                return Ok(Vec::new());
+            }
+            self.link_executable(exe_path, &symlink_path).await?;
+        }
+
+        // With shims the unsuffixed name always dispatches at runtime; otherwise the
+        // first versioned install provides it
+        let default_path = self.config.bin_dir.join(bin_name);
+        if self.link_strategy == LinkStrategy::Shim {
+            if shim_package(&default_path).as_deref() != Some(resolved) {
+                if !self.may_replace(&default_path, exe_path)? {
+                    return Ok(Vec::new());
+                }
+                write_shim(&self.config, &default_path, resolved, exe_name).await?;
+            }
+        } else if claim_default && fs::symlink_metadata(&default_path).await.is_err() {
+            self.link_executable(exe_path, &default_path).await?;
+        }
+        let mut names = vec![format!("{}{}", bin_name, suffix)];
+        if !suffix.is_empty() && fs::symlink_metadata(&default_path).await.is_ok() {
+            names.push(bin_name.to_string());
+        }
+        for name in &names {
+            self.check_shadowing(name)?;
+        }
+        Ok(names)
+    }
+
+    /// Warn when `name` in bin_dir shares its name with another executable on
+    /// PATH, saying which one runs, and record it for `leaf doctor`. Roots for
+    /// other machines or set up with --prefix aren't on this PATH.
+    fn check_shadowing(&self, name: &str) -> Result<()> {
+        if self.cross || self.config.prefix.is_some() {
+            return Ok(());
+        }
+        let Some(path) = env::var_os("PATH") else {
+            return Ok(());
+        };
+        let Some(found) = shadow::find(name, &self.config.bin_dir, &path) else {
+            return Ok(());
+        };
+        if found.leaf_first {
+            print_warning(&tr!("shadow.leaf_first", name, found.other.display()));
+        } else {
+            print_warning(&tr!("shadow.other_first", name, found.other.display()));
+        }
+        shadow::record(&self.config.install_dir, name, &found.other)
+    }
+
+    /// Take the bin_dir entries of one executable of installed package `key`
+    /// away. Plain-name shims go too unless another version still uses them.
+    async fn unlink_from_bin(&self, key: &str, exe_path: &Path, bin_name: &str) -> Result<()> {
+        let (resolved, spec) = split_spec(key);
+        let package_dir = self.config.packages_dir.join(key);
+        let other_versions_installed = self
+            .installed
+            .keys()
+            .any(|k| k != key && split_spec(k).0 == resolved);
+        let mut link_names = vec![bin_name.to_string()];
+        if let Some(spec) = spec {
+            link_names.push(format!("{}{}", bin_name, spec));
+        }
+
+        for link_name in link_names {
+            let symlink_path = self.config.bin_dir.join(&link_name);
+            let is_own_shim = shim_package(&symlink_path).as_deref() == Some(resolved)
+                && !other_versions_installed;
+            if is_own_shim
+                || link_points_into(&symlink_path, &package_dir)
+                || links::is_copy_of(&symlink_path, exe_path)
+            {
+                fs::remove_file(&symlink_path).await?;
+                shadow::forget(&self.config.install_dir, &link_name)?;
+                if let Some(dir) = &self.config.windows_bin_dir {
+                    wsl::remove_bat(dir, &link_name).await?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Undo a failed install: links and shims into the package, its extra files,
+    /// services, fonts and modulefile, the default version it claimed, and the
+    /// package itself
+    async fn roll_back_install(
+        &mut self,
+        name: &str,
+        package: &Package,
+        package_dir: &Path,
+        platform_details: &PlatformDetails,
+    ) {
+        let (resolved, spec) = split_spec(name);
+        // A shim for the package predates this install if another version has it
+        let own_shims = !self
+            .installed
+            .keys()
+            .any(|key| split_spec(key).0 == resolved);
+        let executables: Vec<PathBuf> = platform_details
+            .get_executables()
+            .iter()
+            .map(|executable| package_dir.join(&executable.path))
+            .collect();
+        if let Ok(entries) = std::fs::read_dir(&self.config.bin_dir) {
+            for entry in entries.flatten() {
+                let path = entry.path();
+                if link_points_into(&path, package_dir)
+                    || (own_shims && shim_package(&path).as_deref() == Some(resolved))
+                    || executables
+                        .iter()
+                        .any(|executable| links::is_copy_of(&path, executable))
+                {
+                    fs::remove_file(&path).await.ok();
+                    if let Some(dir) = &self.config.windows_bin_dir {
+                        wsl::remove_bat(dir, &entry.file_name().to_string_lossy())
+                            .await
+                            .ok();
+                    }
+                }
+            }
+        }
+        self.remove_extra_files(name, package_dir).await.ok();
+        service::remove(&package.version, platform_details)
+            .await
+            .ok();
+        let font_dir = fonts::fonts_dir(&self.config).join(name);
+        if fs::symlink_metadata(&font_dir).await.is_ok() {
+            fs::remove_dir_all(&font_dir).await.ok();
+        }
+        let version_installed = self
+            .installed
+            .iter()
+            .any(|(key, other)| split_spec(key).0 == resolved && other.version == package.version);
+        if !version_installed {
+            modulefile::remove(
+                &modulefile::modules_dir(&self.config.install_dir),
+                resolved,
+                &package.version,
+            )
+            .await
+            .ok();
+        }
+        if spec.is_some()
+            && self
+                .config
+                .default_versions
+                .get(resolved)
+                .map(String::as_str)
+                == spec
+        {
+            self.config.default_versions.remove(resolved);
+            self.config.save().await.ok();
+        }
+        match fs::symlink_metadata(package_dir).await {
+            Ok(metadata) if metadata.is_symlink() => fs::remove_file(package_dir).await.ok(),
+            Ok(_) => fs::remove_dir_all(package_dir).await.ok(),
+            Err(_) => None,
+        };
+    }
+
+    pub async fn remove_package(&mut self, name: &str) -> Result<()> {
+        // bin_dir and config.json mustn't change under an install in progress
+        let _lock = self.lock_installs().await?;
+        let (_, _, key) = self.resolve_spec(name);
+        let name = key.as_str();
+
+        if !self.installed.contains_key(name) {
+            print_warning(&tr!("remove.not_installed", name));
+            return Ok(());
+        }
+
+        print_info(&tr!("remove.start", name));
+        self.retract_package(name).await?;
+
+        // Remove package directory
+        let package_dir = self.config.packages_dir.join(name);
+        if package_dir.exists() {
+            fs::remove_dir_all(&package_dir).await?;
+        }
+
+        print_success(&tr!("remove.done", name));
+        Ok(())
+    }
+
+    /// Take installed package `name` out of bin_dir and everywhere else it is
+    /// exposed and forget it, leaving its directory alone
+    async fn retract_package(&mut self, name: &str) -> Result<()> {
+        let (resolved, spec) = split_spec(name);
+        let package_dir = self.config.packages_dir.join(name);
+
+        // Remove symlinks, leaving unsuffixed names alone if another version owns them
+        if let Some(package) = self.installed.get(name)
+            && let Ok(platform_details) = self.get_platform_details(package)
+        {
+            for executable_info in platform_details.get_executables() {
+                let exe_path = package_dir.join(&executable_info.path);
+                self.unlink_from_bin(name, &exe_path, &package.bin_name(&executable_info))
+                    .await?;
+            }
+        }
+
+        self.remove_extra_files(name, &package_dir).await?;
+        if let Some(package) = self.installed.get(name)
+            && let Ok(platform_details) = self.get_platform_details(package)
+        {
+            service::remove(&package.version, platform_details).await?;
+        }
+        let font_dir = fonts::fonts_dir(&self.config).join(name);
+        if fs::symlink_metadata(&font_dir).await.is_ok() {
+            fs::remove_dir_all(&font_dir).await?;
+            tokio::task::spawn_blocking(move || {
+                fonts::refresh_cache(font_dir.parent().unwrap_or(&font_dir))
+            })
+            .await?;
+        }
+
+        if let Some(package) = self.installed.remove(name) {
+            modulefile::remove(
+                &modulefile::modules_dir(&self.config.install_dir),
+                resolved,
+                &package.version,
+            )
+            .await?;
+        }
+
+        if let Some(spec) = spec
+            && self
+                .config
+                .default_versions
+                .get(resolved)
+                .map(String::as_str)
+                == Some(spec)
+        {
+            self.config.default_versions.remove(resolved);
+            self.config.save().await?;
+        }
+        Ok(())
+    }
+
+    /// Link executable `bin` of installed package `name` into bin_dir again,
+    /// after `install --only/--skip-bins` or `leaf unexpose` left it out
+    pub async fn expose_executable(&mut self, name: &str, bin: &str) -> Result<()> {
+        let executable = self.installed_executable(name, bin)?;
+        if !self
+            .link_into_bin_as(&executable, &executable.bin_name)
+            .await?
+        {
+            return Ok(());
+        }
+
+        let key = &executable.key;
+        if let Some(package) = self.installed.get_mut(key) {
+            package
+                .hidden_executables
+                .retain(|hidden| *hidden != executable.name);
+        }
+        self.save_installed(key)?;
+        print_success(&tr!("expose.done", executable.bin_name, key));
+        Ok(())
+    }
+
+    /// Take executable `bin` of installed package `name` out of bin_dir, keeping
+    /// it out through upgrades until `leaf expose` brings it back
+    pub async fn unexpose_executable(&mut self, name: &str, bin: &str) -> Result<()> {
+        let executable = self.installed_executable(name, bin)?;
+        let key = &executable.key;
+        self.unlink_from_bin(key, &executable.path, &executable.bin_name)
+            .await?;
+
+        if let Some(package) = self.installed.get_mut(key)
+            && !package.hidden_executables.contains(&executable.name)
+        {
+            package.hidden_executables.push(executable.name.clone());
+        }
+        self.save_installed(key)?;
+        print_success(&tr!("unexpose.done", executable.bin_name, key));
+        Ok(())
+    }
+
+    /// Expose executable `bin` of installed package `name` as `new_name`, e.g.
+    /// bat as cat. Upgrades keep the name; giving the usual name back undoes it.
+    pub async fn rename_executable(&mut self, name: &str, bin: &str, new_name: &str) -> Result<()> {
+        if new_name.is_empty() || new_name.contains('/') || new_name.starts_with('.') {
+            return Err(anyhow!("'{}' can't be used as a name in bin", new_name));
+        }
+        let executable = self.installed_executable(name, bin)?;
+        let key = &executable.key;
+        let hidden = self.installed[key]
+            .hidden_executables
+            .contains(&executable.name);
+        if !hidden {
+            if !self.link_into_bin_as(&executable, new_name).await? {
+                return Ok(());
+            }
+            if executable.bin_name != new_name {
+                self.unlink_from_bin(key, &executable.path, &executable.bin_name)
+                    .await?;
+            }
+        }
+
+        if let Some(package) = self.installed.get_mut(key) {
+            if new_name == executable.name {
+                package.exposed_as.remove(&executable.name);
+            } else {
+                package
+                    .exposed_as
+                    .insert(executable.name.clone(), new_name.to_string());
+            }
+        }
+        self.save_installed(key)?;
+        print_success(&tr!("link.done", executable.name, key, new_name));
+        if hidden {
+            print_info(&tr!("link.hidden", key, new_name));
+        }
+        Ok(())
+    }
+
+    /// Put an installed executable into bin_dir as `bin_name`, returning whether
+    /// it got there
+    async fn link_into_bin_as(
+        &self,
+        executable: &InstalledExecutable,
+        bin_name: &str,
+    ) -> Result<bool> {
+        let (resolved, spec) = split_spec(&executable.key);
+        let is_default = spec.is_some()
+            && self
+                .config
+                .default_versions
+                .get(resolved)
+                .map(String::as_str)
+                == spec;
+        let names = self
+            .link_into_bin(
+                resolved,
+                spec,
+                is_default,
+                &executable.path,
+                &executable.name,
+                bin_name,
+            )
+            .await?;
+        self.expose_to_windows(&names).await?;
+        Ok(!names.is_empty())
+    }
+
+    /// An executable of an installed package, found by its usual name or the
+    /// one it has in bin_dir
+    fn installed_executable(&self, name: &str, bin: &str) -> Result<InstalledExecutable> {
+        let (_, _, key) = self.resolve_spec(name);
+        let package = self
+            .installed
+            .get(&key)
+            .ok_or_else(|| anyhow!("Package '{}' is not installed", key))?;
+        let executables = self.get_platform_details(package)?.get_executables();
+        let executable = executables
+            .iter()
+            .find(|executable| executable.exposed_name() == bin)
+            .or_else(|| {
+                executables
+                    .iter()
+                    .find(|executable| package.bin_name(executable) == bin)
+            })
+            .ok_or_else(|| {
+                let names: Vec<String> = executables
+                    .iter()
+                    .map(|executable| executable.exposed_name())
+                    .collect();
+                anyhow!(
+                    "{} has no executable named {} (it has {})",
+                    key,
+                    bin,
+                    names.join(", ")
+                )
+            })?;
+        Ok(InstalledExecutable {
+            path: self.config.packages_dir.join(&key).join(&executable.path),
+            name: executable.exposed_name(),
+            bin_name: package.bin_name(executable),
+            key,
+        })
+    }
+
+    /// Attach a note of the user's own to installed package `name`
+    pub fn add_note(&mut self, name: &str, text: &str) -> Result<()> {
+        let (_, _, key) = self.resolve_spec(name);
+        let package = self
+            .installed
+            .get_mut(&key)
+            .ok_or_else(|| anyhow!("Package '{}' is not installed", key))?;
+        if text.trim().is_empty() {
+            return Err(anyhow!("The note is empty"));
+        }
+        package.notes.push(text.trim().to_string());
+        self.save_installed(&key)?;
+        print_success(&tr!("note.added", key));
+        Ok(())
+    }
+
+    /// Drop note `number` (counting from 1) of installed package `name`, or
+    /// all of its notes
+    pub fn remove_note(&mut self, name: &str, number: Option<usize>) -> Result<()> {
+        let (_, _, key) = self.resolve_spec(name);
+        let package = self
+            .installed
+            .get_mut(&key)
+            .ok_or_else(|| anyhow!("Package '{}' is not installed", key))?;
+        match number {
+            Some(number) if number == 0 || number > package.notes.len() => {
+                return Err(anyhow!(
+                    "'{}' has no note {} (it has {})",
+                    key,
+                    number,
+                    package.notes.len()
+                ));
+            }
+            Some(number) => {
+                package.notes.remove(number - 1);
+                self.save_installed(&key)?;
+                print_success(&tr!("note.removed", number, key));
+            }
+            None => {
+                package.notes.clear();
+                self.save_installed(&key)?;
+                print_success(&tr!("note.cleared", key));
+            }
+        }
+        Ok(())
+    }
+
+    /// Every installed package's notes, numbered for `leaf note remove`
+    pub fn list_notes(&self) -> Result<()> {
+        let mut names: Vec<&String> = self
+            .installed
+            .iter()
+            .filter(|(_, package)| !package.notes.is_empty())
+            .map(|(name, _)| name)
+            .collect();
+        if names.is_empty() {
+            print_info(&tr!("note.none"));
+            return Ok(());
+        }
+        names.sort();
+        for name in names {
+            println!("{}", name.bold());
+            for (i, note) in self.installed[name].notes.iter().enumerate() {
+                println!(
+                    "{}",
+                    layout::wrapped(&format!("  {}. {}", i + 1, note), 0, 5)
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Write the metadata of installed package `key` back to its directory
+    fn save_installed(&self, key: &str) -> Result<()> {
+        let Some(package) = self.installed.get(key) else {
+            return Ok(());
+        };
+        let metadata_file = self.config.packages_dir.join(key).join("leaf-package.json");
+        write_atomic(&metadata_file, &serde_json::to_string_pretty(package)?)?;
+        Ok(())
+    }
+
+    /// Point the unsuffixed executable links of a package at one of its installed versions
+    pub async fn switch_version(&mut self, name: &str, version: &str) -> Result<()> {
+        let resolved = self.resolve_name(name);
+        let key = format!("{}@{}", resolved, version);
+
+        let package = self.installed.get(&key).ok_or_else(|| {
+            let mut versions: Vec<&str> = self
+                .installed
+                .keys()
+                .filter_map(|k| k.strip_prefix(&format!("{}@", resolved)))
+                .collect();
+            versions.sort();
+            if versions.is_empty() {
+                anyhow!("No versioned installs of '{}' found", resolved)
+            } else {
+                anyhow!(
+                    "'{}' is not installed (installed versions: {})",
+                    key,
+                    versions.join(", ")
+                )
+            }
+        })?;
+
+        // Shims read the default at runtime; every other kind of entry is repointed
+        let package_dir = self.config.packages_dir.join(&key);
+        for executable_info in self.get_platform_details(package)?.get_executables() {
+            let exe_path = package_dir.join(&executable_info.path);
+            let hidden = package
+                .hidden_executables
+                .contains(&executable_info.exposed_name());
+            if exe_path.exists() && !hidden && self.link_strategy != LinkStrategy::Shim {
+                let symlink_path = self.config.bin_dir.join(package.bin_name(&executable_info));
+                self.link_executable(&exe_path, &symlink_path).await?;
+            }
+        }
+
+        self.config
+            .default_versions
+            .insert(resolved.clone(), version.to_string());
+        self.config.save().await?;
+
+        print_success(&tr!("switch.done", resolved, version, package.version));
+        Ok(())
+    }
+
+    /// " (version)" after a package in a listing, noting a local override
+    fn version_note(&self, name: &str, version: &str) -> String {
+        if self.overridden.contains(name) {
+            format!(" ({}, {})", version, tr!("edit.locally_modified"))
+        } else {
+            format!(" ({})", version)
+        }
+    }
+
+    /// Newer leaf release than this one, if any; checking is a courtesy where
+    /// this is used, so a slow or failed check only warns
+    async fn newer_self(&self) -> Option<String> {
+        let checked = tokio::time::timeout(
+            STATUS_CHECK_TIMEOUT,
+            self_update::newer_release(&self.config),
+        )
+        .await
+        .unwrap_or_else(|_| Err(anyhow!("timed out")));
+        match checked {
+            Ok(newer) => newer,
+            Err(e) => {
+                print_warning(&tr!("upgrade.self_check_failed", e));
+                None
+            }
+        }
+    }
+
+    /// leaf's own line in `list`, since it is managed like a package
+    fn print_self_row(&self) {
+        println!(
+            "{}",
+            layout::row(
+                &format!("  {} - ", self_update::SELF_NAME),
+                &tr!("list.self"),
+                &format!(" ({})", build_info::VERSION)
+            )
+        );
+    }
+
+    pub async fn list_packages(&self, tree: bool) -> Result<()> {
+        if self.installed.is_empty() {
+            print_info(&tr!("list.header"));
+            self.print_self_row();
+            print_info(&tr!("list.empty"));
+            return Ok(());
+        }
+
+        if !tree {
+            print_info(&tr!("list.header"));
+            self.print_self_row();
+            for (name, package) in &self.installed {
+                println!(
+                    "{}",
+                    layout::row(
+                        &format!("  {} - ", name),
+                        &package.description,
+                        &self.version_note(name, &package.version)
+                    )
+                );
+                print_notes(&package.notes, "    ");
+            }
+            return Ok(());
+        }
+
+        print_info(&tr!("list.tree_header", self.installed.len()));
+        self.print_self_row();
+        for (tag, mut names) in group_by_tag(&self.installed) {
+            names.sort();
+            println!("{} ({})", tag, names.len());
+            for (i, name) in names.iter().enumerate() {
+                let branch = if i + 1 == names.len() {
+                    "└─"
+                } else {
+                    "├─"
+                };
+                let package = &self.installed[*name];
+                println!(
+                    "{}",
+                    layout::row(
+                        &format!("  {} {} - ", branch, name),
+                        &package.description,
+                        &self.version_note(name, &package.version)
+                    )
+                );
+                let indent = if i + 1 == names.len() {
+                    "       "
+                } else {
+                    "  │    "
+                };
+                print_notes(&package.notes, indent);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// List every tag in the index with the number of packages carrying it
+    pub async fn list_categories(&self) -> Result<()> {
+        let available = self
+            .packages
+            .iter()
+            .filter(|(_, package)| self.get_platform_details(package).is_ok());
+        let mut categories: Vec<_> = group_by_tag(available).into_iter().collect();
+        categories.sort_by(|(a_tag, a), (b_tag, b)| b.len().cmp(&a.len()).then(a_tag.cmp(b_tag)));
+
+        print_info(&tr!("categories.header", categories.len()));
+        for (tag, names) in categories {
+            println!("  {:<20} {}", tag, names.len());
+        }
+        print_info(&tr!("categories.hint"));
+        Ok(())
+    }
+
+    /// Link a package's extra files next to bin_dir, or copy them for config
+    /// templates, skipping any destination that is already taken
+    async fn expose_extra_files(
+        &self,
+        package_dir: &Path,
+        platform_details: &PlatformDetails,
+    ) -> Result<Vec<ExposedFile>> {
+        let mut exposed = Vec::new();
+        for extra_file in platform_details.extra_files.iter().flatten() {
+            let inside = Path::new(&extra_file.path)
+                .components()
+                .all(|component| matches!(component, Component::Normal(_) | Component::CurDir));
+            if !inside || !plain_file_name(&extra_file.exposed_name()) {
+                print_warning(&tr!("install.extra_invalid", extra_file.path));
+                continue;
+            }
+            let source = package_dir.join(&extra_file.path);
+            let Ok(metadata) = fs::metadata(&source).await else {
+                print_warning(&tr!("install.extra_missing", extra_file.path));
+                continue;
+            };
+            let dir = self.config.extra_files_dir(extra_file.category);
+            let dest = dir.join(extra_file.exposed_name());
+            if fs::symlink_metadata(&dest).await.is_ok() && !link_points_into(&dest, package_dir) {
+                print_warning(&tr!("install.extra_exists", dest.display()));
+                continue;
+            }
+            fs::create_dir_all(&dir).await?;
+
+            if extra_file.category == FileCategory::Config {
+                if !metadata.is_file() {
+                    print_warning(&tr!("install.config_not_file", extra_file.path));
+                    continue;
+                }
+                fs::copy(&source, &dest).await?;
+                let sha256 = tokio::task::spawn_blocking({
+                    let dest = dest.clone();
+                    move || sha256_file(&dest)
+                })
+                .await??;
+                exposed.push(ExposedFile {
+                    path: dest.to_string_lossy().to_string(),
+                    sha256: Some(sha256),
+                });
+            } else {
+                if !self.may_replace(&dest, &source)? {
+                    continue;
+                }
+                self.link_file(&source, &dest).await?;
+                exposed.push(ExposedFile {
+                    path: dest.to_string_lossy().to_string(),
+                    sha256: None,
+                });
+            }
+        }
+        Ok(exposed)
+    }
+
+    /// Remove what expose_extra_files put in place, keeping config files the user
+    /// has edited. Shared copies have no manifest of ours, so their links are
+    /// worked out from the definition instead.
+    async fn remove_extra_files(&self, key: &str, package_dir: &Path) -> Result<()> {
+        let is_shared = fs::symlink_metadata(package_dir)
+            .await
+            .is_ok_and(|metadata| metadata.is_symlink());
+        let recorded = if is_shared {
+            None
+        } else {
+            let package_dir = package_dir.to_path_buf();
+            tokio::task::spawn_blocking(move || manifest::exposed(&package_dir)).await?
+        };
+        let files = recorded.unwrap_or_else(|| {
+            let Some(platform_details) = self
+                .installed
+                .get(key)
+                .and_then(|package| self.get_platform_details(package).ok())
+            else {
+                return Vec::new();
+            };
+            platform_details
+                .extra_files
+                .iter()
+                .flatten()
+                .filter(|extra_file| extra_file.category != FileCategory::Config)
+                .map(|extra_file| ExposedFile {
+                    path: self
+                        .config
+                        .extra_files_dir(extra_file.category)
+                        .join(extra_file.exposed_name())
+                        .to_string_lossy()
+                        .to_string(),
+                    sha256: None,
+                })
+                .collect()
+        });
+
+        for file in files {
+            let path = PathBuf::from(&file.path);
+            match &file.sha256 {
+                None if link_points_into(&path, package_dir) => fs::remove_file(&path).await?,
+                None => {}
+                Some(sha256) => {
+                    let current = tokio::task::spawn_blocking({
+                        let path = path.clone();
+                        move || sha256_file(&path)
+                    })
+                    .await?;
+                    match current {
+                        Ok(current) if &current == sha256 => fs::remove_file(&path).await?,
+                        Ok(_) => print_info(&tr!("remove.config_kept", path.display())),
+                        Err(_) => {}
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// A matching install in the system-wide store that can be linked instead of
+    /// downloading another copy
+    fn shared_copy(&self, key: &str, package: &Package) -> Option<PathBuf> {
+        let dir = self.config.shared_store_dir()?.join(key);
+        let metadata = std::fs::read_to_string(dir.join("leaf-package.json")).ok()?;
+        let shared: Package = serde_json::from_str(&metadata).ok()?;
+        (shared.version == package.version).then_some(dir)
+    }
+
+    /// Under WSL, let Windows run these bin_dir entries through .bat files in
+    /// the configured windows_bin_dir
+    async fn expose_to_windows(&self, names: &[String]) -> Result<()> {
+        let Some(dir) = &self.config.windows_bin_dir else {
+            return Ok(());
+        };
+        if self.cross || !wsl::is_wsl() {
+            return Ok(());
+        }
+        let mut written = 0;
+        for name in names {
+            // Like bin_dir entries, a .bat the user made is only replaced on request
+            let bat = dir.join(format!("{}.bat", name));
+            if wsl::is_foreign_bat(dir, name).await {
+                let replace = match prompt::mode() {
+                    Mode::NoInput => false,
+                    _ => prompt::yes_no(&tr!("install.replace", bat.display()))?,
+                };
+                if !replace {
+                    print_warning(&tr!("install.kept_existing", bat.display()));
+                    continue;
+                }
+            }
+            wsl::write_bat(dir, name, &self.config.bin_dir.join(name)).await?;
+            written += 1;
+        }
+        print_info(&tr!("install.windows_shims", written, dir.display()));
+        Ok(())
+    }
+
+    /// Warn about config paths written the Windows way, which Linux reads as
+    /// relative file names
+    pub fn check_wsl_paths(&self) {
+        if !wsl::is_wsl() {
+            return;
+        }
+        for (setting, path) in wsl::windows_style_paths(&self.config) {
+            print_warning(&tr!("wsl.windows_path", setting, path));
+        }
+    }
+
+    /// Whether installing may put a link at `path`: it's free, already leaf's,
+    /// or the user agrees to replace what's there. Declining, or having nobody
+    /// to ask, keeps the file and skips the link.
+    fn may_replace(&self, path: &Path, exe_path: &Path) -> Result<bool> {
+        if std::fs::symlink_metadata(path).is_err() || links::is_copy_of(path, exe_path) {
+            return Ok(true);
+        }
+        let ours = links::target(path).is_some_and(|target| {
+            target.starts_with(&self.config.install_dir)
+                || self
+                    .config
+                    .shared_store
+                    .as_ref()
+                    .is_some_and(|store| target.starts_with(store))
+        });
+        if ours || shim_target(path).is_some() {
+            return Ok(true);
+        }
+        let replace = match prompt::mode() {
+            Mode::NoInput => false,
+            _ => prompt::yes_no(&tr!("install.replace", path.display()))?,
+        };
+        if !replace {
+            print_warning(&tr!("install.kept_existing", path.display()));
+        }
+        Ok(replace)
+    }
+
+    /// Put an executable into bin_dir as the link strategy says. Portable roots,
+    /// and configs with relative_links, get relative links and wrappers that
+    /// survive moving the whole root.
+    async fn link_executable(&self, exe_path: &Path, link: &Path) -> Result<()> {
+        let target = self.link_target_for(exe_path, link);
+        tokio::task::spawn_blocking({
+            let (strategy, exe_path, link) = (
+                self.link_strategy,
+                exe_path.to_path_buf(),
+                link.to_path_buf(),
+            );
+            let target = target.clone();
+            move || links::expose(strategy, &exe_path, &target, &link)
+        })
+        .await??;
+        events::emit(
+            "symlink",
+            serde_json::json!({"link": link, "target": target}),
+        );
+        Ok(())
+    }
+
+    /// Symlink a file into one of the directories extra files are exposed in
+    async fn link_file(&self, source: &Path, link: &Path) -> Result<()> {
+        let target = self.link_target_for(source, link);
+        replace_symlink(&target, link).await?;
+        events::emit(
+            "symlink",
+            serde_json::json!({"link": link, "target": target}),
+        );
+        Ok(())
+    }
+
+    /// How a link at `link` should refer to `path`
+    fn link_target_for(&self, path: &Path, link: &Path) -> PathBuf {
+        match link.parent() {
+            Some(dir) if self.config.uses_relative_links() => relative_path(dir, path),
+            _ => path.to_path_buf(),
+        }
+    }
+
+    /// An installed package is outdated when the index carries a newer version
+    fn is_outdated(&self, name: &str) -> bool {
+        match (self.installed.get(name), self.packages.get(name)) {
+            (Some(installed), Some(available)) => {
+                compare_versions(&available.version, &installed.version) == Ordering::Greater
+            }
+            _ => false,
+        }
+    }
+
+    pub async fn search_packages(&self, term: &str, state: InstallStateFilter) -> Result<()> {
+        let mut found = Vec::new();
+        let term_lower = term.to_lowercase();
+
+        let mut unavailable = Vec::new();
+        for (name, package) in &self.packages {
+            let state_matches = match state {
+                InstallStateFilter::Any => true,
+                InstallStateFilter::Installed => self.installed.contains_key(name),
+                InstallStateFilter::NotInstalled => !self.installed.contains_key(name),
+                InstallStateFilter::Outdated => self.is_outdated(name),
+            };
+            if !state_matches {
+                continue;
+            }
+
+            let matches_name = name.to_lowercase().contains(&term_lower);
+            let matches_desc = package.description.to_lowercase().contains(&term_lower);
+            let matches_tags = package.tags.as_ref().is_some_and(|tags| {
+                tags.iter()
+                    .any(|tag| tag.to_lowercase().contains(&term_lower))
+            });
+            let matches_alias = package.aliases.as_ref().is_some_and(|aliases| {
+                aliases
+                    .iter()
+                    .any(|alias| alias.to_lowercase().contains(&term_lower))
+            }) || self.config.aliases.iter().any(|(alias, target)| {
+                target == name && alias.to_lowercase().contains(&term_lower)
+            });
+
+            if !(matches_name || matches_desc || matches_tags || matches_alias) {
+                continue;
+            }
+            if self.get_platform_details(package).is_ok() {
+                found.push((name, package));
+            } else {
+                unavailable.push(name.as_str());
+            }
+        }
+
+        if !unavailable.is_empty() {
+            unavailable.sort();
+            print_info(&tr!(
+                "search.unavailable",
+                unavailable.len(),
+                self.platform,
+                unavailable.join(", ")
+            ));
+        }
+        // leaf counts as installed, and as outdated when a newer release is out
+        let newer_self = if state == InstallStateFilter::Outdated
+            && self_update::SELF_NAME.contains(&term_lower)
+        {
+            self.newer_self().await
+        } else {
+            None
+        };
+        if found.is_empty() && newer_self.is_none() {
+            print_info(&tr!("search.none", term));
+            return Ok(());
+        }
+
+        print_info(&tr!(
+            "search.found",
+            found.len() + usize::from(newer_self.is_some())
+        ));
+        if let Some(newer) = newer_self {
+            println!(
+                "{}",
+                layout::row(
+                    &format!("  {} [UPDATE AVAILABLE] - ", self_update::SELF_NAME),
+                    &tr!("list.self"),
+                    &format!(" ({} -> {})", build_info::VERSION, newer)
+                )
+            );
+        }
+        for (name, package) in found {
+            let installed = if self.is_outdated(name) {
+                " [UPDATE AVAILABLE]"
+            } else if self.installed.contains_key(name) {
+                " [INSTALLED]"
+            } else {
+                ""
+            };
+            println!(
+                "{}",
+                layout::row(
+                    &format!("  {}{} - ", name, installed),
+                    &package.description,
+                    &self.version_note(name, &package.version)
+                )
+            );
+            if let Some(tags) = &package.tags
+                && !tags.is_empty()
+            {
+                println!("{}", layout::row("    Tags: ", &tags.join(", "), ""));
+            }
+            if let Some(aliases) = &package.aliases
+                && !aliases.is_empty()
+            {
+                println!("{}", layout::row("    Aliases: ", &aliases.join(", "), ""));
+            }
+        }
+
+        Ok(())
+    }
+
+    pub async fn show_package_info(&self, name: &str, caveats_only: bool) -> Result<()> {
+        let resolved = self.resolve_name(name);
+        let name = resolved.as_str();
+
+        let package = self
+            .packages
+            .get(name)
+            .or_else(|| self.installed.get(name))
+            .ok_or_else(|| self.not_in_index(name))?;
+
+        if caveats_only {
+            // The installed copy's caveats describe what is actually on disk
+            let caveats = self
+                .installed
+                .get(name)
+                .and_then(|installed| installed.caveats.as_ref())
+                .or(package.caveats.as_ref());
+            match caveats {
+                Some(caveats) => println!("{}", caveats.trim_end()),
+                None => print_info(&tr!("info.no_caveats", name)),
+            }
+            return Ok(());
+        }
+
+        // Nothing is cut short here; long values wrap under their label
+        let label_width = tr!("label.info").chars().count() + 1;
+        print_info(&layout::wrapped(
+            &format!("{} - {}", name, package.description),
+            label_width,
+            label_width,
+        ));
+        println!("  Version: {}", package.version);
+        if self.overridden.contains(name) {
+            println!(
+                "  Definition: {} ({})",
+                tr!("edit.locally_modified"),
+                overlay::path(&self.config.overlay_dir(), name)?.display()
+            );
+        }
+        match self.installed.get(name) {
+            Some(installed) if self.is_outdated(name) => println!(
+                "  Installed: {} (update available: {})",
+                installed.version, package.version
+            ),
+            Some(installed) => println!("  Installed: {}", installed.version),
+            None => println!("  Installed: no"),
+        }
+        if let Some(tags) = &package.tags
+            && !tags.is_empty()
+        {
+            println!(
+                "{}",
+                layout::wrapped(&format!("  Tags: {}", tags.join(", ")), 0, 8)
+            );
+        }
+        if let Some(installed) = self.installed.get(name) {
+            for note in &installed.notes {
+                println!("{}", layout::wrapped(&format!("  Note: {}", note), 0, 8));
+            }
+        }
+
+        let mut aliases = package.aliases.clone().unwrap_or_default();
+        aliases.extend(
+            self.config
+                .aliases
+                .iter()
+                .filter(|(_, target)| target.as_str() == name)
+                .map(|(alias, _)| alias.clone()),
+        );
+        if !aliases.is_empty() {
+            println!(
+                "{}",
+                layout::wrapped(&format!("  Aliases: {}", aliases.join(", ")), 0, 11)
+            );
+        }
+
+        let mut platforms: Vec<&String> = package.platforms.keys().collect();
+        platforms.sort();
+        let platforms = platforms
+            .iter()
+            .map(|p| p.as_str())
+            .collect::<Vec<_>>()
+            .join(", ");
+        println!(
+            "{}",
+            layout::wrapped(&format!("  Platforms: {}", platforms), 0, 13)
+        );
+        match self.platform_entry(package) {
+            Ok((key, _, true)) => println!(
+                "  Available for {}: under {} ({} build)",
+                self.platform,
+                platform::emulator_name(&self.platform),
+                key
+            ),
+            Ok(_) => println!("  Available for {}: yes", self.platform),
+            Err(_) => {
+                println!("  Available for {}: no", self.platform);
+                let unsupported =
+                    platform::Unsupported::new(&self.platform, package.platforms.keys());
+                for line in unsupported.details().iter().skip(1) {
+                    println!("{}", layout::wrapped(&format!("    {}", line), 0, 4));
+                }
+            }
+        }
+        if let Some(caveats) = &package.caveats {
+            print_caveats(name, caveats);
+        }
+
+        Ok(())
+    }
+
+    pub async fn upgrade_packages(
+        &mut self,
+        names: &[String],
+        all: bool,
+        fail_fast: bool,
+    ) -> Result<()> {
+        // leaf itself is upgraded by the self-updater, after the packages
+        let (self_requests, names): (Vec<&String>, Vec<&String>) = names
+            .iter()
+            .partition(|name| split_spec(name).0 == self_update::SELF_NAME);
+        let self_spec = self_requests.last().map(|name| split_spec(name).1);
+
+        let candidates: Vec<String> = if all {
+            let mut installed: Vec<String> = self.installed.keys().cloned().collect();
+            installed.sort();
+            installed
+        } else if names.is_empty() && self_spec.is_none() {
+            return Err(anyhow!("Specify packages to upgrade or use --all"));
+        } else {
+            let mut resolved = Vec::new();
+            for name in names {
+                let name = self.resolve_name(name);
+                if !self.installed.contains_key(&name) {
+                    return Err(anyhow!("Package '{}' is not installed", name));
+                }
+                resolved.push(name);
+            }
+            resolved
+        };
+
+        let mut plan = self.build_upgrade_plan(&candidates).await;
+        let self_target = match self_spec {
+            Some(Some(version)) => Some(version.to_string()),
+            Some(None) => self_update::newer_release(&self.config).await?,
+            // Another root, or one for another machine, isn't where this leaf lives
+            None if all && self.own_root && !self.cross => self.newer_self().await,
+            None => None,
+        };
+        if let Some(version) = self_target
+            && version.trim_start_matches('v') != build_info::VERSION
+        {
+            plan.push(UpgradePlanEntry {
+                name: self_update::SELF_NAME.to_string(),
+                current_version: build_info::VERSION.to_string(),
+                new_version: version,
+                download_size: None,
+            });
+        }
+        if plan.is_empty() && candidates.is_empty() {
+            print_info(&tr!("upgrade.self_up_to_date", build_info::VERSION));
+            return Ok(());
+        }
+        if plan.is_empty() {
+            print_info(&tr!("upgrade.up_to_date"));
+            return Ok(());
+        }
+
+        print_info(&tr!("upgrade.plan_header"));
+        let name_width = plan.iter().map(|e| e.name.len()).max().unwrap_or(0).max(7);
+        let version_width = plan
+            .iter()
+            .map(|e| e.current_version.len().max(e.new_version.len()))
+            .max()
+            .unwrap_or(0)
+            .max(7);
+        println!(
+            "  {:<name_width$}  {:<version_width$}    {:<version_width$}  Size",
+            "Package", "Current", "New"
+        );
+        for entry in &plan {
+            let size = entry
+                .download_size
+                .map_or_else(|| "unknown".to_string(), |s| HumanBytes(s).to_string());
+            println!(
+                "  {:<name_width$}  {:<version_width$} -> {:<version_width$}  {}",
+                entry.name, entry.current_version, entry.new_version, size
+            );
+        }
+        let total: u64 = plan.iter().filter_map(|e| e.download_size).sum();
+        println!("  {}", tr!("upgrade.total_size", HumanBytes(total)));
+
+        if !prompt::yes_no(&tr!("upgrade.confirm", plan.len()))? {
+            print_info(&tr!("upgrade.cancelled"));
+            return Ok(());
+        }
+
+        let mut report = BatchReport::new("upgrade", plan.len());
+        for (i, entry) in plan.iter().enumerate() {
+            print_step(&tr!(
+                "upgrade.batch_step",
+                i + 1,
+                plan.len(),
+                entry.name,
+                entry.current_version,
+                entry.new_version
+            ));
+            let result = if entry.name == self_update::SELF_NAME {
+                self_update::update(&self.config, Some(&entry.new_version), false).await
+            } else {
+                self.upgrade_package(&entry.name).await
+            };
+            if let Err(e) = &result {
+                print_error(&tr!("upgrade.failed", entry.name, e));
+            }
+            report.record(&entry.name, &result);
+            if fail_fast && report.has_failures() {
+                break;
+            }
+        }
+
+        report.finish()
+    }
+
+    async fn build_upgrade_plan(&self, candidates: &[String]) -> Vec<UpgradePlanEntry> {
+        let installer = Installer::new(&self.config);
+        let mut plan = Vec::new();
+
+        for name in candidates {
+            if !self.is_outdated(name) {
+                continue;
+            }
+            let installed = &self.installed[name];
+            let available = &self.packages[name];
+
+            let download_size = match self.get_platform_details(available) {
+                Ok(details) => installer.fetch_content_length(&details.url).await,
+                Err(_) => None,
+            };
+
+            plan.push(UpgradePlanEntry {
+                name: name.clone(),
+                current_version: installed.version.clone(),
+                new_version: available.version.clone(),
+                download_size,
+            });
+        }
+
+        plan
+    }
+
+    async fn upgrade_package(&mut self, name: &str) -> Result<()> {
+        // `leaf gc` would take the kept old version for an unfinished install
+        let _lock = self.lock_installs().await?;
+        // Check policy before removing, or a blocked upgrade would just uninstall
+        let (resolved, spec, _) = self.resolve_spec(name);
+        let package = self.index_package(&resolved, spec)?;
+        let sources = self.get_platform_details(&package)?.sources();
+        self.config.policy.check(&resolved, &sources)?;
+
+        // Kept until the new version is installed, to carry its settings over or
+        // to put it back
+        let previous = self.installed[name].clone();
+        // Retracting disables the old version's services; the ones that were
+        // enabled are enabled again on whichever version ends up installed
+        let enabled: Vec<String> = self
+            .get_platform_details(&previous)
+            .map(|platform_details| {
+                platform_details
+                    .services
+                    .iter()
+                    .flatten()
+                    .filter(|svc| service::is_enabled(svc, &previous.version))
+                    .map(|svc| svc.name.clone())
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        // The old version leaves bin_dir, but its files are kept until the new
+        // one is in place, so a failed upgrade can put it back
+        let package_dir = self.config.packages_dir.join(name);
+        let kept = package_dir.with_file_name(format!(".{}.previous", name));
+        self.retract_package(name).await?;
+        if let Err(e) = fs::rename(&package_dir, &kept).await {
+            // Nothing moved, so the old version goes back into bin_dir from where it is
+            self.reinstate(name, previous).await?;
+            self.enable_services(name, &enabled);
+            return Err(e.into());
+        }
+
+        let result = self.install_locked(name, Some(&previous)).await;
+
+        match result {
+            Ok(()) => {
+                self.enable_services(name, &enabled);
+                remove_path(&kept).await?;
+                Ok(())
+            }
+            Err(e) => {
+                let version = previous.version.clone();
+                self.restore_previous(name, previous, &kept).await?;
+                self.enable_services(name, &enabled);
+                print_warning(&tr!("upgrade.restored", name, version));
+                Err(e)
+            }
+        }
+    }
+
+    /// Enable the services of installed package `name` that are listed in `names`
+    fn enable_services(&self, name: &str, names: &[String]) {
+        let Some(package) = self.installed.get(name) else {
+            return;
+        };
+        let Ok(platform_details) = self.get_platform_details(package) else {
+            return;
+        };
+        for svc in platform_details.services.iter().flatten() {
+            if names.contains(&svc.name)
+                && !service::control(svc, &package.version, "enable").unwrap_or(false)
+            {
+                print_warning(&tr!("service.not_reenabled", svc.name, name));
+            }
+        }
+    }
+
+    /// Put back the version of `name` a failed upgrade moved to `kept`
+    async fn restore_previous(&mut self, name: &str, package: Package, kept: &Path) -> Result<()> {
+        let package_dir = self.config.packages_dir.join(name);
+        if fs::symlink_metadata(&package_dir).await.is_ok() {
+            remove_path(&package_dir).await?;
+        }
+        fs::rename(kept, &package_dir).await?;
+        self.reinstate(name, package).await
+    }
+
+    /// Expose retracted package `name` again from its directory and record it
+    /// as installed
+    async fn reinstate(&mut self, name: &str, package: Package) -> Result<()> {
+        let package_dir = self.config.packages_dir.join(name);
+        let shared = fs::symlink_metadata(&package_dir).await?.is_symlink();
+        let platform_details = self.get_platform_details(&package)?.clone();
+        let (resolved, spec) = split_spec(name);
+        self.expose_package(name, resolved, spec, &package, &platform_details, shared)
+            .await?;
+        self.installed.insert(name.to_string(), package);
+        Ok(())
+    }
+
+    /// Version requested for a package by a .leaf-versions file; entries may use aliases
+    fn toolchain_version<'a>(&self, toolchain: &'a Toolchain, name: &str) -> Option<&'a str> {
+        toolchain
+            .versions
+            .iter()
+            .find(|(entry, _)| self.resolve_name(entry) == name)
+            .map(|(_, version)| version.as_str())
+    }
+
+    /// Print names for shell completion, one per line. `available` completes index
+    /// packages (and `name@` to their versions), `installed` completes installed keys.
+    pub fn complete(&self, kind: &str, prefix: &str) {
+        let mut candidates: Vec<String> = if kind == "installed" {
+            self.installed.keys().cloned().collect()
+        } else if let Some((name, _)) = prefix.split_once('@') {
+            self.packages
+                .get(&self.resolve_name(name))
+                .map(|package| {
+                    std::iter::once(&package.version)
+                        .chain(package.versions.iter().flat_map(|versions| versions.keys()))
+                        .map(|version| format!("{}@{}", name, version))
+                        .collect()
+                })
+                .unwrap_or_default()
+        } else {
+            self.packages
+                .iter()
+                .filter(|(_, package)| self.get_platform_details(package).is_ok())
+                .map(|(name, _)| name.clone())
+                .collect()
+        };
+
+        candidates.retain(|candidate| candidate.starts_with(prefix));
+        candidates.sort();
+        candidates.dedup();
+        for candidate in candidates {
+            println!("{}", candidate);
+        }
+    }
+
+    /// Print an .envrc snippet that loads the project's packages through direnv,
+    /// from the same root as this run
+    pub fn direnv_snippet(&self, names: &[String]) {
+        println!("# Added by leaf: packages from {}", VERSIONS_FILE);
+        println!("watch_file {}", VERSIONS_FILE);
+        let mut command = vec!["leaf".to_string()];
+        if let Some(prefix) = &self.config.prefix {
+            command.push("--prefix".to_string());
+            command.push(shell_quote(&prefix.to_string_lossy()));
+        }
+        command.extend(["direnv".to_string(), "env".to_string()]);
+        command.extend(names.iter().map(|name| shell_quote(name)));
+        println!("eval \"$({})\"", command.join(" "));
+    }
+
+    /// Print direnv stdlib commands putting the requested packages and those in the
+    /// nearest .leaf-versions on PATH. Only installed packages are used so that
+    /// entering a directory never triggers a download.
+    pub fn direnv_env(&self, names: &[String]) -> Result<()> {
+        let mut requested: Vec<(String, Option<String>)> = Vec::new();
+        if let Some(toolchain) = Toolchain::current()? {
+            let mut entries: Vec<_> = toolchain.versions.into_iter().collect();
+            entries.sort();
+            for (name, version) in entries {
+                requested.push((self.resolve_name(&name), Some(version)));
+            }
+        }
+        for name in names {
+            let (resolved, spec, _) = self.resolve_spec(name);
+            requested.push((resolved, spec.map(String::from)));
+        }
+
+        for (name, version) in requested {
+            let versioned_key = version.as_ref().map(|v| format!("{}@{}", name, v));
+            let key = versioned_key
+                .filter(|key| self.installed.contains_key(key))
+                .or_else(|| {
+                    let installed = self.installed.get(&name)?;
+                    let matches = version
+                        .as_deref()
+                        .is_none_or(|v| matches_spec(&installed.version, v));
+                    matches.then(|| name.clone())
+                });
+            let Some(key) = key else {
+                let wanted = version.map_or(name.clone(), |v| format!("{}@{}", name, v));
+                eprintln!(
+                    "leaf: {} is not installed; run `leaf install {}`",
+                    wanted, wanted
+                );
+                continue;
+            };
+
+            let package_dir = self.config.packages_dir.join(&key);
+            let platform_details = self.get_platform_details(&self.installed[&key])?;
+            for dir in platform_details.bin_dirs(&package_dir) {
+                println!("PATH_add {}", shell_quote(&dir.to_string_lossy()));
+            }
+            for (var, value) in platform_details.get_env(&package_dir) {
+                if !is_env_name(&var) {
+                    eprintln!(
+                        "leaf: {} sets invalid variable name {:?}; skipping",
+                        key, var
+                    );
+                    continue;
+                }
+                println!("export {}={}", var, shell_quote(&value));
+            }
+        }
+        Ok(())
+    }
+
+    /// Run a command with a package's executables on PATH and its env vars set,
+    /// without exposing anything in bin_dir. Packages that aren't installed are
+    /// unpacked into the cache for the run. Returns the command's exit code.
+    pub async fn exec_package(&self, name: &str, command: &[String]) -> Result<i32> {
+        let (resolved, mut spec, mut key) = self.resolve_spec(name);
+
+        // Without an explicit @version, honor the project's .leaf-versions file
+        let toolchain = Toolchain::current()?;
+        if spec.is_none()
+            && let Some(toolchain) = &toolchain
+            && let Some(version) = self.toolchain_version(toolchain, &resolved)
+        {
+            print_info(&tr!(
+                "exec.toolchain",
+                resolved,
+                version,
+                toolchain.path.display()
+            ));
+            spec = Some(version);
+            key = format!("{}@{}", resolved, version);
+        }
+        let name = key.as_str();
+
+        let (package_dir, platform_details) =
+            self.package_for_run(&resolved, spec, name, &[]).await?;
+
+        let executables = platform_details.get_executables();
+        let mut path_dirs = platform_details.bin_dirs(&package_dir);
+        path_dirs.extend(env::split_paths(&env::var_os("PATH").unwrap_or_default()));
+        let path = env::join_paths(path_dirs)?;
+
+        // Exposed names may differ from the file name inside the package (e.g. nvim.appimage)
+        let program = executables
+            .iter()
+            .find(|e| e.name.as_deref() == Some(command[0].as_str()))
+            .map_or_else(
+                || std::path::PathBuf::from(&command[0]),
+                |e| package_dir.join(&e.path),
+            );
+
+        let status = std::process::Command::new(&program)
+            .args(&command[1..])
+            .env("PATH", path)
+            .envs(platform_details.get_env(&package_dir))
+            .status()
+            .with_context(|| format!("Failed to run '{}'", command[0]))?;
+
+        Ok(status.code().unwrap_or(1))
+    }
+
+    /// Refresh packages.json, then summarize what changed since the last sync,
+    /// listing every change when `list_changes` is set. `repin` names repos
+    /// whose changed fingerprint the user has checked and accepts.
+    pub async fn update_packages(&mut self, list_changes: bool, repin: &[String]) -> Result<()> {
+        print_info(&tr!("update.start"));
+        let before = self.index_versions();
+
+        let packages_url =
+            "https://raw.githubusercontent.com/ktauchathuranga/leaf/main/packages.json";
+        let packages_file = self.config.install_dir.join("packages.json");
+
+        let client = http::client();
+
+        match client.get(packages_url).send().await {
+            Ok(response) => {
+                let response = http::check_rate_limit(response)?;
+                let status = response.status();
+                if status.is_success() {
+                    let content = response.text().await?;
+
+                    match serde_json::from_str::<Value>(&content) {
+                        Ok(_) => {
+                            let content = self.merge_repos(&client, content, repin).await?;
+                            fs::write(&packages_file, &content).await?;
+                            self.packages.clear();
+                            self.load_packages().await?;
+                            print_success(&tr!("update.done"));
+                            for (name, e) in &self.unreadable {
+                                print_warning(&tr!("index.invalid_package", name, e));
+                            }
+                            // The first sync has nothing to compare with
+                            if !before.is_empty() {
+                                let changes = diff::index_changes(&before, &self.index_versions());
+                                print_index_changes(&changes, list_changes);
+                            }
+                            self.warn_retired();
+                            Ok(())
+                        }
+                        Err(e) => Err(anyhow::anyhow!(
+                            "Downloaded content is not valid JSON: {}",
+                            e
+                        )),
+                    }
+                } else {
+                    let error_body = response
+                        .text()
+                        .await
+                        .unwrap_or_else(|_| "Could not read error body".to_string());
+                    Err(anyhow::anyhow!(
+                        "Failed to download packages.json: HTTP {} - {}",
+                        status,
+                        error_body
+                    ))
+                }
+            }
+            Err(e) => Err(anyhow::anyhow!(
+                "Network error downloading packages.json: {}",
+                e
+            )),
+        }
+    }
+
+    /// Name -> version of every package in the index
+    fn index_versions(&self) -> HashMap<String, String> {
+        self.packages
+            .iter()
+            .map(|(name, package)| (name.clone(), package.version.clone()))
+            .collect()
+    }
+
+    /// Warn about installed packages the refreshed index no longer offers,
+    /// with the command to move to their successor where there is one. Ones
+    /// still in the index but unreadable are listed as such already.
+    fn warn_retired(&self) {
+        let mut names: Vec<&str> = self.installed.keys().map(|key| split_spec(key).0).collect();
+        names.sort();
+        names.dedup();
+        for name in names {
+            if self
+                .unreadable
+                .iter()
+                .any(|(unreadable, _)| unreadable == name)
+            {
+                continue;
+            }
+            if let Some(required) = self.too_new.get(name) {
+                print_warning(&tr!("update.needs_newer_leaf", name, required));
+                continue;
+            }
+            match package::retirement(name, &self.packages) {
+                Some(Retirement::Removed) => print_warning(&tr!("update.removed", name)),
+                Some(Retirement::Renamed(new_name)) => {
+                    print_warning(&tr!("update.renamed", name, new_name))
+                }
+                Some(Retirement::Replaced(new_name)) => {
+                    print_warning(&tr!("update.replaced", name, new_name))
+                }
+                None => {}
+            }
+        }
+    }
+
+    /// Add the packages from config.repos that the main index doesn't define.
+    /// An unreachable repo is skipped so the main index still updates, and so
+    /// is one whose fingerprint changed since it was first fetched.
+    async fn merge_repos(
+        &self,
+        client: &reqwest::Client,
+        content: String,
+        repin: &[String],
+    ) -> Result<String> {
+        if self.config.repos.is_empty() {
+            return Ok(content);
+        }
+        let mut index: serde_json::Map<String, Value> = serde_json::from_str(&content)?;
+        let mut pins = trust::load(&self.config.install_dir);
+        for repo in &self.config.repos {
+            let fetched = async {
+                let response = client.get(repo).send().await?.error_for_status()?;
+                let fingerprint = trust::tls_fingerprint(&response);
+                let body = response.bytes().await?;
+                anyhow::Ok((body, fingerprint))
+            }
+            .await;
+            let (body, tls) = match fetched {
+                Ok(fetched) => fetched,
+                Err(e) => {
+                    print_warning(&tr!("update.repo_failed", repo, e));
+                    continue;
+                }
+            };
+            // A signing key outlives certificate renewals, so it is preferred
+            let fingerprint = trust::signing_key(client, repo, &body, &self.config.cache_dir)
+                .await
+                .or(tls);
+            if let Some(fingerprint) = &fingerprint {
+                match trust::check(&mut pins, repo, fingerprint, repin.contains(repo)) {
+                    Trust::New => print_info(&tr!("update.repo_pinned", repo, fingerprint)),
+                    Trust::Known => {}
+                    // Certificates are renewed every few months; a changed
+                    // signing key, or a signature that went missing, is not routine
+                    Trust::Changed { pinned } if trust::is_certificate(&pinned) => {
+                        print_warning(&tr!(
+                            "update.repo_certificate_changed",
+                            repo,
+                            pinned,
+                            fingerprint
+                        ));
+                        trust::check(&mut pins, repo, fingerprint, true);
+                    }
+                    Trust::Changed { pinned } => {
+                        print_error(&tr!("update.repo_changed", repo, pinned, fingerprint));
+                        continue;
+                    }
+                }
+            }
+            match serde_json::from_slice::<serde_json::Map<String, Value>>(&body) {
+                Ok(mut packages) => {
+                    // The main index's format applies to the merged index
+                    let format = packages.remove(package::SCHEMA_KEY);
+                    if let Some(version) = format.as_ref().and_then(Value::as_u64)
+                        && version > package::SCHEMA_VERSION
+                    {
+                        let e = IndexError::NewerFormat(version);
+                        print_warning(&tr!("update.repo_failed", repo, e));
+                        continue;
+                    }
+                    for (name, definition) in packages {
+                        index.entry(name).or_insert(definition);
+                    }
+                }
+                Err(e) => print_warning(&tr!("update.repo_failed", repo, e)),
+            }
+        }
+        trust::save(&self.config.install_dir, &pins)?;
+        Ok(serde_json::to_string_pretty(&index)?)
+    }
+
+    /// Set up this machine from a bootstrap manifest: merge its settings and
+    /// repos into config.json, refresh the index when repos were added, and
+    /// install the packages that are missing. Repos, policy and the cache
+    /// remote are only taken from the manifest when `managed` is set.
+    pub async fn bootstrap(
+        &mut self,
+        location: &str,
+        key: Option<&str>,
+        allow_unsigned: bool,
+        managed: bool,
+        fail_fast: bool,
+    ) -> Result<()> {
+        print_info(&tr!("bootstrap.fetching", location));
+        let client = http::client();
+        let manifest = bootstrap::fetch(
+            &client,
+            location,
+            &self.config.cache_dir,
+            key,
+            allow_unsigned,
+        )
+        .await?;
+        let managed_settings = bootstrap::managed_settings(&manifest);
+        if !managed && !managed_settings.is_empty() {
+            return Err(anyhow!(
+                "The manifest sets {}, which only managed machines may take from a manifest; pass --managed to apply it",
+                managed_settings.join(", ")
+            ));
+        }
+
+        if let Some(overrides) = &manifest.config {
+            bootstrap::apply_config(&mut self.config, overrides)?;
+        }
+        let mut added_repos = false;
+        for repo in &manifest.repos {
+            if !self.config.repos.contains(repo) {
+                self.config.repos.push(repo.clone());
+                added_repos = true;
+            }
+        }
+        if manifest.config.is_some() || added_repos {
+            self.config.save().await?;
+            print_info(&tr!("bootstrap.config_saved"));
+        }
+        if added_repos {
+            self.update_packages(false, &[]).await?;
+        }
+
+        let missing: Vec<String> = manifest
+            .packages
+            .iter()
+            .filter(|name| !self.installed.contains_key(&self.resolve_spec(name).2))
+            .cloned()
+            .collect();
+        if missing.is_empty() {
+            print_success(&tr!("bootstrap.done"));
+            return Ok(());
+        }
+        self.install_packages(&missing, fail_fast).await
+    }
+
+    pub async fn nuke_everything(&self, confirmed: bool) -> Result<()> {
+        if !confirmed {
+            print_error(&tr!("nuke.warning"));
+            print_error(&tr!("nuke.irreversible"));
+            let agreed = match prompt::mode() {
+                Mode::AssumeYes => true,
+                Mode::NoInput => {
+                    print_error("");
+                    print_error(&tr!("nuke.confirm_hint"));
+                    return Ok(());
+                }
+                // Typing the word out is harder to do by accident than "y"
+                Mode::Ask => prompt::text(&tr!("nuke.prompt"), Some(""))? == "nuke",
+            };
+            if !agreed {
+                print_info(&tr!("nuke.cancelled"));
+                return Ok(());
+            }
+        }
+
+        print_warning(&tr!("nuke.activated"));
+        print_warning(&tr!("nuke.start"));
+
+        // Take out of bin everything leaf put there, whichever link strategy
+        // exposed it, the way removing a package recognizes its own entries
+        let executables: Vec<PathBuf> = self
+            .installed
+            .iter()
+            .filter_map(|(key, package)| {
+                let platform_details = self.get_platform_details(package).ok()?;
+                let package_dir = self.config.packages_dir.join(key);
+                Some(
+                    platform_details
+                        .get_executables()
+                        .iter()
+                        .map(|executable| package_dir.join(&executable.path))
+                        .collect::<Vec<_>>(),
+                )
+            })
+            .flatten()
+            .collect();
+        if self.config.bin_dir.exists() {
+            let mut entries = fs::read_dir(&self.config.bin_dir).await?;
+            while let Some(entry) = entries.next_entry().await? {
+                let path = entry.path();
+                let owned = link_points_into(&path, &self.config.install_dir)
+                    || link_points_into(&path, &self.config.packages_dir)
+                    || shim_target(&path).is_some()
+                    || executables
+                        .iter()
+                        .any(|executable| links::is_copy_of(&path, executable));
+                if owned {
+                    fs::remove_file(&path).await?;
+                    print_info(&tr!("nuke.removed_link", path.display()));
+                }
+            }
+        }
+
+        // Remove the entire leaf directory
+        if self.config.install_dir.exists() {
+            fs::remove_dir_all(&self.config.install_dir).await?;
+            print_info(&tr!("nuke.removed_dir", self.config.install_dir.display()));
+        }
+
+        print_success(&tr!("nuke.done"));
+        print_info(&tr!("nuke.remove_binary"));
         print_info(&format!(
-            "Updating from v{} to {}",
-            current_version, target_version
+            "  rm {}",
+            self.config.bin_dir.join("leaf").display()
+        ));
+
+        Ok(())
+    }
+
+    /// Rebuild the Environment Modules tree from the installed packages
+    pub async fn generate_modules(&self) -> Result<()> {
+        let modules_dir = modulefile::modules_dir(&self.config.install_dir);
+        let leaf_modules = modules_dir.join("leaf");
+        if leaf_modules.exists() {
+            fs::remove_dir_all(&leaf_modules).await?;
+        }
+
+        for (key, package) in &self.installed {
+            let (name, _) = split_spec(key);
+            let platform_details = self.get_platform_details(package)?;
+            let package_dir = self.config.packages_dir.join(key);
+            modulefile::write(&modules_dir, name, package, &package_dir, platform_details).await?;
+        }
+
+        print_success(&tr!(
+            "modules.generated",
+            self.installed.len(),
+            modules_dir.display()
+        ));
+        print_info(&tr!("modules.hint"));
+        println!("  module use {}", modules_dir.display());
+        Ok(())
+    }
+
+    /// Write a Dockerfile and build context containing installed packages, and
+    /// optionally build it into an image tagged `tag`
+    pub async fn containerize(
+        &self,
+        names: &[String],
+        tag: &str,
+        output: Option<&Path>,
+        base: &str,
+        build: bool,
+    ) -> Result<()> {
+        let mut keys = Vec::new();
+        for name in names {
+            let (_, _, key) = self.resolve_spec(name);
+            if !self.installed.contains_key(&key) {
+                return Err(anyhow!("Package '{}' is not installed", key));
+            }
+            keys.push(key);
+        }
+
+        let mut packages = Vec::new();
+        for key in &keys {
+            packages.push(ImagePackage {
+                key,
+                source_dir: self.config.packages_dir.join(key),
+                platform_details: self.get_platform_details(&self.installed[key])?,
+            });
+        }
+
+        let context_dir = match output {
+            Some(path) => path.to_path_buf(),
+            None => PathBuf::from(format!("{}-image", tag.replace(['/', ':'], "-"))),
+        };
+        if context_dir.exists() {
+            return Err(anyhow!("{} already exists", context_dir.display()));
+        }
+
+        print_info(&tr!("containerize.start", context_dir.display()));
+        container::write_context(&context_dir, base, &packages)?;
+        print_success(&tr!("containerize.written", context_dir.display()));
+
+        if build {
+            print_step(&tr!("containerize.building", tag));
+            container::build_image(&context_dir, tag)?;
+            print_success(&tr!("containerize.built", tag));
+        } else {
+            print_info(&tr!("containerize.build_hint"));
+            println!("  docker build -t {} {}", tag, context_dir.display());
+        }
+        Ok(())
+    }
+
+    /// Check installed versions against OSV and report known vulnerabilities,
+    /// failing when any are found so CI can gate on it
+    pub async fn audit(&self) -> Result<()> {
+        let client = http::client();
+
+        let mut keys: Vec<&String> = self.installed.keys().collect();
+        keys.sort();
+
+        let mut vulnerable = 0;
+        let mut unchecked = Vec::new();
+        for key in keys {
+            let (name, _) = split_spec(key);
+            let installed = &self.installed[key];
+            // Older installs predate the osv field, so fall back to the index
+            let osv = installed
+                .osv
+                .as_ref()
+                .or_else(|| self.packages.get(name)?.osv.as_ref());
+            let Some(osv) = osv.filter(|_| installed.version != "latest") else {
+                unchecked.push(key.to_string());
+                continue;
+            };
+
+            // One failed lookup shouldn't lose the results for everything else
+            let advisories = match audit::query(&client, osv, &installed.version).await {
+                Ok(advisories) => advisories,
+                Err(e) => {
+                    unchecked.push(format!("{} ({})", key, e));
+                    continue;
+                }
+            };
+            if advisories.is_empty() {
+                println!("  {} {} {}", "✓".green(), key, installed.version);
+                continue;
+            }
+
+            vulnerable += 1;
+            print_warning(&tr!(
+                "audit.vulnerable",
+                key,
+                installed.version,
+                advisories.len()
+            ));
+            for advisory in &advisories {
+                let ids = std::iter::once(&advisory.id)
+                    .chain(&advisory.aliases)
+                    .cloned()
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                println!("    {} {}", ids.red(), advisory.summary);
+                if !advisory.fixed.is_empty() {
+                    println!("      {}", tr!("audit.fixed_in", advisory.fixed.join(", ")));
+                }
+            }
+            match self.packages.get(name) {
+                Some(available)
+                    if audit::fixes_all(&advisories, &installed.version, &available.version) =>
+                {
+                    print_info(&tr!("audit.upgrade_hint", available.version, name));
+                }
+                _ => print_info(&tr!("audit.no_fix", name)),
+            }
+        }
+
+        if !unchecked.is_empty() {
+            print_info(&tr!("audit.unchecked", unchecked.join(", ")));
+        }
+        if vulnerable > 0 {
+            return Err(anyhow!(
+                "{} package(s) have known vulnerabilities",
+                vulnerable
+            ));
+        }
+        print_success(&tr!("audit.clean"));
+        Ok(())
+    }
+
+    fn profile(&self, name: &str) -> Result<&Vec<String>> {
+        self.config.profiles.get(name).ok_or_else(|| {
+            let mut known: Vec<&str> = self.config.profiles.keys().map(String::as_str).collect();
+            known.sort();
+            anyhow!(
+                "No profile named '{}' in config.json (known: {})",
+                name,
+                if known.is_empty() {
+                    "none".to_string()
+                } else {
+                    known.join(", ")
+                }
+            )
+        })
+    }
+
+    pub fn list_profiles(&self) {
+        let mut profiles: Vec<_> = self.config.profiles.iter().collect();
+        profiles.sort();
+        if profiles.is_empty() {
+            print_info(&tr!("profile.none"));
+        }
+        for (name, members) in profiles {
+            println!("  {}: {}", name.bold(), members.join(", "));
+        }
+    }
+
+    /// Install the members of a profile that aren't installed yet
+    pub async fn apply_profile(&mut self, name: &str, fail_fast: bool) -> Result<()> {
+        let missing: Vec<String> = self
+            .profile(name)?
+            .iter()
+            .filter(|member| !self.installed.contains_key(&self.resolve_spec(member).2))
+            .cloned()
+            .collect();
+        if missing.is_empty() {
+            print_success(&tr!("profile.up_to_date", name));
+            return Ok(());
+        }
+        self.install_packages(&missing, fail_fast).await
+    }
+
+    /// Show how the index's definition of an installed package differs from
+    /// the one it was installed from, to review before upgrading
+    pub fn diff_package(&self, name: &str) -> Result<()> {
+        let (resolved, spec, key) = self.resolve_spec(name);
+        let installed = self
+            .installed
+            .get(&key)
+            .ok_or_else(|| anyhow!("Package '{}' is not installed", key))?;
+        let available = self
+            .index_package(&resolved, spec)
+            .map_err(|_| anyhow!("'{}' is no longer in the package index", key))?;
+        let changes = diff::diff(
+            &self.definition_summary(installed)?,
+            &self.definition_summary(&available)?,
+        );
+        if changes.is_empty() {
+            print_success(&tr!("diff.unchanged", key));
+            return Ok(());
+        }
+        print_info(&tr!(
+            "diff.header",
+            key,
+            installed.version,
+            available.version
         ));
+        let show = |value: &Value| value.to_string();
+        for change in changes {
+            match change {
+                Change::Added(path, value) => {
+                    println!("  {} {}: {}", "+".green(), path, show(&value))
+                }
+                Change::Removed(path, value) => {
+                    println!("  {} {}: {}", "-".red(), path, show(&value))
+                }
+                Change::Changed(path, old, new) => println!(
+                    "  {} {}: {} -> {}",
+                    "~".yellow(),
+                    path,
+                    show(&old),
+                    show(&new)
+                ),
+            }
+        }
+        Ok(())
+    }
+
+    /// Open the effective definition of `name` in the user's editor and keep
+    /// the result as a local override, which takes the index's place until it
+    /// is reset
+    pub async fn edit_package(&self, name: &str, reset: bool) -> Result<()> {
+        let name = self.resolve_name(name);
+        let dir = self.config.overlay_dir();
+        let override_path = overlay::path(&dir, &name)?;
+
+        if reset {
+            if !override_path.exists() {
+                print_info(&tr!("edit.no_override", name));
+                return Ok(());
+            }
+            fs::remove_file(&override_path).await?;
+            print_success(&tr!("edit.reset", name));
+            return Ok(());
+        }
+
+        let packages_file = self.config.install_dir.join("packages.json");
+        let index_definition = fs::read_to_string(&packages_file)
+            .await
+            .ok()
+            .and_then(|content| serde_json::from_str::<Value>(&content).ok())
+            .and_then(|mut index| index.get_mut(&name).map(Value::take));
+        let current = match fs::read_to_string(&override_path).await {
+            Ok(content) => content,
+            Err(_) => match &index_definition {
+                Some(definition) => format!("{}\n", serde_json::to_string_pretty(definition)?),
+                None => return Err(self.not_in_index(&name)),
+            },
+        };
+
+        fs::create_dir_all(&dir).await?;
+        let draft = overlay::draft_path(&dir, &name)?;
+        fs::write(&draft, &current).await?;
+        let edited = loop {
+            let result = overlay::edit(&draft).and_then(|_| {
+                let content = std::fs::read_to_string(&draft)?;
+                overlay::validate(&content, build_info::VERSION)
+            });
+            match result {
+                Ok(definition) => break Some(definition),
+                Err(e) => {
+                    print_error(&tr!("edit.invalid", format!("{:#}", e)));
+                    // --yes would reopen an editor that made the same edit forever
+                    if prompt::mode() != Mode::Ask || !prompt::yes_no(&tr!("edit.again"))? {
+                        break None;
+                    }
+                }
+            }
+        };
+        fs::remove_file(&draft).await.ok();
+        let Some(edited) = edited else {
+            return Err(anyhow!("The definition of '{}' was not saved", name));
+        };
+
+        if index_definition.as_ref() == Some(&edited) {
+            if fs::remove_file(&override_path).await.is_ok() {
+                print_success(&tr!("edit.matches_index", name));
+            } else {
+                print_info(&tr!("edit.unchanged", name));
+            }
+            return Ok(());
+        }
+        write_atomic(
+            &override_path,
+            &format!("{}\n", serde_json::to_string_pretty(&edited)?),
+        )?;
+        print_success(&tr!("edit.saved", name, override_path.display()));
+        print_info(&tr!("edit.reset_hint", name));
+        Ok(())
+    }
+
+    /// A definition reduced to what matters for installing it here: its
+    /// top-level fields from the index and this platform's entry
+    fn definition_summary(&self, package: &Package) -> Result<Value> {
+        let mut summary = serde_json::to_value(package.without_install_state())?;
+        if let Some(fields) = summary.as_object_mut() {
+            for field in ["platforms", "versions", "defaults"] {
+                fields.remove(field);
+            }
+            let platform = self.get_platform_details(package).ok();
+            fields.insert("platform".to_string(), serde_json::to_value(platform)?);
+        }
+        Ok(summary)
+    }
+
+    /// Compare a profile with what is installed: missing members, outdated
+    /// members and installed packages the profile doesn't list
+    pub fn diff_profile(&self, name: &str) -> Result<()> {
+        let members: Vec<String> = self
+            .profile(name)?
+            .iter()
+            .map(|member| self.resolve_spec(member).2)
+            .collect();
+        let mut drift = 0;
+        for key in &members {
+            if !self.installed.contains_key(key) {
+                println!("  {} {}", "+".green(), key);
+                drift += 1;
+            } else if self.is_outdated(key) {
+                println!("  {} {} ({})", "~".yellow(), key, tr!("profile.outdated"));
+                drift += 1;
+            }
+        }
+        let mut extra: Vec<&String> = self
+            .installed
+            .keys()
+            .filter(|key| !members.contains(key))
+            .collect();
+        extra.sort();
+        for key in extra {
+            println!("  {} {}", "-".red(), key);
+            drift += 1;
+        }
+        if drift == 0 {
+            print_success(&tr!("profile.no_drift", name));
+        }
+        Ok(())
+    }
+
+    /// Enable, disable or show the status of an installed package's services
+    pub async fn service(&self, action: &str, name: &str) -> Result<()> {
+        let (_, _, key) = self.resolve_spec(name);
+        let package = self
+            .installed
+            .get(&key)
+            .ok_or_else(|| anyhow!("Package '{}' is not installed", key))?;
+        let services = self
+            .get_platform_details(package)?
+            .services
+            .clone()
+            .unwrap_or_default();
+        if services.is_empty() {
+            return Err(anyhow!("Package '{}' has no services", key));
+        }
+
+        let mut failed = 0;
+        for svc in &services {
+            let (svc, version) = (svc.clone(), package.version.clone());
+            let action = action.to_string();
+            let ok = tokio::task::spawn_blocking(move || service::control(&svc, &version, &action))
+                .await??;
+            if !ok {
+                failed += 1;
+            }
+        }
+        // `status` fails for stopped services, which is an answer rather than an error
+        if failed > 0 && action != "status" {
+            return Err(anyhow!("Could not {} {} service(s)", action, failed));
+        }
+        match action {
+            "enable" => print_success(&tr!("service.enabled", key)),
+            "disable" => print_success(&tr!("service.disabled", key)),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Check installed files and bin_dir links against the manifests recorded at
+    /// install time. Works offline and fails when anything has changed.
+    pub async fn verify(&self, names: &[String], json: bool, output: Option<&Path>) -> Result<()> {
+        let mut keys = Vec::new();
+        for name in names {
+            let (_, _, key) = self.resolve_spec(name);
+            if !self.installed.contains_key(&key) {
+                return Err(anyhow!("Package '{}' is not installed", key));
+            }
+            keys.push(key);
+        }
+        if names.is_empty() {
+            keys = self.installed.keys().cloned().collect();
+            keys.sort();
+        }
+
+        let mut entries = Vec::new();
+        let (mut failed, mut unverified) = (0, 0);
+        for key in &keys {
+            let package = &self.installed[key];
+            let package_dir = self.config.packages_dir.join(key);
+            let mut issues = tokio::task::spawn_blocking({
+                let package_dir = package_dir.clone();
+                move || manifest::check(&package_dir)
+            })
+            .await??;
+            issues.extend(self.check_links(key, package, &package_dir));
+
+            let status = if issues.is_empty() {
+                "ok"
+            } else if issues
+                .iter()
+                .all(|issue| issue.problem == Problem::NoManifest)
+            {
+                unverified += 1;
+                "unverified"
+            } else {
+                failed += 1;
+                "failed"
+            };
+
+            if !json {
+                let mark = match status {
+                    "ok" => "✓".green(),
+                    "unverified" => "?".yellow(),
+                    _ => "✗".red(),
+                };
+                println!("  {} {} {}", mark, key, package.version);
+                for issue in &issues {
+                    println!("      {:<12} {}", issue.problem.to_string(), issue.path);
+                }
+            }
+            entries.push(serde_json::json!({
+                "name": key,
+                "version": package.version,
+                "status": status,
+                "issues": issues,
+            }));
+        }
+
+        if json {
+            let report = serde_json::json!({
+                "generated_at": chrono::Utc::now().to_rfc3339(),
+                "leaf_version": build_info::VERSION,
+                "platform": self.platform,
+                "packages": entries,
+                "summary": {
+                    "checked": keys.len(),
+                    "failed": failed,
+                    "unverified": unverified,
+                },
+            });
+            let report = serde_json::to_string_pretty(&report)?;
+            match output {
+                Some(path) => {
+                    fs::write(path, report).await?;
+                    print_success(&tr!("verify.written", path.display()));
+                }
+                None => println!("{}", report),
+            }
+        } else if unverified > 0 {
+            print_info(&tr!("verify.unverified", unverified));
+        }
+
+        if failed > 0 {
+            return Err(anyhow!("{} package(s) failed verification", failed));
+        }
+        if !json {
+            print_success(&tr!("verify.clean", keys.len() - unverified));
+        }
+        Ok(())
+    }
+
+    /// Links (or shims) in bin_dir that an install of `key` should have left behind
+    fn check_links(&self, key: &str, package: &Package, package_dir: &Path) -> Vec<Issue> {
+        let Ok(platform_details) = self.get_platform_details(package) else {
+            return Vec::new();
+        };
+        let (_, spec) = split_spec(key);
+        let mut issues = Vec::new();
+        for executable_info in platform_details.get_executables() {
+            if !package_dir.join(&executable_info.path).exists()
+                || package
+                    .hidden_executables
+                    .contains(&executable_info.exposed_name())
+            {
+                continue;
+            }
+            let link = self.config.bin_dir.join(format!(
+                "{}{}",
+                package.bin_name(&executable_info),
+                spec.unwrap_or("")
+            ));
+            let problem = match std::fs::symlink_metadata(&link) {
+                Err(_) => Problem::Unlinked,
+                Ok(metadata) if metadata.is_symlink() && !link.exists() => Problem::Dangling,
+                Ok(_) => continue,
+            };
+            issues.push(Issue {
+                path: link.to_string_lossy().to_string(),
+                problem,
+            });
+        }
+        issues
+    }
+
+    /// Export an SBOM of every installed package, printed or written to `output`
+    pub async fn export_sbom(&self, format: SbomFormat, output: Option<&Path>) -> Result<()> {
+        let mut packages: Vec<_> = self.installed.iter().collect();
+        packages.sort_by(|a, b| a.0.cmp(b.0));
+        let document = serde_json::to_string_pretty(&sbom::build(format, &packages))?;
+        let Some(path) = output else {
+            println!("{}", document);
+            return Ok(());
+        };
+
+        fs::write(path, document).await?;
+        print_success(&tr!("sbom.written", packages.len(), path.display()));
+        let missing = packages
+            .iter()
+            .filter(|(_, package)| package.provenance.is_none())
+            .count();
+        if missing > 0 {
+            print_warning(&tr!("sbom.missing_provenance", missing));
+        }
+        Ok(())
+    }
+
+    /// Move leaf to `new_root` (which gets leaf/ and bin/, like ~/.local), or,
+    /// when it already lives there, repoint the links still leading to where it
+    /// used to be
+    pub async fn relocate(&mut self, new_root: &Path) -> Result<()> {
+        let new_root = std::path::absolute(new_root)?;
+        let root_of = |leaf_dir: &Path| {
+            leaf_dir
+                .parent()
+                .map(Path::to_path_buf)
+                .ok_or_else(|| anyhow!("{} has no parent directory", leaf_dir.display()))
+        };
+        let current_root = root_of(&self.config.install_dir)?;
+        let mut old_roots = vec![current_root.clone()];
+        if let Some(previous) = &self.config.relocated_from {
+            old_roots.push(root_of(previous)?);
+        }
+        old_roots.retain(|root| root != &new_root);
+
+        if current_root != new_root {
+            print_step(&tr!(
+                "relocate.moving",
+                current_root.display(),
+                new_root.display()
+            ));
+            let (from, to) = (current_root.clone(), new_root.clone());
+            let previous = self.config.relocated_from.clone();
+            tokio::task::spawn_blocking(move || {
+                relocate::move_root(&from, &to, previous.as_deref())
+            })
+            .await??;
+        }
+
+        let relative = self.config.uses_relative_links();
+        let rewritten = tokio::task::spawn_blocking({
+            let new_root = new_root.clone();
+            move || {
+                let mut rewritten = 0;
+                for old_root in &old_roots {
+                    rewritten += relocate::rewrite_links(
+                        &new_root,
+                        &old_root.join("leaf"),
+                        &new_root.join("leaf"),
+                        relative,
+                    )?;
+                    relocate::rewrite_manifests(
+                        &new_root.join("leaf").join("packages"),
+                        old_root,
+                        &new_root,
+                    )?;
+                }
+                anyhow::Ok(rewritten)
+            }
+        })
+        .await??;
+
+        let new_leaf = new_root.join("leaf");
+        self.config.install_dir = new_leaf.clone();
+        self.config.bin_dir = new_root.join("bin");
+        self.config.packages_dir = new_leaf.join("packages");
+        self.config.cache_dir = new_leaf.join("cache");
+        self.config.relocated_from = None;
+        let default_root = dirs::home_dir().map(|home| home.join(".local"));
+        let needs_prefix = default_root.as_ref() != Some(&new_root) && !self.config.portable;
+        self.config.prefix = needs_prefix.then(|| new_root.clone());
+        self.config.save().await?;
+
+        // Shims for an alternate root name it, so write them again for this one
+        let mut bin_entries = fs::read_dir(&self.config.bin_dir).await?;
+        while let Some(entry) = bin_entries.next_entry().await? {
+            if let Some((package, exe)) = shim_target(&entry.path()) {
+                write_shim(&self.config, &entry.path(), &package, &exe).await?;
+            }
+        }
+        self.generate_modules().await?;
+
+        print_success(&tr!("relocate.done", new_root.display(), rewritten));
+        if needs_prefix {
+            print_info(&tr!("relocate.prefix_hint", new_root.display()));
+        }
+        Ok(())
+    }
+
+    /// Whether the on-disk layout is the one this release understands
+    pub fn check_layout(&self) -> Result<()> {
+        migrate::check(self.config.layout_version)
+    }
+
+    /// Bring config.json and package directories written by an older leaf up
+    /// to this release's layout, backing up what changes first
+    pub async fn migrate(&mut self, dry_run: bool) -> Result<()> {
+        let from = self.config.layout_version;
+        if from > migrate::LAYOUT_VERSION {
+            return migrate::check(from);
+        }
+        let pending = migrate::pending(from);
+        if pending.is_empty() {
+            print_success(&tr!("migrate.up_to_date", from));
+            return Ok(());
+        }
+
+        let mut plans = Vec::new();
+        for migration in &pending {
+            plans.push((migration, migration.plan(&self.config)?));
+        }
+        for (migration, actions) in &plans {
+            println!(
+                "{} {} → {}: {}",
+                "•".cyan(),
+                migration.to - 1,
+                migration.to,
+                migration.description
+            );
+            for action in actions {
+                println!("    {}", action);
+            }
+        }
+        if dry_run {
+            print_info(&tr!("migrate.dry_run"));
+            return Ok(());
+        }
 
-        let install_script_url = format!(
-            "https://raw.githubusercontent.com/ktauchathuranga/leaf/{}/install.sh",
-            target_version
+        let backup_dir = self.config.install_dir.join("backups").join(format!(
+            "migrate-{}-{}",
+            from,
+            chrono::Utc::now().format("%Y%m%d%H%M%S")
+        ));
+        let (install_dir, packages_dir) = (
+            self.config.install_dir.clone(),
+            self.config.packages_dir.clone(),
         );
+        let dest = backup_dir.clone();
+        tokio::task::spawn_blocking(move || migrate::backup(&install_dir, &packages_dir, &dest))
+            .await??;
+        print_info(&tr!("migrate.backup", backup_dir.display()));
 
-        print_info("Running the installation/update script...");
+        for (migration, actions) in plans {
+            for action in actions {
+                let action_text = action.to_string();
+                tokio::task::spawn_blocking(move || migrate::apply(&action))
+                    .await?
+                    .with_context(|| {
+                        format!(
+                            "Migration to layout {} failed to {}",
+                            migration.to, action_text
+                        )
+                    })?;
+            }
+            // Record progress per step so a failure later resumes from here
+            self.config.layout_version = migration.to;
+            self.config.save().await?;
+        }
+        print_success(&tr!("migrate.done", migrate::LAYOUT_VERSION));
+        Ok(())
+    }
 
-        let status = std::process::Command::new("sh")
-            .arg("-c")
-            .arg(format!(
-                "curl -sSL {} | bash -s -- --version {}",
-                install_script_url, target_version
-            ))
-            .status()
-            .with_context(|| "Failed to start the update process.")?;
+    /// Delete what leaf left behind that nothing uses: broken links into leaf's
+    /// directory, package directories without metadata, unfinished installs,
+    /// builds and downloads. With `dry_run`, only list them.
+    pub async fn collect_garbage(&self, dry_run: bool) -> Result<()> {
+        // An install in progress has directories that look abandoned
+        let _lock = self.lock_installs().await?;
+        let garbage = gc::find(&self.config);
+        if garbage.is_empty() {
+            print_success(&tr!("gc.clean"));
+            return Ok(());
+        }
 
-        if status.success() {
-            print_success("Update process completed.");
-            print_info("Please restart your terminal if you encounter any issues.");
+        let mut reclaimed = 0;
+        let mut failed = 0;
+        for item in &garbage {
+            let result = if dry_run { Ok(()) } else { gc::remove(item) };
+            match result {
+                Ok(()) => {
+                    reclaimed += item.size;
+                    println!(
+                        "  {} {:<18} {} ({})",
+                        if dry_run { "-".normal() } else { "✓".green() },
+                        item.kind.to_string(),
+                        item.path.display(),
+                        HumanBytes(item.size)
+                    );
+                }
+                Err(e) => {
+                    failed += 1;
+                    println!(
+                        "  {} {:<18} {} ({})",
+                        "✗".red(),
+                        item.kind.to_string(),
+                        item.path.display(),
+                        e
+                    );
+                }
+            }
+        }
+        if dry_run {
+            print_info(&tr!(
+                "gc.would_reclaim",
+                garbage.len(),
+                HumanBytes(reclaimed)
+            ));
         } else {
-            return Err(anyhow::anyhow!(
-                "The update script failed to run. Check the output above for details."
+            print_success(&tr!(
+                "gc.reclaimed",
+                garbage.len() - failed,
+                HumanBytes(reclaimed)
+            ));
+        }
+        if failed > 0 {
+            return Err(anyhow!("{} entries could not be removed", failed));
+        }
+        Ok(())
+    }
+
+    /// Look for paths leaf manages but can't modify, typically left behind by a
+    /// `sudo leaf ...` run, and suggest (or with `fix`, apply) repairs
+    pub async fn doctor(&mut self, fix: bool) -> Result<()> {
+        // Links left pointing at where leaf lived before its directory was moved
+        if fix
+            && self.config.relocated_from.is_some()
+            && let Some(root) = self.config.install_dir.parent().map(Path::to_path_buf)
+        {
+            self.relocate(&root).await?;
+        }
+
+        print_step(&tr!("doctor.ownership"));
+        let uid = unsafe { libc::geteuid() };
+        let roots = [self.config.install_dir.clone(), self.config.bin_dir.clone()];
+        let previous = self.config.relocated_from.clone();
+        let path = env::var_os("PATH").unwrap_or_default();
+        let findings = tokio::task::spawn_blocking(move || {
+            let mut findings = doctor::check_ownership(&[&roots[0], &roots[1]], uid);
+            if let Some(root) = roots[0].parent() {
+                findings.extend(doctor::check_links(root, &roots[0], previous.as_deref()));
+            }
+            findings.extend(doctor::check_shadowing(&roots[1], &roots[0], &path));
+            findings
+        })
+        .await?;
+
+        let mut not_owned = Vec::new();
+        let mut remaining = self.unreadable.len();
+        for (name, e) in &self.unreadable {
+            println!(
+                "  {} {:<18} {}: {}",
+                "✗".red(),
+                tr!("doctor.unreadable"),
+                name,
+                e
+            );
+        }
+        let fixable = |finding: &doctor::Finding| {
+            finding.problem == doctor::Problem::ReadOnly
+                && doctor::may_restore_write(&finding.path, &self.config.packages_dir)
+        };
+        for finding in &findings {
+            let fixed = fix && fixable(finding) && doctor::restore_write(&finding.path).is_ok();
+            if fixed {
+                println!(
+                    "  {} {} ({})",
+                    "✓".green(),
+                    finding.path.display(),
+                    tr!("doctor.fixed")
+                );
+                continue;
+            }
+            // Leaf's executable runs first, which is what installing it was for
+            if finding.problem == doctor::Problem::Shadowed {
+                println!(
+                    "  {} {:<18} {}",
+                    "-".normal(),
+                    finding.problem.to_string(),
+                    finding.path.display()
+                );
+                continue;
+            }
+            remaining += 1;
+            if matches!(finding.problem, doctor::Problem::NotOwned { .. }) {
+                not_owned.push(finding.path.clone());
+            }
+            println!(
+                "  {} {:<18} {}",
+                "✗".red(),
+                finding.problem.to_string(),
+                finding.path.display()
+            );
+        }
+
+        let shadowing = findings.iter().any(|f| {
+            matches!(
+                f.problem,
+                doctor::Problem::Shadowed | doctor::Problem::Shadows
+            )
+        });
+        if remaining == 0 {
+            print_success(&tr!("doctor.healthy"));
+            if shadowing {
+                print_info(&tr!("doctor.shadow_hint"));
+            }
+            return Ok(());
+        }
+        if !not_owned.is_empty() {
+            // chown -R on the topmost paths covers everything below them
+            let mut tops: Vec<&PathBuf> = Vec::new();
+            for path in &not_owned {
+                if !tops.iter().any(|top| path.starts_with(top)) {
+                    tops.push(path);
+                }
+            }
+            let user = env::var("USER").unwrap_or_else(|_| uid.to_string());
+            print_info(&tr!("doctor.chown_hint"));
+            println!(
+                "    sudo chown -R {} {}",
+                user,
+                tops.iter()
+                    .map(|path| shell_quote(&path.to_string_lossy()))
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            );
+        }
+        if !fix && findings.iter().any(fixable) {
+            print_info(&tr!("doctor.fix_hint"));
+        }
+        if let Some(previous) = &self.config.relocated_from {
+            print_info(&tr!(
+                "doctor.relocated_hint",
+                previous.display(),
+                self.config.install_dir.display()
             ));
+        } else if findings
+            .iter()
+            .any(|f| f.problem == doctor::Problem::Dangling)
+        {
+            print_info(&tr!("doctor.dangling_hint"));
+        }
+        if shadowing {
+            print_info(&tr!("doctor.shadow_hint"));
+        }
+        if !self.unreadable.is_empty() {
+            print_info(&tr!("doctor.unreadable_hint"));
+        }
+        Err(anyhow!("{} problem(s) found", remaining))
+    }
+
+    /// One screen summing up leaf's state: its version, the index, installed and
+    /// outdated packages, the cache and broken links, pointing at the command
+    /// that looks into each
+    pub async fn show_status(&self) -> Result<()> {
+        let release = tokio::time::timeout(
+            STATUS_CHECK_TIMEOUT,
+            self_update::newer_release(&self.config),
+        )
+        .await
+        .unwrap_or_else(|_| Err(anyhow!("timed out")));
+        let index_file = self.config.install_dir.join("packages.json");
+        let index_age = fs::metadata(&index_file)
+            .await
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .and_then(|modified| modified.elapsed().ok());
+        let outdated = self
+            .installed
+            .keys()
+            .filter(|name| self.is_outdated(name))
+            .count();
+        let (cache_size, broken_links) = tokio::task::spawn_blocking({
+            let (cache_dir, install_dir) = (
+                self.config.cache_dir.clone(),
+                self.config.install_dir.clone(),
+            );
+            move || {
+                let broken = install_dir.parent().map_or(0, |root| {
+                    doctor::check_links(root, &install_dir, None)
+                        .iter()
+                        .filter(|finding| finding.problem == doctor::Problem::Dangling)
+                        .count()
+                });
+                (status::dir_size(&cache_dir), broken)
+            }
+        })
+        .await?;
+
+        print_info(&tr!("status.header"));
+        match release {
+            Ok(Some(newer)) => println!(
+                "  {}",
+                tr!("status.version_newer", build_info::VERSION, newer)
+            ),
+            Ok(None) => println!("  {}", tr!("status.version_current", build_info::VERSION)),
+            Err(e) => println!(
+                "  {}",
+                tr!("status.version_unknown", build_info::VERSION, e)
+            ),
+        }
+        match index_age {
+            Some(age) => println!(
+                "  {}",
+                tr!("status.index", self.packages.len(), status::age(age))
+            ),
+            None => println!("  {}", tr!("status.index_missing")),
+        }
+        if outdated > 0 {
+            println!(
+                "  {}",
+                tr!("status.installed_outdated", self.installed.len(), outdated)
+            );
+        } else {
+            println!(
+                "  {}",
+                tr!("status.installed_current", self.installed.len())
+            );
+        }
+        println!(
+            "  {}",
+            tr!(
+                "status.cache",
+                HumanBytes(cache_size),
+                self.config.cache_dir.display()
+            )
+        );
+        if broken_links > 0 {
+            println!("  {}", tr!("status.broken_links", broken_links));
+        } else {
+            println!("  {}", tr!("status.no_broken_links"));
+        }
+        Ok(())
+    }
+
+    /// How installed packages have been used, from what leaf recorded on this
+    /// machine: installs, and runs of executables linked through shims. Those
+    /// not run for `unused_days` are listed as candidates for removal.
+    pub fn show_stats(&self, unused_days: i64) -> Result<()> {
+        if self.installed.is_empty() {
+            print_info(&tr!("list.empty"));
+            return Ok(());
+        }
+        // Only shims pass through leaf when an executable runs
+        let tracked: HashSet<String> = std::fs::read_dir(&self.config.bin_dir)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .filter_map(|entry| shim_package(&entry.path()))
+            .collect();
+        let installs = usage::installs(&self.config.install_dir);
+        let now = chrono::Utc::now();
+        let ago = |time: chrono::DateTime<chrono::Utc>| {
+            status::age((now - time).to_std().unwrap_or_default())
+        };
+
+        let mut keys: Vec<&String> = self.installed.keys().collect();
+        keys.sort();
+        let mut unused = Vec::new();
+        print_info(&tr!("stats.header"));
+        for key in keys {
+            let (name, _) = split_spec(key);
+            let metadata_file = self.config.packages_dir.join(key).join("leaf-package.json");
+            let installed_at = installs
+                .get(name)
+                .map(|installs| installs.last)
+                .or_else(|| {
+                    let modified = std::fs::metadata(&metadata_file).ok()?.modified().ok()?;
+                    Some(modified.into())
+                });
+            let installed_text = match (installs.get(name), installed_at) {
+                (Some(installs), _) if installs.count == 1 => {
+                    tr!("stats.installed_once", ago(installs.last))
+                }
+                (Some(installs), _) => {
+                    tr!("stats.installed_times", installs.count, ago(installs.last))
+                }
+                (None, Some(at)) => tr!("stats.installed_at", ago(at)),
+                (None, None) => tr!("stats.install_unknown"),
+            };
+            let runs = usage::total(&usage::runs(&self.config.install_dir, name));
+            let runs_text = match runs {
+                _ if !tracked.contains(name) => tr!("stats.runs_untracked"),
+                Some(runs) if runs.count == 1 => tr!("stats.run_once", ago(runs.last)),
+                Some(runs) => tr!("stats.run_times", runs.count, ago(runs.last)),
+                None => tr!("stats.never_run"),
+            };
+            println!("  {:<18} {}; {}", key, installed_text, runs_text);
+
+            let last_run = runs.map(|runs| runs.last);
+            if tracked.contains(name)
+                && installed_at.is_some_and(|at| usage::unused_for(last_run, at, now, unused_days))
+            {
+                unused.push((key, last_run));
+            }
+        }
+
+        if !unused.is_empty() {
+            print_info(&tr!("stats.unused", unused_days));
+            for (key, last_run) in unused {
+                match last_run {
+                    Some(last_run) => {
+                        println!("  {:<18} {}", key, tr!("stats.last_run", ago(last_run)))
+                    }
+                    None => println!("  {:<18} {}", key, tr!("stats.never_run")),
+                }
+            }
+            print_info(&tr!("stats.unused_hint"));
+        }
+        if self
+            .installed
+            .keys()
+            .any(|key| !tracked.contains(split_spec(key).0))
+        {
+            print_info(&tr!("stats.untracked_hint"));
+        }
+        Ok(())
+    }
+
+    /// Write a redacted diagnostic report to `output`, or print it
+    pub async fn create_report(&self, output: Option<&Path>) -> Result<()> {
+        let report = report::build(&self.config, self.installed.len(), self.packages.len())?;
+        match output {
+            Some(path) => {
+                fs::write(path, report).await?;
+                print_success(&tr!("report.written", path.display()));
+                print_info(&tr!("report.hint"));
+            }
+            None => println!("{}", report),
         }
+        Ok(())
+    }
+
+    /// Clear out partial downloads, staging directories and metadata after Ctrl-C
+    pub fn clean_up_interrupted(&self) -> usize {
+        interrupt::cleanup()
+    }
+
+    /// Record a finished command in the operation journal
+    pub fn record_operation(&self, command: &str, result: &Result<()>) {
+        journal::record(&self.config.install_dir, command, result);
+    }
+
+    /// State to compare against once a command run with `--report` finishes
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot::take(
+            &self.installed,
+            &self.config.bin_dir,
+            &self.config.cache_dir,
+        )
+    }
 
+    /// Write a JSON record of what `command` changed since `before`: packages
+    /// with their versions and download hashes, the files they consist of, new
+    /// downloads and entries added to or removed from bin_dir
+    pub fn write_report(
+        &self,
+        path: &Path,
+        before: &Snapshot,
+        command: &str,
+        result: &Result<()>,
+    ) -> Result<()> {
+        let changes = trail::changes(
+            before,
+            &self.snapshot(),
+            &self.config.packages_dir,
+            &self.config.bin_dir,
+            &self.config.cache_dir,
+        );
+        let report = serde_json::json!({
+            "generated_at": chrono::Utc::now().to_rfc3339(),
+            "leaf_version": build_info::VERSION,
+            "platform": self.platform,
+            "command": report::redact_command(command),
+            "success": result.is_ok(),
+            "error": result.as_ref().err().map(|e| e.to_string()),
+            "packages": changes.packages,
+            "downloads": changes.downloads,
+            "bin": changes.bin,
+        });
+        write_atomic(path, &serde_json::to_string_pretty(&report)?)?;
+        print_info(&tr!("trail.written", path.display()));
         Ok(())
     }
+
+    pub async fn self_update(
+        &self,
+        version: Option<&str>,
+        prerelease: bool,
+        rollback: bool,
+        check: bool,
+    ) -> Result<()> {
+        if rollback {
+            return self_update::rollback(&self.config).await;
+        }
+        if check {
+            return self_update::check(&self.config, prerelease).await;
+        }
+        self_update::update(&self.config, version, prerelease).await
+    }
+}
+
+/// Summarize how the index changed in a sync, and with `list` name each change
+fn print_index_changes(changes: &IndexChanges, list: bool) {
+    if changes.is_empty() {
+        print_info(&tr!("update.no_changes"));
+        return;
+    }
+    print_info(&tr!(
+        "update.changes",
+        changes.added.len(),
+        changes.updated.len(),
+        changes.removed.len()
+    ));
+    if !list {
+        return;
+    }
+    for (name, version) in &changes.added {
+        println!("  {} {} {}", "+".green(), name, version);
+    }
+    for (name, old_version, new_version) in &changes.updated {
+        println!(
+            "  {} {} {} -> {}",
+            "~".yellow(),
+            name,
+            old_version,
+            new_version
+        );
+    }
+    for name in &changes.removed {
+        println!("  {} {}", "-".red(), name);
+    }
+}
+
+/// Print a package's caveats, indented under a heading
+fn print_caveats(name: &str, caveats: &str) {
+    println!("\n{}", tr!("install.caveats", name).bold());
+    for line in caveats.trim_end().lines() {
+        println!("  {}", line);
+    }
+}
+
+/// Print the user's notes on a package under its row in a listing
+fn print_notes(notes: &[String], indent: &str) {
+    for note in notes {
+        let line = format!("{}{} {}", indent, tr!("note.label"), note);
+        let continued = indent.chars().count() + tr!("note.label").chars().count() + 1;
+        println!("{}", layout::wrapped(&line, 0, continued));
+    }
+}
+
+/// Show a package's license terms and require the user to accept them
+fn accept_license(name: &str, terms: &str) -> Result<()> {
+    print_info(&tr!("install.license", name));
+    for line in terms.trim_end().lines() {
+        println!("  {}", line);
+    }
+    let accepted = prompt::yes_no(&tr!("install.license_accept")).map_err(|_| {
+        anyhow!(
+            "{} requires accepting its license terms; pass --yes to accept them",
+            name
+        )
+    })?;
+    if !accepted {
+        return Err(anyhow!("License not accepted; {} was not installed", name));
+    }
+    Ok(())
+}
+
+/// Create (or replace) a symlink in bin_dir
+async fn replace_symlink(target: &Path, link: &Path) -> Result<()> {
+    if fs::symlink_metadata(link).await.is_ok() {
+        fs::remove_file(link).await?;
+    }
+    fs::symlink(target, link).await?;
+    Ok(())
+}
+
+/// Package names under each of their tags; untagged packages are grouped together
+fn group_by_tag<'a>(
+    packages: impl IntoIterator<Item = (&'a String, &'a Package)>,
+) -> BTreeMap<String, Vec<&'a str>> {
+    let mut groups: BTreeMap<String, Vec<&'a str>> = BTreeMap::new();
+    for (name, package) in packages {
+        match package.tags.as_deref() {
+            Some(tags) if !tags.is_empty() => {
+                for tag in tags {
+                    groups.entry(tag.clone()).or_default().push(name);
+                }
+            }
+            _ => groups.entry("untagged".to_string()).or_default().push(name),
+        }
+    }
+    groups
+}
+
+/// Delete a package directory, or the link to it in the shared store
+async fn remove_path(path: &Path) -> std::io::Result<()> {
+    if fs::symlink_metadata(path).await?.is_symlink() {
+        fs::remove_file(path).await
+    } else {
+        fs::remove_dir_all(path).await
+    }
+}
+
+/// Whether `link` is a symlink or wrapper whose target lives inside `dir`
+fn link_points_into(link: &Path, dir: &Path) -> bool {
+    links::target(link).is_some_and(|target| target.starts_with(dir))
 }
 
 // Test suite remains the same but will only test Linux packages
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::collections::HashMap;
     use tokio::fs;
 
     #[tokio::test]
@@ -504,8 +4183,14 @@ mod tests {
             .await
             .expect("Failed to read packages.json. Make sure it's in the project root.");
 
-        let packages: HashMap<String, Package> = serde_json::from_str(&content)
+        let index = package::parse_index(&content, build_info::VERSION)
             .expect("Failed to parse packages.json. Check for syntax errors.");
+        assert!(
+            index.invalid.is_empty(),
+            "Unreadable definitions in packages.json: {:?}",
+            index.invalid
+        );
+        let packages = index.packages;
 
         let client = reqwest::Client::builder()
             .user_agent("leaf-package-manager-test-suite/1.0")
@@ -0,0 +1,284 @@
+use std::env;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+/// Platform keys leaf installs packages for
+pub const SUPPORTED: [&str; 7] = [
+    "linux-x86_64",
+    "linux-aarch64",
+    "linux-riscv64",
+    "macos-x86_64",
+    "macos-aarch64",
+    "windows-x86_64",
+    "windows-aarch64",
+];
+
+/// Key for a universal binary build that serves both macOS architectures
+pub const MACOS_UNIVERSAL: &str = "macos-universal";
+
+/// Platform key of this machine, e.g. "linux-x86_64"
+pub fn current() -> String {
+    format!("{}-{}", env::consts::OS, env::consts::ARCH)
+}
+
+/// Keys a package definition may use for `platform`, best first: its own
+/// entry, then builds shared with other platforms
+pub fn candidates(platform: &str) -> Vec<&str> {
+    let mut keys = vec![platform];
+    if platform.starts_with("macos-") {
+        keys.push(MACOS_UNIVERSAL);
+    }
+    keys
+}
+
+/// Platform whose builds `platform` can run under emulation: x86_64 binaries
+/// run on Apple Silicon through Rosetta 2 and on Windows on ARM through its
+/// built-in x64 emulation
+pub fn emulated_by(platform: &str) -> Option<&'static str> {
+    match platform {
+        "macos-aarch64" => Some("macos-x86_64"),
+        "windows-aarch64" => Some("windows-x86_64"),
+        _ => None,
+    }
+}
+
+/// What runs emulated builds on `platform`, for messages
+pub fn emulator_name(platform: &str) -> &'static str {
+    if platform.starts_with("macos-") {
+        "Rosetta 2"
+    } else {
+        "x64 emulation"
+    }
+}
+
+/// Why a package can't be installed for `platform` and what can be done about
+/// it, worded the same by install, info and search
+#[derive(Debug, PartialEq)]
+pub struct Unsupported {
+    pub platform: String,
+    /// Keys the package has builds for, sorted
+    pub supported: Vec<String>,
+    /// Build that would run under emulation, were it not turned off
+    pub emulated: Option<String>,
+}
+
+impl Unsupported {
+    pub fn new<'a>(platform: &str, keys: impl IntoIterator<Item = &'a String>) -> Self {
+        let mut supported: Vec<String> = keys.into_iter().cloned().collect();
+        supported.sort();
+        let emulated = emulated_by(platform).and_then(|emulated| {
+            candidates(emulated)
+                .into_iter()
+                .find(|key| supported.iter().any(|supported| supported == key))
+                .map(String::from)
+        });
+        Self {
+            platform: platform.to_string(),
+            supported,
+            emulated,
+        }
+    }
+
+    /// What the package does offer, one line each
+    pub fn details(&self) -> Vec<String> {
+        if self.supported.is_empty() {
+            return vec!["It has no builds for any platform".to_string()];
+        }
+        let mut details = vec![format!("Builds exist for: {}", self.supported.join(", "))];
+        if let Some(emulated) = &self.emulated {
+            details.push(format!(
+                "The {} build runs under {}, but \"emulation\" is \"never\" in config.json",
+                emulated,
+                emulator_name(&self.platform)
+            ));
+        }
+        details.push(format!(
+            "Use --platform {} --prefix <dir> to install it into a separate root for a machine of that kind, or ask the package's maintainers for a {} build",
+            self.supported[0], self.platform
+        ));
+        details
+    }
+}
+
+impl std::fmt::Display for Unsupported {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Package not available for platform {}", self.platform)?;
+        for line in self.details() {
+            write!(f, "\n  {}", line)?;
+        }
+        Ok(())
+    }
+}
+
+/// Architectures this machine can run, named like `env::consts::ARCH`
+pub fn runnable_architectures(emulation: bool) -> Vec<&'static str> {
+    let mut architectures = vec![env::consts::ARCH];
+    if emulation
+        && let Some(emulated) = emulated_by(&current())
+        && let Some((_, arch)) = emulated.split_once('-')
+    {
+        architectures.push(arch);
+    }
+    architectures
+}
+
+/// Whether this CPU has `feature`, named as in rustc's target features (e.g.
+/// "avx2", "sve") or an x86-64 microarchitecture level such as "x86-64-v3".
+/// Unknown names count as missing.
+pub fn has_cpu_feature(feature: &str) -> bool {
+    #[cfg(target_arch = "x86_64")]
+    {
+        macro_rules! detected {
+            ($($name:tt),*) => {
+                match feature {
+                    $($name => std::is_x86_feature_detected!($name),)*
+                    "x86-64-v2" => ["sse3", "ssse3", "sse4.1", "sse4.2", "popcnt"]
+                        .iter()
+                        .all(|feature| has_cpu_feature(feature)),
+                    "x86-64-v3" => has_cpu_feature("x86-64-v2")
+                        && ["avx", "avx2", "bmi1", "bmi2", "fma", "lzcnt", "movbe"]
+                            .iter()
+                            .all(|feature| has_cpu_feature(feature)),
+                    "x86-64-v4" => has_cpu_feature("x86-64-v3")
+                        && ["avx512f", "avx512bw", "avx512cd", "avx512dq", "avx512vl"]
+                            .iter()
+                            .all(|feature| has_cpu_feature(feature)),
+                    _ => false,
+                }
+            };
+        }
+        detected!(
+            "sse3", "ssse3", "sse4.1", "sse4.2", "popcnt", "avx", "avx2", "bmi1", "bmi2", "fma",
+            "lzcnt", "movbe", "aes", "sha", "avx512f", "avx512bw", "avx512cd", "avx512dq",
+            "avx512vl"
+        )
+    }
+    #[cfg(target_arch = "aarch64")]
+    {
+        macro_rules! detected {
+            ($($name:tt),*) => {
+                match feature {
+                    $($name => std::arch::is_aarch64_feature_detected!($name),)*
+                    _ => false,
+                }
+            };
+        }
+        detected!(
+            "neon", "aes", "sha2", "crc", "lse", "dotprod", "sve", "sve2"
+        )
+    }
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+    {
+        let _ = feature;
+        false
+    }
+}
+
+const FAT_MAGIC: u32 = 0xcafe_babe;
+const MH_MAGIC: u32 = 0xfeed_face;
+const MH_MAGIC_64: u32 = 0xfeed_facf;
+const CPU_TYPE_X86_64: u32 = 0x0100_0007;
+const CPU_TYPE_ARM64: u32 = 0x0100_000c;
+
+/// Architectures a Mach-O file contains, named like `env::consts::ARCH`, or
+/// None when `path` isn't Mach-O
+pub fn macho_architectures(path: &Path) -> Option<Vec<&'static str>> {
+    let mut header = Vec::new();
+    File::open(path)
+        .ok()?
+        .take(4096)
+        .read_to_end(&mut header)
+        .ok()?;
+    parse_macho(&header)
+}
+
+fn parse_macho(header: &[u8]) -> Option<Vec<&'static str>> {
+    let word =
+        |offset: usize| -> Option<[u8; 4]> { header.get(offset..offset + 4)?.try_into().ok() };
+    let magic = u32::from_be_bytes(word(0)?);
+
+    // Universal binaries start with a big-endian table of slices
+    if magic == FAT_MAGIC {
+        let count = u32::from_be_bytes(word(4)?) as usize;
+        // Java class files share the magic; their version field reads as a big count
+        if count == 0 || count > 16 {
+            return None;
+        }
+        return (0..count)
+            .map(|i| word(8 + i * 20).map(|cpu| arch_name(u32::from_be_bytes(cpu))))
+            .collect();
+    }
+
+    // Single-architecture binaries, written little-endian on both platforms
+    match u32::from_le_bytes(word(0)?) {
+        MH_MAGIC | MH_MAGIC_64 => Some(vec![arch_name(u32::from_le_bytes(word(4)?))]),
+        _ => None,
+    }
+}
+
+fn arch_name(cpu_type: u32) -> &'static str {
+    match cpu_type {
+        CPU_TYPE_X86_64 => "x86_64",
+        CPU_TYPE_ARM64 => "aarch64",
+        _ => "other",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_macho() {
+        let mut fat = Vec::new();
+        fat.extend(FAT_MAGIC.to_be_bytes());
+        fat.extend(2u32.to_be_bytes());
+        for cpu in [CPU_TYPE_X86_64, CPU_TYPE_ARM64] {
+            fat.extend(cpu.to_be_bytes());
+            fat.extend([0; 16]);
+        }
+        assert_eq!(parse_macho(&fat), Some(vec!["x86_64", "aarch64"]));
+
+        let mut thin = Vec::new();
+        thin.extend(MH_MAGIC_64.to_le_bytes());
+        thin.extend(CPU_TYPE_ARM64.to_le_bytes());
+        assert_eq!(parse_macho(&thin), Some(vec!["aarch64"]));
+
+        assert_eq!(parse_macho(b"\x7fELF\x02\x01\x01\x00"), None);
+        // A Java class file (major version 61)
+        assert_eq!(parse_macho(b"\xca\xfe\xba\xbe\x00\x00\x00\x3d"), None);
+    }
+
+    #[test]
+    fn test_candidates() {
+        assert_eq!(candidates("linux-x86_64"), vec!["linux-x86_64"]);
+        assert_eq!(
+            candidates("macos-aarch64"),
+            vec!["macos-aarch64", MACOS_UNIVERSAL]
+        );
+        assert_eq!(emulated_by("macos-aarch64"), Some("macos-x86_64"));
+        assert_eq!(emulated_by("windows-aarch64"), Some("windows-x86_64"));
+        assert_eq!(emulated_by("linux-aarch64"), None);
+        assert_eq!(emulated_by("linux-riscv64"), None);
+    }
+
+    #[test]
+    fn test_unsupported() {
+        let keys = ["macos-x86_64".to_string(), "linux-x86_64".to_string()];
+        let unsupported = Unsupported::new("macos-aarch64", &keys);
+        assert_eq!(unsupported.supported, ["linux-x86_64", "macos-x86_64"]);
+        assert_eq!(unsupported.emulated.as_deref(), Some("macos-x86_64"));
+        assert!(unsupported.details()[1].contains("Rosetta 2"));
+        assert!(
+            unsupported.to_string().starts_with(
+                "Package not available for platform macos-aarch64\n  Builds exist for:"
+            )
+        );
+
+        let unsupported = Unsupported::new("linux-aarch64", &keys);
+        assert_eq!(unsupported.emulated, None);
+        assert!(unsupported.details()[1].contains("--platform linux-x86_64 --prefix <dir>"));
+        assert_eq!(Unsupported::new("linux-aarch64", []).details().len(), 1);
+    }
+}
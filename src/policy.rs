@@ -0,0 +1,154 @@
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+
+/// Install restrictions for managed machines, set in config.json under "policy"
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Policy {
+    /// Packages that may never be installed
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub blocked: Vec<String>,
+    /// When non-empty, only these packages may be installed
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allowed: Vec<String>,
+    /// When non-empty, downloads must come from one of these: the same scheme,
+    /// host and port, and a path at or below the entry's path
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allowed_sources: Vec<String>,
+    /// Fail instead of warning when a package's signature can't be checked
+    /// because cosign isn't installed
+    #[serde(default)]
+    pub require_signature_checks: bool,
+}
+
+impl Policy {
+    /// Fail if `package` (downloaded from any of `urls`) is not permitted. Entries
+    /// may end in `*` to match a prefix.
+    pub fn check(&self, package: &str, urls: &[&str]) -> Result<()> {
+        if self.blocked.iter().any(|entry| matches(entry, package)) {
+            return Err(anyhow!("Package '{}' is blocked by policy", package));
+        }
+        if !self.allowed.is_empty() && !self.allowed.iter().any(|entry| matches(entry, package)) {
+            return Err(anyhow!(
+                "Package '{}' is not on the policy allowlist",
+                package
+            ));
+        }
+        for url in urls {
+            if !self.allowed_sources.is_empty()
+                && !self
+                    .allowed_sources
+                    .iter()
+                    .any(|source| source_allows(source, url))
+            {
+                return Err(anyhow!(
+                    "Package '{}' downloads from {}, which is not an allowed source",
+                    package,
+                    url
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn matches(entry: &str, name: &str) -> bool {
+    match entry.strip_suffix('*') {
+        Some(prefix) => name.starts_with(prefix),
+        None => entry == name,
+    }
+}
+
+/// Whether `url` comes from `source`. The path must match whole segments
+/// ("https://github.com/org" allows ".../org/tool" but not ".../organization"),
+/// unless the entry ends in `*`.
+fn source_allows(source: &str, url: &str) -> bool {
+    let wildcard = source.ends_with('*');
+    let (Ok(source), Ok(url)) = (
+        reqwest::Url::parse(source.trim_end_matches('*')),
+        reqwest::Url::parse(url),
+    ) else {
+        return false;
+    };
+    if source.scheme() != url.scheme()
+        || source.host_str() != url.host_str()
+        || source.port_or_known_default() != url.port_or_known_default()
+    {
+        return false;
+    }
+    let (base, path) = (source.path(), url.path());
+    path == base
+        || ((wildcard || base.ends_with('/')) && path.starts_with(base))
+        || path
+            .strip_prefix(base)
+            .is_some_and(|rest| rest.starts_with('/'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_policy_check() {
+        let policy = Policy {
+            blocked: vec!["code".to_string()],
+            allowed: vec!["go".to_string(), "node*".to_string()],
+            allowed_sources: vec!["https://github.com/".to_string()],
+            ..Policy::default()
+        };
+        assert!(
+            policy
+                .check("go", &["https://github.com/golang/go.tar.gz"])
+                .is_ok()
+        );
+        assert!(
+            policy
+                .check("nodejs", &["https://github.com/nodejs/node.tar.gz"])
+                .is_ok()
+        );
+        assert!(policy.check("code", &["https://github.com/x"]).is_err());
+        assert!(policy.check("nvim", &["https://github.com/x"]).is_err());
+        assert!(
+            policy
+                .check("go", &["https://example.com/go.tar.gz"])
+                .is_err()
+        );
+        assert!(Policy::default().check("anything", &["http://x"]).is_ok());
+    }
+
+    #[test]
+    fn test_source_allows() {
+        assert!(source_allows(
+            "https://github.com/*",
+            "https://github.com/a/b"
+        ));
+        assert!(source_allows(
+            "https://github.com",
+            "https://github.com/a/b"
+        ));
+        assert!(source_allows(
+            "https://github.com/org",
+            "https://github.com/org/b"
+        ));
+        assert!(!source_allows(
+            "https://github.com/org",
+            "https://github.com/organization/b"
+        ));
+        assert!(source_allows(
+            "https://github.com/org*",
+            "https://github.com/organization/b"
+        ));
+        assert!(!source_allows(
+            "https://github.com",
+            "https://github.com.evil.com/a"
+        ));
+        assert!(!source_allows(
+            "https://github.com/",
+            "https://github.com@evil.com/a"
+        ));
+        assert!(!source_allows("https://github.com/", "http://github.com/a"));
+        assert!(!source_allows(
+            "https://github.com/",
+            "https://github.com:8443/a"
+        ));
+    }
+}
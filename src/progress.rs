@@ -0,0 +1,140 @@
+use crate::config::BarStyle;
+use crate::utils::print_info;
+use anyhow::Result;
+use indicatif::{HumanBytes, MultiProgress, ProgressBar, ProgressStyle};
+use std::io::IsTerminal;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How often a transfer reports itself when there is no terminal to draw on
+const LOG_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Bars of the batch being worked through; print_* lines go above them
+static ACTIVE: Mutex<Option<MultiProgress>> = Mutex::new(None);
+
+pub fn bar_chars(style: BarStyle) -> &'static str {
+    match style {
+        BarStyle::Unicode => "█▌ ",
+        BarStyle::Ascii => "=> ",
+    }
+}
+
+/// Progress of a batch of packages: one labeled bar per running download above
+/// an overall bar. The bars are cleared when it is dropped.
+pub struct Batch {
+    multi: MultiProgress,
+    overall: ProgressBar,
+}
+
+impl Batch {
+    pub fn new(total: usize, style: BarStyle) -> Result<Self> {
+        let multi = MultiProgress::new();
+        let overall = multi.add(ProgressBar::new(total as u64));
+        overall.set_style(
+            ProgressStyle::default_bar()
+                .template("  {prefix:20!} [{bar:30}] {pos}/{len}")?
+                .progress_chars(bar_chars(style)),
+        );
+        overall.set_prefix(tr!("progress.packages"));
+        if let Ok(mut active) = ACTIVE.lock() {
+            *active = Some(multi.clone());
+        }
+        Ok(Self { multi, overall })
+    }
+
+    /// Count one more package as done, whether or not it succeeded
+    pub fn inc(&self) {
+        self.overall.inc(1);
+    }
+}
+
+impl Drop for Batch {
+    fn drop(&mut self) {
+        if let Ok(mut active) = ACTIVE.lock() {
+            active.take();
+        }
+        self.overall.finish_and_clear();
+        self.multi.clear().ok();
+    }
+}
+
+/// Whether a batch is drawing its bars
+pub fn active() -> bool {
+    ACTIVE.lock().is_ok_and(|active| active.is_some())
+}
+
+/// Put `bar` in the running batch, above its overall bar
+pub fn attach(bar: ProgressBar) -> ProgressBar {
+    match ACTIVE.lock().ok().and_then(|active| active.clone()) {
+        Some(multi) => multi.insert_from_back(1, bar),
+        None => bar,
+    }
+}
+
+/// Run `print` with the batch's bars lifted, so lines don't tear through them
+pub fn suspend<R>(print: impl FnOnce() -> R) -> R {
+    match ACTIVE.lock().ok().and_then(|active| active.clone()) {
+        Some(multi) => multi.suspend(print),
+        None => print(),
+    }
+}
+
+/// A download's bar; without a terminal, where no bar is drawn, a line every
+/// few seconds instead
+pub struct Transfer {
+    bar: ProgressBar,
+    label: String,
+    total: Option<u64>,
+    /// When progress was last logged, for transfers that log it
+    logged: Option<Instant>,
+}
+
+impl Transfer {
+    pub fn new(bar: ProgressBar, label: &str, total: Option<u64>) -> Self {
+        let logged = (!std::io::stderr().is_terminal()).then(Instant::now);
+        Self {
+            bar,
+            label: label.to_string(),
+            total,
+            logged,
+        }
+    }
+
+    /// Nothing drawn and nothing logged
+    pub fn hidden() -> Self {
+        Self {
+            bar: ProgressBar::hidden(),
+            label: String::new(),
+            total: None,
+            logged: None,
+        }
+    }
+
+    pub fn set_position(&mut self, position: u64) {
+        self.bar.set_position(position);
+        let Some(logged) = &mut self.logged else {
+            return;
+        };
+        if logged.elapsed() < LOG_INTERVAL {
+            return;
+        }
+        *logged = Instant::now();
+        print_info(&match self.total {
+            Some(total) => tr!(
+                "progress.transfer",
+                self.label,
+                HumanBytes(position),
+                HumanBytes(total)
+            ),
+            None => tr!(
+                "progress.transfer_unknown",
+                self.label,
+                HumanBytes(position)
+            ),
+        });
+    }
+
+    pub fn finish(&self) {
+        self.bar.finish_and_clear();
+    }
+}
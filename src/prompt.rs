@@ -0,0 +1,171 @@
+use anyhow::{Result, anyhow};
+use colored::Colorize;
+use std::io::{IsTerminal, Write};
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/// How questions are answered for this run
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// Ask on the terminal when there is one
+    Ask = 0,
+    /// --yes: answer every yes/no question with yes
+    AssumeYes = 1,
+    /// --no-input: never ask; questions fail with a hint instead
+    NoInput = 2,
+}
+
+static MODE: AtomicU8 = AtomicU8::new(Mode::Ask as u8);
+
+/// Held by tests that change the mode, so they don't see each other's
+#[cfg(test)]
+pub static TEST_MODE_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+pub fn set_mode(mode: Mode) {
+    MODE.store(mode as u8, Ordering::Relaxed);
+}
+
+/// The mode in effect; asking needs someone at the keyboard
+pub fn mode() -> Mode {
+    match MODE.load(Ordering::Relaxed) {
+        1 => Mode::AssumeYes,
+        2 => Mode::NoInput,
+        _ if !is_interactive() => Mode::NoInput,
+        _ => Mode::Ask,
+    }
+}
+
+/// Whether someone is at the keyboard to answer prompts
+fn is_interactive() -> bool {
+    std::io::stdin().is_terminal() && std::io::stdout().is_terminal()
+}
+
+fn read_answer(prompt: &str, hint: &str) -> Option<String> {
+    let hint = if hint.is_empty() {
+        String::new()
+    } else {
+        format!(" {}", hint)
+    };
+    print!(
+        "{} {}{} ",
+        tr!("label.prompt").magenta().bold(),
+        prompt,
+        hint
+    );
+    std::io::stdout().flush().ok();
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer).ok()?;
+    Some(answer.trim().to_string())
+}
+
+/// Ask a yes/no question, defaulting to "no" on empty or unreadable input.
+/// Fails without a terminal or under --no-input unless --yes was given.
+pub fn yes_no(prompt: &str) -> Result<bool> {
+    match mode() {
+        Mode::AssumeYes => Ok(true),
+        Mode::NoInput => Err(anyhow!(
+            "{} (no terminal to ask on; pass --yes to agree)",
+            prompt
+        )),
+        Mode::Ask => Ok(read_answer(prompt, "[y/N]")
+            .is_some_and(|answer| matches!(answer.to_lowercase().as_str(), "y" | "yes"))),
+    }
+}
+
+/// Ask a yes/no question that an empty or unreadable answer leaves at
+/// `default`. Without a terminal `default` stands, and --yes answers yes.
+pub fn yes_no_default(prompt: &str, default: bool) -> bool {
+    match mode() {
+        Mode::AssumeYes => true,
+        Mode::NoInput => default,
+        Mode::Ask => {
+            let hint = if default { "[Y/n]" } else { "[y/N]" };
+            read_answer(prompt, hint).map_or(default, |answer| {
+                match answer.to_lowercase().as_str() {
+                    "y" | "yes" => true,
+                    "n" | "no" => false,
+                    _ => default,
+                }
+            })
+        }
+    }
+}
+
+/// Let the user pick any of `options` from a numbered list; empty or invalid
+/// input picks nothing. --yes can't choose, so it fails like --no-input.
+pub fn select(prompt: &str, options: &[String]) -> Result<Vec<usize>> {
+    if mode() != Mode::Ask {
+        return Err(anyhow!("{} (no terminal to ask on)", prompt));
+    }
+    for (i, option) in options.iter().enumerate() {
+        println!("  {:>2}) {}", i + 1, option);
+    }
+    let hint = format!("[1-{}, e.g. \"1 3\"]", options.len());
+    Ok(read_answer(prompt, &hint)
+        .and_then(|answer| parse_selection(&answer, options.len()))
+        .unwrap_or_default())
+}
+
+/// Ask for a line of text, falling back to `default` on empty input and when
+/// nobody can be asked
+pub fn text(prompt: &str, default: Option<&str>) -> Result<String> {
+    let answer = match mode() {
+        Mode::Ask => {
+            let hint = default
+                .filter(|value| !value.is_empty())
+                .map(|value| format!("[{}]", value))
+                .unwrap_or_default();
+            read_answer(prompt, &hint).filter(|answer| !answer.is_empty())
+        }
+        _ => None,
+    };
+    answer
+        .or_else(|| default.map(String::from))
+        .ok_or_else(|| anyhow!("{} (no terminal to ask on)", prompt))
+}
+
+/// Parse "2", "1 3" or "1,3" into zero-based indices below `count`
+fn parse_selection(input: &str, count: usize) -> Option<Vec<usize>> {
+    let mut selected = Vec::new();
+    for part in input
+        .split([',', ' '])
+        .filter(|part| !part.trim().is_empty())
+    {
+        let number: usize = part.trim().parse().ok()?;
+        if number == 0 || number > count {
+            return None;
+        }
+        if !selected.contains(&(number - 1)) {
+            selected.push(number - 1);
+        }
+    }
+    Some(selected)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_selection() {
+        assert_eq!(parse_selection("2\n", 3), Some(vec![1]));
+        assert_eq!(parse_selection("1, 3 1", 3), Some(vec![0, 2]));
+        assert_eq!(parse_selection("", 3), Some(vec![]));
+        assert_eq!(parse_selection("4", 3), None);
+        assert_eq!(parse_selection("x", 3), None);
+    }
+
+    #[test]
+    fn test_assumed_answers() {
+        let _mode = TEST_MODE_LOCK.blocking_lock();
+        set_mode(Mode::AssumeYes);
+        assert!(yes_no("Continue?").unwrap());
+        assert!(yes_no_default("Continue?", false));
+        assert!(select("Pick one", &["a".to_string()]).is_err());
+        assert_eq!(text("Name", Some("leaf")).unwrap(), "leaf");
+        set_mode(Mode::NoInput);
+        assert!(yes_no("Continue?").is_err());
+        assert!(!yes_no_default("Continue?", false));
+        assert!(text("Name", None).is_err());
+        set_mode(Mode::Ask);
+    }
+}
@@ -0,0 +1,161 @@
+use crate::manifest;
+use crate::shim::shim_package;
+use crate::utils::{link_target, relative_path};
+use anyhow::{Result, anyhow};
+use std::fs;
+use std::os::unix::fs::symlink;
+use std::path::{Path, PathBuf};
+
+/// Directories under a root (e.g. ~/.local) where leaf puts links, and how deep
+/// they go: bin and extra files directly, fonts one directory per package
+pub const LINK_DIRS: [(&str, usize); 5] = [
+    ("bin", 1),
+    ("lib", 1),
+    ("include", 1),
+    ("share", 1),
+    ("share/fonts", 2),
+];
+
+/// Symlinks under `dir`, up to `depth` levels down
+pub fn links_in(dir: &Path, depth: usize) -> Vec<PathBuf> {
+    let mut links = Vec::new();
+    let Ok(entries) = fs::read_dir(dir) else {
+        return links;
+    };
+    for entry in entries.flatten() {
+        let Ok(file_type) = entry.file_type() else {
+            continue;
+        };
+        if file_type.is_symlink() {
+            links.push(entry.path());
+        } else if file_type.is_dir() && depth > 1 {
+            links.extend(links_in(&entry.path(), depth - 1));
+        }
+    }
+    links
+}
+
+/// Point every link under `root` that leads into `old` at the same place under
+/// `new`, returning how many were changed
+pub fn rewrite_links(root: &Path, old: &Path, new: &Path, relative: bool) -> Result<usize> {
+    let mut rewritten = 0;
+    for (dir, depth) in LINK_DIRS {
+        for link in links_in(&root.join(dir), depth) {
+            let Some(rest) = link_target(&link)
+                .and_then(|target| target.strip_prefix(old).ok().map(Path::to_path_buf))
+            else {
+                continue;
+            };
+            let target = new.join(rest);
+            let target = match link.parent() {
+                Some(parent) if relative => relative_path(parent, &target),
+                _ => target,
+            };
+            fs::remove_file(&link)?;
+            symlink(target, &link)?;
+            rewritten += 1;
+        }
+    }
+    Ok(rewritten)
+}
+
+/// Move leaf's directory and the links and shims it owns from `old_root` to
+/// `new_root` (both like ~/.local). Links into `also_owned`, where leaf lived
+/// before it was last moved, go too. They still point at the old place
+/// afterwards; rewrite_links fixes them.
+pub fn move_root(old_root: &Path, new_root: &Path, also_owned: Option<&Path>) -> Result<()> {
+    let (old_leaf, new_leaf) = (old_root.join("leaf"), new_root.join("leaf"));
+    if new_leaf.exists() {
+        return Err(anyhow!("{} already exists", new_leaf.display()));
+    }
+    fs::create_dir_all(new_root)?;
+    fs::rename(&old_leaf, &new_leaf).map_err(|e| {
+        anyhow!(
+            "Cannot move {} to {} ({}); move it yourself, then run leaf relocate again",
+            old_leaf.display(),
+            new_leaf.display(),
+            e
+        )
+    })?;
+
+    for (dir, depth) in LINK_DIRS {
+        let old_dir = old_root.join(dir);
+        let mut owned = links_in(&old_dir, depth);
+        if dir == "bin" {
+            owned.extend(
+                fs::read_dir(&old_dir)
+                    .into_iter()
+                    .flatten()
+                    .flatten()
+                    .map(|entry| entry.path())
+                    .filter(|path| shim_package(path).is_some()),
+            );
+        }
+        for path in owned {
+            let leaf_owned = shim_package(&path).is_some()
+                || link_target(&path).is_some_and(|target| {
+                    target.starts_with(&old_leaf)
+                        || also_owned.is_some_and(|dir| target.starts_with(dir))
+                });
+            if !leaf_owned {
+                continue;
+            }
+            let dest = new_root.join(path.strip_prefix(old_root)?);
+            if let Some(parent) = dest.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::rename(&path, &dest)?;
+        }
+    }
+    Ok(())
+}
+
+/// Update the extra file paths recorded in each package's manifest
+pub fn rewrite_manifests(packages_dir: &Path, old_root: &Path, new_root: &Path) -> Result<()> {
+    for entry in fs::read_dir(packages_dir)?.flatten() {
+        let package_dir = entry.path();
+        let Some(mut exposed) = manifest::exposed(&package_dir) else {
+            continue;
+        };
+        if exposed.is_empty() {
+            continue;
+        }
+        for file in &mut exposed {
+            if let Ok(rest) = Path::new(&file.path).strip_prefix(old_root) {
+                file.path = new_root.join(rest).to_string_lossy().to_string();
+            }
+        }
+        manifest::set_exposed(&package_dir, exposed)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rewrite_links() {
+        let root = std::env::temp_dir().join(format!("leaf-relocate-{}", std::process::id()));
+        fs::remove_dir_all(&root).ok();
+        let bin = root.join("bin");
+        fs::create_dir_all(&bin).unwrap();
+        symlink("/old/leaf/packages/go/bin/go", bin.join("go")).unwrap();
+        symlink("/usr/bin/env", bin.join("env")).unwrap();
+
+        let new_leaf = root.join("leaf");
+        assert_eq!(
+            rewrite_links(&root, Path::new("/old/leaf"), &new_leaf, true).unwrap(),
+            1
+        );
+        assert_eq!(
+            fs::read_link(bin.join("go")).unwrap(),
+            Path::new("../leaf/packages/go/bin/go")
+        );
+        assert_eq!(
+            fs::read_link(bin.join("env")).unwrap(),
+            Path::new("/usr/bin/env")
+        );
+        fs::remove_dir_all(&root).ok();
+    }
+}
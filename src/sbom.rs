@@ -0,0 +1,144 @@
+use crate::build_info;
+use crate::package::Package;
+use anyhow::{Result, anyhow};
+use chrono::Utc;
+use serde_json::{Value, json};
+
+/// SBOM document formats `leaf sbom` can write
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SbomFormat {
+    Spdx,
+    CycloneDx,
+}
+
+impl std::str::FromStr for SbomFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "spdx" => Ok(SbomFormat::Spdx),
+            "cyclonedx" => Ok(SbomFormat::CycloneDx),
+            other => Err(anyhow!("Unknown SBOM format '{}'", other)),
+        }
+    }
+}
+
+/// Build an SBOM for installed packages, keyed by their install name
+pub fn build(format: SbomFormat, packages: &[(&String, &Package)]) -> Value {
+    match format {
+        SbomFormat::Spdx => spdx(packages),
+        SbomFormat::CycloneDx => cyclonedx(packages),
+    }
+}
+
+fn spdx(packages: &[(&String, &Package)]) -> Value {
+    let created = Utc::now().format("%Y-%m-%dT%H:%M:%SZ").to_string();
+    let entries: Vec<Value> = packages
+        .iter()
+        .map(|(name, package)| {
+            let mut entry = json!({
+                "name": name,
+                "SPDXID": format!("SPDXRef-Package-{}", spdx_id(name)),
+                "versionInfo": package.version,
+                "downloadLocation": "NOASSERTION",
+                "filesAnalyzed": false,
+                "licenseConcluded": "NOASSERTION",
+                "licenseDeclared": "NOASSERTION",
+                "copyrightText": "NOASSERTION",
+                "description": package.description,
+            });
+            if let Some(provenance) = &package.provenance {
+                entry["downloadLocation"] = json!(provenance.url);
+                entry["comment"] = json!(format!("Resolved to {}", provenance.resolved_url));
+                if !provenance.sha256.is_empty() {
+                    entry["checksums"] =
+                        json!([{"algorithm": "SHA256", "checksumValue": provenance.sha256}]);
+                }
+            }
+            entry
+        })
+        .collect();
+
+    json!({
+        "spdxVersion": "SPDX-2.3",
+        "dataLicense": "CC0-1.0",
+        "SPDXID": "SPDXRef-DOCUMENT",
+        "name": "leaf-packages",
+        "documentNamespace": format!("https://github.com/ktauchathuranga/leaf/spdx/{}", created),
+        "creationInfo": {
+            "created": created,
+            "creators": [format!("Tool: leaf-{}", build_info::VERSION)],
+        },
+        "packages": entries,
+    })
+}
+
+fn cyclonedx(packages: &[(&String, &Package)]) -> Value {
+    let components: Vec<Value> = packages
+        .iter()
+        .map(|(name, package)| {
+            let mut component = json!({
+                "type": "application",
+                "bom-ref": name,
+                "name": name,
+                "version": package.version,
+                "description": package.description,
+            });
+            if let Some(provenance) = &package.provenance {
+                component["externalReferences"] =
+                    json!([{"type": "distribution", "url": provenance.url}]);
+                let mut properties =
+                    vec![json!({"name": "leaf:resolved_url", "value": provenance.resolved_url})];
+                if let Some(time) = provenance.downloaded_at {
+                    properties
+                        .push(json!({"name": "leaf:downloaded_at", "value": time.to_rfc3339()}));
+                }
+                if !provenance.leaf_version.is_empty() {
+                    properties.push(
+                        json!({"name": "leaf:leaf_version", "value": provenance.leaf_version}),
+                    );
+                }
+                component["properties"] = json!(properties);
+                if !provenance.sha256.is_empty() {
+                    component["hashes"] = json!([{"alg": "SHA-256", "content": provenance.sha256}]);
+                }
+            }
+            component
+        })
+        .collect();
+
+    json!({
+        "bomFormat": "CycloneDX",
+        "specVersion": "1.5",
+        "version": 1,
+        "metadata": {
+            "timestamp": Utc::now().to_rfc3339(),
+            "tools": [{"vendor": "leaf", "name": "leaf", "version": build_info::VERSION}],
+        },
+        "components": components,
+    })
+}
+
+/// SPDX identifiers only allow letters, digits, `.` and `-`
+fn spdx_id(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '.' {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spdx_id() {
+        assert_eq!(spdx_id("nodejs@20"), "nodejs-20");
+        assert_eq!(spdx_id("go1.22"), "go1.22");
+    }
+}
@@ -0,0 +1,334 @@
+use crate::build_info;
+use crate::checksum::{find_in_sums, verify_sha256};
+use crate::config::{Config, UpdateChannel};
+use crate::extract::extract_archive_sync;
+use crate::http;
+use crate::installer::Installer;
+use crate::trust;
+use crate::utils::{print_info, print_step, print_success, print_warning};
+use crate::version::compare_versions;
+use anyhow::{Context, Result, anyhow};
+use serde_json::Value;
+use std::cmp::Ordering;
+use std::env;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use tokio::fs;
+
+const RELEASES_URL: &str = "https://api.github.com/repos/ktauchathuranga/leaf/releases";
+const SUMS_ASSET: &str = "SHA256SUMS";
+/// Fingerprint of the key that signs SHA256SUMS, fixed when leaf is built
+/// (LEAF_RELEASE_KEY). Only that key's signature is accepted, whatever else is
+/// in the user's keyring.
+const RELEASE_KEY: Option<&str> = option_env!("LEAF_RELEASE_KEY");
+
+/// Name leaf answers to as a package of its own, e.g. in `leaf upgrade leaf`
+pub const SELF_NAME: &str = "leaf";
+
+/// Report the newest release on the configured channel without installing it
+pub async fn check(config: &Config, prerelease: bool) -> Result<()> {
+    let channel = effective_channel(config, prerelease);
+    print_info(&tr!("self_update.checking_channel", channel));
+
+    let releases = fetch_releases().await?;
+    let latest = latest_on_channel(releases, channel)?;
+    let latest_version = tag_name(&latest)?;
+    let current_version = build_info::VERSION;
+
+    if compare_versions(latest_version, current_version) == Ordering::Greater {
+        print_info(&tr!(
+            "self_update.available",
+            current_version,
+            latest_version,
+            channel
+        ));
+        print_info(&tr!("self_update.available_hint"));
+    } else {
+        print_info(&tr!(
+            "self_update.up_to_date",
+            current_version,
+            channel,
+            latest_version
+        ));
+    }
+    Ok(())
+}
+
+/// Newest release on the configured channel, when it is newer than this binary
+pub async fn newer_release(config: &Config) -> Result<Option<String>> {
+    let releases = fetch_releases().await?;
+    let latest = latest_on_channel(releases, effective_channel(config, false))?;
+    let latest_version = tag_name(&latest)?;
+    Ok(
+        (compare_versions(latest_version, build_info::VERSION) == Ordering::Greater)
+            .then(|| latest_version.to_string()),
+    )
+}
+
+/// Download, verify and swap in a different leaf release, keeping the running
+/// binary as a backup for `--rollback`
+pub async fn update(config: &Config, version: Option<&str>, prerelease: bool) -> Result<()> {
+    if version.is_some() && prerelease {
+        return Err(anyhow!("Cannot specify both --to and --prerelease"));
+    }
+
+    print_info(&tr!("self_update.checking"));
+
+    let releases = fetch_releases().await?;
+
+    let current_version = build_info::VERSION;
+    let target_release = if let Some(v) = version {
+        // Handle specific version, which may also be a downgrade
+        let requested_version = v.trim_start_matches('v');
+        releases
+            .into_iter()
+            .find(|r| {
+                r["tag_name"].as_str().is_some_and(|t| {
+                    t == v
+                        || t == requested_version
+                        || t.trim_start_matches('v') == requested_version
+                })
+            })
+            .ok_or_else(|| anyhow!("Version {} not found", v))?
+    } else {
+        latest_on_channel(releases, effective_channel(config, prerelease))?
+    };
+
+    let target_version = tag_name(&target_release)?;
+
+    if target_version.trim_start_matches('v') == current_version {
+        print_info(&tr!("self_update.already_running", target_version));
+        return Ok(());
+    }
+
+    if target_release["prerelease"].as_bool().unwrap_or(false) {
+        print_warning(&tr!("self_update.prerelease"));
+    }
+
+    print_info(&tr!("self_update.plan", current_version, target_version));
+
+    let platform = format!("{}-{}", env::consts::OS, env::consts::ARCH);
+    let asset_name = format!("leaf-{}.tar.gz", platform);
+    let asset_url = asset_download_url(&target_release, &asset_name).ok_or_else(|| {
+        anyhow!(
+            "Release {} has no build for {} (expected asset {})",
+            target_version,
+            platform,
+            asset_name
+        )
+    })?;
+
+    // Each release gets its own directory since asset names repeat across versions
+    let download_dir = config.cache_dir.join("self-update").join(target_version);
+    fs::create_dir_all(&download_dir).await?;
+
+    let installer = Installer::new(config);
+    print_step(&tr!("self_update.downloading"));
+    let archive_path = installer.download_file(asset_url, &download_dir).await?;
+
+    print_step(&tr!("self_update.verifying"));
+    let sums_url = asset_download_url(&target_release, SUMS_ASSET).ok_or_else(|| {
+        anyhow!(
+            "Release {} does not publish {}, so the download can't be verified",
+            target_version,
+            SUMS_ASSET
+        )
+    })?;
+    let sums_path = installer.download_file(sums_url, &download_dir).await?;
+    verify_signature(&target_release, &installer, &sums_path, &download_dir).await?;
+
+    let sums = fs::read_to_string(&sums_path).await?;
+    let expected = find_in_sums(&sums, &asset_name)
+        .ok_or_else(|| anyhow!("{} does not list {}", SUMS_ASSET, asset_name))?;
+    if let Err(e) = verify_sha256(&archive_path, &expected) {
+        fs::remove_file(&archive_path).await.ok();
+        return Err(e);
+    }
+    print_info(&tr!("self_update.checksum_ok", expected));
+
+    let extract_dir = download_dir.join("extracted");
+    if extract_dir.exists() {
+        fs::remove_dir_all(&extract_dir).await?;
+    }
+    tokio::task::spawn_blocking({
+        let archive_path = archive_path.clone();
+        let extract_dir = extract_dir.clone();
+        move || extract_archive_sync(&archive_path, &extract_dir)
+    })
+    .await??;
+
+    let new_binary = extract_dir.join("leaf");
+    if !new_binary.exists() {
+        return Err(anyhow!("Release archive does not contain a leaf binary"));
+    }
+
+    let current_exe = env::current_exe().context("Cannot determine the leaf executable path")?;
+    backup_current(config, &current_exe, current_version).await?;
+    replace_binary(&new_binary, &current_exe).await?;
+
+    fs::remove_dir_all(&download_dir).await.ok();
+
+    print_success(&tr!("self_update.done", target_version));
+    print_info(&tr!("self_update.rollback_hint"));
+    Ok(())
+}
+
+/// Restore the binary saved by the last update
+pub async fn rollback(config: &Config) -> Result<()> {
+    let (backup_path, version_path) = backup_paths(config);
+    if !backup_path.exists() {
+        return Err(anyhow!("No backup found; nothing to roll back to"));
+    }
+
+    let backup_version = fs::read_to_string(&version_path)
+        .await
+        .unwrap_or_else(|_| "unknown".to_string());
+    let current_exe = env::current_exe().context("Cannot determine the leaf executable path")?;
+
+    print_info(&tr!(
+        "self_update.rolling_back",
+        build_info::VERSION,
+        backup_version.trim()
+    ));
+    replace_binary(&backup_path, &current_exe).await?;
+    fs::remove_file(&backup_path).await?;
+    fs::remove_file(&version_path).await.ok();
+
+    print_success(&tr!("self_update.rollback_done"));
+    Ok(())
+}
+
+/// `--prerelease` switches to the prerelease channel for a single run
+fn effective_channel(config: &Config, prerelease: bool) -> UpdateChannel {
+    if prerelease {
+        UpdateChannel::Prerelease
+    } else {
+        config.channel
+    }
+}
+
+async fn fetch_releases() -> Result<Vec<Value>> {
+    let client = http::client();
+
+    let response = http::get(&client, RELEASES_URL)
+        .header("Accept", "application/vnd.github+json")
+        .send()
+        .await?;
+    let response = http::check_rate_limit(response)?;
+
+    if !response.status().is_success() {
+        return Err(anyhow!(
+            "Failed to fetch releases: HTTP {}",
+            response.status()
+        ));
+    }
+
+    Ok(response.json().await?)
+}
+
+/// Most recently published release on a channel
+fn latest_on_channel(releases: Vec<Value>, channel: UpdateChannel) -> Result<Value> {
+    let want_prerelease = channel == UpdateChannel::Prerelease;
+    releases
+        .into_iter()
+        .filter(|r| r["prerelease"].as_bool().unwrap_or(false) == want_prerelease)
+        .max_by(|a, b| {
+            a["published_at"]
+                .as_str()
+                .unwrap_or("")
+                .cmp(b["published_at"].as_str().unwrap_or(""))
+        })
+        .ok_or_else(|| {
+            if want_prerelease {
+                anyhow!("No prerelease versions found")
+            } else {
+                anyhow!("No stable releases found")
+            }
+        })
+}
+
+fn tag_name(release: &Value) -> Result<&str> {
+    release["tag_name"]
+        .as_str()
+        .ok_or_else(|| anyhow!("Invalid release data: missing tag_name"))
+}
+
+fn asset_download_url<'a>(release: &'a Value, name: &str) -> Option<&'a str> {
+    release["assets"]
+        .as_array()?
+        .iter()
+        .find(|asset| asset["name"].as_str() == Some(name))?["browser_download_url"]
+        .as_str()
+}
+
+/// Check the detached GPG signature of SHA256SUMS against the release key.
+/// Builds with a release key refuse releases that aren't signed by it.
+async fn verify_signature(
+    release: &Value,
+    installer: &Installer,
+    sums_path: &Path,
+    download_dir: &Path,
+) -> Result<()> {
+    let signature_name = format!("{}.asc", SUMS_ASSET);
+    let signature_url = asset_download_url(release, &signature_name);
+    let (signature_url, key) = match (signature_url, RELEASE_KEY) {
+        (Some(url), Some(key)) => (url, key),
+        (None, Some(_)) => {
+            return Err(anyhow!(
+                "Release {} is not signed ({} is missing)",
+                tag_name(release)?,
+                signature_name
+            ));
+        }
+        (Some(_), None) => {
+            print_warning(&tr!("self_update.no_release_key", SUMS_ASSET));
+            return Ok(());
+        }
+        (None, None) => return Ok(()),
+    };
+
+    let signature_path = installer.download_file(signature_url, download_dir).await?;
+    match trust::gpg_verify(&signature_path, sums_path).await {
+        Ok(Some(status)) if trust::signed_by(&status, key) => {
+            print_info(&tr!("self_update.signature_ok"));
+            Ok(())
+        }
+        Ok(Some(_)) => Err(anyhow!(
+            "{} is not signed by the leaf release key {}",
+            SUMS_ASSET,
+            key
+        )),
+        Ok(None) => Err(anyhow!("Signature verification of {} failed", SUMS_ASSET)),
+        Err(_) => Err(anyhow!("gpg is needed to verify {}", SUMS_ASSET)),
+    }
+}
+
+fn backup_paths(config: &Config) -> (PathBuf, PathBuf) {
+    let backup_dir = config.install_dir.join("backup");
+    (backup_dir.join("leaf"), backup_dir.join("leaf.version"))
+}
+
+async fn backup_current(config: &Config, current_exe: &Path, version: &str) -> Result<()> {
+    let (backup_path, version_path) = backup_paths(config);
+    if let Some(parent) = backup_path.parent() {
+        fs::create_dir_all(parent).await?;
+    }
+    fs::copy(current_exe, &backup_path).await?;
+    fs::write(&version_path, version).await?;
+    Ok(())
+}
+
+/// Swap in a new binary via rename so the executable is never half-written
+async fn replace_binary(new_binary: &Path, current_exe: &Path) -> Result<()> {
+    let staged = current_exe.with_file_name(".leaf.new");
+    fs::copy(new_binary, &staged).await?;
+
+    let mut perms = fs::metadata(&staged).await?.permissions();
+    perms.set_mode(0o755);
+    fs::set_permissions(&staged, perms).await?;
+
+    fs::rename(&staged, current_exe)
+        .await
+        .with_context(|| format!("Failed to replace {}", current_exe.display()))?;
+    Ok(())
+}
@@ -0,0 +1,295 @@
+use crate::package::{PlatformDetails, Service};
+use anyhow::{Result, anyhow};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use tokio::fs;
+
+/// Per-user directory for unit files: ~/.config/systemd/user, or
+/// ~/Library/LaunchAgents on macOS
+fn unit_dir() -> Result<PathBuf> {
+    let dir = if cfg!(target_os = "macos") {
+        dirs::home_dir().map(|home| home.join("Library").join("LaunchAgents"))
+    } else {
+        dirs::config_dir().map(|config| config.join("systemd").join("user"))
+    };
+    dir.ok_or_else(|| anyhow!("Cannot find the user service directory"))
+}
+
+/// systemd unit name, or launchd label on macOS. The version is part of it so
+/// that installed versions of a package don't share (and fight over) one unit.
+fn unit_name(service: &Service, version: &str) -> String {
+    let id = format!("{}-{}", unit_word(&service.name), unit_word(version));
+    if cfg!(target_os = "macos") {
+        format!("dev.leaf.{}", id)
+    } else {
+        format!("leaf-{}.service", id)
+    }
+}
+
+/// Keep only characters systemd allows in unit names (and that are safe in a
+/// file name)
+fn unit_word(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || "._:".contains(c) {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+fn unit_path(service: &Service, version: &str) -> Result<PathBuf> {
+    let file = if cfg!(target_os = "macos") {
+        format!("{}.plist", unit_name(service, version))
+    } else {
+        unit_name(service, version)
+    };
+    Ok(unit_dir()?.join(file))
+}
+
+/// Command line with "{package_dir}" expanded; the program is relative to the package
+fn command_line(service: &Service, package_dir: &Path) -> Vec<String> {
+    let package_dir_str = package_dir.to_string_lossy();
+    service
+        .command
+        .iter()
+        .enumerate()
+        .map(|(i, arg)| {
+            let arg = arg.replace("{package_dir}", &package_dir_str);
+            if i == 0 && Path::new(&arg).is_relative() {
+                package_dir.join(arg).to_string_lossy().to_string()
+            } else {
+                arg
+            }
+        })
+        .collect()
+}
+
+fn systemd_unit(service: &Service, command: &[String], env: &[(String, String)]) -> String {
+    let description = service
+        .description
+        .clone()
+        .unwrap_or_else(|| format!("{} (installed by leaf)", service.name));
+    let mut unit = format!(
+        "# Generated by leaf\n[Unit]\nDescription={}\n\n[Service]\nExecStart={}\n",
+        systemd_text(&description),
+        command
+            .iter()
+            .map(|arg| systemd_quote(&arg.replace('$', "$$")))
+            .collect::<Vec<_>>()
+            .join(" ")
+    );
+    for (key, value) in env {
+        unit.push_str(&format!(
+            "Environment={}\n",
+            systemd_quote(&format!("{}={}", key, value))
+        ));
+    }
+    unit.push_str("Restart=on-failure\n\n[Install]\nWantedBy=default.target\n");
+    unit
+}
+
+fn launchd_plist(
+    service: &Service,
+    version: &str,
+    command: &[String],
+    env: &[(String, String)],
+) -> String {
+    let mut plist = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <!DOCTYPE plist PUBLIC \"-//Apple//DTD PLIST 1.0//EN\" \"http://www.apple.com/DTDs/PropertyList-1.0.dtd\">\n\
+         <plist version=\"1.0\">\n<dict>\n  <key>Label</key>\n  <string>{}</string>\n  <key>ProgramArguments</key>\n  <array>\n",
+        xml_escape(&unit_name(service, version))
+    );
+    for arg in command {
+        plist.push_str(&format!("    <string>{}</string>\n", xml_escape(arg)));
+    }
+    plist.push_str("  </array>\n");
+    if !env.is_empty() {
+        plist.push_str("  <key>EnvironmentVariables</key>\n  <dict>\n");
+        for (key, value) in env {
+            plist.push_str(&format!(
+                "    <key>{}</key>\n    <string>{}</string>\n",
+                xml_escape(key),
+                xml_escape(value)
+            ));
+        }
+        plist.push_str("  </dict>\n");
+    }
+    plist.push_str(
+        "  <key>RunAtLoad</key>\n  <true/>\n  <key>KeepAlive</key>\n  <true/>\n</dict>\n</plist>\n",
+    );
+    plist
+}
+
+/// Write unit files for the services of a package's `version`. They stay
+/// disabled until `leaf service enable`.
+pub async fn write(
+    package_dir: &Path,
+    version: &str,
+    platform_details: &PlatformDetails,
+) -> Result<()> {
+    let env = platform_details.get_env(package_dir);
+    for service in platform_details.services.iter().flatten() {
+        let command = command_line(service, package_dir);
+        let content = if cfg!(target_os = "macos") {
+            launchd_plist(service, version, &command, &env)
+        } else {
+            systemd_unit(service, &command, &env)
+        };
+        let path = unit_path(service, version)?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        fs::write(path, content).await?;
+    }
+    reload();
+    Ok(())
+}
+
+/// Stop and disable the services of a package's `version` and delete their
+/// unit files
+pub async fn remove(version: &str, platform_details: &PlatformDetails) -> Result<()> {
+    for service in platform_details.services.iter().flatten() {
+        let path = unit_path(service, version)?;
+        if !path.exists() {
+            continue;
+        }
+        run(service, version, "disable", true).ok();
+        fs::remove_file(path).await?;
+    }
+    reload();
+    Ok(())
+}
+
+/// Run `action` (enable, disable or status) for one service of a package's
+/// `version` through systemctl or launchctl, returning whether it succeeded
+pub fn control(service: &Service, version: &str, action: &str) -> Result<bool> {
+    run(service, version, action, false)
+}
+
+/// Whether the user has enabled a service of a package's `version`
+pub fn is_enabled(service: &Service, version: &str) -> bool {
+    run(service, version, "is-enabled", true).unwrap_or(false)
+}
+
+fn run(service: &Service, version: &str, action: &str, quiet: bool) -> Result<bool> {
+    let name = unit_name(service, version);
+    let mut command = if cfg!(target_os = "macos") {
+        let mut command = Command::new("launchctl");
+        match action {
+            "enable" => command
+                .arg("load")
+                .arg("-w")
+                .arg(unit_path(service, version)?),
+            "disable" => command
+                .arg("unload")
+                .arg("-w")
+                .arg(unit_path(service, version)?),
+            _ => command.arg("list").arg(name),
+        };
+        command
+    } else {
+        let mut command = Command::new("systemctl");
+        command.arg("--user");
+        match action {
+            "enable" | "disable" => command.arg(action).arg("--now"),
+            "is-enabled" => command.arg("is-enabled").arg("--quiet"),
+            _ => command.arg("status").arg("--no-pager"),
+        };
+        command.arg(name);
+        command
+    };
+    if quiet {
+        command.stdout(Stdio::null()).stderr(Stdio::null());
+    }
+    let status = command
+        .status()
+        .map_err(|e| anyhow!("Cannot run {:?}: {}", command.get_program(), e))?;
+    Ok(status.success())
+}
+
+/// Make systemd notice new or removed unit files; launchd reads them on load
+fn reload() {
+    if !cfg!(target_os = "macos") {
+        Command::new("systemctl")
+            .args(["--user", "daemon-reload"])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .ok();
+    }
+}
+
+/// Quote a word for ExecStart= or Environment=, escaping specifiers and control
+/// characters so a newline can't start another directive. Only ExecStart=
+/// expands variables, so its words have `$` doubled beforehand.
+fn systemd_quote(value: &str) -> String {
+    let escaped: String = value
+        .chars()
+        .map(|c| match c {
+            '\\' => "\\\\".to_string(),
+            '"' => "\\\"".to_string(),
+            '%' => "%%".to_string(),
+            '\n' => "\\n".to_string(),
+            c if c.is_control() => format!("\\x{:02x}", c as u32),
+            c => c.to_string(),
+        })
+        .collect();
+    format!("\"{}\"", escaped)
+}
+
+/// A line of free text for a unit file: one line, with specifiers escaped and no
+/// trailing backslash that would continue it onto the next
+fn systemd_text(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| if c.is_control() { ' ' } else { c })
+        .collect::<String>()
+        .replace('%', "%%")
+        .trim_end_matches(['\\', ' '])
+        .to_string()
+}
+
+fn xml_escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_systemd_unit() {
+        let service = Service {
+            name: "syncthing".to_string(),
+            command: vec![
+                "syncthing".to_string(),
+                "--home={package_dir}/100% \"data\"".to_string(),
+                "--gui-address=\n[Service]\tx".to_string(),
+                "$HOME".to_string(),
+            ],
+            description: Some("Sync 100%\n[Service]\nExecStartPre=/bin/evil \\".to_string()),
+        };
+        let command = command_line(&service, Path::new("/pkgs/syncthing"));
+        let env = vec![
+            ("GOMAXPROCS".to_string(), "$2".to_string()),
+            ("TOKEN".to_string(), "x\nExecStartPre=/bin/evil".to_string()),
+        ];
+        let unit = systemd_unit(&service, &command, &env);
+        assert!(unit.contains(
+            "ExecStart=\"/pkgs/syncthing/syncthing\" \"--home=/pkgs/syncthing/100%% \\\"data\\\"\" \"--gui-address=\\n[Service]\\x09x\" \"$$HOME\"\n"
+        ));
+        assert!(unit.contains("Environment=\"GOMAXPROCS=$2\"\n"));
+        assert!(unit.contains("Environment=\"TOKEN=x\\nExecStartPre=/bin/evil\"\n"));
+        assert!(unit.contains("Description=Sync 100%% [Service] ExecStartPre=/bin/evil\n"));
+        assert!(!unit.contains("\nExecStartPre"));
+        assert!(unit_name(&service, "1.27+git/x").contains("syncthing-1.27_git_x"));
+    }
+}
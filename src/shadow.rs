@@ -0,0 +1,126 @@
+use crate::utils::write_atomic;
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+/// Executables in bin_dir known to share their name with one elsewhere on PATH
+const RECORD_FILE: &str = "shadowed.json";
+
+/// Another executable on PATH with the same name as one of leaf's
+#[derive(Debug, Clone, PartialEq)]
+pub struct Shadow {
+    pub other: PathBuf,
+    /// Whether bin_dir comes before it on PATH, so leaf's runs
+    pub leaf_first: bool,
+}
+
+fn is_executable(path: &Path) -> bool {
+    fs::metadata(path)
+        .is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+}
+
+fn same_dir(a: &Path, b: &Path) -> bool {
+    a == b
+        || matches!(
+            (fs::canonicalize(a), fs::canonicalize(b)),
+            (Ok(a), Ok(b)) if a == b
+        )
+}
+
+/// The first executable called `name` on `path` (a PATH value) outside bin_dir
+pub fn find(name: &str, bin_dir: &Path, path: &OsStr) -> Option<Shadow> {
+    let mut leaf_first = false;
+    for dir in std::env::split_paths(path) {
+        if same_dir(&dir, bin_dir) {
+            leaf_first = true;
+            continue;
+        }
+        let candidate = dir.join(name);
+        if is_executable(&candidate) {
+            return Some(Shadow {
+                other: candidate,
+                leaf_first,
+            });
+        }
+    }
+    None
+}
+
+/// Names recorded as shadowing (or shadowed by) another executable, with where
+/// that one was
+pub fn recorded(install_dir: &Path) -> BTreeMap<String, PathBuf> {
+    fs::read_to_string(install_dir.join(RECORD_FILE))
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .unwrap_or_default()
+}
+
+fn save(install_dir: &Path, shadows: &BTreeMap<String, PathBuf>) -> Result<()> {
+    let path = install_dir.join(RECORD_FILE);
+    if shadows.is_empty() {
+        if path.exists() {
+            fs::remove_file(path)?;
+        }
+        return Ok(());
+    }
+    write_atomic(&path, &serde_json::to_string_pretty(shadows)?)?;
+    Ok(())
+}
+
+pub fn record(install_dir: &Path, name: &str, other: &Path) -> Result<()> {
+    let mut shadows = recorded(install_dir);
+    shadows.insert(name.to_string(), other.to_path_buf());
+    save(install_dir, &shadows)
+}
+
+pub fn forget(install_dir: &Path, name: &str) -> Result<()> {
+    let mut shadows = recorded(install_dir);
+    if shadows.remove(name).is_some() {
+        save(install_dir, &shadows)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find() {
+        let root = std::env::temp_dir().join(format!("leaf-shadow-test-{}", std::process::id()));
+        fs::remove_dir_all(&root).ok();
+        let (bin, system, empty) = (root.join("bin"), root.join("usr"), root.join("opt"));
+        for dir in [&bin, &system, &empty] {
+            fs::create_dir_all(dir).unwrap();
+        }
+        for (path, mode) in [
+            (system.join("node"), 0o755),
+            (system.join("notes"), 0o644),
+            (bin.join("node"), 0o755),
+        ] {
+            fs::write(&path, "").unwrap();
+            fs::set_permissions(&path, fs::Permissions::from_mode(mode)).unwrap();
+        }
+
+        let path = std::env::join_paths([&empty, &bin, &system]).unwrap();
+        assert_eq!(
+            find("node", &bin, &path),
+            Some(Shadow {
+                other: system.join("node"),
+                leaf_first: true
+            })
+        );
+        assert_eq!(find("notes", &bin, &path), None);
+        let path = std::env::join_paths([&system, &bin]).unwrap();
+        assert!(find("node", &bin, &path).is_some_and(|shadow| !shadow.leaf_first));
+
+        record(&root, "node", &system.join("node")).unwrap();
+        assert_eq!(recorded(&root).get("node"), Some(&system.join("node")));
+        forget(&root, "node").unwrap();
+        assert!(!root.join(RECORD_FILE).exists());
+        fs::remove_dir_all(&root).ok();
+    }
+}
@@ -0,0 +1,311 @@
+use crate::config::Config;
+use crate::package::Package;
+use crate::platform;
+use crate::toolchain::Toolchain;
+use crate::usage;
+use crate::utils::{plain_file_name, shell_quote};
+use crate::version::split_spec;
+use anyhow::{Context, Result, anyhow};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::convert::Infallible;
+use std::env;
+use std::io::Write;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use tokio::fs;
+
+const SHIM_MARKER: &str = "# leaf shim:";
+
+/// Write a shim that asks leaf for the active version of `package` on every invocation
+pub async fn write_shim(config: &Config, path: &Path, package: &str, exe: &str) -> Result<()> {
+    // The marker line names both, separated by whitespace
+    if [package, exe]
+        .iter()
+        .any(|name| name.is_empty() || name.chars().any(|c| c.is_whitespace() || c.is_control()))
+    {
+        return Err(anyhow!("Cannot write a shim for '{}' '{}'", package, exe));
+    }
+    let leaf_exe = env::current_exe().context("Cannot determine the leaf executable path")?;
+    // Portable shims find leaf next to themselves so the root can move; shims
+    // in an alternate root must keep resolving against that root
+    let (leaf_exe, prefix_arg) = match &config.prefix {
+        _ if config.portable => ("\"$(dirname \"$0\")/leaf\"".to_string(), String::new()),
+        Some(prefix) => (
+            shell_quote(&leaf_exe.to_string_lossy()),
+            format!(" --prefix {}", shell_quote(&prefix.to_string_lossy())),
+        ),
+        None => (shell_quote(&leaf_exe.to_string_lossy()), String::new()),
+    };
+    let script = format!(
+        "#!/bin/sh\n{} {} {}\nexec {}{} __shim {} {} -- \"$@\"\n",
+        SHIM_MARKER,
+        package,
+        exe,
+        leaf_exe,
+        prefix_arg,
+        shell_quote(package),
+        shell_quote(exe)
+    );
+
+    if fs::symlink_metadata(path).await.is_ok() {
+        fs::remove_file(path).await?;
+    }
+    fs::write(path, script).await?;
+    let mut perms = fs::metadata(path).await?.permissions();
+    perms.set_mode(0o755);
+    fs::set_permissions(path, perms).await?;
+    Ok(())
+}
+
+/// The package a shim in bin_dir belongs to, if `path` is a leaf shim
+pub fn shim_package(path: &Path) -> Option<String> {
+    shim_target(path).map(|(package, _)| package)
+}
+
+/// The package and executable a leaf shim at `path` runs
+pub fn shim_target(path: &Path) -> Option<(String, String)> {
+    if path.is_symlink() {
+        return None;
+    }
+    let content = std::fs::read_to_string(path).ok()?;
+    let marker_line = content.lines().nth(1)?.strip_prefix(SHIM_MARKER)?;
+    let mut parts = marker_line.split_whitespace();
+    Some((parts.next()?.to_string(), parts.next()?.to_string()))
+}
+
+/// Environment variable that overrides the active version for one shell,
+/// e.g. LEAF_NODEJS_VERSION=18
+pub fn version_env_var(package: &str) -> String {
+    format!(
+        "LEAF_{}_VERSION",
+        package.to_uppercase().replace(['-', '.', '@'], "_")
+    )
+}
+
+/// Resolve the active version of `package` and replace this process with `exe` from it.
+/// Lookup order: environment variable, .leaf-versions, then the global default.
+pub async fn run(config: &Config, package: &str, exe: &str, args: &[String]) -> Result<Infallible> {
+    let package = resolve_package(config, package);
+    let package = package.as_str();
+    let (version, source) = if let Ok(version) = env::var(version_env_var(package)) {
+        (Some(version), version_env_var(package))
+    } else if let Some(toolchain) = Toolchain::current()?
+        && let Some(version) = toolchain_version(config, &toolchain, package)
+    {
+        (
+            Some(version.to_string()),
+            toolchain.path.display().to_string(),
+        )
+    } else if let Some(version) = config.default_versions.get(package) {
+        (Some(version.clone()), "the global default".to_string())
+    } else {
+        (None, String::new())
+    };
+
+    // The version picks a directory under packages_dir, so it mustn't lead out of it
+    if let Some(version) = &version
+        && !plain_file_name(version)
+    {
+        return Err(anyhow!(
+            "'{}' from {} is not a valid version",
+            version,
+            source
+        ));
+    }
+    let key = match &version {
+        Some(version) => format!("{}@{}", package, version),
+        None => package.to_string(),
+    };
+    let package_dir = config.packages_dir.join(&key);
+    let metadata_file = package_dir.join("leaf-package.json");
+    if !metadata_file.exists() {
+        return Err(match version {
+            Some(_) => anyhow!(
+                "{} requested by {} is not installed. Run: leaf install {}",
+                key,
+                source,
+                key
+            ),
+            None => anyhow!("{} is not installed. Run: leaf install {}", key, key),
+        });
+    }
+
+    let content = fs::read_to_string(&metadata_file).await?;
+    let installed: Package = serde_json::from_str(&content)?;
+    let exe_path = find_executable(&package_dir, &installed, exe)?;
+    usage::record_run(&config.install_dir, package, exe);
+    if config.log_shim_runs {
+        log_run(config, package, exe, &installed.version, &source, args);
+    }
+    let err = std::process::Command::new(&exe_path).args(args).exec();
+    Err(anyhow!("Failed to run {}: {}", exe_path.display(), err))
+}
+
+/// Names in packages_dir, e.g. go and go@1.21
+fn installed_keys(config: &Config) -> impl Iterator<Item = String> {
+    std::fs::read_dir(&config.packages_dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+}
+
+/// The package `name` stands for: itself when installed under that name,
+/// otherwise the one it is an alias of, as install would resolve it
+fn resolve_package(config: &Config, name: &str) -> String {
+    if installed_keys(config).any(|key| split_spec(&key).0 == name) {
+        return name.to_string();
+    }
+    if let Some(target) = config.aliases.get(name) {
+        return target.clone();
+    }
+    installed_keys(config)
+        .filter_map(|key| {
+            let metadata = config.packages_dir.join(&key).join("leaf-package.json");
+            let content = std::fs::read_to_string(metadata).ok()?;
+            Some((key, serde_json::from_str::<Package>(&content).ok()?))
+        })
+        .filter(|(_, package)| package.aliases.iter().flatten().any(|alias| alias == name))
+        .map(|(key, _)| split_spec(&key).0.to_string())
+        .min()
+        .unwrap_or_else(|| name.to_string())
+}
+
+/// The version .leaf-versions asks for `package`, whose entry may also use one
+/// of its aliases
+fn toolchain_version<'a>(
+    config: &Config,
+    toolchain: &'a Toolchain,
+    package: &str,
+) -> Option<&'a str> {
+    if let Some(version) = toolchain.versions.get(package) {
+        return Some(version);
+    }
+    toolchain
+        .versions
+        .iter()
+        .filter(|(entry, _)| resolve_package(config, entry) == package)
+        .min()
+        .map(|(_, version)| version.as_str())
+}
+
+/// One line of the shim run log kept when config.json sets log_shim_runs
+#[derive(Serialize)]
+struct RunLogEntry<'a> {
+    time: DateTime<Utc>,
+    package: &'a str,
+    executable: &'a str,
+    version: &'a str,
+    /// What chose the version: the environment variable, the .leaf-versions
+    /// file or the global default; absent when only one version is installed
+    #[serde(skip_serializing_if = "str::is_empty")]
+    selected_by: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cwd: Option<PathBuf>,
+    /// SHA-256 of the arguments, each followed by a NUL byte, so that runs can
+    /// be matched up without the log holding what was passed
+    args_sha256: String,
+}
+
+pub fn run_log_path(install_dir: &Path) -> PathBuf {
+    install_dir.join("logs").join("runs.jsonl")
+}
+
+/// Append a run to the run log; like the journal this is best-effort, and a
+/// failure never keeps the program from starting
+fn log_run(
+    config: &Config,
+    package: &str,
+    exe: &str,
+    version: &str,
+    source: &str,
+    args: &[String],
+) {
+    let mut hasher = Sha256::new();
+    for arg in args {
+        hasher.update(arg.as_bytes());
+        hasher.update([0]);
+    }
+    let entry = RunLogEntry {
+        time: Utc::now(),
+        package,
+        executable: exe,
+        version,
+        selected_by: source,
+        cwd: env::current_dir().ok(),
+        args_sha256: format!("{:x}", hasher.finalize()),
+    };
+    let Ok(line) = serde_json::to_string(&entry) else {
+        return;
+    };
+    if let Ok(mut file) = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(run_log_path(&config.install_dir))
+    {
+        writeln!(file, "{}", line).ok();
+    }
+}
+
+fn find_executable(package_dir: &Path, package: &Package, exe: &str) -> Result<PathBuf> {
+    package
+        .platform_or_emulated(&platform::current())
+        .into_iter()
+        .map(|(details, _)| details)
+        .flat_map(|details| details.get_executables())
+        .find(|executable_info| executable_info.exposed_name() == exe)
+        .map(|executable_info| package_dir.join(executable_info.path))
+        .ok_or_else(|| {
+            anyhow!(
+                "{} does not provide an executable named '{}'",
+                package_dir.display(),
+                exe
+            )
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_shim_quoting_and_aliases() {
+        let root = std::env::temp_dir().join(format!(
+            "leaf-shim-test-{}/it's $(here)",
+            std::process::id()
+        ));
+        std::fs::remove_dir_all(&root).ok();
+        let mut config = Config::load_or_create(Some(&root), None).await.unwrap();
+        let shim = root.join("nvim");
+        write_shim(&config, &shim, "nvim", "nvim").await.unwrap();
+        let script = std::fs::read_to_string(&shim).unwrap();
+        assert!(
+            script.contains(&shell_quote(&root.to_string_lossy())),
+            "{}",
+            script
+        );
+        let syntax = std::process::Command::new("sh")
+            .arg("-n")
+            .arg(&shim)
+            .status();
+        assert!(syntax.unwrap().success());
+        assert!(write_shim(&config, &shim, "nvim", "a b").await.is_err());
+
+        let package_dir = config.packages_dir.join("nvim@0.10");
+        std::fs::create_dir_all(&package_dir).unwrap();
+        std::fs::write(
+            package_dir.join("leaf-package.json"),
+            r#"{"description": "editor", "version": "0.10", "aliases": ["neovim"], "platforms": {}}"#,
+        )
+        .unwrap();
+        assert_eq!(resolve_package(&config, "nvim"), "nvim");
+        assert_eq!(resolve_package(&config, "neovim"), "nvim");
+        config.aliases.insert("vim".to_string(), "nvim".to_string());
+        assert_eq!(resolve_package(&config, "vim"), "nvim");
+        assert_eq!(resolve_package(&config, "emacs"), "emacs");
+        std::fs::remove_dir_all(root.parent().unwrap()).ok();
+    }
+}
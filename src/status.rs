@@ -0,0 +1,45 @@
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+/// Total size of the files under `path`, not following symlinks
+pub fn dir_size(path: &Path) -> u64 {
+    let Ok(metadata) = fs::symlink_metadata(path) else {
+        return 0;
+    };
+    if !metadata.is_dir() {
+        return metadata.len();
+    }
+    fs::read_dir(path)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| dir_size(&entry.path()))
+        .sum()
+}
+
+/// Roughly how long ago something happened, e.g. "3 days ago"
+pub fn age(elapsed: Duration) -> String {
+    let minutes = elapsed.as_secs() / 60;
+    let (count, one, many) = match minutes {
+        0 => return tr!("status.age_now"),
+        1..60 => (minutes, "status.age_minute", "status.age_minutes"),
+        60..1440 => (minutes / 60, "status.age_hour", "status.age_hours"),
+        _ => (minutes / 1440, "status.age_day", "status.age_days"),
+    };
+    tr!(if count == 1 { one } else { many }, count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_age() {
+        assert_eq!(age(Duration::from_secs(30)), "just now");
+        assert_eq!(age(Duration::from_secs(60)), "1 minute ago");
+        assert_eq!(age(Duration::from_secs(3 * 3600 + 5)), "3 hours ago");
+        assert_eq!(age(Duration::from_secs(86400)), "1 day ago");
+        assert_eq!(age(Duration::from_secs(10 * 86400)), "10 days ago");
+    }
+}
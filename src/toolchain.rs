@@ -0,0 +1,69 @@
+use crate::utils::plain_file_name;
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// Per-project version file, similar to asdf's .tool-versions
+pub const VERSIONS_FILE: &str = ".leaf-versions";
+
+/// Package versions requested by the nearest .leaf-versions file
+pub struct Toolchain {
+    pub path: PathBuf,
+    pub versions: HashMap<String, String>,
+}
+
+impl Toolchain {
+    /// Walk up from `start` looking for a .leaf-versions file
+    pub fn discover(start: &Path) -> Result<Option<Self>> {
+        for dir in start.ancestors() {
+            let path = dir.join(VERSIONS_FILE);
+            if path.is_file() {
+                let content = std::fs::read_to_string(&path)
+                    .with_context(|| format!("Failed to read {}", path.display()))?;
+                return Ok(Some(Self {
+                    versions: parse_versions(&content),
+                    path,
+                }));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Discover from the current working directory
+    pub fn current() -> Result<Option<Self>> {
+        Self::discover(&std::env::current_dir()?)
+    }
+}
+
+/// Parse `<package> <version>` lines, ignoring blank lines and `#` comments.
+/// A version names a package directory, so one that isn't a plain file name
+/// (e.g. `0.10/../../repo`) is dropped rather than let out of packages_dir.
+fn parse_versions(content: &str) -> HashMap<String, String> {
+    content
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or_default().trim())
+        .filter_map(|line| {
+            let mut parts = line.split_whitespace();
+            let name = parts.next()?;
+            let version = parts.next().filter(|version| plain_file_name(version))?;
+            Some((name.to_string(), version.to_string()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_versions() {
+        let versions = parse_versions("# tools\nnodejs 20\n\ngo 1.22.5 # pinned\nbroken\n");
+        assert_eq!(versions.len(), 2);
+        assert_eq!(versions["nodejs"], "20");
+        assert_eq!(versions["go"], "1.22.5");
+
+        let versions = parse_versions("nvim 0.10/../../../../home/u/repo/x\ngo ..\nrg 14\n");
+        assert_eq!(versions.len(), 1);
+        assert_eq!(versions["rg"], "14");
+    }
+}
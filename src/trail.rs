@@ -0,0 +1,278 @@
+use crate::cache::{self, CacheEntry};
+use crate::links;
+use crate::manifest;
+use crate::package::{Package, Provenance};
+use crate::version::compare_versions;
+use serde::Serialize;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// What an operation may change, taken before it runs so that `--report` can
+/// describe the difference afterwards
+pub struct Snapshot {
+    installed: BTreeMap<String, serde_json::Value>,
+    /// Entries of bin_dir and what each leads to, if leaf can tell
+    bin: BTreeMap<String, Option<PathBuf>>,
+    downloads: BTreeMap<String, CacheEntry>,
+}
+
+impl Snapshot {
+    pub fn take(installed: &HashMap<String, Package>, bin_dir: &Path, cache_dir: &Path) -> Self {
+        Self {
+            installed: installed
+                .iter()
+                .filter_map(|(key, package)| {
+                    Some((key.clone(), serde_json::to_value(package).ok()?))
+                })
+                .collect(),
+            bin: fs::read_dir(bin_dir)
+                .into_iter()
+                .flatten()
+                .flatten()
+                .map(|entry| {
+                    (
+                        entry.file_name().to_string_lossy().to_string(),
+                        links::target(&entry.path()),
+                    )
+                })
+                .collect(),
+            downloads: cache::entries(cache_dir),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct PackageChange {
+    pub name: String,
+    /// installed, upgraded, downgraded, reinstalled, removed or changed (e.g. by `leaf expose`)
+    pub action: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub previous_version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub provenance: Option<Provenance>,
+    /// Files the package now consists of, from its install manifest
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub files: Vec<ReportedFile>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReportedFile {
+    pub path: PathBuf,
+    /// Absent for links placed outside the package directory
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+}
+
+/// An artifact fetched into the download cache
+#[derive(Debug, Serialize)]
+pub struct ReportedDownload {
+    pub url: String,
+    pub resolved_url: String,
+    pub sha256: String,
+    pub file: PathBuf,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BinChange {
+    pub path: PathBuf,
+    /// created, removed or retargeted
+    pub action: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target: Option<PathBuf>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Changes {
+    pub packages: Vec<PackageChange>,
+    pub downloads: Vec<ReportedDownload>,
+    pub bin: Vec<BinChange>,
+}
+
+fn version(package: &serde_json::Value) -> Option<String> {
+    package["version"].as_str().map(str::to_string)
+}
+
+/// Files recorded for the package installed in `package_dir`
+fn package_files(package_dir: &Path) -> Vec<ReportedFile> {
+    let Some(manifest) = manifest::load(package_dir) else {
+        return Vec::new();
+    };
+    let files = manifest
+        .files
+        .into_iter()
+        .map(|(path, entry)| ReportedFile {
+            path: package_dir.join(path),
+            sha256: Some(entry.sha256),
+        });
+    let exposed = manifest.exposed.into_iter().map(|file| ReportedFile {
+        path: PathBuf::from(file.path),
+        sha256: file.sha256,
+    });
+    files.chain(exposed).collect()
+}
+
+/// What changed between `before` and `after`
+pub fn changes(
+    before: &Snapshot,
+    after: &Snapshot,
+    packages_dir: &Path,
+    bin_dir: &Path,
+    cache_dir: &Path,
+) -> Changes {
+    let mut packages = Vec::new();
+    for (key, package) in &after.installed {
+        let previous = before.installed.get(key);
+        let action = match previous {
+            None => "installed",
+            Some(previous) if previous == package => continue,
+            Some(previous) => match (version(previous), version(package)) {
+                (Some(old), Some(new)) if old != new => match compare_versions(&new, &old) {
+                    Ordering::Less => "downgraded",
+                    _ => "upgraded",
+                },
+                _ if previous["provenance"] != package["provenance"] => "reinstalled",
+                _ => "changed",
+            },
+        };
+        let files = if action == "changed" {
+            Vec::new()
+        } else {
+            package_files(&packages_dir.join(key))
+        };
+        packages.push(PackageChange {
+            name: key.clone(),
+            action,
+            previous_version: previous.and_then(version),
+            version: version(package),
+            provenance: serde_json::from_value(package["provenance"].clone()).ok(),
+            files,
+        });
+    }
+    for (key, package) in &before.installed {
+        if !after.installed.contains_key(key) {
+            packages.push(PackageChange {
+                name: key.clone(),
+                action: "removed",
+                previous_version: version(package),
+                version: None,
+                provenance: None,
+                files: Vec::new(),
+            });
+        }
+    }
+
+    let downloads = after
+        .downloads
+        .iter()
+        .filter(|(url, entry)| {
+            before
+                .downloads
+                .get(*url)
+                .is_none_or(|previous| previous.sha256 != entry.sha256)
+        })
+        .map(|(url, entry)| ReportedDownload {
+            url: url.clone(),
+            resolved_url: entry.resolved_url.clone(),
+            sha256: entry.sha256.clone(),
+            file: cache_dir.join(&entry.file),
+        })
+        .collect();
+
+    let mut bin = Vec::new();
+    for (name, target) in &after.bin {
+        let action = match before.bin.get(name) {
+            None => "created",
+            Some(previous) if previous != target => "retargeted",
+            Some(_) => continue,
+        };
+        bin.push(BinChange {
+            path: bin_dir.join(name),
+            action,
+            target: target.clone(),
+        });
+    }
+    for name in before.bin.keys() {
+        if !after.bin.contains_key(name) {
+            bin.push(BinChange {
+                path: bin_dir.join(name),
+                action: "removed",
+                target: None,
+            });
+        }
+    }
+
+    Changes {
+        packages,
+        downloads,
+        bin,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn package(version: &str, sha256: &str) -> Package {
+        serde_json::from_value(serde_json::json!({
+            "description": "test",
+            "version": version,
+            "platforms": {},
+            "versions": null,
+            "provenance": {"url": "https://example.com/tool.tar.gz", "resolved_url": "https://example.com/tool.tar.gz", "sha256": sha256}
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_changes() {
+        let root = std::env::temp_dir().join(format!("leaf-trail-test-{}", std::process::id()));
+        fs::remove_dir_all(&root).ok();
+        let (packages_dir, bin_dir, cache_dir) =
+            (root.join("packages"), root.join("bin"), root.join("cache"));
+        for dir in [&packages_dir, &bin_dir, &cache_dir] {
+            fs::create_dir_all(dir).unwrap();
+        }
+        let tool_dir = packages_dir.join("tool");
+        fs::create_dir_all(&tool_dir).unwrap();
+        fs::write(tool_dir.join("tool"), "#!/bin/sh\n").unwrap();
+        manifest::record(&tool_dir, &BTreeMap::new()).unwrap();
+        std::os::unix::fs::symlink("/bin/true", bin_dir.join("old")).unwrap();
+
+        let installed = HashMap::from([
+            ("tool".to_string(), package("1.0", "aa")),
+            ("gone".to_string(), package("2.0", "bb")),
+            ("same".to_string(), package("3.0", "cc")),
+        ]);
+        let before = Snapshot::take(&installed, &bin_dir, &cache_dir);
+
+        fs::remove_file(bin_dir.join("old")).unwrap();
+        std::os::unix::fs::symlink(tool_dir.join("tool"), bin_dir.join("tool")).unwrap();
+        let installed = HashMap::from([
+            ("tool".to_string(), package("1.1", "dd")),
+            ("same".to_string(), package("3.0", "cc")),
+        ]);
+        let after = Snapshot::take(&installed, &bin_dir, &cache_dir);
+
+        let changes = changes(&before, &after, &packages_dir, &bin_dir, &cache_dir);
+        let actions: Vec<(&str, &str)> = changes
+            .packages
+            .iter()
+            .map(|change| (change.name.as_str(), change.action))
+            .collect();
+        assert_eq!(actions, [("tool", "upgraded"), ("gone", "removed")]);
+        assert_eq!(changes.packages[0].previous_version.as_deref(), Some("1.0"));
+        assert_eq!(
+            changes.packages[0].provenance.as_ref().unwrap().sha256,
+            "dd"
+        );
+        assert_eq!(changes.packages[0].files.len(), 1);
+        let bin: Vec<&str> = changes.bin.iter().map(|change| change.action).collect();
+        assert_eq!(bin, ["created", "removed"]);
+        assert!(changes.downloads.is_empty());
+        fs::remove_dir_all(&root).ok();
+    }
+}
@@ -0,0 +1,190 @@
+use crate::utils::write_atomic;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use reqwest::{Client, Response};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use tokio::fs;
+
+/// Fingerprint a repo had when leaf first fetched it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Pin {
+    /// "gpg:<key fingerprint>" for signed indexes, else "tls:<sha256 of the certificate>"
+    pub fingerprint: String,
+    pub first_seen: DateTime<Utc>,
+}
+
+/// How a repo's fingerprint compares with the pinned one
+#[derive(Debug, PartialEq)]
+pub enum Trust {
+    /// Not seen before; now pinned
+    New,
+    Known,
+    /// Differs from the pin, which is kept
+    Changed {
+        pinned: String,
+    },
+}
+
+fn pins_path(install_dir: &Path) -> PathBuf {
+    install_dir.join("repo-fingerprints.json")
+}
+
+/// Pinned fingerprints by repo URL
+pub fn load(install_dir: &Path) -> HashMap<String, Pin> {
+    std::fs::read_to_string(pins_path(install_dir))
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .unwrap_or_default()
+}
+
+pub fn save(install_dir: &Path, pins: &HashMap<String, Pin>) -> Result<()> {
+    write_atomic(
+        &pins_path(install_dir),
+        &serde_json::to_string_pretty(pins)?,
+    )?;
+    Ok(())
+}
+
+/// Compare `fingerprint` with the pin for `repo`, pinning it on first use or
+/// when `repin` accepts a change
+pub fn check(pins: &mut HashMap<String, Pin>, repo: &str, fingerprint: &str, repin: bool) -> Trust {
+    match pins.get(repo) {
+        Some(pin) if pin.fingerprint == fingerprint => return Trust::Known,
+        Some(pin) if !repin => {
+            return Trust::Changed {
+                pinned: pin.fingerprint.clone(),
+            };
+        }
+        _ => {}
+    }
+    let new = !pins.contains_key(repo);
+    pins.insert(
+        repo.to_string(),
+        Pin {
+            fingerprint: fingerprint.to_string(),
+            first_seen: Utc::now(),
+        },
+    );
+    if new { Trust::New } else { Trust::Known }
+}
+
+/// Whether a fingerprint is only the hash of a server's TLS certificate,
+/// which changes every time the certificate is renewed
+pub fn is_certificate(fingerprint: &str) -> bool {
+    fingerprint.starts_with("tls:")
+}
+
+/// Hash of the certificate the server presented, for https responses from a
+/// client built with tls_info
+pub fn tls_fingerprint(response: &Response) -> Option<String> {
+    let certificate = response
+        .extensions()
+        .get::<reqwest::tls::TlsInfo>()?
+        .peer_certificate()?;
+    Some(format!("tls:{:x}", Sha256::digest(certificate)))
+}
+
+/// Fingerprint of the key that signed `content`, when the repo publishes a
+/// detached signature at `<repo>.asc` that gpg accepts
+pub async fn signing_key(
+    client: &Client,
+    repo: &str,
+    content: &[u8],
+    work_dir: &Path,
+) -> Option<String> {
+    let response = client.get(format!("{}.asc", repo)).send().await.ok()?;
+    let signature = response.error_for_status().ok()?.bytes().await.ok()?;
+    fs::create_dir_all(work_dir).await.ok()?;
+    let index_path = work_dir.join("repo-index.json");
+    let signature_path = work_dir.join("repo-index.json.asc");
+    fs::write(&index_path, content).await.ok()?;
+    fs::write(&signature_path, &signature).await.ok()?;
+    let status = gpg_verify(&signature_path, &index_path).await;
+    fs::remove_file(&index_path).await.ok();
+    fs::remove_file(&signature_path).await.ok();
+    valid_signer(&status.ok()??).map(|key| format!("gpg:{}", key))
+}
+
+/// Check a detached signature with gpg, returning its status lines when the
+/// signature is good. Any key in the keyring passes, so callers that expect a
+/// particular signer check it with `signed_by`. An error means gpg couldn't run.
+pub async fn gpg_verify(signature: &Path, data: &Path) -> std::io::Result<Option<String>> {
+    let output = tokio::process::Command::new("gpg")
+        .args(["--batch", "--status-fd", "1", "--verify"])
+        .arg(signature)
+        .arg(data)
+        .output()
+        .await?;
+    Ok(output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).to_string()))
+}
+
+/// Key fingerprint from gpg's `[GNUPG:] VALIDSIG <fingerprint> ...` status line
+fn valid_signer(status: &str) -> Option<String> {
+    status.lines().find_map(|line| {
+        let mut fields = line.strip_prefix("[GNUPG:] VALIDSIG ")?.split_whitespace();
+        fields.next().map(str::to_string)
+    })
+}
+
+/// Whether gpg's status lines show a valid signature by `fingerprint`, as the
+/// signing (sub)key or its primary key; spaces and case don't matter
+pub fn signed_by(status: &str, fingerprint: &str) -> bool {
+    let wanted: String = fingerprint
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect::<String>()
+        .to_uppercase();
+    !wanted.is_empty()
+        && status.lines().any(|line| {
+            line.strip_prefix("[GNUPG:] VALIDSIG ")
+                .is_some_and(|fields| {
+                    let fields: Vec<&str> = fields.split_whitespace().collect();
+                    // The primary key's fingerprint is the tenth field, when present
+                    [fields.first(), fields.get(9)]
+                        .into_iter()
+                        .flatten()
+                        .any(|key| key.to_uppercase() == wanted)
+                })
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check() {
+        let mut pins = HashMap::new();
+        let repo = "https://example.com/index.json";
+        assert_eq!(check(&mut pins, repo, "tls:aa", false), Trust::New);
+        assert_eq!(check(&mut pins, repo, "tls:aa", false), Trust::Known);
+        assert_eq!(
+            check(&mut pins, repo, "tls:bb", false),
+            Trust::Changed {
+                pinned: "tls:aa".to_string()
+            }
+        );
+        assert_eq!(pins[repo].fingerprint, "tls:aa");
+        assert_eq!(check(&mut pins, repo, "tls:bb", true), Trust::Known);
+        assert_eq!(pins[repo].fingerprint, "tls:bb");
+        assert!(is_certificate("tls:bb"));
+        assert!(!is_certificate("gpg:ABCD"));
+    }
+
+    #[test]
+    fn test_valid_signer() {
+        let status = "[GNUPG:] NEWSIG\n[GNUPG:] GOODSIG 1234 Repo <repo@example.com>\n\
+                      [GNUPG:] VALIDSIG ABCDEF0123456789 2026-10-16 1760000000 0 4 0 22 8 00 ABCDEF0123456789\n";
+        assert_eq!(valid_signer(status).as_deref(), Some("ABCDEF0123456789"));
+        assert_eq!(valid_signer("[GNUPG:] BADSIG 1234"), None);
+        assert!(signed_by(status, "abcd ef01 2345 6789"));
+        assert!(!signed_by(status, "0000000000000000"));
+        assert!(!signed_by(status, ""));
+    }
+}
@@ -0,0 +1,166 @@
+use crate::lock::{self, FileLock};
+use crate::utils::write_atomic;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Where `leaf stats` finds its numbers. They never leave this machine:
+/// installs are counted in installs.json, and runs through a shim in
+/// runs/<package>/<executable>, each holding a count and the latest time.
+fn usage_dir(install_dir: &Path) -> PathBuf {
+    install_dir.join("usage")
+}
+
+/// Held while a count is read and written back, so concurrent installs and
+/// runs don't lose each other's
+fn lock(install_dir: &Path) -> std::io::Result<FileLock> {
+    lock::acquire(&usage_dir(install_dir).join(".lock"))
+}
+
+fn installs_path(install_dir: &Path) -> PathBuf {
+    usage_dir(install_dir).join("installs.json")
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Installs {
+    pub count: u64,
+    pub last: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Runs {
+    pub count: u64,
+    pub last: DateTime<Utc>,
+}
+
+/// How often each package was installed, and when last
+pub fn installs(install_dir: &Path) -> BTreeMap<String, Installs> {
+    fs::read_to_string(installs_path(install_dir))
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .unwrap_or_default()
+}
+
+/// Count an install of `package`; best-effort, like the journal
+pub fn record_install(install_dir: &Path, package: &str) {
+    let Ok(_lock) = lock(install_dir) else {
+        return;
+    };
+    let mut installs = installs(install_dir);
+    let entry = installs.entry(package.to_string()).or_insert(Installs {
+        count: 0,
+        last: Utc::now(),
+    });
+    entry.count += 1;
+    entry.last = Utc::now();
+    if let Ok(content) = serde_json::to_string_pretty(&installs) {
+        write_atomic(&installs_path(install_dir), &content).ok();
+    }
+}
+
+/// Count a run of `exe` from `package`; called by shims, so it must stay cheap
+/// and never get in the way of the program starting
+pub fn record_run(install_dir: &Path, package: &str, exe: &str) {
+    let dir = usage_dir(install_dir).join("runs").join(package);
+    if fs::create_dir_all(&dir).is_err() {
+        return;
+    }
+    let Ok(_lock) = lock(install_dir) else {
+        return;
+    };
+    let path = dir.join(exe);
+    let count = read_runs(&path).map_or(0, |runs| runs.count);
+    let runs = Runs {
+        count: count + 1,
+        last: Utc::now(),
+    };
+    if let Ok(content) = serde_json::to_string(&runs) {
+        write_atomic(&path, &content).ok();
+    }
+}
+
+/// Runs recorded in `path`. Older versions appended a byte per run instead, so
+/// there the size is the count and the modification time the latest run.
+fn read_runs(path: &Path) -> Option<Runs> {
+    let content = fs::read(path).ok()?;
+    if let Ok(runs) = serde_json::from_slice(&content) {
+        return Some(runs);
+    }
+    Some(Runs {
+        count: content.len() as u64,
+        last: fs::metadata(path).ok()?.modified().ok()?.into(),
+    })
+}
+
+/// Runs of each of `package`'s executables that went through a shim
+pub fn runs(install_dir: &Path, package: &str) -> BTreeMap<String, Runs> {
+    let dir = usage_dir(install_dir).join("runs").join(package);
+    fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|entry| !entry.file_name().to_string_lossy().starts_with('.'))
+        .filter_map(|entry| {
+            let runs = read_runs(&entry.path())?;
+            Some((entry.file_name().to_string_lossy().to_string(), runs))
+        })
+        .collect()
+}
+
+/// All runs of a package's executables together
+pub fn total(runs: &BTreeMap<String, Runs>) -> Option<Runs> {
+    runs.values().copied().reduce(|a, b| Runs {
+        count: a.count + b.count,
+        last: a.last.max(b.last),
+    })
+}
+
+/// Whether a package has gone unused for `days`: not run since then, or never
+/// run and installed before then
+pub fn unused_for(
+    last_run: Option<DateTime<Utc>>,
+    installed: DateTime<Utc>,
+    now: DateTime<Utc>,
+    days: i64,
+) -> bool {
+    last_run.unwrap_or(installed) < now - chrono::Duration::days(days)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_usage() {
+        let root = std::env::temp_dir().join(format!("leaf-usage-test-{}", std::process::id()));
+        fs::remove_dir_all(&root).ok();
+        record_install(&root, "tool");
+        record_install(&root, "tool");
+        assert_eq!(installs(&root)["tool"].count, 2);
+
+        assert_eq!(total(&runs(&root, "tool")), None);
+        for exe in ["tool", "tool", "helper"] {
+            record_run(&root, "tool", exe);
+        }
+        let runs = runs(&root, "tool");
+        assert_eq!(runs["tool"].count, 2);
+        assert_eq!(total(&runs).unwrap().count, 3);
+
+        // Counted the old way, a byte per run
+        let legacy = root.join("usage").join("runs").join("old");
+        fs::create_dir_all(&legacy).unwrap();
+        fs::write(legacy.join("old"), "\n\n\n").unwrap();
+        record_run(&root, "old", "old");
+        assert_eq!(super::runs(&root, "old")["old"].count, 4);
+
+        let now = Utc::now();
+        let long_ago = now - chrono::Duration::days(200);
+        assert!(unused_for(None, long_ago, now, 90));
+        assert!(!unused_for(None, now, now, 90));
+        assert!(unused_for(Some(long_ago), now, now, 90));
+        assert!(!unused_for(Some(now), long_ago, now, 90));
+        fs::remove_dir_all(&root).ok();
+    }
+}
@@ -1,24 +1,72 @@
+use crate::interrupt;
+use crate::progress;
 use colored::Colorize;
+use std::io::Write;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+
+static LOG_FILE: OnceLock<Mutex<std::fs::File>> = OnceLock::new();
+
+/// Log files larger than this are rotated to `<name>.old` at startup
+const MAX_LOG_SIZE: u64 = 1024 * 1024;
+
+/// Mirror every message printed through these helpers into a log file
+pub fn init_log(path: &Path) {
+    if std::fs::metadata(path).is_ok_and(|m| m.len() > MAX_LOG_SIZE) {
+        std::fs::rename(path, path.with_extension("log.old")).ok();
+    }
+    if let Ok(file) = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+    {
+        LOG_FILE.set(Mutex::new(file)).ok();
+    }
+}
+
+pub fn log_path(install_dir: &Path) -> PathBuf {
+    install_dir.join("logs").join("leaf.log")
+}
+
+fn log_line(level: &str, msg: &str) {
+    if let Some(file) = LOG_FILE.get()
+        && let Ok(mut file) = file.lock()
+    {
+        writeln!(
+            file,
+            "{} [{}] {}",
+            chrono::Utc::now().format("%Y-%m-%dT%H:%M:%SZ"),
+            level,
+            msg
+        )
+        .ok();
+    }
+}
 
 pub fn print_success(msg: &str) {
-    println!("{} {}", "[SUCCESS]".green().bold(), msg);
+    log_line("SUCCESS", msg);
+    progress::suspend(|| println!("{} {}", tr!("label.success").green().bold(), msg));
 }
 
 pub fn print_error(msg: &str) {
-    println!("{} {}", "[ERROR]".red().bold(), msg);
+    log_line("ERROR", msg);
+    progress::suspend(|| println!("{} {}", tr!("label.error").red().bold(), msg));
 }
 
 pub fn print_info(msg: &str) {
-    println!("{} {}", "[INFO]".blue().bold(), msg);
+    log_line("INFO", msg);
+    progress::suspend(|| println!("{} {}", tr!("label.info").blue().bold(), msg));
 }
 
 pub fn print_warning(msg: &str) {
-    println!("{} {}", "[WARNING]".yellow().bold(), msg);
+    log_line("WARNING", msg);
+    progress::suspend(|| println!("{} {}", tr!("label.warning").yellow().bold(), msg));
 }
 
 // Additional utility functions for more specific use cases
 pub fn print_step(msg: &str) {
-    println!("{} {}", "[STEP]".cyan().bold(), msg);
+    log_line("STEP", msg);
+    progress::suspend(|| println!("{} {}", tr!("label.step").cyan().bold(), msg));
 }
 
 // pub fn print_debug(msg: &str) {
@@ -28,3 +76,155 @@ pub fn print_step(msg: &str) {
 // pub fn print_progress(msg: &str) {
 //     println!("{} {}", "[PROGRESS]".white().bold(), msg);
 // }
+
+/// Replace `path` with `contents` in one step, via a hidden `.<name>.part`
+/// file beside it, so an interruption never leaves it half-written
+pub fn write_atomic(path: &Path, contents: &str) -> std::io::Result<()> {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let partial = path.with_file_name(format!(".{}.part", name));
+    interrupt::started(&partial);
+    std::fs::write(&partial, contents)?;
+    std::fs::rename(&partial, path)?;
+    interrupt::finished(&partial);
+    Ok(())
+}
+
+/// Where a symlink points, with relative targets resolved lexically against
+/// the link's directory
+pub fn link_target(link: &Path) -> Option<PathBuf> {
+    let target = std::fs::read_link(link).ok()?;
+    Some(resolve_from(link, &target))
+}
+
+/// `target` as seen from the file `from`: relative targets are resolved
+/// lexically against `from`'s directory
+pub fn resolve_from(from: &Path, target: &Path) -> PathBuf {
+    if target.is_absolute() {
+        return target.to_path_buf();
+    }
+    let mut resolved = from.parent().map(Path::to_path_buf).unwrap_or_default();
+    for component in target.components() {
+        match component {
+            Component::ParentDir => {
+                resolved.pop();
+            }
+            Component::CurDir => {}
+            other => resolved.push(other),
+        }
+    }
+    resolved
+}
+
+/// Path from directory `from` to `to`, both absolute, e.g. bin -> ../leaf/packages/go
+pub fn relative_path(from: &Path, to: &Path) -> PathBuf {
+    let from: Vec<Component> = from.components().collect();
+    let to: Vec<Component> = to.components().collect();
+    let common = from.iter().zip(&to).take_while(|(a, b)| a == b).count();
+    let mut path = PathBuf::new();
+    for _ in common..from.len() {
+        path.push("..");
+    }
+    for component in &to[common..] {
+        path.push(component);
+    }
+    path
+}
+
+/// Single-quote a value for sh
+pub fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+/// Whether `file` is a single file name, with no directories, `..` or root
+pub fn plain_file_name(file: &str) -> bool {
+    let mut components = Path::new(file).components();
+    matches!(components.next(), Some(Component::Normal(_))) && components.next().is_none()
+}
+
+/// Whether `name` can be a shell variable: `[A-Za-z_][A-Za-z0-9_]*`
+pub fn is_env_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Optimal string alignment distance: edits needed to turn `a` into `b`, counting
+/// a swap of two adjacent characters as one edit so "isntall" is close to "install"
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut d = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in d.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cell) in d[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            d[i][j] = (d[i - 1][j] + 1)
+                .min(d[i][j - 1] + 1)
+                .min(d[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+            }
+        }
+    }
+    d[a.len()][b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_edit_distance() {
+        assert_eq!(edit_distance("install", "install"), 0);
+        assert_eq!(edit_distance("isntall", "install"), 1);
+        assert_eq!(edit_distance("remvoe", "remove"), 1);
+        assert_eq!(edit_distance("lst", "list"), 1);
+        assert!(edit_distance("isntall", "list") > 2);
+    }
+
+    #[test]
+    fn test_shell_quote() {
+        assert_eq!(shell_quote("go"), "'go'");
+        assert_eq!(shell_quote("it's $(x)"), "'it'\\''s $(x)'");
+    }
+
+    #[test]
+    fn test_plain_file_name() {
+        assert!(plain_file_name("a.tar.gz"));
+        assert!(!plain_file_name("/etc/passwd"));
+        assert!(!plain_file_name("sub/a.tar.gz"));
+        assert!(!plain_file_name(".."));
+        assert!(!plain_file_name(""));
+    }
+
+    #[test]
+    fn test_is_env_name() {
+        assert!(is_env_name("GOROOT"));
+        assert!(is_env_name("_x1"));
+        assert!(!is_env_name("1X"));
+        assert!(!is_env_name(""));
+        assert!(!is_env_name("X; rm -rf ~"));
+    }
+
+    #[test]
+    fn test_relative_path() {
+        assert_eq!(
+            relative_path(
+                Path::new("/home/me/.local/bin"),
+                Path::new("/home/me/.local/leaf/packages/go/bin/go")
+            ),
+            Path::new("../leaf/packages/go/bin/go")
+        );
+        assert_eq!(
+            relative_path(Path::new("/mnt/usb/bin"), Path::new("/home/me/go")),
+            Path::new("../../../home/me/go")
+        );
+    }
+}
@@ -0,0 +1,88 @@
+use std::cmp::Ordering;
+
+/// Split a `name@version` install spec into the package name and requested version
+pub fn split_spec(spec: &str) -> (&str, Option<&str>) {
+    match spec.split_once('@') {
+        Some((name, version)) if !name.is_empty() && !version.is_empty() => (name, Some(version)),
+        _ => (spec, None),
+    }
+}
+
+/// Whether `version` satisfies a user-supplied version prefix, so "18" matches "18.20.4"
+/// but not "180.1"
+pub fn matches_spec(version: &str, spec: &str) -> bool {
+    let version = version.trim_start_matches('v');
+    let spec = spec.trim_start_matches('v');
+    version == spec
+        || version
+            .strip_prefix(spec)
+            .is_some_and(|rest| rest.starts_with('.') || rest.starts_with('-'))
+}
+
+/// Compare dotted version strings numerically where possible, so "1.10" sorts after
+/// "1.9". A pre-release suffix ("1.2.0-beta") sorts before the release it precedes.
+pub fn compare_versions(a: &str, b: &str) -> Ordering {
+    let (a_core, a_pre) = split_prerelease(a.trim_start_matches('v'));
+    let (b_core, b_pre) = split_prerelease(b.trim_start_matches('v'));
+
+    let mut a_parts = a_core.split('.');
+    let mut b_parts = b_core.split('.');
+    loop {
+        let ordering = match (a_parts.next(), b_parts.next()) {
+            (None, None) => break,
+            (Some(x), None) => compare_segment(x, "0"),
+            (None, Some(y)) => compare_segment("0", y),
+            (Some(x), Some(y)) => compare_segment(x, y),
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+
+    match (a_pre, b_pre) {
+        (None, None) => Ordering::Equal,
+        (None, Some(_)) => Ordering::Greater,
+        (Some(_), None) => Ordering::Less,
+        (Some(x), Some(y)) => x.cmp(y),
+    }
+}
+
+fn split_prerelease(version: &str) -> (&str, Option<&str>) {
+    match version.split_once('-') {
+        Some((core, pre)) => (core, Some(pre)),
+        None => (version, None),
+    }
+}
+
+fn compare_segment(a: &str, b: &str) -> Ordering {
+    match (a.parse::<u64>(), b.parse::<u64>()) {
+        (Ok(x), Ok(y)) => x.cmp(&y),
+        _ => a.cmp(b),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_spec() {
+        assert_eq!(split_spec("node@18"), ("node", Some("18")));
+        assert_eq!(split_spec("node"), ("node", None));
+        assert_eq!(split_spec("node@"), ("node@", None));
+    }
+
+    #[test]
+    fn test_matches_spec() {
+        assert!(matches_spec("18.20.4", "18"));
+        assert!(matches_spec("v18.20.4", "18.20"));
+        assert!(!matches_spec("180.1.0", "18"));
+    }
+
+    #[test]
+    fn test_compare_versions() {
+        assert_eq!(compare_versions("1.10.0", "1.9.9"), Ordering::Greater);
+        assert_eq!(compare_versions("v1.2", "1.2.0"), Ordering::Equal);
+        assert_eq!(compare_versions("1.2.0-beta", "1.2.0"), Ordering::Less);
+    }
+}
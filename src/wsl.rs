@@ -0,0 +1,123 @@
+use crate::config::Config;
+use anyhow::{Result, anyhow};
+use std::env;
+use std::path::Path;
+use tokio::fs;
+
+/// First line after `@echo off` in the .bat files leaf writes, so removal only
+/// touches its own
+const BAT_MARKER: &str = "rem leaf wsl shim";
+
+/// Whether leaf runs inside the Windows Subsystem for Linux
+pub fn is_wsl() -> bool {
+    env::var_os("WSL_DISTRO_NAME").is_some()
+        || std::fs::read_to_string("/proc/sys/kernel/osrelease")
+            .is_ok_and(|release| release.to_lowercase().contains("microsoft"))
+}
+
+/// A path written the Windows way (`C:\tools`, `C:/tools`, `\\server\share`),
+/// which Linux treats as a relative file name
+pub fn is_windows_style(path: &str) -> bool {
+    let bytes = path.as_bytes();
+    path.contains('\\') || (bytes.len() >= 2 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':')
+}
+
+/// Config paths that look like Windows paths, as (setting, value)
+pub fn windows_style_paths(config: &Config) -> Vec<(&'static str, String)> {
+    [
+        ("install_dir", Some(config.install_dir.as_path())),
+        ("bin_dir", Some(config.bin_dir.as_path())),
+        ("packages_dir", Some(config.packages_dir.as_path())),
+        ("cache_dir", Some(config.cache_dir.as_path())),
+        ("shared_store", config.shared_store.as_deref()),
+        ("windows_bin_dir", config.windows_bin_dir.as_deref()),
+    ]
+    .into_iter()
+    .filter_map(|(setting, path)| {
+        let path = path?.to_string_lossy().to_string();
+        is_windows_style(&path).then_some((setting, path))
+    })
+    .collect()
+}
+
+/// A .bat file running `target` in `distro`. cmd expands `%` even inside quotes,
+/// so it is doubled; a `"` would end the quoting and can't be escaped, so it is
+/// refused.
+fn bat_script(distro: Option<&str>, target: &Path) -> Result<String> {
+    let quote = |value: &str| {
+        if value.contains('"') || value.chars().any(char::is_control) {
+            return Err(anyhow!("Cannot write a .bat file for '{}'", value));
+        }
+        Ok(format!("\"{}\"", value.replace('%', "%%")))
+    };
+    let distro = match distro {
+        Some(name) => format!(" -d {}", quote(name)?),
+        None => String::new(),
+    };
+    Ok(format!(
+        "@echo off\r\n{}\r\nwsl.exe{} -- {} %*\r\n",
+        BAT_MARKER,
+        distro,
+        quote(&target.to_string_lossy())?
+    ))
+}
+
+/// Whether `dir` has a `<name>.bat` that leaf didn't write
+pub async fn is_foreign_bat(dir: &Path, name: &str) -> bool {
+    let path = dir.join(format!("{}.bat", name));
+    match fs::read_to_string(&path).await {
+        Ok(content) => !content.contains(BAT_MARKER),
+        Err(_) => fs::symlink_metadata(&path).await.is_ok(),
+    }
+}
+
+/// Write `<name>.bat` into `dir` (a Windows directory, seen from Linux, e.g.
+/// /mnt/c/Users/me/bin) running `target` inside this distribution
+pub async fn write_bat(dir: &Path, name: &str, target: &Path) -> Result<()> {
+    fs::create_dir_all(dir).await?;
+    let distro = env::var("WSL_DISTRO_NAME").ok();
+    fs::write(
+        dir.join(format!("{}.bat", name)),
+        bat_script(distro.as_deref(), target)?,
+    )
+    .await?;
+    Ok(())
+}
+
+/// Remove `<name>.bat` from `dir` if leaf wrote it
+pub async fn remove_bat(dir: &Path, name: &str) -> Result<()> {
+    let path = dir.join(format!("{}.bat", name));
+    if let Ok(content) = fs::read_to_string(&path).await
+        && content.contains(BAT_MARKER)
+    {
+        fs::remove_file(&path).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_windows_style() {
+        assert!(is_windows_style("C:\\Users\\me\\.local"));
+        assert!(is_windows_style("d:/tools"));
+        assert!(is_windows_style("\\\\server\\share"));
+        assert!(!is_windows_style("/mnt/c/Users/me/bin"));
+        assert!(!is_windows_style("/home/me/.local/bin"));
+    }
+
+    #[test]
+    fn test_bat_script() {
+        assert_eq!(
+            bat_script(Some("Ubuntu"), Path::new("/home/me/.local/bin/rg")).unwrap(),
+            "@echo off\r\nrem leaf wsl shim\r\nwsl.exe -d \"Ubuntu\" -- \"/home/me/.local/bin/rg\" %*\r\n"
+        );
+        assert_eq!(
+            bat_script(None, Path::new("/home/me/100%/rg")).unwrap(),
+            "@echo off\r\nrem leaf wsl shim\r\nwsl.exe -- \"/home/me/100%%/rg\" %*\r\n"
+        );
+        assert!(bat_script(None, Path::new("/home/me/a\" & calc & \"")).is_err());
+    }
+}
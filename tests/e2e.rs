@@ -0,0 +1,606 @@
+//! Installs the fixture packages in tests/fixtures with the leaf binary into a
+//! temporary prefix, served from a local HTTP server, and compares what lands
+//! in bin/ and the package directory with tests/golden. Run with
+//! `UPDATE_GOLDEN=1` to rewrite the golden files after an intended change.
+
+#[path = "support/serve.rs"]
+mod serve;
+
+use sha2::{Digest, Sha256};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+fn fixtures_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("fixtures")
+}
+
+/// Serve tests/fixtures over HTTP from a background thread, returning the base URL
+fn serve_fixtures() -> String {
+    serve::serve(|path| {
+        path.strip_prefix('/')
+            .filter(|name| !name.contains('/') && !name.contains(".."))
+            .and_then(|name| std::fs::read(fixtures_dir().join(name)).ok())
+    })
+}
+
+/// A temporary leaf prefix, deleted when dropped
+struct Prefix(PathBuf);
+
+impl Prefix {
+    fn new(index: &serde_json::Value) -> Self {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let root = std::env::temp_dir().join(format!(
+            "leaf-e2e-{}-{}",
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::remove_dir_all(&root).ok();
+        std::fs::create_dir_all(root.join("leaf")).unwrap();
+        let prefix = Self(root);
+        prefix.set_index(index);
+        prefix
+    }
+
+    /// Replace the index, as `leaf update` would
+    fn set_index(&self, index: &serde_json::Value) {
+        std::fs::write(self.0.join("leaf").join("packages.json"), index.to_string()).unwrap();
+    }
+
+    fn run(&self, args: &[&str]) -> Output {
+        self.run_with_env(args, &[])
+    }
+
+    fn run_with_env(&self, args: &[&str], env: &[(&str, &str)]) -> Output {
+        Command::new(env!("CARGO_BIN_EXE_leaf"))
+            .arg("--prefix")
+            .arg(&self.0)
+            .args(["--allow-root", "--yes"])
+            .args(args)
+            .env("HOME", &self.0)
+            .env("NO_COLOR", "1")
+            .env_remove("VISUAL")
+            .envs(env.iter().copied())
+            .output()
+            .unwrap()
+    }
+
+    fn leaf(&self, args: &[&str]) {
+        let output = self.run(args);
+        assert!(
+            output.status.success(),
+            "leaf {} failed:\n{}{}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stdout),
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    fn package_dir(&self, name: &str) -> PathBuf {
+        self.0.join("leaf").join("packages").join(name)
+    }
+
+    /// Anything in the packages directory, and half-downloaded files in the cache
+    fn debris(&self) -> Vec<String> {
+        let leaf_dir = self.0.join("leaf");
+        let mut debris: Vec<String> = std::fs::read_dir(leaf_dir.join("packages"))
+            .into_iter()
+            .flatten()
+            .flatten()
+            .map(|entry| format!("packages/{}", entry.file_name().to_string_lossy()))
+            .collect();
+        debris.extend(
+            std::fs::read_dir(leaf_dir.join("cache"))
+                .into_iter()
+                .flatten()
+                .flatten()
+                .map(|entry| entry.file_name().to_string_lossy().to_string())
+                .filter(|name| name.ends_with(".part"))
+                .map(|name| format!("cache/{}", name)),
+        );
+        debris.extend(
+            std::fs::read_dir(leaf_dir.join("modules").join("leaf"))
+                .into_iter()
+                .flatten()
+                .flatten()
+                .map(|entry| format!("modules/leaf/{}", entry.file_name().to_string_lossy())),
+        );
+        debris
+    }
+
+    /// One line per entry under bin/ and the package directory: kind, mode and
+    /// path, plus the target of symlinks, with the prefix written as $ROOT
+    fn listing(&self, name: &str) -> String {
+        let mut lines = Vec::new();
+        for dir in [self.0.join("bin"), self.package_dir(name)] {
+            walk(&dir, &mut lines);
+        }
+        lines.sort();
+        let mut listing = lines
+            .iter()
+            .map(|(_, line)| {
+                let line = line.replace(&self.0.to_string_lossy().to_string(), "$ROOT");
+                // macOS hands out /var temp paths that resolve to /private/var
+                match self.0.canonicalize() {
+                    Ok(canonical) => {
+                        line.replace(&canonical.to_string_lossy().to_string(), "$ROOT")
+                    }
+                    Err(_) => line,
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        listing.push('\n');
+        listing
+    }
+}
+
+impl Drop for Prefix {
+    fn drop(&mut self) {
+        std::fs::remove_dir_all(&self.0).ok();
+    }
+}
+
+/// Entries under `dir` as (path, listing line), so they sort by path
+fn walk(dir: &Path, lines: &mut Vec<(String, String)>) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let metadata = std::fs::symlink_metadata(&path).unwrap();
+        let shown = path.to_string_lossy().to_string();
+        if metadata.file_type().is_symlink() {
+            let target = std::fs::read_link(&path).unwrap();
+            let line = format!("link {} -> {}", shown, target.display());
+            lines.push((shown, line));
+        } else if metadata.is_dir() {
+            let line = format!("dir  {:o} {}", metadata.permissions().mode() & 0o777, shown);
+            lines.push((shown, line));
+            walk(&path, lines);
+        } else {
+            let line = format!("file {:o} {}", metadata.permissions().mode() & 0o777, shown);
+            lines.push((shown, line));
+        }
+    }
+}
+
+/// Definition of fixture package `name`, fetching `file` as the given type
+fn definition(base_url: &str, file: &str, extra: serde_json::Value) -> serde_json::Value {
+    let platform = format!("{}-{}", std::env::consts::OS, std::env::consts::ARCH);
+    let mut details = serde_json::json!({"url": format!("{}/{}", base_url, file)});
+    details
+        .as_object_mut()
+        .unwrap()
+        .extend(extra.as_object().unwrap().clone());
+    let mut platforms = serde_json::Map::new();
+    platforms.insert(platform, details);
+    serde_json::json!({
+        "description": "fixture package",
+        "version": "1.0",
+        "platforms": platforms
+    })
+}
+
+/// Package name of the tar.gz fixture in `hello_tgz_prefix`'s index
+const HELLO_TGZ: &str = "hello-tgz";
+
+/// A prefix whose index has the tar.gz fixture, served from `base_url`, as
+/// `hello-tgz`, with `extra` added to its platform entry. The index is returned
+/// too, for tests that change it.
+fn hello_tgz_prefix(base_url: &str, extra: serde_json::Value) -> (Prefix, serde_json::Value) {
+    let mut details = serde_json::json!({
+        "type": "archive",
+        "executables": [{"path": "hello-1.0/bin/hello", "name": HELLO_TGZ}]
+    });
+    details
+        .as_object_mut()
+        .unwrap()
+        .extend(extra.as_object().unwrap().clone());
+    let index = serde_json::json!({ HELLO_TGZ: definition(base_url, "hello.tar.gz", details) });
+    (Prefix::new(&index), index)
+}
+
+/// Install `name`, compare the result with tests/golden/<name>.txt, check the
+/// recorded metadata, then remove it and check nothing is left
+fn install_and_remove(name: &str, file: &str, extra: serde_json::Value) {
+    let base_url = serve_fixtures();
+    let index = serde_json::json!({ name: definition(&base_url, file, extra) });
+    let prefix = Prefix::new(&index);
+
+    prefix.leaf(&["install", name]);
+
+    let listing = prefix.listing(name);
+    let golden_path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("golden")
+        .join(format!("{}.txt", name));
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        std::fs::write(&golden_path, &listing).unwrap();
+    }
+    let golden = std::fs::read_to_string(&golden_path).unwrap_or_default();
+    assert_eq!(listing, golden, "{} differs from the golden listing", name);
+
+    let output = Command::new(prefix.0.join("bin").join(name))
+        .output()
+        .unwrap();
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "hello from leaf\n");
+
+    let metadata: serde_json::Value = serde_json::from_str(
+        &std::fs::read_to_string(prefix.package_dir(name).join("leaf-package.json")).unwrap(),
+    )
+    .unwrap();
+    assert_eq!(metadata["version"], "1.0");
+    let provenance = &metadata["provenance"];
+    assert_eq!(provenance["url"], format!("{}/{}", base_url, file));
+    let fixture = std::fs::read(fixtures_dir().join(file)).unwrap();
+    assert_eq!(
+        provenance["sha256"],
+        format!("{:x}", Sha256::digest(&fixture))
+    );
+
+    prefix.leaf(&["remove", name]);
+    assert_eq!(prefix.listing(name), "\n", "{} left files behind", name);
+}
+
+#[test]
+fn test_tar_gz() {
+    install_and_remove(
+        "hello-tgz",
+        "hello.tar.gz",
+        serde_json::json!({
+            "type": "archive",
+            "executables": [{"path": "hello-1.0/bin/hello", "name": "hello-tgz"}]
+        }),
+    );
+}
+
+#[test]
+fn test_zip() {
+    install_and_remove(
+        "hello-zip",
+        "hello.zip",
+        serde_json::json!({
+            "type": "archive",
+            "executables": [{"path": "hello-1.0/bin/hello", "name": "hello-zip"}]
+        }),
+    );
+}
+
+#[test]
+fn test_binary() {
+    install_and_remove(
+        "hello-bin",
+        "hello",
+        serde_json::json!({
+            "type": "binary",
+            "executables": [{"path": "hello", "name": "hello-bin"}]
+        }),
+    );
+}
+
+#[test]
+fn test_build() {
+    install_and_remove(
+        "hello-build",
+        "hello-src.tar.gz",
+        serde_json::json!({
+            "type": "build",
+            "build_commands": [
+                "mkdir -p {package_dir}/bin",
+                "cp hello.sh {package_dir}/bin/hello && chmod 755 {package_dir}/bin/hello"
+            ],
+            "executables": [{"path": "bin/hello", "name": "hello-build"}]
+        }),
+    );
+}
+
+/// Make the install of the tar.gz fixture fail at `point`, check it was taken
+/// back completely, then check that a normal install still works
+fn fail_and_recover(point: &str, args: &[&str]) {
+    let name = HELLO_TGZ;
+    let (prefix, _) = hello_tgz_prefix(&serve_fixtures(), serde_json::json!({}));
+
+    let output = prefix.run(&[&["--inject-fault", point, "install", name], args].concat());
+    assert!(!output.status.success(), "install survived {}", point);
+    let messages = format!(
+        "{}{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(messages.contains(&format!("Injected fault: {}", point)));
+    assert_eq!(prefix.listing(name), "\n", "{} left files in bin", point);
+    assert!(
+        prefix.debris().is_empty(),
+        "{} left {:?}",
+        point,
+        prefix.debris()
+    );
+
+    prefix.leaf(&[&["install", name], args].concat());
+    let output = Command::new(prefix.0.join("bin").join(name))
+        .output()
+        .unwrap();
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "hello from leaf\n");
+}
+
+#[test]
+fn test_fault_mid_download() {
+    fail_and_recover("mid-download", &[]);
+}
+
+#[test]
+fn test_fault_mid_extract() {
+    fail_and_recover("mid-extract", &[]);
+}
+
+#[test]
+fn test_fault_before_symlink() {
+    fail_and_recover("before-symlink", &[]);
+}
+
+#[test]
+fn test_fault_after_symlink() {
+    fail_and_recover("after-symlink", &[]);
+    fail_and_recover("after-symlink", &["--link", "shim"]);
+}
+
+/// Install the tar.gz fixture checked against a published SHA256SUMS, then
+/// against one listing the wrong hash, which must fail and keep nothing
+#[test]
+fn test_checksum_url() {
+    let name = HELLO_TGZ;
+    let base_url = serve_fixtures();
+    for (sums, valid) in [("SHA256SUMS", true), ("SHA256SUMS.bad", false)] {
+        let checksum_url = format!("{}/{}", base_url, sums);
+        let (prefix, _) = hello_tgz_prefix(
+            &base_url,
+            serde_json::json!({ "checksum_url": checksum_url }),
+        );
+
+        let output = prefix.run(&["install", name]);
+        assert_eq!(output.status.success(), valid, "install with {}", sums);
+        if valid {
+            continue;
+        }
+        let messages = format!(
+            "{}{}",
+            String::from_utf8_lossy(&output.stdout),
+            String::from_utf8_lossy(&output.stderr)
+        );
+        assert!(messages.contains("Checksum mismatch"), "{}", messages);
+        assert_eq!(prefix.listing(name), "\n");
+        assert!(prefix.debris().is_empty(), "left {:?}", prefix.debris());
+        assert!(
+            !prefix
+                .0
+                .join("leaf")
+                .join("cache")
+                .join("hello.tar.gz")
+                .exists()
+        );
+    }
+}
+
+/// Download the tar.gz fixture without installing it, copying it out of the cache
+#[test]
+fn test_download() {
+    let name = HELLO_TGZ;
+    let base_url = serve_fixtures();
+    let checksum_url = format!("{}/SHA256SUMS", base_url);
+    let (prefix, _) = hello_tgz_prefix(
+        &base_url,
+        serde_json::json!({ "checksum_url": checksum_url }),
+    );
+
+    let out = prefix.0.join("mirror");
+    std::fs::create_dir_all(&out).unwrap();
+    let output = prefix.run(&["download", name, "-o", &out.to_string_lossy()]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("2325595b3136430ade3e91648dede92bb5a75dfd40a695954e8660f0ad9252d4"),
+        "{}",
+        stdout
+    );
+    assert!(out.join("hello.tar.gz").exists());
+    assert!(!prefix.package_dir(name).exists());
+}
+
+/// A definition for a future leaf is refused with a pointer to self-update,
+/// without breaking the rest of the index even though this leaf can't parse it
+#[test]
+fn test_min_leaf_version() {
+    let name = HELLO_TGZ;
+    let (prefix, mut index) = hello_tgz_prefix(&serve_fixtures(), serde_json::json!({}));
+    index["future"] = serde_json::json!({
+        "description": "uses a newer definition format",
+        "version": "2.0",
+        "min_leaf_version": "999.0",
+        "platforms": [{"key": "any"}]
+    });
+    prefix.set_index(&index);
+
+    let output = prefix.run(&["install", "future"]);
+    assert!(!output.status.success());
+    let messages = format!(
+        "{}{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(messages.contains("needs leaf 999.0"), "{}", messages);
+    assert!(messages.contains("leaf self-update"), "{}", messages);
+
+    prefix.leaf(&["install", name]);
+}
+
+/// A definition this leaf can't read is skipped and named when asked for,
+/// while the rest of the index keeps working
+#[test]
+fn test_unreadable_definition() {
+    let name = HELLO_TGZ;
+    let (prefix, mut index) = hello_tgz_prefix(&serve_fixtures(), serde_json::json!({}));
+    index["broken"] = serde_json::json!({"description": "platforms as a list", "version": "1.0", "platforms": []});
+    prefix.set_index(&index);
+
+    let output = prefix.run(&["install", "broken"]);
+    assert!(!output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("definition of 'broken' cannot be read"),
+        "{}",
+        stdout
+    );
+
+    prefix.leaf(&["install", name]);
+}
+
+/// An edited definition is kept as a local override that installs use and
+/// info points out, until it is reset; an invalid edit is refused
+#[test]
+fn test_edit() {
+    let name = HELLO_TGZ;
+    let (prefix, _) = hello_tgz_prefix(&serve_fixtures(), serde_json::json!({}));
+
+    // sed -i isn't portable, so the editor writes a copy and moves it back
+    let editor = |script: &str| {
+        let path = prefix.0.join("editor");
+        let content = format!(
+            "#!/bin/sh\nsed '{}' \"$1\" > \"$1.new\" && mv \"$1.new\" \"$1\"\n",
+            script
+        );
+        std::fs::write(&path, content).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        path.to_string_lossy().to_string()
+    };
+    let output = prefix.run_with_env(
+        &["edit", name],
+        &[("EDITOR", &editor("s/fixture package/edited package/"))],
+    );
+    assert!(output.status.success(), "{:?}", output);
+    let output = prefix.run(&["info", name]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("edited package"), "{}", stdout);
+    assert!(stdout.contains("locally modified"), "{}", stdout);
+    prefix.leaf(&["install", name]);
+    let metadata = std::fs::read_to_string(prefix.package_dir(name).join("leaf-package.json"));
+    assert!(metadata.unwrap().contains("edited package"));
+
+    let output = prefix.run_with_env(
+        &["edit", name],
+        &[("EDITOR", &editor("s/\"platforms\"/\"x\"/"))],
+    );
+    assert!(!output.status.success());
+    let output = prefix.run(&["info", name]);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("edited package"));
+
+    prefix.leaf(&["edit", name, "--reset"]);
+    let output = prefix.run(&["info", name]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("fixture package"), "{}", stdout);
+    assert!(!stdout.contains("locally modified"), "{}", stdout);
+}
+
+/// Notes are kept in the installed metadata, shown by list and info, and
+/// survive an upgrade
+#[test]
+fn test_note() {
+    let name = HELLO_TGZ;
+    let (prefix, mut index) = hello_tgz_prefix(&serve_fixtures(), serde_json::json!({}));
+
+    prefix.leaf(&["install", name]);
+    prefix.leaf(&["note", "add", name, "pinned for scripts"]);
+    for args in [&["list"][..], &["info", name]] {
+        let output = prefix.run(args);
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(stdout.contains("pinned for scripts"), "{}", stdout);
+    }
+
+    index[name]["version"] = "1.1".into();
+    prefix.set_index(&index);
+    prefix.leaf(&["upgrade", name]);
+    let metadata = std::fs::read_to_string(prefix.package_dir(name).join("leaf-package.json"));
+    let metadata: serde_json::Value = serde_json::from_str(&metadata.unwrap()).unwrap();
+    assert_eq!(metadata["version"], "1.1");
+    assert_eq!(metadata["notes"], serde_json::json!(["pinned for scripts"]));
+
+    prefix.leaf(&["note", "remove", name]);
+    let output = prefix.run(&["info", name]);
+    assert!(!String::from_utf8_lossy(&output.stdout).contains("pinned for scripts"));
+}
+
+/// With log_shim_runs set, each run through a shim is logged with the version
+/// it used, and counted by `leaf stats`
+#[test]
+fn test_shim_run_log() {
+    let name = HELLO_TGZ;
+    let (prefix, _) = hello_tgz_prefix(&serve_fixtures(), serde_json::json!({}));
+
+    prefix.leaf(&["install", "--link", "shim", name]);
+    let config_file = prefix.0.join("leaf").join("config.json");
+    let mut config: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&config_file).unwrap()).unwrap();
+    config["log_shim_runs"] = true.into();
+    std::fs::write(&config_file, config.to_string()).unwrap();
+
+    let output = Command::new(prefix.0.join("bin").join(name))
+        .arg("--flag")
+        .env("HOME", &prefix.0)
+        .output()
+        .unwrap();
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "hello from leaf\n");
+
+    let log = std::fs::read_to_string(prefix.0.join("leaf").join("logs").join("runs.jsonl"));
+    let entry: serde_json::Value = serde_json::from_str(log.unwrap().trim()).unwrap();
+    assert_eq!(entry["package"], name);
+    assert_eq!(entry["version"], "1.0");
+    assert_eq!(
+        entry["args_sha256"],
+        format!("{:x}", Sha256::digest(b"--flag\0"))
+    );
+
+    let output = prefix.run(&["stats"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("run once"), "{}", stdout);
+}
+
+/// An upgrade that fails puts the installed version back as it was
+#[test]
+fn test_failed_upgrade_keeps_old_version() {
+    let name = HELLO_TGZ;
+    let base_url = serve_fixtures();
+    let (prefix, mut index) = hello_tgz_prefix(&base_url, serde_json::json!({}));
+    prefix.leaf(&["install", name]);
+    prefix.leaf(&["note", "add", name, "keep me"]);
+
+    for details in index[name]["platforms"]
+        .as_object_mut()
+        .unwrap()
+        .values_mut()
+    {
+        details["url"] = format!("{}/missing.tar.gz", base_url).into();
+    }
+    index[name]["version"] = "1.1".into();
+    prefix.set_index(&index);
+    let output = prefix.run(&["upgrade", name]);
+    assert!(!output.status.success(), "{:?}", output);
+
+    let metadata = std::fs::read_to_string(prefix.package_dir(name).join("leaf-package.json"));
+    let metadata: serde_json::Value = serde_json::from_str(&metadata.unwrap()).unwrap();
+    assert_eq!(metadata["version"], "1.0");
+    assert_eq!(metadata["notes"], serde_json::json!(["keep me"]));
+    let output = Command::new(prefix.0.join("bin").join(name))
+        .output()
+        .unwrap();
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "hello from leaf\n");
+    assert_eq!(
+        prefix.debris(),
+        [
+            format!("packages/{}", name),
+            format!("modules/leaf/{}", name)
+        ]
+    );
+}